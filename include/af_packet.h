@@ -0,0 +1,65 @@
+/*
+ * C interface to the af_packet crate's TPACKET_V3 rings, built with the `ffi` feature.
+ *
+ * Functions returning an int return 0 or a negative errno, see afp_strerror. Packet data points
+ * into the ring: it is valid until its block is consumed, and every block must be consumed
+ * before its ring is closed.
+ */
+#ifndef AF_PACKET_H
+#define AF_PACKET_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct AfpRing AfpRing;
+typedef struct AfpBlock AfpBlock;
+
+typedef struct AfpPacket {
+    uint32_t tp_sec;
+    uint32_t tp_nsec;
+    /* length on the wire */
+    uint32_t wire_len;
+    /* bytes captured */
+    uint32_t captured_len;
+    /* 0 if the kernel didn't report one */
+    uint32_t rxhash;
+    /* 0 if unknown */
+    int32_t if_index;
+    /* ethertype in host order, 0 if unknown */
+    uint16_t protocol;
+    /* PACKET_HOST, PACKET_OUTGOING etc., 0xff if unknown */
+    uint8_t packet_type;
+    /* the frame from its link-layer header, or the network header for cooked rings */
+    const uint8_t *data;
+    size_t data_len;
+} AfpPacket;
+
+typedef struct AfpStats {
+    uint32_t packets;
+    uint32_t drops;
+    uint32_t freeze_q_cnt;
+} AfpStats;
+
+/* opens a ring with the default settings on if_name */
+int afp_ring_open(const char *if_name, int promisc, AfpRing **out);
+/* waits up to timeout_ms (forever if negative) for a block, -EAGAIN if none came */
+int afp_ring_next_block(AfpRing *ring, int timeout_ms, AfpBlock **out);
+uint32_t afp_block_packet_count(const AfpBlock *block);
+/* 1 with the next packet in *out, 0 once every packet has been walked */
+int afp_block_next_packet(AfpBlock *block, AfpPacket *out);
+/* hands the block back to the kernel and frees it */
+void afp_block_consume(AfpBlock *block);
+/* reads and resets the kernel counters */
+int afp_ring_stats(const AfpRing *ring, AfpStats *out);
+void afp_ring_close(AfpRing *ring);
+const char *afp_strerror(int err);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,110 @@
+//!Picks up packet sockets handed over by systemd, so a capture service can run without
+//!CAP_NET_RAW. systemd passes descriptors to a service from its file descriptor store, or from
+//!socket units, as LISTEN_FDS descriptors starting at 3 with their names in LISTEN_FDNAMES. Here
+//!a descriptor's name is taken to be the interface its socket is for, set with
+//!`FileDescriptorName=` or the FDNAME= sent along with FDSTORE=1. The sockets should be plain
+//!ones, fresh from socket(2), as the ring is set up and bound on them here.
+
+use libc::{c_int, fcntl, getpid, AF_PACKET, FD_CLOEXEC, F_SETFD, SO_DOMAIN};
+use std::env;
+use std::io::{self, Error, ErrorKind};
+use std::os::unix::io::RawFd;
+
+use error::{Operation, RingError};
+use rx::{setup_step, Ring, RingLabel, RingSettings};
+use socket;
+
+//the first descriptor systemd passes, after stdin, stdout and stderr
+const LISTEN_FDS_START: RawFd = 3;
+
+///A descriptor passed in by systemd
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListenFd {
+    pub fd: RawFd,
+    ///Its name from LISTEN_FDNAMES, if systemd gave one
+    pub name: Option<String>,
+}
+
+///Returns the descriptors systemd passed to this process, none if it wasn't started with any.
+///They are made close-on-exec. With `unset_env` the LISTEN_* variables are removed, so child
+///processes don't think the descriptors are for them
+pub fn listen_fds(unset_env: bool) -> io::Result<Vec<ListenFd>> {
+    let fds = read_listen_env();
+    if unset_env {
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+    }
+    let (count, names) = match fds? {
+        Some(fds) => fds,
+        None => return Ok(Vec::new()),
+    };
+    let mut names = names.into_iter();
+    let mut fds = Vec::with_capacity(count);
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count as RawFd {
+        if unsafe { fcntl(fd, F_SETFD, FD_CLOEXEC) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        fds.push(ListenFd {
+            fd,
+            name: names.next().filter(|name| !name.is_empty()),
+        });
+    }
+    Ok(fds)
+}
+
+///Returns the descriptors systemd passed that are AF_PACKET sockets
+pub fn packet_fds(unset_env: bool) -> io::Result<Vec<ListenFd>> {
+    Ok(listen_fds(unset_env)?
+        .into_iter()
+        .filter(|listen| is_packet_socket(listen.fd))
+        .collect())
+}
+
+///Creates a ring on every packet socket systemd passed, with `settings`. Each ring binds to the
+///interface its descriptor is named after, or to `if_name` in the settings for descriptors
+///without a name, and is labelled with its position among them as the index
+pub fn activated_rings(settings: &RingSettings) -> Result<Vec<Ring>, RingError> {
+    let fds = setup_step(
+        &RingLabel::from_settings(settings),
+        Operation::Other(String::from("reading LISTEN_FDS")),
+        packet_fds(true),
+    )?;
+    let mut rings = Vec::with_capacity(fds.len());
+    for (index, listen) in fds.into_iter().enumerate() {
+        let mut settings = settings.clone();
+        if let Some(name) = listen.name {
+            settings.if_name = name;
+        }
+        settings.index = index as u32;
+        rings.push(Ring::from_raw_fd(listen.fd, settings)?);
+    }
+    Ok(rings)
+}
+
+//the descriptor count and names, or None if the variables aren't there or are for another process
+fn read_listen_env() -> io::Result<Option<(usize, Vec<String>)>> {
+    let pid = match env::var("LISTEN_PID") {
+        Ok(pid) => pid,
+        Err(_) => return Ok(None),
+    };
+    let pid: c_int = pid
+        .parse()
+        .map_err(|_| Error::new(ErrorKind::InvalidData, format!("LISTEN_PID={}", pid)))?;
+    if pid != unsafe { getpid() } {
+        return Ok(None);
+    }
+    let count = env::var("LISTEN_FDS").unwrap_or_default();
+    let count: usize = count
+        .parse()
+        .map_err(|_| Error::new(ErrorKind::InvalidData, format!("LISTEN_FDS={}", count)))?;
+    let names = match env::var("LISTEN_FDNAMES") {
+        Ok(names) => names.split(':').map(String::from).collect(),
+        Err(_) => Vec::new(),
+    };
+    Ok(Some((count, names)))
+}
+
+fn is_packet_socket(fd: RawFd) -> bool {
+    socket::sol_socket_int(fd, SO_DOMAIN).ok() == Some(AF_PACKET)
+}
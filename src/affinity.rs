@@ -0,0 +1,150 @@
+//!Where a NIC's receive work runs, and steering it to the CPUs the capture threads are pinned to.
+//!With PACKET_FANOUT_CPU a packet goes to the ring of the CPU that received it, which is the CPU
+//!its queue's IRQ fired on, or the CPU RPS handed it to. Rings pinned to other CPUs get nothing.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+///One of a NIC's interrupts and the CPUs it may fire on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueueIrq {
+    pub irq: u32,
+    ///Name from /proc/interrupts, usually the interface or device name and a queue number
+    pub name: String,
+    pub cpus: Vec<usize>,
+}
+
+///Reads the interrupts of the named interface and their affinities. These come from the
+///device's MSI vectors in sysfs, or failing that the /proc/interrupts lines naming the interface
+///or its device
+pub fn irqs(if_name: &str) -> io::Result<Vec<QueueIrq>> {
+    let device = PathBuf::from("/sys/class/net").join(if_name).join("device");
+    let msi: Vec<u32> = fs::read_dir(device.join("msi_irqs"))
+        .map(|dir| {
+            dir.filter_map(|e| e.ok()?.file_name().to_str()?.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    let device_name = fs::read_link(&device)
+        .ok()
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()));
+    let mut found = Vec::new();
+    for line in fs::read_to_string("/proc/interrupts")?.lines() {
+        let mut fields = line.split_whitespace();
+        let irq: u32 = match fields
+            .next()
+            .and_then(|f| f.trim_end_matches(':').parse().ok())
+        {
+            Some(irq) => irq,
+            None => continue,
+        };
+        let name = fields.last().unwrap_or("");
+        let named = name.starts_with(if_name)
+            || device_name
+                .as_ref()
+                .is_some_and(|d| name.starts_with(d.as_str()));
+        if msi.contains(&irq) || (msi.is_empty() && named) {
+            found.push(QueueIrq {
+                irq,
+                name: String::from(name),
+                cpus: read_cpu_list(&format!("/proc/irq/{}/smp_affinity_list", irq))?,
+            });
+        }
+    }
+    Ok(found)
+}
+
+///Returns the CPUs the interface's interrupts can fire on that aren't in `worker_cpus`, warning
+///about each. Packets received there reach no ring under PACKET_FANOUT_CPU
+pub fn misaligned_cpus(if_name: &str, worker_cpus: &[usize]) -> io::Result<Vec<usize>> {
+    let mut cpus: Vec<usize> = irqs(if_name)?
+        .into_iter()
+        .flat_map(|q| q.cpus)
+        .filter(|c| !worker_cpus.contains(c))
+        .collect();
+    cpus.sort_unstable();
+    cpus.dedup();
+    for _cpu in &cpus {
+        trace_event!(
+            Warn,
+            "{} receives on CPU {}, which has no capture thread",
+            if_name,
+            _cpu
+        );
+    }
+    Ok(cpus)
+}
+
+///Has RPS hand every packet the interface receives to one of `cpus`, on all its receive queues.
+///Needs root
+pub fn set_rps(if_name: &str, cpus: &[usize]) -> io::Result<()> {
+    set_queue_masks(if_name, "rx-", "rps_cpus", cpus)
+}
+
+///Has XPS pick a transmit queue only from `cpus`, on all the interface's transmit queues. Needs
+///root
+pub fn set_xps(if_name: &str, cpus: &[usize]) -> io::Result<()> {
+    set_queue_masks(if_name, "tx-", "xps_cpus", cpus)
+}
+
+///Points RPS at the capture threads' CPUs if the interrupts fire on any other, so
+///PACKET_FANOUT_CPU reaches every ring. Returns true if RPS was changed
+pub fn align(if_name: &str, worker_cpus: &[usize]) -> io::Result<bool> {
+    if misaligned_cpus(if_name, worker_cpus)?.is_empty() {
+        return Ok(false);
+    }
+    set_rps(if_name, worker_cpus)?;
+    Ok(true)
+}
+
+fn set_queue_masks(if_name: &str, prefix: &str, attr: &str, cpus: &[usize]) -> io::Result<()> {
+    let mask = cpu_mask(cpus);
+    let queues = PathBuf::from("/sys/class/net").join(if_name).join("queues");
+    for entry in fs::read_dir(queues)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with(prefix) {
+            fs::write(entry.path().join(attr), &mask)?;
+        }
+    }
+    Ok(())
+}
+
+//"0-3,8,10-11"
+fn read_cpu_list(path: &str) -> io::Result<Vec<usize>> {
+    let bad = |_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("bad CPU list in {}", path),
+        )
+    };
+    let mut cpus = Vec::new();
+    for range in fs::read_to_string(path)?
+        .trim()
+        .split(',')
+        .filter(|r| !r.is_empty())
+    {
+        let mut ends = range.splitn(2, '-');
+        let first: usize = ends.next().unwrap_or("").parse().map_err(bad)?;
+        let last: usize = match ends.next() {
+            Some(l) => l.parse().map_err(bad)?,
+            None => first,
+        };
+        cpus.extend(first..=last);
+    }
+    Ok(cpus)
+}
+
+//comma-separated 32 bit hex words, most significant first, as sysfs bitmaps are written
+fn cpu_mask(cpus: &[usize]) -> String {
+    let words = cpus.iter().max().map_or(1, |m| m / 32 + 1);
+    let mut mask = vec![0u32; words];
+    for &cpu in cpus {
+        mask[cpu / 32] |= 1 << (cpu % 32);
+    }
+    mask.iter()
+        .rev()
+        .map(|w| format!("{:08x}", w))
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -0,0 +1,275 @@
+//!Scrubs captures before they are shared. An `Anonymizer` set on a `PcapWriter` or
+//!`PcapngWriter` rewrites every frame as it is written: IPv4 and IPv6 addresses are zeroed or
+//!remapped, and what follows the TCP or UDP header, or the IP header for other protocols and
+//!later fragments, can be zeroed. Checksums the rewrite touches are updated, so tools don't flag
+//!every packet. Packets sent from the capturing host often carry only the partial checksum left
+//!for the NIC to finish, those were never valid and still aren't.
+//!
+//!The prefix-preserving remapping works like Crypto-PAn: each bit of an address is flipped or not
+//!depending on a keyed hash (SipHash-2-4) of the bits before it, so two addresses sharing an
+//!n-bit prefix still share one after remapping, and subnets stay recognisable. The same key gives
+//!the same mapping in every file. IPv6 extension headers aren't walked, so packets with them have
+//!their payload zeroed from the end of the fixed header.
+
+use linktype::{LINKTYPE_ETHERNET, LINKTYPE_LINUX_SLL, LINKTYPE_RAW};
+use vlan::VlanStack;
+
+const ETH_P_IP: u16 = 0x0800;
+const ETH_P_IPV6: u16 = 0x86dd;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+//the LINKTYPE_LINUX_SLL header ends with the protocol
+const SLL_HDR_LEN: usize = 16;
+
+///What is done to IP addresses
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AddressMode {
+    #[default]
+    Keep,
+    ///Every address becomes 0.0.0.0 or ::
+    Zero,
+    ///Addresses are remapped keeping shared prefixes, under the 128-bit key
+    PrefixPreserving([u8; 16]),
+}
+
+///How frames are scrubbed. The default leaves them as they are
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Anonymizer {
+    pub addresses: AddressMode,
+    ///Zero everything after the transport header
+    pub zero_payloads: bool,
+}
+
+impl Anonymizer {
+    ///Scrubs `frame`, which starts with the `link_type` header. Frames of other link types than
+    ///Ethernet, Linux cooked and raw IP, and frames that aren't IP, are left alone
+    pub fn apply(&self, link_type: u32, frame: &mut [u8]) {
+        let l3 = match l3_offset(link_type, frame) {
+            Some(l3) => l3,
+            None => return,
+        };
+        match frame.get(l3).map(|b| b >> 4) {
+            Some(4) => self.ipv4(frame, l3),
+            Some(6) => self.ipv6(frame, l3),
+            _ => {}
+        }
+    }
+
+    fn ipv4(&self, frame: &mut [u8], l3: usize) {
+        let ihl = usize::from(frame[l3] & 0x0f) * 4;
+        if ihl < 20 || frame.len() < l3 + ihl {
+            return;
+        }
+        let total_len = usize::from(u16::from_be_bytes([frame[l3 + 2], frame[l3 + 3]]));
+        let end = frame.len().min(l3 + total_len.max(ihl));
+        let protocol = frame[l3 + 9];
+        let first_fragment = u16::from_be_bytes([frame[l3 + 6], frame[l3 + 7]]) & 0x1fff == 0;
+        let delta = self.remap(&mut frame[l3 + 12..l3 + 20]);
+        delta.apply(frame, l3 + 10);
+        let l4 = l3 + ihl;
+        if first_fragment {
+            self.transport(frame, protocol, l4, end, delta, false);
+        } else if self.zero_payloads {
+            zero(frame, l4, end);
+        }
+    }
+
+    fn ipv6(&self, frame: &mut [u8], l3: usize) {
+        if frame.len() < l3 + 40 {
+            return;
+        }
+        let payload_len = usize::from(u16::from_be_bytes([frame[l3 + 4], frame[l3 + 5]]));
+        let end = frame.len().min(l3 + 40 + payload_len);
+        let protocol = frame[l3 + 6];
+        let delta = self.remap(&mut frame[l3 + 8..l3 + 40]);
+        self.transport(frame, protocol, l3 + 40, end, delta, true);
+    }
+
+    //fixes up the TCP or UDP checksum for the new addresses, which its pseudo header covers, and
+    //zeroes the payload. UDP over IPv4 may have no checksum, UDP over IPv6 always has one
+    fn transport(
+        &self,
+        frame: &mut [u8],
+        protocol: u8,
+        l4: usize,
+        end: usize,
+        mut delta: Delta,
+        ipv6: bool,
+    ) {
+        let (csum_at, hdr_len) = match protocol {
+            IPPROTO_TCP => match frame.get(l4 + 12) {
+                Some(&off) => (l4 + 16, usize::from(off >> 4) * 4),
+                None => return,
+            },
+            IPPROTO_UDP => (l4 + 6, 8),
+            _ => {
+                if self.zero_payloads {
+                    zero(frame, l4, end);
+                }
+                return;
+            }
+        };
+        let payload = l4 + hdr_len.max(8);
+        if self.zero_payloads && payload < end {
+            delta.old += sum16(&frame[payload..end]);
+            zero(frame, payload, end);
+        }
+        if csum_at + 2 > end {
+            return;
+        }
+        let csum = u16::from_be_bytes([frame[csum_at], frame[csum_at + 1]]);
+        if protocol == IPPROTO_UDP && csum == 0 && !ipv6 {
+            return;
+        }
+        delta.apply(frame, csum_at);
+        if protocol == IPPROTO_UDP && frame[csum_at..csum_at + 2] == [0, 0] {
+            frame[csum_at..csum_at + 2].copy_from_slice(&[0xff, 0xff]);
+        }
+    }
+
+    //rewrites the source and destination address in `addrs` and returns the checksum change
+    fn remap(&self, addrs: &mut [u8]) -> Delta {
+        let old = sum16(addrs);
+        let half = addrs.len() / 2;
+        match self.addresses {
+            AddressMode::Keep => {}
+            AddressMode::Zero => zero(addrs, 0, addrs.len()),
+            AddressMode::PrefixPreserving(ref key) => {
+                let (src, dst) = addrs.split_at_mut(half);
+                prefix_preserving(key, src);
+                prefix_preserving(key, dst);
+            }
+        }
+        Delta {
+            old,
+            new: sum16(addrs),
+        }
+    }
+}
+
+//where the IP header starts, if the frame carries IP
+fn l3_offset(link_type: u32, frame: &[u8]) -> Option<usize> {
+    let (ethertype, l3) = match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut stack = VlanStack::new(frame, None);
+            for _ in &mut stack {}
+            (stack.ethertype()?, stack.ethertype_offset() + 2)
+        }
+        LINKTYPE_LINUX_SLL => {
+            let b = frame.get(SLL_HDR_LEN - 2..SLL_HDR_LEN)?;
+            (u16::from_be_bytes([b[0], b[1]]), SLL_HDR_LEN)
+        }
+        LINKTYPE_RAW => return Some(0),
+        _ => return None,
+    };
+    match ethertype {
+        ETH_P_IP | ETH_P_IPV6 => Some(l3),
+        _ => None,
+    }
+}
+
+//the ones' complement sums of the bytes before and after a rewrite
+#[derive(Clone, Copy)]
+struct Delta {
+    old: u32,
+    new: u32,
+}
+
+impl Delta {
+    //updates the checksum at `at` incrementally, as in RFC 1624: HC' = ~(~HC + ~m + m')
+    fn apply(&self, frame: &mut [u8], at: usize) {
+        let csum = match frame.get(at..at + 2) {
+            Some(b) => u16::from_be_bytes([b[0], b[1]]),
+            None => return,
+        };
+        let sum = u32::from(!csum) + u32::from(!fold(self.old)) + u32::from(fold(self.new));
+        frame[at..at + 2].copy_from_slice(&(!fold(sum)).to_be_bytes());
+    }
+}
+
+fn sum16(bytes: &[u8]) -> u32 {
+    bytes.chunks(2).fold(0u32, |sum, word| {
+        let word = u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]);
+        let sum = sum + u32::from(word);
+        (sum & 0xffff) + (sum >> 16)
+    })
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+fn zero(frame: &mut [u8], start: usize, end: usize) {
+    if start < end {
+        for b in &mut frame[start..end] {
+            *b = 0;
+        }
+    }
+}
+
+//flips bit i of `addr` by a keyed hash of the original bits before it
+fn prefix_preserving(key: &[u8; 16], addr: &mut [u8]) {
+    let original = addr.to_vec();
+    let mut prefix = vec![0u8; original.len() + 1];
+    for bit in 0..original.len() * 8 {
+        let (byte, shift) = (bit / 8, 7 - bit % 8);
+        //the length goes in too, so a prefix of zeros doesn't hash like a shorter one
+        prefix[original.len()] = bit as u8;
+        if siphash24(key, &prefix) & 1 == 1 {
+            addr[byte] ^= 1 << shift;
+        }
+        prefix[byte] |= original[byte] & (1 << shift);
+    }
+}
+
+fn siphash24(key: &[u8; 16], data: &[u8]) -> u64 {
+    let k0 = u64::from_le_bytes([
+        key[0], key[1], key[2], key[3], key[4], key[5], key[6], key[7],
+    ]);
+    let k1 = u64::from_le_bytes([
+        key[8], key[9], key[10], key[11], key[12], key[13], key[14], key[15],
+    ]);
+    let mut v = [
+        k0 ^ 0x736f_6d65_7073_6575,
+        k1 ^ 0x646f_7261_6e64_6f6d,
+        k0 ^ 0x6c79_6765_6e65_7261,
+        k1 ^ 0x7465_6462_7974_6573,
+    ];
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let m = u64::from_le_bytes([
+            chunk[0], chunk[1], chunk[2], chunk[3], chunk[4], chunk[5], chunk[6], chunk[7],
+        ]);
+        v[3] ^= m;
+        sip_rounds(&mut v, 2);
+        v[0] ^= m;
+    }
+    let mut last = [0u8; 8];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    last[7] = data.len() as u8;
+    let m = u64::from_le_bytes(last);
+    v[3] ^= m;
+    sip_rounds(&mut v, 2);
+    v[0] ^= m;
+    v[2] ^= 0xff;
+    sip_rounds(&mut v, 4);
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+fn sip_rounds(v: &mut [u64; 4], rounds: usize) {
+    for _ in 0..rounds {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+}
@@ -0,0 +1,256 @@
+//!Packet inter-arrival times and microburst detection from ring timestamps. A block's packets
+//!come in capture order, so walking blocks as they are handed out gives the gaps between
+//!consecutive packets for the cost of a subtraction each. A microburst is a stretch where more
+//!bytes arrived within a short window than the link or its consumers are meant to take, which
+//!average rates over seconds hide.
+
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+
+#[cfg(target_os = "linux")]
+use rx::Block;
+use rx::RawPacket;
+
+//buckets are powers of two nanoseconds up to ~4 seconds, longer gaps go in the last one
+const GAP_BUCKETS: usize = 32;
+
+///Histogram of the gaps between consecutive packets
+#[derive(Clone, Debug, Default)]
+pub struct GapHistogram {
+    buckets: [u64; GAP_BUCKETS],
+    total: u64,
+    sum: Duration,
+    min: Option<Duration>,
+    max: Duration,
+}
+
+impl GapHistogram {
+    pub fn new() -> GapHistogram {
+        GapHistogram::default()
+    }
+
+    #[inline]
+    pub fn record(&mut self, gap: Duration) {
+        let nanos = gap.as_nanos().max(1) as u64;
+        let bucket = (63 - nanos.leading_zeros() as usize).min(GAP_BUCKETS - 1);
+        self.buckets[bucket] += 1;
+        self.total += 1;
+        self.sum += gap;
+        self.min = Some(self.min.map_or(gap, |min| min.min(gap)));
+        if gap > self.max {
+            self.max = gap;
+        }
+    }
+
+    ///Number of gaps recorded
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    ///Shortest gap recorded, zero if none were
+    pub fn min(&self) -> Duration {
+        self.min.unwrap_or_default()
+    }
+
+    ///Longest gap recorded
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    ///Mean gap, zero if none were recorded
+    pub fn mean(&self) -> Duration {
+        match self.total {
+            0 => Duration::from_secs(0),
+            n => self.sum / n as u32,
+        }
+    }
+
+    ///Returns the count in each bucket, bucket `i` holding gaps from 2^i up to 2^(i+1)
+    ///nanoseconds
+    pub fn buckets(&self) -> &[u64; GAP_BUCKETS] {
+        &self.buckets
+    }
+
+    ///Returns an upper bound for the gap at percentile `p` (0-100), accurate to within a factor
+    ///of two
+    pub fn percentile(&self, p: f64) -> Duration {
+        if self.total == 0 {
+            return Duration::from_secs(0);
+        }
+        let target = ((p.clamp(0.0, 100.0) / 100.0) * self.total as f64).ceil() as u64;
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target.max(1) {
+                let upper = Duration::from_nanos(1u64 << (i + 1));
+                return upper.min(self.max);
+            }
+        }
+        self.max
+    }
+
+    pub fn reset(&mut self) {
+        *self = GapHistogram::default();
+    }
+}
+
+///What counts as a microburst: more than `max_bytes` arriving within any `window`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BurstThreshold {
+    pub window: Duration,
+    pub max_bytes: u64,
+}
+
+impl BurstThreshold {
+    ///The threshold for traffic above `bits_per_sec` sustained over `window`, e.g. a link's
+    ///line rate or what the consumers can keep up with
+    pub fn from_rate(bits_per_sec: u64, window: Duration) -> BurstThreshold {
+        BurstThreshold {
+            window,
+            max_bytes: (bits_per_sec as f64 / 8.0 * window.as_secs_f64()) as u64,
+        }
+    }
+}
+
+///A stretch of traffic over the burst threshold
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Microburst {
+    ///Capture time of the first packet in the window that went over the threshold
+    pub start: SystemTime,
+    ///Capture time of the last packet while it was still over
+    pub end: SystemTime,
+    ///Packets and bytes from `start` to `end`
+    pub packets: u64,
+    pub bytes: u64,
+    ///Most bytes seen within one window during the burst
+    pub peak_window_bytes: u64,
+}
+
+impl Microburst {
+    ///How long the burst lasted
+    pub fn duration(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap_or_default()
+    }
+}
+
+///Measures inter-arrival times and finds microbursts in the packets it is shown, which should be
+///those of one ring in the order the ring handed them out
+#[derive(Clone, Debug)]
+pub struct ArrivalAnalyzer {
+    threshold: Option<BurstThreshold>,
+    gaps: GapHistogram,
+    last: Option<SystemTime>,
+    out_of_order: u64,
+    //packets in the current window, oldest first, and their total bytes
+    window: VecDeque<(SystemTime, u64)>,
+    window_bytes: u64,
+    burst: Option<Microburst>,
+    bursts: Vec<Microburst>,
+}
+
+impl ArrivalAnalyzer {
+    ///Creates an analyzer measuring gaps only, or finding bursts over `threshold` as well
+    pub fn new(threshold: Option<BurstThreshold>) -> ArrivalAnalyzer {
+        ArrivalAnalyzer {
+            threshold,
+            gaps: GapHistogram::new(),
+            last: None,
+            out_of_order: 0,
+            window: VecDeque::new(),
+            window_bytes: 0,
+            burst: None,
+            bursts: Vec::new(),
+        }
+    }
+
+    ///Records a packet of `len` bytes on the wire captured at `time`
+    pub fn record(&mut self, time: SystemTime, len: u32) {
+        match self.last.map(|last| time.duration_since(last)) {
+            Some(Ok(gap)) => self.gaps.record(gap),
+            //timestamps going backwards, e.g. after a clock step or across fanout rings
+            Some(Err(_)) => {
+                self.out_of_order += 1;
+                return;
+            }
+            None => {}
+        }
+        self.last = Some(time);
+        if let Some(threshold) = self.threshold {
+            self.track_burst(threshold, time, u64::from(len));
+        }
+    }
+
+    ///Records every packet in `packets`
+    pub fn record_packets(&mut self, packets: &[RawPacket<'_>]) {
+        for packet in packets {
+            self.record(packet.timestamp(), packet.wire_len());
+        }
+    }
+
+    ///Records every packet in `block`
+    #[cfg(target_os = "linux")]
+    pub fn record_block(&mut self, block: &Block<'_>) {
+        self.record_packets(&block.get_raw_packets());
+    }
+
+    fn track_burst(&mut self, threshold: BurstThreshold, time: SystemTime, len: u64) {
+        self.window.push_back((time, len));
+        self.window_bytes += len;
+        while let Some(&(oldest, bytes)) = self.window.front() {
+            match time.duration_since(oldest) {
+                Ok(age) if age > threshold.window => {
+                    self.window.pop_front();
+                    self.window_bytes -= bytes;
+                }
+                _ => break,
+            }
+        }
+        if self.window_bytes > threshold.max_bytes {
+            match self.burst.as_mut() {
+                Some(burst) => {
+                    burst.end = time;
+                    burst.packets += 1;
+                    burst.bytes += len;
+                    burst.peak_window_bytes = burst.peak_window_bytes.max(self.window_bytes);
+                }
+                None => {
+                    let start = self.window.front().map_or(time, |&(t, _)| t);
+                    self.burst = Some(Microburst {
+                        start,
+                        end: time,
+                        packets: self.window.len() as u64,
+                        bytes: self.window_bytes,
+                        peak_window_bytes: self.window_bytes,
+                    });
+                }
+            }
+        } else if let Some(burst) = self.burst.take() {
+            self.bursts.push(burst);
+        }
+    }
+
+    ///Returns the inter-arrival times seen so far
+    pub fn gaps(&self) -> &GapHistogram {
+        &self.gaps
+    }
+
+    ///Number of packets left out because they were timestamped before the one before them
+    pub fn out_of_order(&self) -> u64 {
+        self.out_of_order
+    }
+
+    ///Returns true while the traffic is over the burst threshold
+    pub fn in_burst(&self) -> bool {
+        self.burst.is_some()
+    }
+
+    ///Takes the microbursts that have ended since the last call
+    pub fn take_bursts(&mut self) -> Vec<Microburst> {
+        std::mem::take(&mut self.bursts)
+    }
+
+    ///Clears the gaps and bursts recorded, keeping the threshold
+    pub fn reset(&mut self) {
+        *self = ArrivalAnalyzer::new(self.threshold);
+    }
+}
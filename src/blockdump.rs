@@ -0,0 +1,331 @@
+//!Copies raw TPACKET_V3 blocks to disk, for replaying hard to reproduce parsing problems offline
+//!and for regression tests that feed the same blocks to the parsers every time.
+//!
+//!`BlockDump` copies retired blocks to files while armed. Each file holds one block as the
+//!kernel wrote it, block descriptor first, up to blk_len. Read one back with `fs::read` and hand
+//!it to `rx::Block::from_bytes` to run the crate's parsers over it again.
+//!
+//!`BlockWriter` and `BlockReplay` keep a run of blocks in one file together with the ring
+//!settings they were captured with. The file starts with a header, in the byte order of the
+//!machine that wrote it as the blocks are:
+//!
+//!- magic `0x41465033`, version (u16) and header length (u16)
+//!- tp_block_size, tp_block_nr, tp_frame_size, tp_frame_nr, tp_retire_blk_tov, tp_sizeof_priv
+//!  and tp_feature_req_word (u32 each)
+//!- the pcap link type (u32), `0xffffffff` if the ring had none
+//!- the length of the interface name (u16) and the name
+//!
+//!Then each block is its length (u32) followed by the block.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[cfg(target_os = "linux")]
+use rx::{Block, Ring, RingLabel};
+use tpacket3::TpacketReq3;
+
+const MAGIC: u32 = 0x4146_5033;
+const VERSION: u16 = 1;
+//magic, version, header length, the seven ring settings and the link type
+const FIXED_HEADER_LEN: usize = 4 + 2 + 2 + 7 * 4 + 4;
+const NO_LINK_TYPE: u32 = 0xffff_ffff;
+
+#[derive(Debug)]
+struct Inner {
+    dir: PathBuf,
+    remaining: AtomicU64,
+    written: AtomicU64,
+    failed: AtomicU64,
+}
+
+///A handle to a block dump. Clones share it, so the one given to rings in
+///`RingSettings::block_dump` can be armed from another thread, e.g. a signal or admin endpoint
+#[derive(Clone, Debug)]
+pub struct BlockDump {
+    inner: Arc<Inner>,
+}
+
+impl BlockDump {
+    ///Writes blocks to `dir`, creating it if needed. Nothing is written until `arm` is called
+    pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<BlockDump> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(BlockDump {
+            inner: Arc::new(Inner {
+                dir,
+                remaining: AtomicU64::new(0),
+                written: AtomicU64::new(0),
+                failed: AtomicU64::new(0),
+            }),
+        })
+    }
+
+    ///Copies the next `blocks` blocks retired by any ring sharing this dump, replacing whatever
+    ///was left of an earlier `arm`
+    pub fn arm(&self, blocks: u64) {
+        self.inner.remaining.store(blocks, Ordering::SeqCst);
+    }
+
+    ///Stops copying blocks
+    pub fn disarm(&self) {
+        self.arm(0);
+    }
+
+    ///Returns the number of blocks still to be copied
+    pub fn remaining(&self) -> u64 {
+        self.inner.remaining.load(Ordering::SeqCst)
+    }
+
+    ///Returns the number of block files written
+    pub fn written(&self) -> u64 {
+        self.inner.written.load(Ordering::SeqCst)
+    }
+
+    ///Returns the number of blocks that couldn't be written, each is traced
+    pub fn failed(&self) -> u64 {
+        self.inner.failed.load(Ordering::SeqCst)
+    }
+
+    ///Returns the directory block files are written to
+    pub fn dir(&self) -> &Path {
+        &self.inner.dir
+    }
+
+    //writes the block if the dump is armed, taking one from the remaining count. Files are named
+    //after the ring and the block's sequence number so dumps from several rings don't collide
+    #[cfg(target_os = "linux")]
+    pub(crate) fn offer(&self, label: &RingLabel, seq: u64, block: &[u8]) {
+        let take = self
+            .inner
+            .remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+        if take.is_err() {
+            return;
+        }
+        let name = format!("{}-{}-{:010}.block", label.if_name, label.index, seq);
+        let path = self.inner.dir.join(name);
+        match fs::write(&path, block) {
+            Ok(()) => {
+                self.inner.written.fetch_add(1, Ordering::SeqCst);
+                trace_event!(Debug, "ring {}: block {} dumped to {:?}", label, seq, path);
+            }
+            Err(e) => {
+                self.inner.failed.fetch_add(1, Ordering::SeqCst);
+                trace_event!(Warn, "ring {}: dumping block {} failed: {}", label, seq, e);
+            }
+        }
+    }
+}
+
+///What a block file records about the ring its blocks came from
+#[derive(Clone, Debug)]
+pub struct BlockFileInfo {
+    pub if_name: String,
+    pub ring_settings: TpacketReq3,
+    ///As returned by `Ring::link_type`
+    pub link_type: Option<u32>,
+}
+
+impl BlockFileInfo {
+    #[cfg(target_os = "linux")]
+    pub fn for_ring(ring: &Ring) -> BlockFileInfo {
+        BlockFileInfo {
+            if_name: ring.label().if_name.clone(),
+            ring_settings: ring.ring_settings().clone(),
+            link_type: ring.link_type(),
+        }
+    }
+}
+
+///Writes blocks to a block file
+#[derive(Debug)]
+pub struct BlockWriter<W: Write> {
+    out: W,
+    blocks: u64,
+}
+
+impl<W: Write> BlockWriter<W> {
+    ///Writes the header for `info` to `out`
+    pub fn new(mut out: W, info: &BlockFileInfo) -> io::Result<BlockWriter<W>> {
+        let name = info.if_name.as_bytes();
+        let mut header = Vec::with_capacity(FIXED_HEADER_LEN + 2 + name.len());
+        let req = &info.ring_settings;
+        header.extend_from_slice(&MAGIC.to_ne_bytes());
+        header.extend_from_slice(&VERSION.to_ne_bytes());
+        header.extend_from_slice(&((FIXED_HEADER_LEN + 2 + name.len()) as u16).to_ne_bytes());
+        for field in &[
+            req.tp_block_size,
+            req.tp_block_nr,
+            req.tp_frame_size,
+            req.tp_frame_nr,
+            req.tp_retire_blk_tov,
+            req.tp_sizeof_priv,
+            req.tp_feature_req_word,
+            info.link_type.unwrap_or(NO_LINK_TYPE),
+        ] {
+            header.extend_from_slice(&field.to_ne_bytes());
+        }
+        header.extend_from_slice(&(name.len() as u16).to_ne_bytes());
+        header.extend_from_slice(name);
+        out.write_all(&header)?;
+        Ok(BlockWriter { out, blocks: 0 })
+    }
+
+    ///Writes the header for `ring`'s settings to `out`
+    #[cfg(target_os = "linux")]
+    pub fn for_ring(out: W, ring: &Ring) -> io::Result<BlockWriter<W>> {
+        BlockWriter::new(out, &BlockFileInfo::for_ring(ring))
+    }
+
+    ///Writes the part of `block` the kernel wrote. Call it before the block is consumed
+    #[cfg(target_os = "linux")]
+    pub fn write_block(&mut self, block: &Block<'_>) -> io::Result<()> {
+        self.write_bytes(block.as_bytes())
+    }
+
+    ///Writes a block held as bytes, e.g. one read from a `BlockDump` file
+    pub fn write_bytes(&mut self, block: &[u8]) -> io::Result<()> {
+        self.out.write_all(&(block.len() as u32).to_ne_bytes())?;
+        self.out.write_all(block)?;
+        self.blocks += 1;
+        Ok(())
+    }
+
+    ///Returns the number of blocks written
+    pub fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    ///Flushes and returns the output
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+///Reads the blocks of a block file back in the order they were written
+#[derive(Debug)]
+pub struct BlockReplay<R: Read> {
+    input: R,
+    info: BlockFileInfo,
+    buf: Vec<u8>,
+    blocks: u64,
+}
+
+impl BlockReplay<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<BlockReplay<BufReader<File>>> {
+        BlockReplay::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> BlockReplay<R> {
+    ///Reads the header. Fails with `InvalidData` if `input` isn't a block file, or was written on
+    ///a machine of the other byte order, whose blocks this one can't parse
+    pub fn new(mut input: R) -> io::Result<BlockReplay<R>> {
+        let mut fixed = [0; FIXED_HEADER_LEN];
+        input.read_exact(&mut fixed)?;
+        let u32_at = |at: usize| {
+            u32::from_ne_bytes([fixed[at], fixed[at + 1], fixed[at + 2], fixed[at + 3]])
+        };
+        let u16_at = |at: usize| u16::from_ne_bytes([fixed[at], fixed[at + 1]]);
+        if u32_at(0) == MAGIC.swap_bytes() {
+            return Err(invalid("block file written with the other byte order"));
+        }
+        if u32_at(0) != MAGIC {
+            return Err(invalid("not a block file"));
+        }
+        if u16_at(4) != VERSION {
+            return Err(invalid(&format!(
+                "unknown block file version {}",
+                u16_at(4)
+            )));
+        }
+        let header_len = u16_at(6) as usize;
+        let mut rest = vec![0; header_len.saturating_sub(FIXED_HEADER_LEN)];
+        input.read_exact(&mut rest)?;
+        if rest.len() < 2 {
+            return Err(invalid("block file header too short"));
+        }
+        let name_len = u16::from_ne_bytes([rest[0], rest[1]]) as usize;
+        let name = rest
+            .get(2..2 + name_len)
+            .ok_or_else(|| invalid("block file header too short"))?;
+        let ring_settings = TpacketReq3 {
+            tp_block_size: u32_at(8),
+            tp_block_nr: u32_at(12),
+            tp_frame_size: u32_at(16),
+            tp_frame_nr: u32_at(20),
+            tp_retire_blk_tov: u32_at(24),
+            tp_sizeof_priv: u32_at(28),
+            tp_feature_req_word: u32_at(32),
+        };
+        let link_type = match u32_at(36) {
+            NO_LINK_TYPE => None,
+            link_type => Some(link_type),
+        };
+        Ok(BlockReplay {
+            input,
+            info: BlockFileInfo {
+                if_name: String::from_utf8_lossy(name).into_owned(),
+                ring_settings,
+                link_type,
+            },
+            buf: Vec::new(),
+            blocks: 0,
+        })
+    }
+
+    ///Returns what the file records about the ring
+    pub fn info(&self) -> &BlockFileInfo {
+        &self.info
+    }
+
+    ///Reads the next block, `None` at the end of the file. The block borrows the replay, so it
+    ///has to be dropped before the next one is read
+    #[cfg(target_os = "linux")]
+    pub fn next_block(&mut self) -> io::Result<Option<Block<'_>>> {
+        match self.next_bytes()? {
+            Some(bytes) => Block::from_bytes(bytes)
+                .map(Some)
+                .ok_or_else(|| invalid("block too short for a block descriptor")),
+            None => Ok(None),
+        }
+    }
+
+    ///Reads the next block as bytes, `None` at the end of the file
+    pub fn next_bytes(&mut self) -> io::Result<Option<&mut [u8]>> {
+        let mut len = [0; 4];
+        match self.input.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let len = u32::from_ne_bytes(len) as usize;
+        if len > self.info.ring_settings.tp_block_size as usize {
+            return Err(invalid(&format!(
+                "block of {} bytes is larger than the ring's blocks",
+                len
+            )));
+        }
+        self.buf.resize(len, 0);
+        self.input.read_exact(&mut self.buf)?;
+        self.blocks += 1;
+        Ok(Some(&mut self.buf))
+    }
+
+    ///Returns the number of blocks read
+    pub fn blocks(&self) -> u64 {
+        self.blocks
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
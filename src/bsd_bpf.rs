@@ -0,0 +1,297 @@
+//!Capture through a /dev/bpf device on macOS and FreeBSD, so capture applications can at least be
+//!run and tested on a developer machine before being deployed against the Linux rings. It
+//!implements `capture::Capture`, delivering each read buffer as one batch
+
+use std::ffi::CString;
+use std::io::{self, Error, ErrorKind};
+use std::mem;
+use std::os::raw::{c_char, c_int, c_long, c_uint, c_ulong, c_void};
+use std::time::{Duration, UNIX_EPOCH};
+
+use libc::{
+    bpf_hdr, close, ioctl, open, read, BIOCGBLEN, BIOCGDLT, BIOCGSTATS, BIOCIMMEDIATE, BIOCPROMISC,
+    BIOCSBLEN, BIOCSETF, BIOCSETIF, EBUSY, O_RDWR,
+};
+
+use capture::{Capture, CaptureStats};
+use filter::SockFilter;
+use rx::{label_error, RawPacket, RingLabel, RingTotals};
+
+//BPF_WORDALIGN, records in a read buffer start on these boundaries
+#[cfg(target_vendor = "apple")]
+const BPF_ALIGNMENT: usize = 4;
+#[cfg(not(target_vendor = "apple"))]
+const BPF_ALIGNMENT: usize = mem::size_of::<c_long>();
+
+//devices are tried in order until one isn't in use
+const MAX_DEVICES: u32 = 256;
+
+#[repr(C)]
+struct IfReq {
+    ifr_name: [c_char; 16],
+    data: [u8; 16],
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct bpf_program {
+    bf_len: c_uint,
+    bf_insns: *const SockFilter,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct bpf_stat {
+    bs_recv: c_uint,
+    bs_drop: c_uint,
+}
+
+///Settings to be used to open a BPF device
+#[derive(Clone, Debug)]
+pub struct BpfSettings {
+    ///Interface name
+    pub if_name: String,
+    ///Size of the kernel's read buffer, which is also the most one batch can hold
+    pub buffer_size: u32,
+    pub promisc: bool,
+    ///Deliver packets as soon as they arrive rather than when the buffer fills
+    pub immediate: bool,
+    ///Optional user tag used alongside the interface to identify the device
+    pub tag: Option<String>,
+}
+
+impl Default for BpfSettings {
+    fn default() -> BpfSettings {
+        BpfSettings {
+            if_name: String::from("en0"),
+            buffer_size: 1 << 20,
+            promisc: true,
+            immediate: true,
+            tag: None,
+        }
+    }
+}
+
+///A BPF device attached to an interface
+#[derive(Debug)]
+pub struct Device {
+    fd: c_int,
+    label: RingLabel,
+    buf: Vec<u8>,
+    totals: RingTotals,
+    last_stats: (u32, u32),
+}
+
+impl Device {
+    ///Opens a BPF device on the specified interface name in promiscuous mode
+    pub fn from_if_name(if_name: &str) -> io::Result<Device> {
+        Device::new(BpfSettings {
+            if_name: String::from(if_name),
+            ..BpfSettings::default()
+        })
+    }
+
+    ///Opens a BPF device from the supplied BpfSettings struct
+    pub fn new(settings: BpfSettings) -> io::Result<Device> {
+        let label = RingLabel {
+            if_name: settings.if_name.clone(),
+            index: 0,
+            tag: settings.tag.clone(),
+        };
+        let fd = open_device().map_err(|e| label_error(&label, "open", e))?;
+        //closed by Drop if a later step fails
+        let mut device = Device {
+            fd,
+            label,
+            buf: Vec::new(),
+            totals: RingTotals::default(),
+            last_stats: (0, 0),
+        };
+
+        //the buffer size has to be set before the interface
+        let mut blen: c_uint = settings.buffer_size;
+        device.ioctl(
+            "BIOCSBLEN",
+            BIOCSBLEN,
+            &mut blen as *mut c_uint as *mut c_void,
+        )?;
+        let mut req = IfReq {
+            ifr_name: [0; 16],
+            data: [0; 16],
+        };
+        if settings.if_name.len() >= req.ifr_name.len() {
+            return Err(label_error(
+                &device.label,
+                "BIOCSETIF",
+                Error::new(ErrorKind::InvalidInput, "Interface name too long"),
+            ));
+        }
+        for (a, c) in req.ifr_name.iter_mut().zip(settings.if_name.bytes()) {
+            *a = c as c_char;
+        }
+        device.ioctl(
+            "BIOCSETIF",
+            BIOCSETIF,
+            &mut req as *mut IfReq as *mut c_void,
+        )?;
+        if settings.immediate {
+            let mut on: c_uint = 1;
+            device.ioctl(
+                "BIOCIMMEDIATE",
+                BIOCIMMEDIATE,
+                &mut on as *mut c_uint as *mut c_void,
+            )?;
+        }
+        if settings.promisc {
+            device.ioctl("BIOCPROMISC", BIOCPROMISC as c_ulong, std::ptr::null_mut())?;
+        }
+        //the kernel may have rounded the size, reads must use exactly its size
+        let mut actual: c_uint = 0;
+        device.ioctl(
+            "BIOCGBLEN",
+            BIOCGBLEN,
+            &mut actual as *mut c_uint as *mut c_void,
+        )?;
+        device.buf = vec![0u8; actual as usize];
+        trace_event!(Info, "bpf device {} up (fd {})", device.label, device.fd);
+        Ok(device)
+    }
+
+    ///Returns the label identifying this device
+    pub fn label(&self) -> &RingLabel {
+        &self.label
+    }
+
+    ///Returns running totals of what this device has delivered to the application
+    pub fn totals(&self) -> RingTotals {
+        self.totals
+    }
+
+    ///Returns the link type (DLT_*) of the interface
+    pub fn datalink(&self) -> io::Result<u32> {
+        let mut dlt: c_uint = 0;
+        self.ioctl("BIOCGDLT", BIOCGDLT, &mut dlt as *mut c_uint as *mut c_void)?;
+        Ok(dlt)
+    }
+
+    fn ioctl(&self, step: &str, request: c_ulong, arg: *mut c_void) -> io::Result<()> {
+        match unsafe { ioctl(self.fd, request, arg) } {
+            -1 => Err(label_error(&self.label, step, Error::last_os_error())),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Capture for Device {
+    fn next_block(&mut self, f: &mut dyn FnMut(&RawPacket<'_>)) -> io::Result<usize> {
+        let len = unsafe {
+            read(
+                self.fd,
+                self.buf.as_mut_ptr() as *mut c_void,
+                self.buf.len(),
+            )
+        };
+        if len < 0 {
+            return Err(label_error(&self.label, "read", Error::last_os_error()));
+        }
+        let data = &self.buf[..len as usize];
+        let mut offset = 0;
+        let mut count = 0;
+        let mut bytes = 0;
+        while offset + mem::size_of::<bpf_hdr>() <= data.len() {
+            let hdr: bpf_hdr =
+                unsafe { std::ptr::read_unaligned(data[offset..].as_ptr() as *const bpf_hdr) };
+            let start = offset + hdr.bh_hdrlen as usize;
+            let end = start + hdr.bh_caplen as usize;
+            if end > data.len() {
+                break;
+            }
+            let timestamp = UNIX_EPOCH
+                + Duration::new(
+                    hdr.bh_tstamp.tv_sec as u64,
+                    (hdr.bh_tstamp.tv_usec as u32).saturating_mul(1000),
+                );
+            f(&RawPacket::from_frame(
+                &data[start..end],
+                hdr.bh_datalen,
+                timestamp,
+            ));
+            count += 1;
+            bytes += u64::from(hdr.bh_caplen);
+            offset = word_align(end);
+        }
+        self.totals.blocks += 1;
+        self.totals.packets += count as u64;
+        self.totals.bytes += bytes;
+        Ok(count)
+    }
+
+    fn label(&self) -> &RingLabel {
+        Device::label(self)
+    }
+
+    fn totals(&self) -> RingTotals {
+        Device::totals(self)
+    }
+
+    ///BIOCGSTATS counts from when the device was opened, so this reports the difference from
+    ///the previous call
+    fn stats(&mut self) -> io::Result<CaptureStats> {
+        let mut stat = bpf_stat::default();
+        self.ioctl(
+            "BIOCGSTATS",
+            BIOCGSTATS,
+            &mut stat as *mut bpf_stat as *mut c_void,
+        )?;
+        let (recv, drop) = self.last_stats;
+        self.last_stats = (stat.bs_recv, stat.bs_drop);
+        Ok(CaptureStats {
+            packets: u64::from(stat.bs_recv.wrapping_sub(recv)),
+            drops: u64::from(stat.bs_drop.wrapping_sub(drop)),
+        })
+    }
+
+    fn attach_filter(&mut self, prog: &[SockFilter]) -> io::Result<()> {
+        let mut program = bpf_program {
+            bf_len: prog.len() as c_uint,
+            bf_insns: prog.as_ptr(),
+        };
+        self.ioctl(
+            "BIOCSETF",
+            BIOCSETF,
+            &mut program as *mut bpf_program as *mut c_void,
+        )
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        unsafe {
+            close(self.fd);
+        }
+    }
+}
+
+#[inline]
+fn word_align(x: usize) -> usize {
+    (x + (BPF_ALIGNMENT - 1)) & !(BPF_ALIGNMENT - 1)
+}
+
+fn open_device() -> io::Result<c_int> {
+    for i in 0..MAX_DEVICES {
+        let path = CString::new(format!("/dev/bpf{}", i))?;
+        let fd = unsafe { open(path.as_ptr(), O_RDWR) };
+        if fd >= 0 {
+            return Ok(fd);
+        }
+        let e = Error::last_os_error();
+        if e.raw_os_error() != Some(EBUSY) {
+            return Err(e);
+        }
+    }
+    Err(Error::new(
+        ErrorKind::NotFound,
+        "every /dev/bpf device is busy",
+    ))
+}
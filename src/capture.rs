@@ -0,0 +1,210 @@
+//!Common surface over the receive and transmit backends, so a processing loop can be written once
+//!and pointed at an AF_PACKET ring, an AF_XDP socket, libpcap, or a test double that hands out
+//!`RawPacket`s. Single packet reads stay on the backends themselves, as ring packets borrow the
+//!block they are in
+
+use std::io;
+
+#[cfg(target_os = "linux")]
+use filter;
+use filter::SockFilter;
+use rx::{RawPacket, RingLabel, RingTotals};
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+use xdp;
+#[cfg(target_os = "linux")]
+use {rx, rx_v2, tx::Player};
+
+///Packets seen and dropped by a capture since the previous call to `Capture::stats`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CaptureStats {
+    pub packets: u64,
+    pub drops: u64,
+}
+
+///A source of packets that is consumed a block at a time, or a packet at a time where the
+///backend allows it
+pub trait Capture {
+    ///Waits for the next block of packets, passes each of them to `f`, and then releases the
+    ///block back to the backend. Returns the number of packets in the block, and fails with the
+    ///socket's error, e.g. when the interface goes down. Backends without blocks hand out a
+    ///batch of packets instead
+    fn next_block(&mut self, f: &mut dyn FnMut(&RawPacket<'_>)) -> io::Result<usize>;
+
+    ///Waits for the next packet and passes it to `f`, returning the number of packets passed.
+    ///A ring packet borrows its block, which is only released once all of its packets are done
+    ///with, so by default this drains the next block. Backends that read one packet at a time,
+    ///such as files, pass just the one
+    fn next_packet(&mut self, f: &mut dyn FnMut(&RawPacket<'_>)) -> io::Result<usize> {
+        self.next_block(f)
+    }
+
+    ///Label identifying this capture in statistics and errors
+    fn label(&self) -> &RingLabel;
+
+    ///Running totals of what this capture has delivered
+    fn totals(&self) -> RingTotals;
+
+    ///Reads the backend's counters, resetting them
+    fn stats(&mut self) -> io::Result<CaptureStats>;
+
+    ///Attaches a classic BPF program so unwanted packets are dropped before they are delivered
+    fn attach_filter(&mut self, prog: &[SockFilter]) -> io::Result<()>;
+}
+
+///A sink that transmits whole link-layer frames
+pub trait Inject {
+    fn send_frame(&mut self, frame: &[u8]) -> io::Result<()>;
+}
+
+//a ring block, handed out whole and given back once all of its packets are read
+#[cfg(target_os = "linux")]
+trait RingBlock {
+    fn packets(&self) -> Vec<RawPacket<'_>>;
+    fn release(&mut self);
+}
+
+#[cfg(target_os = "linux")]
+impl RingBlock for rx::Block<'_> {
+    fn packets(&self) -> Vec<RawPacket<'_>> {
+        self.get_raw_packets()
+    }
+
+    fn release(&mut self) {
+        self.mark_as_consumed()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl RingBlock for rx_v2::Block<'_> {
+    fn packets(&self) -> Vec<RawPacket<'_>> {
+        self.get_raw_packets()
+    }
+
+    fn release(&mut self) {
+        self.mark_as_consumed()
+    }
+}
+
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+impl RingBlock for xdp::Block<'_> {
+    fn packets(&self) -> Vec<RawPacket<'_>> {
+        self.get_raw_packets()
+    }
+
+    fn release(&mut self) {
+        self.mark_as_consumed()
+    }
+}
+
+//passes each packet of `block` to `f` and releases it, returning how many there were
+#[cfg(target_os = "linux")]
+fn deliver<B: RingBlock>(mut block: B, f: &mut dyn FnMut(&RawPacket<'_>)) -> usize {
+    let packets = block.packets();
+    for packet in &packets {
+        f(packet);
+    }
+    let count = packets.len();
+    drop(packets);
+    block.release();
+    count
+}
+
+#[cfg(target_os = "linux")]
+impl Capture for rx::Ring {
+    fn next_block(&mut self, f: &mut dyn FnMut(&RawPacket<'_>)) -> io::Result<usize> {
+        match self.try_get_block(None)? {
+            Some(block) => Ok(deliver(block, f)),
+            None => Ok(0),
+        }
+    }
+
+    fn label(&self) -> &RingLabel {
+        rx::Ring::label(self)
+    }
+
+    fn totals(&self) -> RingTotals {
+        rx::Ring::totals(self)
+    }
+
+    fn stats(&mut self) -> io::Result<CaptureStats> {
+        let stats = self.get_rx_statistics()?.stats;
+        Ok(CaptureStats {
+            packets: u64::from(stats.tp_packets),
+            drops: u64::from(stats.tp_drops),
+        })
+    }
+
+    fn attach_filter(&mut self, prog: &[SockFilter]) -> io::Result<()> {
+        filter::attach_filter(self.socket.fd, prog)
+            .map_err(|e| rx::label_error(rx::Ring::label(self), "SO_ATTACH_FILTER", e))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Capture for rx_v2::Ring {
+    fn next_block(&mut self, f: &mut dyn FnMut(&RawPacket<'_>)) -> io::Result<usize> {
+        match self.try_get_block(None)? {
+            Some(block) => Ok(deliver(block, f)),
+            None => Ok(0),
+        }
+    }
+
+    fn label(&self) -> &RingLabel {
+        rx_v2::Ring::label(self)
+    }
+
+    fn totals(&self) -> RingTotals {
+        rx_v2::Ring::totals(self)
+    }
+
+    fn stats(&mut self) -> io::Result<CaptureStats> {
+        let stats = self.get_rx_statistics()?.stats;
+        Ok(CaptureStats {
+            packets: u64::from(stats.tp_packets),
+            drops: u64::from(stats.tp_drops),
+        })
+    }
+
+    fn attach_filter(&mut self, prog: &[SockFilter]) -> io::Result<()> {
+        filter::attach_filter(self.socket.fd, prog)
+            .map_err(|e| rx::label_error(rx_v2::Ring::label(self), "SO_ATTACH_FILTER", e))
+    }
+}
+
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+impl Capture for xdp::Ring {
+    fn next_block(&mut self, f: &mut dyn FnMut(&RawPacket<'_>)) -> io::Result<usize> {
+        Ok(deliver(self.get_block(), f))
+    }
+
+    fn label(&self) -> &RingLabel {
+        xdp::Ring::label(self)
+    }
+
+    fn totals(&self) -> RingTotals {
+        xdp::Ring::totals(self)
+    }
+
+    fn stats(&mut self) -> io::Result<CaptureStats> {
+        self.stats_since_last()
+    }
+
+    ///Filtering an XSK is the job of the XDP program that redirects to it
+    fn attach_filter(&mut self, _prog: &[SockFilter]) -> io::Result<()> {
+        Err(rx::label_error(
+            xdp::Ring::label(self),
+            "SO_ATTACH_FILTER",
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "AF_XDP sockets are filtered by their XDP program",
+            ),
+        ))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Inject for Player {
+    fn send_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        Player::send_frame(self, frame)
+    }
+}
@@ -0,0 +1,341 @@
+//!Streaming compression for capture output, so recording full packets on a busy link doesn't fill
+//!the disk as fast. `GzipWriter` (feature `gzip`, links zlib) writes .gz files any tool reads,
+//!`ZstdWriter` (feature `zstd`, links libzstd) compresses better for the same CPU. Both wrap any
+//!`Write` and go between a writer and its file, e.g. `PcapWriter::new(GzipWriter::new(file, 6)?,
+//!link_type)`. The stream is ended when the writer is dropped, call `finish` to see errors.
+
+#[cfg(feature = "zstd")]
+use std::ffi::CStr;
+use std::io::{self, Error, ErrorKind, Write};
+#[cfg(feature = "gzip")]
+use std::mem;
+#[cfg(feature = "gzip")]
+use std::os::raw::{c_char, c_int, c_uint, c_ulong, c_void};
+#[cfg(all(feature = "zstd", not(feature = "gzip")))]
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+
+const OUT_BUF_LEN: usize = 64 * 1024;
+
+#[cfg(feature = "gzip")]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct z_stream {
+    next_in: *const u8,
+    avail_in: c_uint,
+    total_in: c_ulong,
+    next_out: *mut u8,
+    avail_out: c_uint,
+    total_out: c_ulong,
+    msg: *const c_char,
+    state: *mut c_void,
+    zalloc: *const c_void,
+    zfree: *const c_void,
+    opaque: *mut c_void,
+    data_type: c_int,
+    adler: c_ulong,
+    reserved: c_ulong,
+}
+
+#[cfg(feature = "gzip")]
+const Z_OK: c_int = 0;
+#[cfg(feature = "gzip")]
+const Z_STREAM_END: c_int = 1;
+#[cfg(feature = "gzip")]
+const Z_BUF_ERROR: c_int = -5;
+#[cfg(feature = "gzip")]
+const Z_NO_FLUSH: c_int = 0;
+#[cfg(feature = "gzip")]
+const Z_SYNC_FLUSH: c_int = 2;
+#[cfg(feature = "gzip")]
+const Z_FINISH: c_int = 4;
+#[cfg(feature = "gzip")]
+const Z_DEFLATED: c_int = 8;
+//15 bits of window, plus 16 for a gzip header and trailer rather than a zlib one
+#[cfg(feature = "gzip")]
+const GZIP_WINDOW_BITS: c_int = 15 + 16;
+#[cfg(feature = "gzip")]
+const DEFAULT_MEM_LEVEL: c_int = 8;
+#[cfg(feature = "gzip")]
+const Z_DEFAULT_STRATEGY: c_int = 0;
+
+#[cfg(feature = "gzip")]
+#[link(name = "z")]
+extern "C" {
+    fn zlibVersion() -> *const c_char;
+    fn deflateInit2_(
+        strm: *mut z_stream,
+        level: c_int,
+        method: c_int,
+        window_bits: c_int,
+        mem_level: c_int,
+        strategy: c_int,
+        version: *const c_char,
+        stream_size: c_int,
+    ) -> c_int;
+    fn deflate(strm: *mut z_stream, flush: c_int) -> c_int;
+    fn deflateEnd(strm: *mut z_stream) -> c_int;
+}
+
+///Gzip compresses everything written to it into `out`
+#[cfg(feature = "gzip")]
+pub struct GzipWriter<W: Write> {
+    //boxed as zlib keeps a pointer back to the stream in its state
+    strm: Box<z_stream>,
+    out: Option<W>,
+    buf: Vec<u8>,
+}
+
+#[cfg(feature = "gzip")]
+impl<W: Write> GzipWriter<W> {
+    ///Starts a gzip stream at `level`, from 1 (fastest) to 9 (smallest). 0 stores the data
+    ///uncompressed
+    pub fn new(out: W, level: u32) -> io::Result<GzipWriter<W>> {
+        let mut strm: Box<z_stream> = Box::new(unsafe { mem::zeroed() });
+        let ret = unsafe {
+            deflateInit2_(
+                &mut *strm,
+                level.min(9) as c_int,
+                Z_DEFLATED,
+                GZIP_WINDOW_BITS,
+                DEFAULT_MEM_LEVEL,
+                Z_DEFAULT_STRATEGY,
+                zlibVersion(),
+                mem::size_of::<z_stream>() as c_int,
+            )
+        };
+        if ret != Z_OK {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("deflateInit2 failed: {}", ret),
+            ));
+        }
+        Ok(GzipWriter {
+            strm,
+            out: Some(out),
+            buf: vec![0; OUT_BUF_LEN],
+        })
+    }
+
+    //feeds `input` to deflate with `flush`, writing out whatever it produces. Returns once the
+    //input is used up and, when flushing, deflate has nothing more to give
+    fn deflate(&mut self, input: &[u8], flush: c_int) -> io::Result<()> {
+        self.strm.next_in = input.as_ptr();
+        self.strm.avail_in = input.len() as c_uint;
+        loop {
+            self.strm.next_out = self.buf.as_mut_ptr();
+            self.strm.avail_out = self.buf.len() as c_uint;
+            let ret = unsafe { deflate(&mut *self.strm, flush) };
+            if ret != Z_OK && ret != Z_STREAM_END && ret != Z_BUF_ERROR {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("deflate failed: {}", ret),
+                ));
+            }
+            let produced = self.buf.len() - self.strm.avail_out as usize;
+            if let Some(ref mut out) = self.out {
+                out.write_all(&self.buf[..produced])?;
+            }
+            let drained = self.strm.avail_out != 0;
+            //finishing only ends with Z_STREAM_END, however much output space was left
+            if ret == Z_STREAM_END || (flush != Z_FINISH && drained && self.strm.avail_in == 0) {
+                return Ok(());
+            }
+        }
+    }
+
+    ///Ends the gzip stream and returns the output
+    pub fn finish(mut self) -> io::Result<W> {
+        self.deflate(&[], Z_FINISH)?;
+        let mut out = self.out.take().unwrap();
+        out.flush()?;
+        Ok(out)
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.deflate(buf, Z_NO_FLUSH)?;
+        Ok(buf.len())
+    }
+
+    ///Writes out everything compressed so far, so the file can be read up to here
+    fn flush(&mut self) -> io::Result<()> {
+        self.deflate(&[], Z_SYNC_FLUSH)?;
+        match self.out {
+            Some(ref mut out) => out.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl<W: Write> Drop for GzipWriter<W> {
+    fn drop(&mut self) {
+        if self.out.is_some() {
+            let _ = self.deflate(&[], Z_FINISH);
+            if let Some(ref mut out) = self.out {
+                let _ = out.flush();
+            }
+        }
+        unsafe { deflateEnd(&mut *self.strm) };
+    }
+}
+
+#[cfg(feature = "gzip")]
+unsafe impl<W: Write + Send> Send for GzipWriter<W> {}
+
+#[cfg(feature = "zstd")]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct ZSTD_inBuffer {
+    src: *const c_void,
+    size: usize,
+    pos: usize,
+}
+
+#[cfg(feature = "zstd")]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct ZSTD_outBuffer {
+    dst: *mut c_void,
+    size: usize,
+    pos: usize,
+}
+
+#[cfg(feature = "zstd")]
+#[allow(non_camel_case_types)]
+enum ZSTD_CStream {}
+
+#[cfg(feature = "zstd")]
+#[link(name = "zstd")]
+extern "C" {
+    fn ZSTD_createCStream() -> *mut ZSTD_CStream;
+    fn ZSTD_freeCStream(zcs: *mut ZSTD_CStream) -> usize;
+    fn ZSTD_initCStream(zcs: *mut ZSTD_CStream, level: c_int) -> usize;
+    fn ZSTD_compressStream(
+        zcs: *mut ZSTD_CStream,
+        output: *mut ZSTD_outBuffer,
+        input: *mut ZSTD_inBuffer,
+    ) -> usize;
+    fn ZSTD_flushStream(zcs: *mut ZSTD_CStream, output: *mut ZSTD_outBuffer) -> usize;
+    fn ZSTD_endStream(zcs: *mut ZSTD_CStream, output: *mut ZSTD_outBuffer) -> usize;
+    fn ZSTD_isError(code: usize) -> c_uint;
+    fn ZSTD_getErrorName(code: usize) -> *const c_char;
+}
+
+///Zstandard compresses everything written to it into `out`
+#[cfg(feature = "zstd")]
+pub struct ZstdWriter<W: Write> {
+    zcs: *mut ZSTD_CStream,
+    out: Option<W>,
+    buf: Vec<u8>,
+}
+
+#[cfg(feature = "zstd")]
+impl<W: Write> ZstdWriter<W> {
+    ///Starts a zstd frame at `level`, from 1 (fastest) to 19, or up to 22 with much more memory.
+    ///3 is zstd's default
+    pub fn new(out: W, level: i32) -> io::Result<ZstdWriter<W>> {
+        let zcs = unsafe { ZSTD_createCStream() };
+        if zcs.is_null() {
+            return Err(Error::new(ErrorKind::Other, "ZSTD_createCStream failed"));
+        }
+        let writer = ZstdWriter {
+            zcs,
+            out: Some(out),
+            buf: vec![0; OUT_BUF_LEN],
+        };
+        zstd_check(unsafe { ZSTD_initCStream(zcs, level) })?;
+        Ok(writer)
+    }
+
+    //runs `step` until it reports nothing left to write, writing out what it produces
+    fn drain<F>(&mut self, mut step: F) -> io::Result<()>
+    where
+        F: FnMut(*mut ZSTD_CStream, &mut ZSTD_outBuffer) -> usize,
+    {
+        loop {
+            let mut output = ZSTD_outBuffer {
+                dst: self.buf.as_mut_ptr() as *mut c_void,
+                size: self.buf.len(),
+                pos: 0,
+            };
+            let remaining = zstd_check(step(self.zcs, &mut output))?;
+            if let Some(ref mut out) = self.out {
+                out.write_all(&self.buf[..output.pos])?;
+            }
+            if remaining == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    ///Ends the zstd frame and returns the output
+    pub fn finish(mut self) -> io::Result<W> {
+        self.drain(|zcs, output| unsafe { ZSTD_endStream(zcs, output) })?;
+        let mut out = self.out.take().unwrap();
+        out.flush()?;
+        Ok(out)
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<W: Write> Write for ZstdWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut input = ZSTD_inBuffer {
+            src: buf.as_ptr() as *const c_void,
+            size: buf.len(),
+            pos: 0,
+        };
+        while input.pos < input.size {
+            let mut output = ZSTD_outBuffer {
+                dst: self.buf.as_mut_ptr() as *mut c_void,
+                size: self.buf.len(),
+                pos: 0,
+            };
+            zstd_check(unsafe { ZSTD_compressStream(self.zcs, &mut output, &mut input) })?;
+            if let Some(ref mut out) = self.out {
+                out.write_all(&self.buf[..output.pos])?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    ///Writes out everything compressed so far, so the file can be read up to here
+    fn flush(&mut self) -> io::Result<()> {
+        self.drain(|zcs, output| unsafe { ZSTD_flushStream(zcs, output) })?;
+        match self.out {
+            Some(ref mut out) => out.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<W: Write> Drop for ZstdWriter<W> {
+    fn drop(&mut self) {
+        if self.out.is_some() {
+            let _ = self.drain(|zcs, output| unsafe { ZSTD_endStream(zcs, output) });
+            if let Some(ref mut out) = self.out {
+                let _ = out.flush();
+            }
+        }
+        unsafe { ZSTD_freeCStream(self.zcs) };
+    }
+}
+
+#[cfg(feature = "zstd")]
+unsafe impl<W: Write + Send> Send for ZstdWriter<W> {}
+
+#[cfg(feature = "zstd")]
+fn zstd_check(code: usize) -> io::Result<usize> {
+    if unsafe { ZSTD_isError(code) } == 0 {
+        return Ok(code);
+    }
+    let name = unsafe { CStr::from_ptr(ZSTD_getErrorName(code)) };
+    Err(Error::new(
+        ErrorKind::Other,
+        format!("zstd: {}", name.to_string_lossy()),
+    ))
+}
@@ -0,0 +1,223 @@
+//!Runs the ring threads of a `RingGroup` and hands copies of their packets to application workers
+//!over bounded channels, so slow processing builds up in the channels, where it can be seen and
+//!shed, rather than in the rings, where the kernel drops whole blocks. The channels can also be
+//!the application's own, async ones included, through `sink::PacketSink`.
+
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+
+use group::RingGroup;
+use rx::{OwnedPacket, RawPacket, RingLabel, RingSettings};
+use sink::{Offer, PacketSink};
+use spill::{self, SpillConfig, SpillReceiver, SpillSender};
+
+///What a ring thread does when a worker's channel is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    ///Wait for the worker, letting the ring fill up behind it
+    Block,
+    ///Drop the packet and count it in `Dispatcher::dropped`
+    Drop,
+}
+
+///Which worker each packet goes to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Routing {
+    ///Ring `i` sends to worker `i` modulo the number of workers
+    ByRing,
+    ///By the kernel's flow hash, which needs RXHASH in `tp_feature_req_word`. Falls back to the
+    ///symmetric hash for packets without one. The two directions of a flow may differ
+    RxHash,
+    ///By `RawPacket::symmetric_hash`, so both directions of a flow reach the same worker.
+    ///Packets that aren't TCP or UDP over IP go by ring
+    SymmetricHash,
+}
+
+///How packets are handed to workers
+#[derive(Clone, Debug)]
+pub struct DispatchConfig {
+    ///Number of worker channels
+    pub workers: usize,
+    ///Packets each channel holds before `overflow` applies
+    pub capacity: usize,
+    pub overflow: Overflow,
+    pub routing: Routing,
+}
+
+impl Default for DispatchConfig {
+    fn default() -> DispatchConfig {
+        DispatchConfig {
+            workers: 1,
+            capacity: 4096,
+            overflow: Overflow::Block,
+            routing: Routing::ByRing,
+        }
+    }
+}
+
+///Packets sinks turned away, by why
+#[derive(Debug, Default)]
+pub struct SinkDrops {
+    full: AtomicU64,
+    closed: AtomicU64,
+}
+
+impl SinkDrops {
+    ///Returns the number of packets dropped because a sink's channel was full
+    pub fn full(&self) -> u64 {
+        self.full.load(Ordering::Relaxed)
+    }
+
+    ///Returns the number of packets dropped because nothing was receiving from a sink
+    pub fn closed(&self) -> u64 {
+        self.closed.load(Ordering::Relaxed)
+    }
+}
+
+///A running `RingGroup` feeding worker channels, routing packets to workers as `routing` says.
+///With hash routing a flow stays with one worker however fanout spreads it over the rings, so
+///stateful workers need no sharding of their own
+pub struct Dispatcher {
+    group: RingGroup,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Dispatcher {
+    ///Spawns `num_threads` rings with `settings` as `RingGroup::spawn` does and returns the
+    ///receiving end of each worker's channel. Dropping a receiver makes its rings discard packets
+    pub fn spawn(
+        settings: RingSettings,
+        num_threads: usize,
+        config: DispatchConfig,
+    ) -> io::Result<(Dispatcher, Vec<Receiver<OwnedPacket>>)> {
+        if config.workers == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a dispatcher needs at least one worker",
+            ));
+        }
+        let (senders, receivers): (Vec<SyncSender<OwnedPacket>>, Vec<_>) = (0..config.workers)
+            .map(|_| sync_channel(config.capacity))
+            .unzip();
+        let dropped = Arc::new(AtomicU64::new(0));
+        let counter = dropped.clone();
+        let overflow = config.overflow;
+        let routing = config.routing;
+        let group = RingGroup::spawn(settings, num_threads, move |label, packet| {
+            let sender = &senders[route(routing, label, packet) % senders.len()];
+            let sent = match overflow {
+                Overflow::Block => sender.send(packet.to_owned()).is_ok(),
+                Overflow::Drop => match sender.try_send(packet.to_owned()) {
+                    Ok(()) => true,
+                    Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+                },
+            };
+            if !sent {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        })?;
+        Ok((Dispatcher { group, dropped }, receivers))
+    }
+
+    ///Like `spawn`, but a full channel spills to a file in `spill.dir` rather than applying
+    ///`config.overflow` straight away. `config.capacity` packets are held in memory and up to
+    ///`spill.max_bytes` on disk for each worker, and `config.overflow` applies once that is full
+    pub fn spawn_spilling(
+        settings: RingSettings,
+        num_threads: usize,
+        config: DispatchConfig,
+        spill: SpillConfig,
+    ) -> io::Result<(Dispatcher, Vec<SpillReceiver>)> {
+        if config.workers == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a dispatcher needs at least one worker",
+            ));
+        }
+        let spill = SpillConfig {
+            capacity: config.capacity,
+            ..spill
+        };
+        let (senders, receivers): (Vec<SpillSender>, Vec<_>) = (0..config.workers)
+            .map(|_| spill::channel(&spill))
+            .collect::<io::Result<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+        let dropped = Arc::new(AtomicU64::new(0));
+        let counter = dropped.clone();
+        let overflow = config.overflow;
+        let routing = config.routing;
+        let group = RingGroup::spawn(settings, num_threads, move |label, packet| {
+            let sender = &senders[route(routing, label, packet) % senders.len()];
+            let sent = match overflow {
+                Overflow::Block => sender.send(packet.to_owned()),
+                Overflow::Drop => sender.try_send(packet.to_owned()),
+            };
+            if !sent {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        })?;
+        Ok((Dispatcher { group, dropped }, receivers))
+    }
+
+    ///Like `spawn`, but hands packets to `sinks`, e.g. wrapped tokio channels, routed over them
+    ///as `routing` says. How much each holds is up to the sink, packets it turns away are
+    ///counted in `dropped` and, by why, in `SinkDrops`
+    pub fn spawn_sinks<S: PacketSink>(
+        settings: RingSettings,
+        num_threads: usize,
+        routing: Routing,
+        sinks: Vec<S>,
+    ) -> io::Result<(Dispatcher, Arc<SinkDrops>)> {
+        if sinks.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a dispatcher needs at least one sink",
+            ));
+        }
+        let dropped = Arc::new(AtomicU64::new(0));
+        let drops = Arc::new(SinkDrops::default());
+        let (counter, by_reason) = (dropped.clone(), drops.clone());
+        let group = RingGroup::spawn(settings, num_threads, move |label, packet| {
+            let sink = &sinks[route(routing, label, packet) % sinks.len()];
+            let reason = match sink.offer(packet.to_owned()) {
+                Offer::Sent => return,
+                Offer::Full => &by_reason.full,
+                Offer::Closed => &by_reason.closed,
+            };
+            reason.fetch_add(1, Ordering::Relaxed);
+            counter.fetch_add(1, Ordering::Relaxed);
+        })?;
+        Ok((Dispatcher { group, dropped }, drops))
+    }
+
+    ///Returns the number of packets dropped because a channel was full or its receiver was gone
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    ///Returns the group running the rings
+    pub fn group(&self) -> &RingGroup {
+        &self.group
+    }
+
+    ///Stops the ring threads and waits for them. With `Overflow::Block` a ring thread waits on a
+    ///full channel until its worker catches up, so the workers must keep receiving, or drop their
+    ///receivers, until this returns
+    pub fn join(self) -> thread::Result<()> {
+        self.group.join()
+    }
+}
+
+//the number of the worker `packet` goes to, before taking it modulo the number of workers
+fn route(routing: Routing, label: &RingLabel, packet: &RawPacket<'_>) -> usize {
+    let hash = match routing {
+        Routing::ByRing => None,
+        Routing::RxHash => packet.rxhash().or_else(|| packet.symmetric_hash()),
+        Routing::SymmetricHash => packet.symmetric_hash(),
+    };
+    hash.map_or(label.index as usize, |h| h as usize)
+}
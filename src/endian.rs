@@ -0,0 +1,11 @@
+//!tpacket headers are written by the kernel in native byte order, so they are parsed with these
+//!rather than nom's little-endian parsers
+
+#[cfg(target_endian = "big")]
+pub(crate) use nom::number::complete::{
+    be_i32 as ne_i32, be_u16 as ne_u16, be_u32 as ne_u32, be_u64 as ne_u64,
+};
+#[cfg(target_endian = "little")]
+pub(crate) use nom::number::complete::{
+    le_i32 as ne_i32, le_u16 as ne_u16, le_u32 as ne_u32, le_u64 as ne_u64,
+};
@@ -0,0 +1,448 @@
+//!Errors that say which step of setting up or running a ring failed and with what parameters.
+//!Ring constructors return `RingError`; everything else still returns `io::Error`, but errors
+//!labelled with a ring and step carry a `RingError` inside, which `RingError::from` recovers.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use libc::EINVAL;
+use libc::{c_int, EACCES, ENETDOWN, ENODEV, ENXIO, EPERM};
+
+use netns::NetNs;
+use rx::{BlockAnomaly, Membership, RingLabel};
+use tpacket3::TimestampSource;
+
+///A step on a ring's socket, with the parameters it was given
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operation {
+    ///socket(2), with SOCK_RAW or SOCK_DGRAM
+    Socket {
+        kind: c_int,
+    },
+    ///setns(2) into the namespace the socket is opened in
+    Netns(NetNs),
+    ///Waiting for the interface to appear and come up
+    WaitForInterface {
+        timeout: Duration,
+    },
+    Promisc,
+    Offloads,
+    ///Hardware timestamping and PACKET_TIMESTAMP, for a timestamp source other than software
+    Timestamping(TimestampSource),
+    ///PACKET_VERSION, with TPACKET_V2 or TPACKET_V3
+    Version(c_int),
+    ///PACKET_RX_RING
+    RxRing {
+        block_size: u32,
+        block_nr: u32,
+        frame_size: u32,
+        frame_nr: u32,
+    },
+    ///mmap(2) of the whole ring
+    Mmap {
+        len: usize,
+    },
+    ///bind(2) to the interface, with the ethertype in host order
+    Bind {
+        protocol: u16,
+    },
+    IgnoreOutgoing,
+    AddMembership(Membership),
+    ///PACKET_FANOUT, with the group id in the low 16 bits and the method and flags above
+    Fanout {
+        word: u32,
+    },
+    Statistics,
+    ///Waiting for or reading packets
+    Receive,
+    ///Any step without parameters of its own, by name
+    Other(String),
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Socket { kind } => write!(f, "socket (type {})", kind),
+            Operation::WaitForInterface { timeout } => {
+                write!(f, "waiting for the interface ({:?})", timeout)
+            }
+            Operation::Netns(ns) => write!(f, "setns ({})", ns.path().display()),
+            Operation::Promisc => write!(f, "promisc"),
+            Operation::Offloads => write!(f, "offloads"),
+            Operation::Timestamping(source) => write!(f, "timestamping ({:?})", source),
+            Operation::Version(v) => write!(f, "PACKET_VERSION (TPACKET_V{})", v + 1),
+            Operation::RxRing {
+                block_size,
+                block_nr,
+                frame_size,
+                frame_nr,
+            } => write!(
+                f,
+                "PACKET_RX_RING ({} blocks of {} bytes, {} frames of {} bytes)",
+                block_nr, block_size, frame_nr, frame_size
+            ),
+            Operation::Mmap { len } => write!(f, "mmap ({} bytes)", len),
+            Operation::Bind { protocol } => write!(f, "bind (protocol {:#06x})", protocol),
+            Operation::IgnoreOutgoing => write!(f, "PACKET_IGNORE_OUTGOING"),
+            Operation::AddMembership(m) => write!(f, "PACKET_ADD_MEMBERSHIP ({:?})", m),
+            Operation::Fanout { word } => write!(f, "PACKET_FANOUT ({:#x})", word),
+            Operation::Statistics => write!(f, "PACKET_STATISTICS"),
+            Operation::Receive => write!(f, "receive"),
+            Operation::Other(name) => f.write_str(name),
+        }
+    }
+}
+
+///A constraint the kernel puts on ring geometry or fanout that the settings break
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    NoBlocks,
+    ///tp_block_size must be a multiple of the page size
+    BlockSizeUnaligned {
+        block_size: u32,
+        page_size: u32,
+    },
+    ///tp_frame_size must be a multiple of TPACKET_ALIGNMENT, 16
+    FrameSizeUnaligned {
+        frame_size: u32,
+    },
+    ///A frame must hold at least the tpacket header and sockaddr_ll
+    FrameTooSmall {
+        frame_size: u32,
+        min: u32,
+    },
+    ///A block must hold its header, tp_sizeof_priv and at least one smallest frame
+    BlockTooSmall {
+        block_size: u32,
+        min: u32,
+    },
+    ///tp_frame_nr must be the frames that fit in a block times tp_block_nr
+    FrameCountMismatch {
+        frame_nr: u32,
+        expected: u32,
+    },
+    ///The ring has more frames than fit in 32 bits
+    RingTooLarge {
+        block_nr: u32,
+        frames_per_block: u32,
+    },
+    UnknownFanoutMode(c_int),
+    ///Flags other than ROLLOVER, UNIQUEID, IGNORE_OUTGOING and DEFRAG
+    UnknownFanoutFlags(c_int),
+    ///PACKET_FANOUT_FLAG_UNIQUEID has the kernel pick the group id, so it can't join a given one
+    UniqueIdWithGroup {
+        group: u16,
+    },
+    ///The kernel accepts the mode in a new group, so the group already exists with another mode
+    ///or flags
+    FanoutModeMismatch {
+        mode: &'static str,
+        group: u16,
+    },
+    ///This kernel doesn't support the mode or flags
+    FanoutUnsupported {
+        mode: &'static str,
+        flags: c_int,
+    },
+    ///`wait_for_interface` can't be combined with `netns`, as it watches the caller's namespace
+    WaitInNetns,
+    ///A setting that only TPACKET_V3 rings honour was given to a TPACKET_V2 one
+    NotOnTpacketV2(&'static str),
+}
+
+impl ConfigError {
+    ///Returns `Unsupported` for what the kernel lacks, `InvalidInput` for the rest
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            ConfigError::FanoutUnsupported { .. } => io::ErrorKind::Unsupported,
+            _ => io::ErrorKind::InvalidInput,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::NoBlocks => write!(f, "tp_block_nr is 0"),
+            ConfigError::BlockSizeUnaligned {
+                block_size,
+                page_size,
+            } => write!(
+                f,
+                "tp_block_size {} is not a multiple of the page size {}",
+                block_size, page_size
+            ),
+            ConfigError::FrameSizeUnaligned { frame_size } => {
+                write!(f, "tp_frame_size {} is not a multiple of 16", frame_size)
+            }
+            ConfigError::FrameTooSmall { frame_size, min } => write!(
+                f,
+                "tp_frame_size {} is smaller than the {} byte frame header",
+                frame_size, min
+            ),
+            ConfigError::BlockTooSmall { block_size, min } => write!(
+                f,
+                "tp_block_size {} is smaller than the {} bytes of block header, private area and \
+                 one frame",
+                block_size, min
+            ),
+            ConfigError::FrameCountMismatch { frame_nr, expected } => write!(
+                f,
+                "tp_frame_nr is {}, but the blocks hold {} frames",
+                frame_nr, expected
+            ),
+            ConfigError::RingTooLarge {
+                block_nr,
+                frames_per_block,
+            } => write!(
+                f,
+                "{} blocks of {} frames is more frames than the kernel can count",
+                block_nr, frames_per_block
+            ),
+            ConfigError::UnknownFanoutMode(mode) => write!(f, "unknown fanout mode {}", mode),
+            ConfigError::UnknownFanoutFlags(flags) => {
+                write!(f, "unknown fanout flags {:#x}", flags)
+            }
+            ConfigError::UniqueIdWithGroup { group } => write!(
+                f,
+                "PACKET_FANOUT_FLAG_UNIQUEID can't be used with fanout group {}",
+                group
+            ),
+            ConfigError::FanoutModeMismatch { mode, group } => write!(
+                f,
+                "fanout group {} already exists with a different mode or flags than {}",
+                group, mode
+            ),
+            ConfigError::FanoutUnsupported { mode, flags } => write!(
+                f,
+                "{} with flags {:#x} is not supported by this kernel",
+                mode, flags
+            ),
+            ConfigError::WaitInNetns => write!(
+                f,
+                "wait_for_interface only sees the caller's network namespace, not netns"
+            ),
+            ConfigError::NotOnTpacketV2(setting) => {
+                write!(f, "{} is only supported on TPACKET_V3 rings", setting)
+            }
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
+
+#[derive(Debug)]
+pub enum RingError {
+    ///`op` failed on the ring labelled `label`
+    Op {
+        label: RingLabel,
+        op: Operation,
+        source: io::Error,
+    },
+    ///Opening the socket was refused because the process lacks CAP_NET_RAW, which every
+    ///AF_PACKET socket needs
+    MissingCapNetRaw {
+        label: RingLabel,
+        op: Operation,
+        source: io::Error,
+    },
+    ///The kernel rejected `op` with EINVAL, or it wasn't attempted, and `reason` is the constraint
+    ///it broke
+    Invalid {
+        label: RingLabel,
+        op: Operation,
+        reason: ConfigError,
+        source: io::Error,
+    },
+    ///Block `seq` was malformed and handed back to the kernel, under `ParsePolicy::Error`
+    MalformedBlock {
+        label: RingLabel,
+        seq: u64,
+        anomaly: BlockAnomaly,
+        ///`InvalidData` describing the anomaly
+        source: io::Error,
+    },
+    ///An error not tied to a step
+    Io(io::Error),
+}
+
+impl RingError {
+    ///Builds the error for `op` failing, as `MissingCapNetRaw` if it was socket(2) failing with
+    ///EPERM or EACCES
+    pub fn new(label: &RingLabel, op: Operation, source: io::Error) -> RingError {
+        let denied = matches!(source.raw_os_error(), Some(EPERM) | Some(EACCES));
+        if denied && matches!(op, Operation::Socket { .. }) {
+            return RingError::MissingCapNetRaw {
+                label: label.clone(),
+                op,
+                source,
+            };
+        }
+        RingError::Op {
+            label: label.clone(),
+            op,
+            source,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn malformed(label: &RingLabel, seq: u64, anomaly: BlockAnomaly) -> RingError {
+        RingError::MalformedBlock {
+            label: label.clone(),
+            seq,
+            anomaly,
+            source: io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", anomaly)),
+        }
+    }
+
+    //a constraint broken before anything reached the kernel
+    #[cfg(target_os = "linux")]
+    pub(crate) fn invalid(label: &RingLabel, op: Operation, reason: ConfigError) -> RingError {
+        RingError::Invalid {
+            label: label.clone(),
+            op,
+            source: io::Error::new(reason.kind(), "not attempted"),
+            reason,
+        }
+    }
+
+    ///Turns an EINVAL into `Invalid` if `check` finds the constraint that was broken
+    #[cfg(target_os = "linux")]
+    pub(crate) fn explain<F>(self, check: F) -> RingError
+    where
+        F: FnOnce() -> Result<(), ConfigError>,
+    {
+        match self {
+            RingError::Op { label, op, source } if source.raw_os_error() == Some(EINVAL) => {
+                match check() {
+                    Err(reason) => RingError::Invalid {
+                        label,
+                        op,
+                        reason,
+                        source,
+                    },
+                    Ok(()) => RingError::Op { label, op, source },
+                }
+            }
+            e => e,
+        }
+    }
+
+    ///Returns the kind of the underlying OS error, or of the broken constraint for `Invalid`
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            RingError::Invalid { reason, .. } => reason.kind(),
+            e => e.io_error().kind(),
+        }
+    }
+
+    ///Returns the errno of the underlying OS error, if it came from one
+    pub fn raw_os_error(&self) -> Option<i32> {
+        self.io_error().raw_os_error()
+    }
+
+    ///Returns true if the error says the interface went down or away: ENETDOWN, ENODEV or ENXIO.
+    ///The ring sees packets again once it is back up, unless it was removed
+    pub fn is_interface_down(&self) -> bool {
+        matches!(
+            self.raw_os_error(),
+            Some(ENETDOWN) | Some(ENODEV) | Some(ENXIO)
+        )
+    }
+
+    ///Returns the step that failed, or `None` for `Io`
+    pub fn operation(&self) -> Option<&Operation> {
+        match self {
+            RingError::Op { op, .. }
+            | RingError::MissingCapNetRaw { op, .. }
+            | RingError::Invalid { op, .. } => Some(op),
+            RingError::MalformedBlock { .. } => Some(&Operation::Receive),
+            RingError::Io(_) => None,
+        }
+    }
+
+    ///Returns the ring the step failed on, or `None` for `Io`
+    pub fn label(&self) -> Option<&RingLabel> {
+        match self {
+            RingError::Op { label, .. }
+            | RingError::MissingCapNetRaw { label, .. }
+            | RingError::Invalid { label, .. }
+            | RingError::MalformedBlock { label, .. } => Some(label),
+            RingError::Io(_) => None,
+        }
+    }
+
+    ///Returns the underlying I/O error
+    pub fn io_error(&self) -> &io::Error {
+        match self {
+            RingError::Op { source, .. }
+            | RingError::MissingCapNetRaw { source, .. }
+            | RingError::Invalid { source, .. }
+            | RingError::MalformedBlock { source, .. }
+            | RingError::Io(source) => source,
+        }
+    }
+}
+
+impl fmt::Display for RingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RingError::Op { label, op, source } => write!(f, "{}: {}: {}", label, op, source),
+            RingError::MissingCapNetRaw { label, op, source } => write!(
+                f,
+                "{}: {}: {}: AF_PACKET sockets on {} need CAP_NET_RAW. Run as root, grant it to the \
+                 binary with `setcap cap_net_raw=eip`, or in a container add it with \
+                 `--cap-add=NET_RAW`",
+                label, op, source, label.if_name
+            ),
+            RingError::Invalid {
+                label,
+                op,
+                reason,
+                source,
+            } => write!(f, "{}: {}: {} ({})", label, op, reason, source),
+            RingError::MalformedBlock {
+                label,
+                seq,
+                anomaly,
+                ..
+            } => write!(f, "{}: malformed block {}: {:?}", label, seq, anomaly),
+            RingError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for RingError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(self.io_error())
+    }
+}
+
+impl From<io::Error> for RingError {
+    ///Unwraps the `RingError` inside an error built from one, so the step survives a trip
+    ///through `io::Result`
+    fn from(e: io::Error) -> RingError {
+        if !e.get_ref().is_some_and(|inner| inner.is::<RingError>()) {
+            return RingError::Io(e);
+        }
+        let kind = e.kind();
+        match e.into_inner().map(|inner| inner.downcast::<RingError>()) {
+            Some(Ok(inner)) => *inner,
+            //checked above
+            _ => RingError::Io(io::Error::from(kind)),
+        }
+    }
+}
+
+impl From<RingError> for io::Error {
+    ///Keeps the kind of the underlying error, with the `RingError` inside
+    fn from(e: RingError) -> io::Error {
+        match e {
+            RingError::Io(e) => e,
+            e => io::Error::new(e.kind(), e),
+        }
+    }
+}
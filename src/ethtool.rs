@@ -0,0 +1,258 @@
+//!Receive offloads that change what a capture sees, through the SIOCETHTOOL ioctl. GRO and LRO
+//!merge consecutive TCP segments into one large packet before AF_PACKET gets them, so packet
+//!counts, sizes and timings no longer match the wire. Rx VLAN offload moves the 802.1Q tag out
+//!of the frame and into tp_vlan_tci.
+
+use std::io::{self, Error};
+use std::mem;
+
+use libc::{c_char, c_ulong, c_void, ioctl, IF_NAMESIZE};
+
+use socket::Socket;
+use timestamp::{Timestamping, HWTSTAMP_FILTER_ALL};
+use tpacket3::TimestampSource;
+
+const SIOCETHTOOL: c_ulong = 0x8946;
+
+const ETHTOOL_GFLAGS: u32 = 0x25;
+const ETHTOOL_SFLAGS: u32 = 0x26;
+const ETHTOOL_GGRO: u32 = 0x2b;
+const ETHTOOL_SGRO: u32 = 0x2c;
+const ETHTOOL_GCHANNELS: u32 = 0x3c;
+const ETHTOOL_GET_TS_INFO: u32 = 0x41;
+
+const ETH_FLAG_RXVLAN: u32 = 1 << 8;
+const ETH_FLAG_LRO: u32 = 1 << 15;
+
+//struct ethtool_value
+#[repr(C)]
+struct EthtoolValue {
+    cmd: u32,
+    data: u32,
+}
+
+///An interface's queue counts, from ETHTOOL_GCHANNELS. Queues are either dedicated to one
+///direction or "combined" rx/tx pairs, depending on the driver
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Channels {
+    cmd: u32,
+    pub max_rx: u32,
+    pub max_tx: u32,
+    pub max_other: u32,
+    pub max_combined: u32,
+    pub rx_count: u32,
+    pub tx_count: u32,
+    pub other_count: u32,
+    pub combined_count: u32,
+}
+
+impl Channels {
+    ///Returns the number of queues the interface receives on
+    #[inline]
+    pub fn rx_queues(&self) -> u32 {
+        self.rx_count + self.combined_count
+    }
+}
+
+///An interface's timestamping abilities, from ETHTOOL_GET_TS_INFO
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TsInfo {
+    cmd: u32,
+    ///The SOF_TIMESTAMPING_* flags the interface supports
+    pub so_timestamping: u32,
+    ///The N of the interface's PTP hardware clock /dev/ptpN, -1 if it has none
+    pub phc_index: i32,
+    ///A bit for each HWTSTAMP_TX_* type it supports
+    pub tx_types: u32,
+    tx_reserved: [u32; 3],
+    ///A bit for each HWTSTAMP_FILTER_* filter it supports
+    pub rx_filters: u32,
+    rx_reserved: [u32; 3],
+}
+
+//struct ifreq with ifr_data set
+#[repr(C)]
+struct IfReqData {
+    ifr_name: [c_char; IF_NAMESIZE],
+    ifr_data: *mut c_void,
+    _pad: [u8; 16],
+}
+
+///Which receive offloads are on, or for `OffloadGuard::disable` which ones to turn off
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Offloads {
+    ///Generic receive offload, done in software by the kernel
+    pub gro: bool,
+    ///Large receive offload, done by the NIC
+    pub lro: bool,
+    ///Rx VLAN offload, `rxvlan` in ethtool -k
+    pub rx_vlan: bool,
+}
+
+impl Offloads {
+    ///GRO and LRO, the offloads that merge packets
+    pub const MERGING: Offloads = Offloads {
+        gro: true,
+        lro: true,
+        rx_vlan: false,
+    };
+}
+
+fn ethtool(sock: &Socket, cmd: u32, data: u32) -> io::Result<u32> {
+    let mut value = EthtoolValue { cmd, data };
+    ethtool_request(sock, &mut value)?;
+    Ok(value.data)
+}
+
+//`cmd` must be the first field of `T`, as in every ethtool_* struct
+fn ethtool_request<T>(sock: &Socket, cmd: &mut T) -> io::Result<()> {
+    ifreq_data_ioctl(sock, SIOCETHTOOL, cmd)
+}
+
+//an interface ioctl taking a struct ifreq whose ifr_data points at `data`
+pub(crate) fn ifreq_data_ioctl<T>(sock: &Socket, request: c_ulong, data: &mut T) -> io::Result<()> {
+    let mut req: IfReqData = unsafe { mem::zeroed() };
+    if sock.if_name.len() >= IF_NAMESIZE {
+        return Err(Error::new(
+            io::ErrorKind::InvalidInput,
+            "interface name too long",
+        ));
+    }
+    for (dst, src) in req.ifr_name.iter_mut().zip(sock.if_name.bytes()) {
+        *dst = src as c_char;
+    }
+    req.ifr_data = data as *mut T as *mut c_void;
+    match unsafe { ioctl(sock.fd, request as _, &mut req) } {
+        -1 => Err(Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+///Reads which receive offloads are on for the socket's interface
+pub fn get_offloads(sock: &Socket) -> io::Result<Offloads> {
+    let gro = ethtool(sock, ETHTOOL_GGRO, 0)? != 0;
+    let flags = ethtool(sock, ETHTOOL_GFLAGS, 0)?;
+    Ok(Offloads {
+        gro,
+        lro: flags & ETH_FLAG_LRO != 0,
+        rx_vlan: flags & ETH_FLAG_RXVLAN != 0,
+    })
+}
+
+///Reads the socket's interface's queue counts. Fails with EOPNOTSUPP for drivers without
+///multiple queues, such as most virtual interfaces
+pub fn get_channels(sock: &Socket) -> io::Result<Channels> {
+    let mut channels = Channels {
+        cmd: ETHTOOL_GCHANNELS,
+        ..Channels::default()
+    };
+    ethtool_request(sock, &mut channels)?;
+    Ok(channels)
+}
+
+impl TsInfo {
+    ///Returns the timestamp sources a ring on the interface can use
+    pub fn rx_sources(&self) -> Vec<TimestampSource> {
+        let flags = Timestamping::from_bits_truncate(self.so_timestamping);
+        let mut sources = Vec::new();
+        if flags.contains(Timestamping::RX_SOFTWARE) || flags.contains(Timestamping::SOFTWARE) {
+            sources.push(TimestampSource::Software);
+        }
+        //the ring asks the NIC for every packet, which drivers that only stamp PTP can't do
+        let rx_all = self.rx_filters & (1 << HWTSTAMP_FILTER_ALL) != 0;
+        if flags.contains(Timestamping::RX_HARDWARE) && rx_all {
+            if flags.contains(Timestamping::RAW_HARDWARE) {
+                sources.push(TimestampSource::HardwareRaw);
+            }
+            if flags.contains(Timestamping::SYS_HARDWARE) {
+                sources.push(TimestampSource::HardwareSystem);
+            }
+        }
+        sources
+    }
+
+    ///True if a ring on the interface can use `source`. The kernel's own timestamps always work
+    pub fn supports(&self, source: TimestampSource) -> bool {
+        source == TimestampSource::Software || self.rx_sources().contains(&source)
+    }
+}
+
+///Reads the socket's interface's timestamping abilities
+pub fn get_ts_info(sock: &Socket) -> io::Result<TsInfo> {
+    let mut info = TsInfo {
+        cmd: ETHTOOL_GET_TS_INFO,
+        ..TsInfo::default()
+    };
+    ethtool_request(sock, &mut info)?;
+    Ok(info)
+}
+
+///Turns the socket's interface's receive offloads on or off to match `want`, only touching the
+///ones that differ. Needs CAP_NET_ADMIN, and fails with EOPNOTSUPP for offloads the driver can't
+///change
+pub fn set_offloads(sock: &Socket, want: Offloads) -> io::Result<()> {
+    let have = get_offloads(sock)?;
+    if have.gro != want.gro {
+        ethtool(sock, ETHTOOL_SGRO, want.gro as u32)?;
+    }
+    if have.lro != want.lro || have.rx_vlan != want.rx_vlan {
+        let mut flags = ethtool(sock, ETHTOOL_GFLAGS, 0)?;
+        flags &= !(ETH_FLAG_LRO | ETH_FLAG_RXVLAN);
+        if want.lro {
+            flags |= ETH_FLAG_LRO;
+        }
+        if want.rx_vlan {
+            flags |= ETH_FLAG_RXVLAN;
+        }
+        ethtool(sock, ETHTOOL_SFLAGS, flags)?;
+    }
+    Ok(())
+}
+
+///Keeps some of an interface's receive offloads off while it is held, and turns back on the ones
+///it turned off when dropped
+#[derive(Debug)]
+pub struct OffloadGuard {
+    sock: Socket,
+    saved: Offloads,
+}
+
+impl OffloadGuard {
+    ///Turns off the offloads set in `off` on the socket's interface
+    pub fn disable(sock: &Socket, off: Offloads) -> io::Result<OffloadGuard> {
+        let saved = get_offloads(sock)?;
+        set_offloads(
+            sock,
+            Offloads {
+                gro: saved.gro && !off.gro,
+                lro: saved.lro && !off.lro,
+                rx_vlan: saved.rx_vlan && !off.rx_vlan,
+            },
+        )?;
+        Ok(OffloadGuard {
+            sock: sock.clone(),
+            saved,
+        })
+    }
+
+    ///Returns the offloads that were on before the guard was made
+    #[inline]
+    pub fn saved(&self) -> Offloads {
+        self.saved
+    }
+}
+
+impl Drop for OffloadGuard {
+    fn drop(&mut self) {
+        if let Err(_e) = set_offloads(&self.sock, self.saved) {
+            trace_event!(
+                Warn,
+                "failed to restore offloads on {}: {}",
+                self.sock.if_name,
+                _e
+            );
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//!Writes per-packet metadata instead of packets, for feeding log pipelines that want to know
+//!what went past without storing payloads. Each packet becomes one record with its timestamp,
+//!lengths, interface, direction, ethertype, VLAN tag, RX hash and, for IP packets, its 5-tuple.
+//!Fields a packet doesn't have are left out. Records are JSON objects, one per line, or
+//!CBOR maps written back to back as a CBOR sequence (RFC 8742).
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+
+use flow::FiveTuple;
+use rx::{OwnedPacket, PacketType, RawPacket};
+use tpacket3::{SockaddrLl, Tpacket3Hdr};
+use vlan::VlanTag;
+
+///How records are encoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    ///One JSON object per line
+    JsonLines,
+    ///One CBOR map per record
+    Cbor,
+}
+
+///The metadata exported for a packet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PacketMetadata {
+    pub tp_sec: u32,
+    pub tp_nsec: u32,
+    pub wire_len: u32,
+    pub captured_len: u32,
+    ///sll_ifindex, when the packet came with a sockaddr_ll
+    pub if_index: Option<i32>,
+    pub packet_type: Option<PacketType>,
+    ///sll_protocol in host byte order
+    pub protocol: Option<u16>,
+    pub vlan: Option<VlanTag>,
+    pub rxhash: Option<u32>,
+    pub five_tuple: Option<FiveTuple>,
+}
+
+impl PacketMetadata {
+    pub fn from_packet(packet: &RawPacket<'_>) -> PacketMetadata {
+        PacketMetadata::new(
+            &packet.tpacket3_hdr,
+            packet.sockaddr_ll().as_ref(),
+            packet.five_tuple(),
+        )
+    }
+
+    pub fn from_owned(packet: &OwnedPacket) -> PacketMetadata {
+        PacketMetadata::new(
+            &packet.tpacket3_hdr,
+            packet.sockaddr_ll.as_ref(),
+            packet.five_tuple(),
+        )
+    }
+
+    fn new(
+        hdr: &Tpacket3Hdr,
+        sll: Option<&SockaddrLl>,
+        five_tuple: Option<FiveTuple>,
+    ) -> PacketMetadata {
+        PacketMetadata {
+            tp_sec: hdr.tp_sec,
+            tp_nsec: hdr.tp_nsec,
+            wire_len: hdr.tp_len,
+            captured_len: hdr.tp_snaplen,
+            if_index: sll.map(|sll| sll.sll_ifindex),
+            packet_type: sll.map(|sll| PacketType::from(sll.sll_pkttype)),
+            protocol: sll.map(|sll| sll.sll_protocol),
+            vlan: hdr.vlan_tag(),
+            rxhash: hdr.rxhash(),
+            five_tuple,
+        }
+    }
+
+    //the record's fields in output order, leaving out the ones the packet doesn't have
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        let mut fields = vec![
+            ("ts_sec", Value::Uint(u64::from(self.tp_sec))),
+            ("ts_nsec", Value::Uint(u64::from(self.tp_nsec))),
+            ("wire_len", Value::Uint(u64::from(self.wire_len))),
+            ("captured_len", Value::Uint(u64::from(self.captured_len))),
+        ];
+        if let Some(if_index) = self.if_index {
+            fields.push(("if_index", Value::Int(i64::from(if_index))));
+        }
+        if let Some(packet_type) = self.packet_type {
+            fields.push(("packet_type", Value::Text(packet_type_name(packet_type))));
+        }
+        if let Some(protocol) = self.protocol {
+            fields.push(("protocol", Value::Uint(u64::from(protocol))));
+        }
+        if let Some(vlan) = self.vlan {
+            fields.push((
+                "vlan",
+                Value::Map(vec![
+                    ("tpid", Value::Uint(u64::from(vlan.tpid))),
+                    ("vid", Value::Uint(u64::from(vlan.vid()))),
+                    ("pcp", Value::Uint(u64::from(vlan.pcp()))),
+                ]),
+            ));
+        }
+        if let Some(rxhash) = self.rxhash {
+            fields.push(("rxhash", Value::Uint(u64::from(rxhash))));
+        }
+        if let Some(ref tuple) = self.five_tuple {
+            fields.push(("src", Value::Text(tuple.src.to_string())));
+            fields.push(("dst", Value::Text(tuple.dst.to_string())));
+            fields.push(("src_port", Value::Uint(u64::from(tuple.src_port))));
+            fields.push(("dst_port", Value::Uint(u64::from(tuple.dst_port))));
+            fields.push(("ip_protocol", Value::Uint(u64::from(tuple.protocol))));
+        }
+        fields
+    }
+}
+
+enum Value {
+    Uint(u64),
+    Int(i64),
+    Text(String),
+    Map(Vec<(&'static str, Value)>),
+}
+
+///Writes a metadata record for every packet it is given
+#[derive(Debug)]
+pub struct MetadataWriter<W: Write> {
+    out: W,
+    format: ExportFormat,
+    records: u64,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> MetadataWriter<W> {
+    pub fn new(out: W, format: ExportFormat) -> MetadataWriter<W> {
+        MetadataWriter {
+            out,
+            format,
+            records: 0,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn write_packet(&mut self, packet: &RawPacket<'_>) -> io::Result<()> {
+        self.write_metadata(&PacketMetadata::from_packet(packet))
+    }
+
+    pub fn write_owned(&mut self, packet: &OwnedPacket) -> io::Result<()> {
+        self.write_metadata(&PacketMetadata::from_owned(packet))
+    }
+
+    pub fn write_metadata(&mut self, metadata: &PacketMetadata) -> io::Result<()> {
+        let fields = metadata.fields();
+        self.buf.clear();
+        match self.format {
+            ExportFormat::JsonLines => {
+                let mut line = String::new();
+                json_map(&fields, &mut line);
+                line.push('\n');
+                self.buf.extend_from_slice(line.as_bytes());
+            }
+            ExportFormat::Cbor => cbor_map(&fields, &mut self.buf),
+        }
+        self.out.write_all(&self.buf)?;
+        self.records += 1;
+        Ok(())
+    }
+
+    ///Returns the number of records written
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    pub fn format(&self) -> ExportFormat {
+        self.format
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    ///Flushes and returns the output
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn packet_type_name(packet_type: PacketType) -> String {
+    match packet_type {
+        PacketType::Host => String::from("host"),
+        PacketType::Broadcast => String::from("broadcast"),
+        PacketType::Multicast => String::from("multicast"),
+        PacketType::OtherHost => String::from("otherhost"),
+        PacketType::Outgoing => String::from("outgoing"),
+        PacketType::Other(pkttype) => pkttype.to_string(),
+    }
+}
+
+//keys and text values are all plain ASCII made here, so nothing needs escaping
+fn json_map(fields: &[(&'static str, Value)], out: &mut String) {
+    out.push('{');
+    for (i, &(key, ref value)) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "\"{}\":", key);
+        match *value {
+            Value::Uint(n) => {
+                let _ = write!(out, "{}", n);
+            }
+            Value::Int(n) => {
+                let _ = write!(out, "{}", n);
+            }
+            Value::Text(ref text) => {
+                let _ = write!(out, "\"{}\"", text);
+            }
+            Value::Map(ref map) => json_map(map, out),
+        }
+    }
+    out.push('}');
+}
+
+fn cbor_map(fields: &[(&'static str, Value)], out: &mut Vec<u8>) {
+    cbor_head(5, fields.len() as u64, out);
+    for &(key, ref value) in fields {
+        cbor_text(key, out);
+        match *value {
+            Value::Uint(n) => cbor_head(0, n, out),
+            Value::Int(n) if n >= 0 => cbor_head(0, n as u64, out),
+            //major type 1 holds -1 - n
+            Value::Int(n) => cbor_head(1, (-1 - n) as u64, out),
+            Value::Text(ref text) => cbor_text(text, out),
+            Value::Map(ref map) => cbor_map(map, out),
+        }
+    }
+}
+
+fn cbor_text(text: &str, out: &mut Vec<u8>) {
+    cbor_head(3, text.len() as u64, out);
+    out.extend_from_slice(text.as_bytes());
+}
+
+//the initial byte with the major type in the top 3 bits, then the argument in the fewest bytes
+fn cbor_head(major: u8, n: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    if n < 24 {
+        out.push(major | n as u8);
+    } else if n <= u64::from(u8::MAX) {
+        out.extend_from_slice(&[major | 24, n as u8]);
+    } else if n <= u64::from(u16::MAX) {
+        out.push(major | 25);
+        out.extend_from_slice(&(n as u16).to_be_bytes());
+    } else if n <= u64::from(u32::MAX) {
+        out.push(major | 26);
+        out.extend_from_slice(&(n as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
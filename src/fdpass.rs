@@ -0,0 +1,288 @@
+//!Hands a configured ring to another process over a Unix socket, for privilege separation: a
+//!small process with CAP_NET_RAW opens and sets up the ring, sends it with `send_ring`, and the
+//!unprivileged process doing the capture gets a working `Ring` back from `recv_ring`. The socket
+//!itself travels as SCM_RIGHTS ancillary data, along with what the receiver needs to map its
+//!ring. Both processes then share the one socket and its ring. The sender keeps the ring's
+//!promiscuous mode and offload guards, which undo their changes when its ring is dropped, so it
+//!should hold on to it for as long as the capture runs.
+
+use libc::{
+    c_int, c_void, close, cmsghdr, iovec, msghdr, recvmsg, sendmsg, CMSG_DATA, CMSG_FIRSTHDR,
+    CMSG_LEN, CMSG_SPACE, MSG_CMSG_CLOEXEC, MSG_CTRUNC, SCM_RIGHTS, SOL_SOCKET,
+};
+use std::io::{self, Error, ErrorKind, Read};
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::ptr;
+
+use rx::{Ring, RingLabel};
+use socket::Socket;
+use tpacket3::TpacketReq3;
+
+const MAGIC: &[u8; 8] = b"AFPRING1";
+//what a ring description may take up, a couple of names over the fixed fields
+const MAX_DESCRIPTION: usize = 4096;
+
+///Sends `fd` over `stream` along with `data`, which must not be empty. The receiver gets its own
+///descriptor for the same open file
+pub fn send_fd(stream: &UnixStream, fd: RawFd, data: &[u8]) -> io::Result<()> {
+    if data.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "a descriptor needs at least one byte to go with",
+        ));
+    }
+    let space = unsafe { CMSG_SPACE(mem::size_of::<c_int>() as u32) } as usize;
+    //u64s keep the control buffer aligned for cmsghdr
+    let mut control = vec![0u64; (space + 7) / 8];
+    let mut iov = iovec {
+        iov_base: data.as_ptr() as *mut c_void,
+        iov_len: data.len(),
+    };
+    let mut msg: msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = space as _;
+    unsafe {
+        let cmsg = CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = SOL_SOCKET;
+        (*cmsg).cmsg_type = SCM_RIGHTS;
+        (*cmsg).cmsg_len = CMSG_LEN(mem::size_of::<c_int>() as u32) as _;
+        ptr::write_unaligned(CMSG_DATA(cmsg) as *mut c_int, fd);
+    }
+    let sent = unsafe { sendmsg(stream.as_raw_fd(), &msg, 0) };
+    if sent < 0 {
+        return Err(Error::last_os_error());
+    }
+    //the descriptor went with the first byte, the rest is plain data
+    let mut rest = &data[sent as usize..];
+    while !rest.is_empty() {
+        let n = io::Write::write(&mut &*stream, rest)?;
+        rest = &rest[n..];
+    }
+    Ok(())
+}
+
+///Receives a descriptor sent with `send_fd`, reading the data that came with it into `buf`.
+///Returns the descriptor, opened close-on-exec, and the number of bytes read. On a stream socket
+///the data may come in several reads, the descriptor only with the first
+pub fn recv_fd(stream: &UnixStream, buf: &mut [u8]) -> io::Result<(RawFd, usize)> {
+    let space = unsafe { CMSG_SPACE(mem::size_of::<c_int>() as u32) } as usize;
+    let mut control = vec![0u64; (space + 7) / 8];
+    let mut iov = iovec {
+        iov_base: buf.as_mut_ptr() as *mut c_void,
+        iov_len: buf.len(),
+    };
+    let mut msg: msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = space as _;
+    let read = unsafe { recvmsg(stream.as_raw_fd(), &mut msg, MSG_CMSG_CLOEXEC) };
+    if read < 0 {
+        return Err(Error::last_os_error());
+    }
+    let mut fd = None;
+    let mut cmsg: *const cmsghdr = unsafe { CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let hdr = unsafe { &*cmsg };
+        if hdr.cmsg_level == SOL_SOCKET && hdr.cmsg_type == SCM_RIGHTS {
+            let got = unsafe { ptr::read_unaligned(CMSG_DATA(cmsg) as *const c_int) };
+            //a second descriptor in one message would be a confused sender, don't leak either
+            if let Some(extra) = fd.replace(got) {
+                unsafe { close(extra) };
+            }
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+    match fd {
+        Some(fd) if msg.msg_flags & MSG_CTRUNC == 0 => Ok((fd, read as usize)),
+        Some(fd) => {
+            unsafe { close(fd) };
+            Err(Error::new(
+                ErrorKind::Other,
+                "descriptor control message truncated",
+            ))
+        }
+        None if read == 0 => Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "the sender closed the socket",
+        )),
+        None => Err(Error::new(
+            ErrorKind::InvalidData,
+            "message came without a descriptor",
+        )),
+    }
+}
+
+///Sends `ring`'s socket over `stream`, along with its interface, socket kind, block layout,
+///fanout and label, for `recv_ring` in another process. `ring` keeps working
+pub fn send_ring(stream: &UnixStream, ring: &Ring) -> io::Result<()> {
+    let mut body = Vec::new();
+    body.extend_from_slice(MAGIC);
+    let socket = &ring.socket;
+    body.extend_from_slice(&socket.if_index.to_le_bytes());
+    body.extend_from_slice(&socket.sock_type.to_le_bytes());
+    body.extend_from_slice(&socket.kind.to_le_bytes());
+    body.extend_from_slice(&socket.hatype.to_le_bytes());
+    let opts = ring.ring_settings();
+    for field in &[
+        opts.tp_block_size,
+        opts.tp_block_nr,
+        opts.tp_frame_size,
+        opts.tp_frame_nr,
+        opts.tp_retire_blk_tov,
+        opts.tp_sizeof_priv,
+        opts.tp_feature_req_word,
+    ] {
+        body.extend_from_slice(&field.to_le_bytes());
+    }
+    body.extend_from_slice(&ring.fanout().to_le_bytes());
+    let label = ring.label();
+    body.extend_from_slice(&label.index.to_le_bytes());
+    put_str(&mut body, Some(&socket.if_name));
+    put_str(&mut body, label.tag.as_deref());
+    if body.len() > MAX_DESCRIPTION {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "ring description too long",
+        ));
+    }
+    let mut data = (body.len() as u32).to_le_bytes().to_vec();
+    data.extend_from_slice(&body);
+    send_fd(stream, socket.fd, &data)
+}
+
+///Receives a ring sent with `send_ring` and maps it. The socket is already bound and in its
+///fanout group, so packets are shared with the sending process if it keeps reading too
+pub fn recv_ring(stream: &UnixStream) -> io::Result<Ring> {
+    let mut len = [0u8; 4];
+    let (fd, read) = recv_fd(stream, &mut len)?;
+    match describe(stream, &len, read).and_then(|desc| desc.into_ring(fd)) {
+        Ok(ring) => Ok(ring),
+        Err(e) => {
+            unsafe { close(fd) };
+            Err(e)
+        }
+    }
+}
+
+//what came with the descriptor
+struct Description {
+    socket: Socket,
+    opts: TpacketReq3,
+    fanout: c_int,
+    label: RingLabel,
+}
+
+impl Description {
+    fn into_ring(self, fd: RawFd) -> io::Result<Ring> {
+        let socket = Socket { fd, ..self.socket };
+        Ring::attach(socket, self.label, self.opts, self.fanout)
+    }
+}
+
+//reads the rest of the description after the first `read` bytes of its length
+fn describe(stream: &UnixStream, len: &[u8; 4], read: usize) -> io::Result<Description> {
+    let mut len = *len;
+    (&*stream).read_exact(&mut len[read..])?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_DESCRIPTION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("ring description of {} bytes", len),
+        ));
+    }
+    let mut body = vec![0; len];
+    (&*stream).read_exact(&mut body)?;
+    let mut r = Reader { buf: &body };
+    if r.take(8)? != MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "not a ring description"));
+    }
+    let if_index = r.u32()?;
+    let sock_type = r.u32()? as c_int;
+    let kind = r.u32()? as c_int;
+    let hatype = u16::from_le_bytes([r.u8()?, r.u8()?]);
+    let opts = TpacketReq3 {
+        tp_block_size: r.u32()?,
+        tp_block_nr: r.u32()?,
+        tp_frame_size: r.u32()?,
+        tp_frame_nr: r.u32()?,
+        tp_retire_blk_tov: r.u32()?,
+        tp_sizeof_priv: r.u32()?,
+        tp_feature_req_word: r.u32()?,
+    };
+    let fanout = r.u32()? as c_int;
+    let index = r.u32()?;
+    let if_name = r.str()?.unwrap_or_default();
+    let tag = r.str()?;
+    Ok(Description {
+        socket: Socket {
+            fd: -1,
+            if_name: if_name.clone(),
+            if_index,
+            sock_type,
+            kind,
+            hatype,
+        },
+        opts,
+        fanout,
+        label: RingLabel {
+            if_name,
+            index,
+            tag,
+        },
+    })
+}
+
+//a u16 length, or 0xffff for none, then the bytes
+fn put_str(out: &mut Vec<u8>, s: Option<&str>) {
+    match s {
+        Some(s) => {
+            let s = &s.as_bytes()[..s.len().min(0xfffe)];
+            out.extend_from_slice(&(s.len() as u16).to_le_bytes());
+            out.extend_from_slice(s);
+        }
+        None => out.extend_from_slice(&0xffffu16.to_le_bytes()),
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.buf.len() < n {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "ring description cut short",
+            ));
+        }
+        let (head, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn str(&mut self) -> io::Result<Option<String>> {
+        let len = u16::from_le_bytes([self.u8()?, self.u8()?]);
+        if len == 0xffff {
+            return Ok(None);
+        }
+        let bytes = self.take(usize::from(len))?;
+        String::from_utf8(bytes.to_vec())
+            .map(Some)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "name isn't UTF-8"))
+    }
+}
@@ -0,0 +1,235 @@
+//!A C ABI over the TPACKET_V3 ring, for applications and language bindings that aren't Rust.
+//!`include/af_packet.h` declares it. A ring is opened, blocks are taken from it one at a time,
+//!the packets in a block are walked with `afp_block_next_packet`, and the block is then handed
+//!back with `afp_block_consume`. Packet data points into the ring, so it stays valid only until
+//!its block is consumed, and every block must be consumed before its ring is closed. Functions
+//!returning an int return 0 or a negative errno.
+
+use libc::{c_char, c_int, EAGAIN, EINVAL, EIO};
+use std::ffi::CStr;
+use std::io;
+use std::mem;
+use std::time::Duration;
+
+use error::RingError;
+use rx::{Block, RawPacket, Ring, RingSettings};
+
+///An open ring
+pub struct AfpRing {
+    ring: Ring,
+}
+
+///A block taken from a ring, with the packets still to be walked
+pub struct AfpBlock {
+    //both really borrow from the ring's mmap, which outlives the block as long as the caller
+    //consumes it before closing the ring
+    block: Block<'static>,
+    packets: Vec<RawPacket<'static>>,
+    next: usize,
+}
+
+///A packet in a block
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct AfpPacket {
+    pub tp_sec: u32,
+    pub tp_nsec: u32,
+    ///Length on the wire
+    pub wire_len: u32,
+    ///Bytes captured, which `data_len` can't exceed
+    pub captured_len: u32,
+    ///The RX hash, 0 if the kernel didn't report one
+    pub rxhash: u32,
+    ///Index of the interface the packet was seen on, 0 if unknown
+    pub if_index: i32,
+    ///Ethertype in host order, 0 if unknown
+    pub protocol: u16,
+    ///PACKET_HOST, PACKET_OUTGOING etc., 0xff if unknown
+    pub packet_type: u8,
+    ///The frame from its link-layer header on, or from the network header for cooked rings
+    pub data: *const u8,
+    pub data_len: usize,
+}
+
+///Kernel counters of a ring since they were last read
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AfpStats {
+    pub packets: u32,
+    pub drops: u32,
+    pub freeze_q_cnt: u32,
+}
+
+///Opens a ring with the default settings on `if_name`, in promiscuous mode if `promisc` isn't 0,
+///and stores it in `*out`
+///
+///# Safety
+///`if_name` must be a NUL-terminated string and `out` must be valid to write
+#[no_mangle]
+pub unsafe extern "C" fn afp_ring_open(
+    if_name: *const c_char,
+    promisc: c_int,
+    out: *mut *mut AfpRing,
+) -> c_int {
+    if if_name.is_null() || out.is_null() {
+        return -EINVAL;
+    }
+    let if_name = match CStr::from_ptr(if_name).to_str() {
+        Ok(name) => String::from(name),
+        Err(_) => return -EINVAL,
+    };
+    let settings = RingSettings {
+        if_name,
+        promisc: promisc != 0,
+        ..RingSettings::default()
+    };
+    match Ring::new(settings) {
+        Ok(ring) => {
+            *out = Box::into_raw(Box::new(AfpRing { ring }));
+            0
+        }
+        Err(e) => ring_errno(&e),
+    }
+}
+
+///Waits up to `timeout_ms` for a block, forever if it is negative, and stores it in `*out`.
+///Returns -EAGAIN if none was retired in time
+///
+///# Safety
+///`ring` must come from `afp_ring_open` and `out` must be valid to write
+#[no_mangle]
+pub unsafe extern "C" fn afp_ring_next_block(
+    ring: *mut AfpRing,
+    timeout_ms: c_int,
+    out: *mut *mut AfpBlock,
+) -> c_int {
+    if ring.is_null() || out.is_null() {
+        return -EINVAL;
+    }
+    let timeout = if timeout_ms < 0 {
+        None
+    } else {
+        Some(Duration::from_millis(timeout_ms as u64))
+    };
+    let block = match (*ring).ring.try_get_block(timeout) {
+        Ok(Some(block)) => block,
+        Ok(None) => return -EAGAIN,
+        Err(e) => return ring_errno(&e),
+    };
+    let block: Block<'static> = mem::transmute(block);
+    let packets: Vec<RawPacket<'static>> = mem::transmute(block.get_raw_packets());
+    *out = Box::into_raw(Box::new(AfpBlock {
+        block,
+        packets,
+        next: 0,
+    }));
+    0
+}
+
+///Returns the number of packets in `block`
+///
+///# Safety
+///`block` must come from `afp_ring_next_block` and not be consumed yet
+#[no_mangle]
+pub unsafe extern "C" fn afp_block_packet_count(block: *const AfpBlock) -> u32 {
+    if block.is_null() {
+        return 0;
+    }
+    (*block).packets.len() as u32
+}
+
+///Stores the block's next packet in `*out` and returns 1, or returns 0 once every packet has
+///been walked
+///
+///# Safety
+///`block` must come from `afp_ring_next_block` and not be consumed yet, and `out` must be valid
+///to write
+#[no_mangle]
+pub unsafe extern "C" fn afp_block_next_packet(block: *mut AfpBlock, out: *mut AfpPacket) -> c_int {
+    if block.is_null() || out.is_null() {
+        return -EINVAL;
+    }
+    let block = &mut *block;
+    let packet = match block.packets.get(block.next) {
+        Some(packet) => packet,
+        None => return 0,
+    };
+    block.next += 1;
+    let sll = packet.sockaddr_ll();
+    let data = packet.l2();
+    *out = AfpPacket {
+        tp_sec: packet.tpacket3_hdr.tp_sec,
+        tp_nsec: packet.tpacket3_hdr.tp_nsec,
+        wire_len: packet.wire_len(),
+        captured_len: packet.captured_len(),
+        rxhash: packet.rxhash().unwrap_or(0),
+        if_index: sll.as_ref().map_or(0, |sll| sll.sll_ifindex),
+        protocol: sll.as_ref().map_or(0, |sll| sll.sll_protocol),
+        packet_type: sll.as_ref().map_or(0xff, |sll| sll.sll_pkttype),
+        data: data.as_ptr(),
+        data_len: data.len(),
+    };
+    1
+}
+
+///Hands `block` back to the kernel and frees it. Its packets' data must not be used after this
+///
+///# Safety
+///`block` must come from `afp_ring_next_block` and not be consumed already
+#[no_mangle]
+pub unsafe extern "C" fn afp_block_consume(block: *mut AfpBlock) {
+    if block.is_null() {
+        return;
+    }
+    let mut block = Box::from_raw(block);
+    block.packets.clear();
+    block.block.mark_as_consumed();
+}
+
+///Reads and resets the ring's kernel counters into `*out`
+///
+///# Safety
+///`ring` must come from `afp_ring_open` and `out` must be valid to write
+#[no_mangle]
+pub unsafe extern "C" fn afp_ring_stats(ring: *const AfpRing, out: *mut AfpStats) -> c_int {
+    if ring.is_null() || out.is_null() {
+        return -EINVAL;
+    }
+    match (*ring).ring.get_rx_statistics() {
+        Ok(s) => {
+            *out = AfpStats {
+                packets: s.stats.tp_packets,
+                drops: s.stats.tp_drops,
+                freeze_q_cnt: s.stats.tp_freeze_q_cnt,
+            };
+            0
+        }
+        Err(e) => io_errno(&e),
+    }
+}
+
+///Unmaps the ring and closes its socket, restoring promiscuous mode
+///
+///# Safety
+///`ring` must come from `afp_ring_open`, with all its blocks consumed, and isn't valid after
+#[no_mangle]
+pub unsafe extern "C" fn afp_ring_close(ring: *mut AfpRing) {
+    if ring.is_null() {
+        return;
+    }
+    Box::from_raw(ring).ring.close();
+}
+
+///Returns the NUL-terminated name of `-errno` as returned by the other functions, for messages
+#[no_mangle]
+pub extern "C" fn afp_strerror(errno: c_int) -> *const c_char {
+    unsafe { libc::strerror(errno.saturating_abs()) as *const c_char }
+}
+
+fn ring_errno(e: &RingError) -> c_int {
+    -e.raw_os_error().unwrap_or(EINVAL)
+}
+
+fn io_errno(e: &io::Error) -> c_int {
+    -e.raw_os_error().unwrap_or(EIO)
+}
@@ -0,0 +1,121 @@
+//!Classic BPF socket filters, so the kernel drops unwanted packets before they reach the ring.
+//!Programs can be generated with `tcpdump -dd <expression>`, whose output maps one to one onto
+//!`SockFilter::new`
+
+#[cfg(target_os = "linux")]
+use std::io::{self, Error, ErrorKind};
+#[cfg(target_os = "linux")]
+use std::mem;
+
+#[cfg(target_os = "linux")]
+use libc::{c_int, c_void, setsockopt, socklen_t, SOL_SOCKET, SO_ATTACH_FILTER, SO_DETACH_FILTER};
+
+///One classic BPF instruction, laid out like the kernel's struct sock_filter
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SockFilter {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+impl SockFilter {
+    pub const fn new(code: u16, jt: u8, jf: u8, k: u32) -> SockFilter {
+        SockFilter { code, jt, jf, k }
+    }
+}
+
+///Drops packets sent by this host, using the packet type ancillary load. Equivalent to
+///PACKET_IGNORE_OUTGOING on kernels that lack it
+pub const DROP_OUTGOING: [SockFilter; 4] = [
+    SockFilter::new(0x30, 0, 0, 0xffff_f004), //ldb [pkttype]
+    SockFilter::new(0x15, 0, 1, 4),           //jeq #PACKET_OUTGOING
+    SockFilter::new(0x06, 0, 0, 0),           //ret #0
+    SockFilter::new(0x06, 0, 0, 0xffff_ffff), //ret #-1
+];
+
+//struct sock_fprog
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct SockFprog {
+    len: u16,
+    filter: *const SockFilter,
+}
+
+#[cfg(target_os = "linux")]
+impl SockFprog {
+    fn new(prog: &[SockFilter]) -> io::Result<SockFprog> {
+        if prog.is_empty() || prog.len() > u16::MAX as usize {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "a BPF program needs between 1 and 65535 instructions",
+            ));
+        }
+        Ok(SockFprog {
+            len: prog.len() as u16,
+            filter: prog.as_ptr(),
+        })
+    }
+}
+
+///Attaches `prog` to the socket, replacing any filter already attached
+#[cfg(target_os = "linux")]
+pub fn attach_filter(fd: c_int, prog: &[SockFilter]) -> io::Result<()> {
+    let fprog = SockFprog::new(prog)?;
+    match unsafe {
+        setsockopt(
+            fd,
+            SOL_SOCKET,
+            SO_ATTACH_FILTER,
+            &fprog as *const SockFprog as *const c_void,
+            mem::size_of::<SockFprog>() as socklen_t,
+        )
+    } {
+        0 => {
+            trace_event!(
+                Debug,
+                "fd {}: attached a {} instruction filter",
+                fd,
+                prog.len()
+            );
+            Ok(())
+        }
+        _ => {
+            let e = Error::last_os_error();
+            trace_event!(
+                Warn,
+                "fd {}: attaching a {} instruction filter failed: {}",
+                fd,
+                prog.len(),
+                e
+            );
+            Err(e)
+        }
+    }
+}
+
+///Removes the socket's filter, so it sees every packet again
+#[cfg(target_os = "linux")]
+pub fn detach_filter(fd: c_int) -> io::Result<()> {
+    let unused: c_int = 0;
+    match unsafe {
+        setsockopt(
+            fd,
+            SOL_SOCKET,
+            SO_DETACH_FILTER,
+            &unused as *const c_int as *const c_void,
+            mem::size_of::<c_int>() as socklen_t,
+        )
+    } {
+        0 => {
+            trace_event!(Debug, "fd {}: detached its filter", fd);
+            Ok(())
+        }
+        _ => {
+            let e = Error::last_os_error();
+            trace_event!(Warn, "fd {}: detaching its filter failed: {}", fd, e);
+            Err(e)
+        }
+    }
+}
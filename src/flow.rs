@@ -0,0 +1,131 @@
+//!Flow identification, for bucketing packets by flow in userspace when the NIC's RX hash is
+//!missing or isn't symmetric
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+pub const IPPROTO_TCP: u8 = 6;
+pub const IPPROTO_UDP: u8 = 17;
+pub const IPPROTO_SCTP: u8 = 132;
+
+///Addresses, ports and protocol of a packet. Ports are 0 for protocols without them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FiveTuple {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub protocol: u8,
+}
+
+impl FiveTuple {
+    ///Parses the tuple from a packet starting at its IPv4 or IPv6 header. Fragments after the
+    ///first carry no ports, so they get 0 like protocols without ports
+    pub fn from_l3(l3: &[u8]) -> Option<FiveTuple> {
+        match l3.first()? >> 4 {
+            4 => {
+                let ihl = ((l3[0] & 0x0f) as usize) * 4;
+                if ihl < 20 || l3.len() < ihl {
+                    return None;
+                }
+                let frag_offset = u16::from_be_bytes([l3[6], l3[7]]) & 0x1fff;
+                let protocol = l3[9];
+                let src = Ipv4Addr::new(l3[12], l3[13], l3[14], l3[15]);
+                let dst = Ipv4Addr::new(l3[16], l3[17], l3[18], l3[19]);
+                let (src_port, dst_port) = if frag_offset == 0 {
+                    ports(protocol, &l3[ihl..])
+                } else {
+                    (0, 0)
+                };
+                Some(FiveTuple {
+                    src: IpAddr::V4(src),
+                    dst: IpAddr::V4(dst),
+                    src_port,
+                    dst_port,
+                    protocol,
+                })
+            }
+            6 => {
+                if l3.len() < 40 {
+                    return None;
+                }
+                let protocol = l3[6];
+                let mut src = [0u8; 16];
+                let mut dst = [0u8; 16];
+                src.copy_from_slice(&l3[8..24]);
+                dst.copy_from_slice(&l3[24..40]);
+                //extension headers are not walked, flows using them hash without ports
+                let (src_port, dst_port) = ports(protocol, &l3[40..]);
+                Some(FiveTuple {
+                    src: IpAddr::V6(Ipv6Addr::from(src)),
+                    dst: IpAddr::V6(Ipv6Addr::from(dst)),
+                    src_port,
+                    dst_port,
+                    protocol,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    ///Returns the tuple with source and destination swapped
+    pub fn reversed(&self) -> FiveTuple {
+        FiveTuple {
+            src: self.dst,
+            dst: self.src,
+            src_port: self.dst_port,
+            dst_port: self.src_port,
+            protocol: self.protocol,
+        }
+    }
+
+    ///Hash that is the same for both directions of a flow
+    pub fn symmetric_hash(&self) -> u32 {
+        let a = (self.src, self.src_port);
+        let b = (self.dst, self.dst_port);
+        let (lo, hi) = if a <= b { (a, b) } else { (b, a) };
+
+        let mut h = Fnv1a::new();
+        h.write_addr(lo.0);
+        h.write(&lo.1.to_be_bytes());
+        h.write_addr(hi.0);
+        h.write(&hi.1.to_be_bytes());
+        h.write(&[self.protocol]);
+        h.finish()
+    }
+}
+
+fn ports(protocol: u8, l4: &[u8]) -> (u16, u16) {
+    match protocol {
+        IPPROTO_TCP | IPPROTO_UDP | IPPROTO_SCTP if l4.len() >= 4 => (
+            u16::from_be_bytes([l4[0], l4[1]]),
+            u16::from_be_bytes([l4[2], l4[3]]),
+        ),
+        _ => (0, 0),
+    }
+}
+
+struct Fnv1a(u32);
+
+impl Fnv1a {
+    fn new() -> Fnv1a {
+        Fnv1a(0x811c_9dc5)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u32::from(*b);
+            self.0 = self.0.wrapping_mul(0x0100_0193);
+        }
+    }
+
+    fn write_addr(&mut self, addr: IpAddr) {
+        match addr {
+            IpAddr::V4(a) => self.write(&a.octets()),
+            IpAddr::V6(a) => self.write(&a.octets()),
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        self.0
+    }
+}
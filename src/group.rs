@@ -0,0 +1,438 @@
+//!Fanout groups of rings on one or more interfaces, each ring drained by its own thread pinned to a
+//!CPU. This is the thread-per-ring setup most applications build around `rx::Ring`.
+
+use std::io;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use libc::{close, cpu_set_t, getpid, sched_setaffinity, CPU_SET, CPU_SETSIZE};
+
+use error::RingError;
+use ethtool;
+use rx::{RawPacket, Ring, RingLabel, RingSettings, RingTotals};
+use shutdown::ShutdownToken;
+use socket::{Socket, PF_PACKET};
+use tpacket3::TpacketStatsV3;
+
+//the shutdown token wakes waiting workers, this only bounds each wait
+const WAIT_INTERVAL: Duration = Duration::from_secs(1);
+
+///Rings sharing one fanout group, each owned by a worker thread
+pub struct RingGroup {
+    labels: Vec<RingLabel>,
+    cpus: Vec<usize>,
+    workers: Vec<JoinHandle<FinalStats>>,
+    shutdown: ShutdownToken,
+    restarts: Arc<AtomicU64>,
+}
+
+type IncidentHook = dyn Fn(&Incident) + Send + Sync;
+
+///What a worker's ring saw, returned by `RingGroup::shutdown`
+#[derive(Clone, Debug)]
+pub struct FinalStats {
+    pub label: RingLabel,
+    ///Summed over every ring the worker had, if it was restarted
+    pub totals: RingTotals,
+    ///Kernel counters since they were last read, from the last ring only. `None` if it couldn't
+    ///be read
+    pub stats: Option<TpacketStatsV3>,
+}
+
+///Something that happened to a worker of a group made with `RingGroup::spawn_supervised`
+#[derive(Debug)]
+pub struct Incident {
+    pub label: RingLabel,
+    pub kind: IncidentKind,
+}
+
+#[derive(Debug)]
+pub enum IncidentKind {
+    ///The handler panicked with this message. The ring is closed and reopened
+    Panicked(String),
+    ///Receiving failed because the interface went down or was removed. The ring is closed and
+    ///reopened, which waits for the interface to come back
+    InterfaceDown(RingError),
+    ///Opening the new ring failed. It is retried after a delay until the group stops
+    ReopenFailed(RingError),
+    ///The worker is running again on a new ring
+    Restarted,
+}
+
+impl RingGroup {
+    ///Opens `num_threads` rings with `settings`, numbered from 0 in `index`, and spawns a thread
+    ///for each that calls `handler` on every packet. With 0, `default_thread_count` is used.
+    ///The threads are named after the ring label and thread `i` is pinned to CPU `i` modulo the
+    ///CPUs available. All rings are open before any thread starts, so a failure to open one is
+    ///returned here and nothing is left running
+    pub fn spawn<F>(settings: RingSettings, num_threads: usize, handler: F) -> io::Result<RingGroup>
+    where
+        F: Fn(&RingLabel, &RawPacket<'_>) + Send + Sync + 'static,
+    {
+        RingGroup::spawn_inner(vec![(settings, num_threads)], handler, None)
+    }
+
+    ///Like `spawn`, but opens rings on several interfaces, a thread count for each, and hands the
+    ///packets of all of them to the one `handler`. The label passed with each packet names the
+    ///interface it came from. Interfaces without a `fanout_group` get one of their own, as a
+    ///fanout group can't span interfaces
+    pub fn spawn_multi<F>(
+        interfaces: Vec<(RingSettings, usize)>,
+        handler: F,
+    ) -> io::Result<RingGroup>
+    where
+        F: Fn(&RingLabel, &RawPacket<'_>) + Send + Sync + 'static,
+    {
+        RingGroup::spawn_inner(interfaces, handler, None)
+    }
+
+    ///Like `spawn_multi`, but a worker whose handler panics, or whose interface goes down or
+    ///away, gets a new ring with the same settings, which rejoins the fanout group, and carries
+    ///on. Each panic, interface loss, failed reopen and restart is passed to `on_incident` from
+    ///the worker's thread
+    pub fn spawn_supervised<F, I>(
+        interfaces: Vec<(RingSettings, usize)>,
+        handler: F,
+        on_incident: I,
+    ) -> io::Result<RingGroup>
+    where
+        F: Fn(&RingLabel, &RawPacket<'_>) + Send + Sync + 'static,
+        I: Fn(&Incident) + Send + Sync + 'static,
+    {
+        RingGroup::spawn_inner(interfaces, handler, Some(Arc::new(on_incident)))
+    }
+
+    fn spawn_inner<F>(
+        interfaces: Vec<(RingSettings, usize)>,
+        handler: F,
+        on_incident: Option<Arc<IncidentHook>>,
+    ) -> io::Result<RingGroup>
+    where
+        F: Fn(&RingLabel, &RawPacket<'_>) + Send + Sync + 'static,
+    {
+        //made first so failing to make it doesn't leave rings open
+        let shutdown = ShutdownToken::new()?;
+        let multi = interfaces.len() > 1;
+        let mut rings = Vec::new();
+        for (pos, (mut settings, num_threads)) in interfaces.into_iter().enumerate() {
+            if multi && settings.fanout_group.is_none() {
+                let pid = unsafe { getpid() } as usize;
+                settings.fanout_group = Some((pid.wrapping_add(pos) & 0xFFFF) as u16);
+            }
+            let num_threads = match num_threads {
+                0 => RingGroup::default_thread_count(&settings),
+                n => n,
+            };
+            for i in 0..num_threads {
+                let settings = RingSettings {
+                    index: i as u32,
+                    ..settings.clone()
+                };
+                match Ring::new(settings.clone()) {
+                    Ok(ring) => rings.push((ring, settings)),
+                    Err(e) => {
+                        rings.into_iter().for_each(|(r, _)| r.close());
+                        return Err(e.into());
+                    }
+                }
+            }
+        }
+        let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+        let handler = Arc::new(handler);
+        let mut group = RingGroup {
+            labels: rings.iter().map(|r| r.0.label().clone()).collect(),
+            cpus: (0..rings.len()).map(|i| i % cpus).collect(),
+            workers: Vec::with_capacity(rings.len()),
+            shutdown: shutdown.clone(),
+            restarts: Arc::new(AtomicU64::new(0)),
+        };
+        let mut pending = rings.into_iter().zip(group.cpus.clone());
+        while let Some(((ring, settings), cpu)) = pending.next() {
+            //the worker takes its ring from here, so it can still be closed if the thread never
+            //starts and the closure is dropped
+            let name = ring.label().to_string();
+            let slot = Arc::new(Mutex::new(Some(ring)));
+            let taken = slot.clone();
+            let handler = handler.clone();
+            let shutdown = shutdown.clone();
+            let supervisor = on_incident.clone().map(|hook| Supervisor {
+                settings,
+                hook,
+                restarts: group.restarts.clone(),
+            });
+            let worker = thread::Builder::new().name(name).spawn(move || {
+                let ring = taken
+                    .lock()
+                    .unwrap()
+                    .take()
+                    .expect("ring left for the worker");
+                pin_to_cpu(ring.label(), cpu);
+                match supervisor {
+                    Some(s) => s.run(ring, &*handler, &shutdown),
+                    None => {
+                        let mut ring = ring;
+                        //the ring sees packets again once the interface is back up
+                        while let Err(_e) = run(&mut ring, &*handler, &shutdown) {
+                            trace_event!(Warn, "ring {}: {}", ring.label(), _e);
+                        }
+                        finish(ring)
+                    }
+                }
+            });
+            match worker {
+                Ok(w) => group.workers.push(w),
+                Err(e) => {
+                    if let Some(ring) = slot.lock().unwrap().take() {
+                        ring.close();
+                    }
+                    pending.for_each(|((ring, _), _)| ring.close());
+                    group.stop();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(group)
+    }
+
+    ///Returns the number of receive queues of the interface, so each queue's flows go to one
+    ///thread, or the number of CPUs if the driver doesn't report queues
+    pub fn default_thread_count(settings: &RingSettings) -> usize {
+        let queues = Socket::from_if_name(&settings.if_name, PF_PACKET)
+            .and_then(|sock| {
+                let channels = ethtool::get_channels(&sock);
+                unsafe { close(sock.fd) };
+                channels
+            })
+            .map(|c| c.rx_queues() as usize);
+        match queues {
+            Ok(n) if n > 0 => n,
+            _ => thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    ///Returns the labels of the rings, in index order
+    pub fn labels(&self) -> &[RingLabel] {
+        &self.labels
+    }
+
+    ///Returns the CPU each ring's thread was pinned to, in index order, e.g. for
+    ///`affinity::align`
+    pub fn cpus(&self) -> &[usize] {
+        &self.cpus
+    }
+
+    ///Returns the number of times supervised workers got a new ring
+    pub fn restarts(&self) -> u64 {
+        self.restarts.load(Ordering::Relaxed)
+    }
+
+    ///Returns the number of rings in the group
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    ///Returns true if the group has no rings
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    ///Asks every worker to finish the block it is on and exit, without waiting for them
+    pub fn stop(&self) {
+        self.shutdown.trigger();
+    }
+
+    ///Returns the token that stops the group, which can be triggered from anywhere, including a
+    ///SIGTERM handler, to wake and stop every worker at once
+    pub fn shutdown_token(&self) -> ShutdownToken {
+        self.shutdown.clone()
+    }
+
+    ///Stops the workers and waits for them, returning each ring's final statistics. Workers finish
+    ///the block they are handling, and their rings are closed, restoring promiscuous mode and
+    ///offloads, before this returns. Returns the panic of the first worker that panicked, if any
+    pub fn shutdown(mut self) -> thread::Result<Vec<FinalStats>> {
+        self.stop();
+        let mut stats = Vec::with_capacity(self.workers.len());
+        let mut result = Ok(());
+        for worker in mem::take(&mut self.workers) {
+            match worker.join() {
+                Ok(s) => stats.push(s),
+                Err(e) => {
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        result.map(|()| stats)
+    }
+
+    ///Stops the workers and waits for them to exit. Returns the panic of the first worker that
+    ///panicked, if any did
+    pub fn join(self) -> thread::Result<()> {
+        self.shutdown().map(|_| ())
+    }
+}
+
+impl Drop for RingGroup {
+    fn drop(&mut self) {
+        self.stop();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+//hands packets to `handler` until the group stops, or returns the error if the interface went
+//down or away. Other receive errors are logged and the worker carries on
+fn run<F>(ring: &mut Ring, handler: &F, shutdown: &ShutdownToken) -> Result<(), RingError>
+where
+    F: Fn(&RingLabel, &RawPacket<'_>),
+{
+    let label = ring.label().clone();
+    ring.set_shutdown(shutdown.clone());
+    while !shutdown.is_triggered() {
+        match ring.try_get_block(Some(WAIT_INTERVAL)) {
+            Ok(Some(mut block)) => {
+                for packet in block.get_raw_packets() {
+                    handler(&label, &packet);
+                }
+                block.mark_as_consumed();
+            }
+            Ok(None) => {}
+            Err(e) if e.is_interface_down() => return Err(e),
+            Err(_e) => trace_event!(Warn, "ring {}: {}", label, _e),
+        }
+    }
+    Ok(())
+}
+
+//reads the ring's last kernel statistics and closes it
+fn finish(ring: Ring) -> FinalStats {
+    let stats = ring.get_rx_statistics().ok().map(|s| s.stats);
+    let last = FinalStats {
+        label: ring.label().clone(),
+        totals: ring.totals(),
+        stats,
+    };
+    ring.close();
+    last
+}
+
+fn add_totals(a: RingTotals, b: RingTotals) -> RingTotals {
+    RingTotals {
+        blocks: a.blocks + b.blocks,
+        packets: a.packets + b.packets,
+        bytes: a.bytes + b.bytes,
+        missed_blocks: a.missed_blocks + b.missed_blocks,
+        malformed_blocks: a.malformed_blocks + b.malformed_blocks,
+        malformed_frames: a.malformed_frames + b.malformed_frames,
+    }
+}
+
+//what a supervised worker needs to replace its ring
+struct Supervisor {
+    settings: RingSettings,
+    hook: Arc<IncidentHook>,
+    restarts: Arc<AtomicU64>,
+}
+
+impl Supervisor {
+    fn run<F>(&self, ring: Ring, handler: &F, shutdown: &ShutdownToken) -> FinalStats
+    where
+        F: Fn(&RingLabel, &RawPacket<'_>),
+    {
+        let label = ring.label().clone();
+        let mut ring = ring;
+        let mut totals = RingTotals::default();
+        loop {
+            //a panic leaves the block it was in leased, and an interface that went away leaves a
+            //socket bound to nothing, so the ring is replaced rather than reused
+            let result =
+                panic::catch_unwind(AssertUnwindSafe(|| run(&mut ring, handler, shutdown)));
+            let incident = match result {
+                Ok(Ok(())) => {
+                    let mut last = finish(ring);
+                    last.totals = add_totals(totals, last.totals);
+                    return last;
+                }
+                Ok(Err(e)) => {
+                    trace_event!(Error, "ring {}: interface down: {}", label, e);
+                    IncidentKind::InterfaceDown(e)
+                }
+                Err(payload) => {
+                    let message = payload
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    trace_event!(Error, "ring {}: handler panicked: {}", label, message);
+                    IncidentKind::Panicked(message)
+                }
+            };
+            totals = add_totals(totals, ring.totals());
+            ring.close();
+            self.report(&label, incident);
+            ring = match self.reopen(&label, shutdown) {
+                Some(r) => r,
+                None => {
+                    return FinalStats {
+                        label,
+                        totals,
+                        stats: None,
+                    }
+                }
+            };
+        }
+    }
+
+    fn reopen(&self, label: &RingLabel, shutdown: &ShutdownToken) -> Option<Ring> {
+        let mut delay = Duration::from_millis(100);
+        while !shutdown.is_triggered() {
+            match Ring::new(self.settings.clone()) {
+                Ok(ring) => {
+                    self.restarts.fetch_add(1, Ordering::Relaxed);
+                    self.report(label, IncidentKind::Restarted);
+                    return Some(ring);
+                }
+                Err(e) => {
+                    self.report(label, IncidentKind::ReopenFailed(e));
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(Duration::from_secs(5));
+                }
+            }
+        }
+        None
+    }
+
+    fn report(&self, label: &RingLabel, kind: IncidentKind) {
+        (self.hook)(&Incident {
+            label: label.clone(),
+            kind,
+        });
+    }
+}
+
+//pinning is best effort, e.g. a cgroup may not allow the CPU
+fn pin_to_cpu(_label: &RingLabel, cpu: usize) {
+    if cpu >= CPU_SETSIZE as usize {
+        return;
+    }
+    unsafe {
+        let mut set: cpu_set_t = mem::zeroed();
+        CPU_SET(cpu, &mut set);
+        if sched_setaffinity(0, mem::size_of::<cpu_set_t>(), &set) != 0 {
+            trace_event!(
+                Warn,
+                "ring {}: could not pin to CPU {}: {}",
+                _label,
+                cpu,
+                io::Error::last_os_error()
+            );
+        }
+    }
+}
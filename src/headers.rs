@@ -0,0 +1,331 @@
+//!Parsed views of the common Ethernet, IP, TCP and UDP headers, for applications that only need
+//!the basics and don't want to wire up a separate protocol parsing crate
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use nom::number::complete::{be_u16, be_u32, be_u8};
+
+use flow::{IPPROTO_TCP, IPPROTO_UDP};
+use vlan::VlanStack;
+
+#[derive(Clone, Debug)]
+pub struct EthernetHeader {
+    pub dst: [u8; 6],
+    pub src: [u8; 6],
+    ///Ethertype after any VLAN tags still present in the frame
+    pub ethertype: u16,
+}
+
+#[derive(Clone, Debug)]
+pub struct Ipv4Header {
+    ///Header length in bytes
+    pub header_len: u8,
+    pub tos: u8,
+    pub total_len: u16,
+    pub id: u16,
+    ///Flags in the top 3 bits, fragment offset in 8-byte units in the rest
+    pub flags_frag_offset: u16,
+    pub ttl: u8,
+    pub protocol: u8,
+    pub checksum: u16,
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+}
+
+impl Ipv4Header {
+    #[inline]
+    pub fn dont_fragment(&self) -> bool {
+        self.flags_frag_offset & 0x4000 != 0
+    }
+
+    #[inline]
+    pub fn more_fragments(&self) -> bool {
+        self.flags_frag_offset & 0x2000 != 0
+    }
+
+    ///Fragment offset in bytes
+    #[inline]
+    pub fn fragment_offset(&self) -> u16 {
+        (self.flags_frag_offset & 0x1fff) * 8
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Ipv6Header {
+    pub traffic_class: u8,
+    pub flow_label: u32,
+    pub payload_len: u16,
+    pub next_header: u8,
+    pub hop_limit: u8,
+    pub src: Ipv6Addr,
+    pub dst: Ipv6Addr,
+}
+
+#[derive(Clone, Debug)]
+pub enum IpHeader {
+    V4(Ipv4Header),
+    V6(Ipv6Header),
+}
+
+impl IpHeader {
+    ///IP protocol of the payload. For IPv6 this is the first next header, extension headers are
+    ///not walked
+    #[inline]
+    pub fn protocol(&self) -> u8 {
+        match *self {
+            IpHeader::V4(ref h) => h.protocol,
+            IpHeader::V6(ref h) => h.next_header,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TcpHeader {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    ///Header length in bytes
+    pub header_len: u8,
+    ///FIN, SYN, RST, PSH, ACK, URG, ECE, CWR from the lowest bit up
+    pub flags: u8,
+    pub window: u16,
+    pub checksum: u16,
+    pub urgent_ptr: u16,
+}
+
+impl TcpHeader {
+    #[inline]
+    pub fn fin(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+
+    #[inline]
+    pub fn syn(&self) -> bool {
+        self.flags & 0x02 != 0
+    }
+
+    #[inline]
+    pub fn rst(&self) -> bool {
+        self.flags & 0x04 != 0
+    }
+
+    #[inline]
+    pub fn ack_flag(&self) -> bool {
+        self.flags & 0x10 != 0
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UdpHeader {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub length: u16,
+    pub checksum: u16,
+}
+
+#[derive(Clone, Debug)]
+pub enum TransportHeader {
+    Tcp(TcpHeader),
+    Udp(UdpHeader),
+}
+
+///Headers parsed from a packet. Each layer is `None` if it was missing, truncated, or of a type
+///that isn't parsed, and parsing stops at the first layer that couldn't be read
+#[derive(Clone, Debug)]
+pub struct Headers<'a> {
+    pub ethernet: Option<EthernetHeader>,
+    pub ip: Option<IpHeader>,
+    pub transport: Option<TransportHeader>,
+    ///Bytes after the last header that was parsed
+    pub payload: &'a [u8],
+}
+
+impl<'a> Headers<'a> {
+    ///Parses a frame that starts at its Ethernet header
+    pub fn from_ethernet(l2: &'a [u8]) -> Headers<'a> {
+        let mut stack = VlanStack::new(l2, None);
+        while stack.next().is_some() {}
+        let ethernet = match (l2.get(..12), stack.ethertype()) {
+            (Some(macs), Some(ethertype)) => {
+                let mut dst = [0u8; 6];
+                let mut src = [0u8; 6];
+                dst.copy_from_slice(&macs[..6]);
+                src.copy_from_slice(&macs[6..]);
+                Some(EthernetHeader {
+                    dst,
+                    src,
+                    ethertype,
+                })
+            }
+            _ => None,
+        };
+        match ethernet {
+            Some(eth) => {
+                let mut headers = Headers::from_ip(&l2[stack.ethertype_offset() + 2..]);
+                headers.ethernet = Some(eth);
+                headers
+            }
+            None => Headers {
+                ethernet: None,
+                ip: None,
+                transport: None,
+                payload: l2,
+            },
+        }
+    }
+
+    ///Parses a packet that starts at its IPv4 or IPv6 header
+    pub fn from_ip(l3: &'a [u8]) -> Headers<'a> {
+        let mut headers = Headers {
+            ethernet: None,
+            ip: None,
+            transport: None,
+            payload: l3,
+        };
+
+        let (rest, ip) = match l3.first().map(|b| b >> 4) {
+            Some(4) => match get_ipv4_header(l3) {
+                Ok((_, h))
+                    if (h.header_len as usize) >= 20 && (h.header_len as usize) <= l3.len() =>
+                {
+                    let rest = &l3[h.header_len as usize..];
+                    (rest, IpHeader::V4(h))
+                }
+                _ => return headers,
+            },
+            Some(6) => match get_ipv6_header(l3) {
+                Ok((rest, h)) => (rest, IpHeader::V6(h)),
+                _ => return headers,
+            },
+            _ => return headers,
+        };
+
+        //only the first fragment carries the transport header
+        let first_fragment = match ip {
+            IpHeader::V4(ref h) => h.fragment_offset() == 0,
+            IpHeader::V6(_) => true,
+        };
+        let protocol = ip.protocol();
+        headers.ip = Some(ip);
+        headers.payload = rest;
+        if !first_fragment {
+            return headers;
+        }
+
+        match protocol {
+            IPPROTO_TCP => {
+                if let Ok((_, h)) = get_tcp_header(rest) {
+                    if (h.header_len as usize) >= 20 && (h.header_len as usize) <= rest.len() {
+                        headers.payload = &rest[h.header_len as usize..];
+                        headers.transport = Some(TransportHeader::Tcp(h));
+                    }
+                }
+            }
+            IPPROTO_UDP => {
+                if let Ok((payload, h)) = get_udp_header(rest) {
+                    headers.payload = payload;
+                    headers.transport = Some(TransportHeader::Udp(h));
+                }
+            }
+            _ => {}
+        }
+        headers
+    }
+}
+
+named!(
+    get_ipv4_header<Ipv4Header>,
+    do_parse!(
+        version_ihl: be_u8
+            >> tos: be_u8
+            >> total_len: be_u16
+            >> id: be_u16
+            >> flags_frag_offset: be_u16
+            >> ttl: be_u8
+            >> protocol: be_u8
+            >> checksum: be_u16
+            >> src: be_u32
+            >> dst: be_u32
+            >> (Ipv4Header {
+                header_len: (version_ihl & 0x0f) * 4,
+                tos,
+                total_len,
+                id,
+                flags_frag_offset,
+                ttl,
+                protocol,
+                checksum,
+                src: Ipv4Addr::from(src),
+                dst: Ipv4Addr::from(dst)
+            })
+    )
+);
+
+named!(
+    get_ipv6_header<Ipv6Header>,
+    do_parse!(
+        vtf: be_u32
+            >> payload_len: be_u16
+            >> next_header: be_u8
+            >> hop_limit: be_u8
+            >> src: take!(16)
+            >> dst: take!(16)
+            >> (Ipv6Header {
+                traffic_class: (vtf >> 20) as u8,
+                flow_label: vtf & 0x000f_ffff,
+                payload_len,
+                next_header,
+                hop_limit,
+                src: ipv6_addr(src),
+                dst: ipv6_addr(dst)
+            })
+    )
+);
+
+named!(
+    get_tcp_header<TcpHeader>,
+    do_parse!(
+        src_port: be_u16
+            >> dst_port: be_u16
+            >> seq: be_u32
+            >> ack: be_u32
+            >> offset_flags: be_u16
+            >> window: be_u16
+            >> checksum: be_u16
+            >> urgent_ptr: be_u16
+            >> (TcpHeader {
+                src_port,
+                dst_port,
+                seq,
+                ack,
+                header_len: ((offset_flags >> 12) * 4) as u8,
+                flags: offset_flags as u8,
+                window,
+                checksum,
+                urgent_ptr
+            })
+    )
+);
+
+named!(
+    get_udp_header<UdpHeader>,
+    do_parse!(
+        src_port: be_u16
+            >> dst_port: be_u16
+            >> length: be_u16
+            >> checksum: be_u16
+            >> (UdpHeader {
+                src_port,
+                dst_port,
+                length,
+                checksum
+            })
+    )
+);
+
+fn ipv6_addr(b: &[u8]) -> Ipv6Addr {
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(b);
+    Ipv6Addr::from(octets)
+}
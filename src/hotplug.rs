@@ -0,0 +1,285 @@
+//!Watches rtnetlink link notifications (RTMGRP_LINK) to notice the capture interface going down
+//!or away, as USB NICs and hotplugged VM interfaces do, and to bring the capture back when the
+//!interface returns. A ring on a removed interface just stops receiving, it never errors.
+
+use std::io::{self, Error};
+use std::mem;
+use std::time::Duration;
+
+use libc::{
+    bind, c_int, c_void, close, poll, pollfd, recv, sa_family_t, sockaddr, socket, socklen_t,
+    AF_NETLINK, EAGAIN, EINTR, MSG_DONTWAIT, POLLIN, SOCK_CLOEXEC, SOCK_RAW,
+};
+
+use rx::{Ring, RingLabel, RingSettings};
+use socket::IfFlags;
+
+const NETLINK_ROUTE: c_int = 0;
+const RTMGRP_LINK: u32 = 1;
+
+const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+const IFLA_IFNAME: u16 = 3;
+
+//struct nlmsghdr
+const NLMSG_HDRLEN: usize = 16;
+//struct ifinfomsg
+const IFINFO_LEN: usize = 16;
+//struct rtattr
+const RTA_HDRLEN: usize = 4;
+
+//struct sockaddr_nl
+#[repr(C)]
+struct SockaddrNl {
+    nl_family: sa_family_t,
+    nl_pad: u16,
+    nl_pid: u32,
+    nl_groups: u32,
+}
+
+///A change to an interface, from RTM_NEWLINK or RTM_DELLINK
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkEvent {
+    pub index: u32,
+    pub name: String,
+    pub flags: IfFlags,
+    ///The interface was removed, rather than changed or added
+    pub removed: bool,
+}
+
+impl LinkEvent {
+    ///Returns true if the interface is up and has carrier, so a capture on it can receive
+    #[inline]
+    pub fn is_usable(&self) -> bool {
+        !self.removed && self.flags.contains(IfFlags::UP | IfFlags::RUNNING)
+    }
+}
+
+///A netlink socket subscribed to link notifications for every interface. Needs no privileges
+#[derive(Debug)]
+pub struct LinkWatcher {
+    fd: c_int,
+    buf: Vec<u8>,
+    pending: Vec<LinkEvent>,
+}
+
+impl LinkWatcher {
+    pub fn new() -> io::Result<LinkWatcher> {
+        let fd = unsafe { socket(AF_NETLINK, SOCK_RAW | SOCK_CLOEXEC, NETLINK_ROUTE) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        let mut sa: SockaddrNl = unsafe { mem::zeroed() };
+        sa.nl_family = AF_NETLINK as sa_family_t;
+        sa.nl_groups = RTMGRP_LINK;
+        if unsafe {
+            bind(
+                fd,
+                &sa as *const _ as *const sockaddr,
+                mem::size_of::<SockaddrNl>() as socklen_t,
+            )
+        } < 0
+        {
+            let e = Error::last_os_error();
+            unsafe { close(fd) };
+            return Err(e);
+        }
+        Ok(LinkWatcher {
+            fd,
+            buf: vec![0; 16384],
+            pending: Vec::new(),
+        })
+    }
+
+    ///Returns the next link event, waiting up to `timeout` for one, or forever with `None`.
+    ///Returns `Ok(None)` if none arrived in time
+    pub fn next_event(&mut self, timeout: Option<Duration>) -> io::Result<Option<LinkEvent>> {
+        loop {
+            if !self.pending.is_empty() {
+                return Ok(Some(self.pending.remove(0)));
+            }
+            let mut pfd = pollfd {
+                fd: self.fd,
+                events: POLLIN,
+                revents: 0,
+            };
+            let ms = timeout.map_or(-1, |t| t.as_millis().min(c_int::MAX as u128) as c_int);
+            match unsafe { poll(&mut pfd, 1, ms) } {
+                0 => return Ok(None),
+                n if n < 0 => {
+                    let e = Error::last_os_error();
+                    if e.raw_os_error() == Some(EINTR) {
+                        continue;
+                    }
+                    return Err(e);
+                }
+                _ => (),
+            }
+            let n = unsafe {
+                recv(
+                    self.fd,
+                    self.buf.as_mut_ptr() as *mut c_void,
+                    self.buf.len(),
+                    MSG_DONTWAIT,
+                )
+            };
+            if n < 0 {
+                let e = Error::last_os_error();
+                match e.raw_os_error() {
+                    Some(EINTR) | Some(EAGAIN) => continue,
+                    //ENOBUFS means notifications were dropped, the caller should recheck state
+                    _ => return Err(e),
+                }
+            }
+            parse_messages(&self.buf[..n as usize], &mut self.pending);
+        }
+    }
+}
+
+impl Drop for LinkWatcher {
+    fn drop(&mut self) {
+        unsafe { close(self.fd) };
+    }
+}
+
+fn read_u16(b: &[u8], at: usize) -> u16 {
+    u16::from_ne_bytes([b[at], b[at + 1]])
+}
+
+fn read_u32(b: &[u8], at: usize) -> u32 {
+    u32::from_ne_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+//netlink messages and attributes are padded to 4 bytes
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn parse_messages(mut buf: &[u8], out: &mut Vec<LinkEvent>) {
+    while buf.len() >= NLMSG_HDRLEN {
+        let len = read_u32(buf, 0) as usize;
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            return;
+        }
+        let kind = read_u16(buf, 4);
+        if kind == RTM_NEWLINK || kind == RTM_DELLINK {
+            if let Some(e) = parse_link(&buf[NLMSG_HDRLEN..len], kind == RTM_DELLINK) {
+                out.push(e);
+            }
+        }
+        buf = &buf[align(len).min(buf.len())..];
+    }
+}
+
+fn parse_link(msg: &[u8], removed: bool) -> Option<LinkEvent> {
+    if msg.len() < IFINFO_LEN {
+        return None;
+    }
+    let index = read_u32(msg, 4);
+    let flags = IfFlags::from_bits_truncate(read_u32(msg, 8) as u16);
+    let mut attrs = &msg[IFINFO_LEN..];
+    let mut name = None;
+    while attrs.len() >= RTA_HDRLEN {
+        let len = read_u16(attrs, 0) as usize;
+        if len < RTA_HDRLEN || len > attrs.len() {
+            break;
+        }
+        if read_u16(attrs, 2) == IFLA_IFNAME {
+            let value = &attrs[RTA_HDRLEN..len];
+            let end = value.iter().position(|&c| c == 0).unwrap_or(value.len());
+            name = Some(String::from_utf8_lossy(&value[..end]).into_owned());
+        }
+        attrs = &attrs[align(len).min(attrs.len())..];
+    }
+    Some(LinkEvent {
+        index,
+        name: name?,
+        flags,
+        removed,
+    })
+}
+
+///What happened to the capture interface, as reported by `Reattach::poll`
+#[derive(Debug)]
+pub enum LinkChange {
+    ///The interface lost carrier or was set down, the ring was closed
+    Down,
+    ///The interface was removed, the ring was closed
+    Removed,
+    ///The interface is usable again and a new ring was opened on it
+    Reattached,
+}
+
+///Keeps a ring open on an interface that may come and go. The ring is closed when the interface
+///goes down or is removed, and reopened with the same settings when it is back
+pub struct Reattach {
+    settings: RingSettings,
+    ring: Option<Ring>,
+    watcher: LinkWatcher,
+}
+
+impl Reattach {
+    ///Opens the ring. If the interface isn't there yet the ring is opened once it appears
+    pub fn new(settings: RingSettings) -> io::Result<Reattach> {
+        //subscribe first, so an interface appearing while the ring is opened isn't missed
+        let watcher = LinkWatcher::new()?;
+        let ring = match Ring::new(settings.clone()) {
+            Ok(r) => Some(r),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Reattach {
+            settings,
+            ring,
+            watcher,
+        })
+    }
+
+    ///Returns the ring, or `None` while the interface is down or missing. Clones of it must not
+    ///outlive the next `LinkChange`, as the ring is unmapped when the interface goes away
+    #[inline]
+    pub fn ring(&mut self) -> Option<&mut Ring> {
+        self.ring.as_mut()
+    }
+
+    ///Returns the label the ring has whenever it is open
+    pub fn label(&self) -> RingLabel {
+        RingLabel::from_settings(&self.settings)
+    }
+
+    ///Handles link events for the interface, waiting up to `timeout` for each. Returns the change
+    ///made to the ring, or `Ok(None)` if there was none. A failed reopen is returned as an error
+    ///and retried on the interface's next event
+    pub fn poll(&mut self, timeout: Option<Duration>) -> io::Result<Option<LinkChange>> {
+        while let Some(event) = self.watcher.next_event(timeout)? {
+            if event.name != self.settings.if_name {
+                continue;
+            }
+            if self.ring.is_some() && !event.is_usable() {
+                if let Some(ring) = self.ring.take() {
+                    ring.close();
+                }
+                trace_event!(Warn, "interface {} went away, ring closed", event.name);
+                return Ok(Some(if event.removed {
+                    LinkChange::Removed
+                } else {
+                    LinkChange::Down
+                }));
+            }
+            if self.ring.is_none() && event.is_usable() {
+                self.ring = Some(Ring::new(self.settings.clone())?);
+                trace_event!(Info, "interface {} is back, ring reopened", event.name);
+                return Ok(Some(LinkChange::Reattached));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Drop for Reattach {
+    fn drop(&mut self) {
+        if let Some(ring) = self.ring.take() {
+            ring.close();
+        }
+    }
+}
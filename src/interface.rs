@@ -0,0 +1,189 @@
+//!How an interface relates to the devices around it, read from sysfs. Captures on virtual
+//!interfaces see different frames than the device underneath: a VLAN subinterface (eth0.100)
+//!receives frames with their tag already removed, and a bond receives what each of its slaves
+//!received, reported under the bond's ifindex.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use socket;
+use vlan::{VlanTag, ETH_P_8021Q};
+
+///What kind of device an interface is, from DEVTYPE in its uevent
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InterfaceKind {
+    ///An 802.1Q subinterface of `parent`. `vid` is only known while the 8021q module exposes
+    ///`/proc/net/vlan`
+    Vlan { parent: String, vid: Option<u16> },
+    ///A bond aggregating `slaves`
+    Bond { slaves: Vec<String> },
+    ///Any other DEVTYPE, e.g. "bridge" or "veth"
+    Other(String),
+    ///A device without a DEVTYPE, usually a physical NIC
+    Plain,
+}
+
+///Operational state of an interface as in RFC 2863, from its operstate in sysfs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperState {
+    Up,
+    Down,
+    ///Up, but waiting for an external event such as 802.1X authentication
+    Dormant,
+    ///Down because a device it is stacked on is down
+    LowerLayerDown,
+    NotPresent,
+    Testing,
+    ///The driver doesn't report a state, common for virtual interfaces that still pass traffic
+    Unknown,
+}
+
+impl OperState {
+    ///Returns true if the interface can pass traffic. Unknown counts, as that is what
+    ///interfaces without carrier detection report
+    #[inline]
+    pub fn is_usable(self) -> bool {
+        self == OperState::Up || self == OperState::Unknown
+    }
+}
+
+///An interface and the interfaces it is stacked on or enslaved to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceInfo {
+    pub name: String,
+    pub index: u32,
+    pub kind: InterfaceKind,
+    ///ifindex of the device this one sends and receives through (iflink), if it isn't itself
+    pub lower_index: Option<u32>,
+    ///Bond or bridge this interface is enslaved to
+    pub master: Option<String>,
+}
+
+impl InterfaceInfo {
+    ///Reads the relationships of the named interface
+    pub fn from_if_name(name: &str) -> io::Result<InterfaceInfo> {
+        let index = read_u32(name, "ifindex")?;
+        let iflink = read_u32(name, "iflink")?;
+        let master = fs::read_link(sysfs(name).join("master"))
+            .ok()
+            .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()));
+        let uevent = fs::read_to_string(sysfs(name).join("uevent"))?;
+        let devtype = uevent
+            .lines()
+            .find(|l| l.starts_with("DEVTYPE="))
+            .map(|l| l["DEVTYPE=".len()..].to_string());
+        let kind = match devtype.as_deref() {
+            Some("vlan") => InterfaceKind::Vlan {
+                parent: socket::get_if_name(iflink)?,
+                vid: read_vid(name),
+            },
+            Some("bond") => InterfaceKind::Bond {
+                slaves: fs::read_to_string(sysfs(name).join("bonding/slaves"))?
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect(),
+            },
+            Some(other) => InterfaceKind::Other(other.to_string()),
+            None => InterfaceKind::Plain,
+        };
+        Ok(InterfaceInfo {
+            name: String::from(name),
+            index,
+            kind,
+            lower_index: if iflink != index { Some(iflink) } else { None },
+            master,
+        })
+    }
+
+    ///Returns the tag every frame captured on a VLAN subinterface had on the wire. The kernel
+    ///removes it before the subinterface sees the frame, so `RawPacket::vlan_tag` is `None` for
+    ///these packets even though the same frames captured on the parent carry the tag
+    pub fn vlan_tag(&self) -> Option<VlanTag> {
+        match self.kind {
+            InterfaceKind::Vlan { vid: Some(vid), .. } => Some(VlanTag {
+                tpid: ETH_P_8021Q,
+                tci: vid,
+            }),
+            _ => None,
+        }
+    }
+
+    ///Returns the physical interfaces whose traffic this one carries, following VLAN parents
+    ///and bond slaves
+    pub fn underlying(&self) -> io::Result<Vec<String>> {
+        match self.kind {
+            InterfaceKind::Vlan { ref parent, .. } => {
+                InterfaceInfo::from_if_name(parent)?.underlying()
+            }
+            InterfaceKind::Bond { ref slaves } => Ok(slaves.clone()),
+            _ => Ok(vec![self.name.clone()]),
+        }
+    }
+}
+
+///Reads the operational state of the named interface
+pub fn oper_state(name: &str) -> io::Result<OperState> {
+    Ok(
+        match fs::read_to_string(sysfs(name).join("operstate"))?.trim() {
+            "up" => OperState::Up,
+            "down" => OperState::Down,
+            "dormant" => OperState::Dormant,
+            "lowerlayerdown" => OperState::LowerLayerDown,
+            "notpresent" => OperState::NotPresent,
+            "testing" => OperState::Testing,
+            _ => OperState::Unknown,
+        },
+    )
+}
+
+///Waits until the named interface exists and is usable, checking with a backoff from 50ms up to
+///1s. Fails with `ErrorKind::TimedOut` if it isn't by `timeout`
+pub fn wait_for(name: &str, timeout: Duration) -> io::Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut delay = Duration::from_millis(50);
+    loop {
+        let state = oper_state(name);
+        match state {
+            Ok(s) if s.is_usable() => return Ok(()),
+            Ok(_) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            let why = match state {
+                Ok(s) => format!("still {:?}", s),
+                Err(_) => String::from("doesn't exist"),
+            };
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("interface {} {} after {:?}", name, why, timeout),
+            ));
+        }
+        trace_event!(Debug, "waiting for interface {}", name);
+        thread::sleep(delay.min(deadline - now));
+        delay = (delay * 2).min(Duration::from_secs(1));
+    }
+}
+
+fn sysfs(name: &str) -> PathBuf {
+    PathBuf::from("/sys/class/net").join(name)
+}
+
+fn read_u32(name: &str, attr: &str) -> io::Result<u32> {
+    fs::read_to_string(sysfs(name).join(attr))?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+//"eth0.100  VID: 100	 REORDER_HDR: 1  dev->priv_flags: 1" on the first line
+fn read_vid(name: &str) -> Option<u16> {
+    let info = fs::read_to_string(PathBuf::from("/proc/net/vlan").join(name)).ok()?;
+    let mut words = info.split_whitespace();
+    words.find(|w| *w == "VID:")?;
+    words.next()?.parse().ok()
+}
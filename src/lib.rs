@@ -1,8 +1,95 @@
+#[macro_use]
+extern crate bitflags;
 extern crate libc;
 #[macro_use]
 extern crate nom;
 
+#[macro_use]
+mod macros;
+
+#[cfg(target_os = "linux")]
+pub mod activation;
+#[cfg(target_os = "linux")]
+pub mod affinity;
+pub mod anonymize;
+pub mod arrival;
+pub mod blockdump;
+#[cfg(all(feature = "bpf", any(target_os = "macos", target_os = "freebsd")))]
+pub mod bsd_bpf;
+pub mod capture;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub mod compress;
+#[cfg(target_os = "linux")]
+pub mod dispatch;
+mod endian;
+pub mod error;
+#[cfg(target_os = "linux")]
+pub mod ethtool;
+pub mod export;
+#[cfg(target_os = "linux")]
+pub mod fdpass;
+#[cfg(all(feature = "ffi", target_os = "linux"))]
+pub mod ffi;
+pub mod filter;
+pub mod flow;
+#[cfg(target_os = "linux")]
+pub mod group;
+#[cfg(feature = "headers")]
+pub mod headers;
+#[cfg(target_os = "linux")]
+pub mod hotplug;
+#[cfg(target_os = "linux")]
+pub mod interface;
+#[cfg(feature = "libpcap")]
+pub mod libpcap;
+pub mod linktype;
+pub mod merge;
+pub mod netns;
+pub mod offline;
+#[cfg(target_os = "linux")]
+pub mod parallel;
+pub mod pcap;
+pub mod pcapng;
+#[cfg(target_os = "linux")]
+pub mod phc;
+#[cfg(target_os = "linux")]
+pub mod privileges;
+#[cfg(target_os = "linux")]
+pub mod probe;
+#[cfg(all(feature = "prometheus", target_os = "linux"))]
+pub mod prometheus;
+pub mod quarantine;
+pub mod radiotap;
+pub mod rotate;
 pub mod rx;
+#[cfg(target_os = "linux")]
+pub mod rx_v2;
+#[cfg(target_os = "linux")]
+pub mod shutdown;
+#[cfg(target_os = "linux")]
+pub mod simple;
+pub mod sink;
+#[cfg(target_os = "linux")]
 pub mod socket;
+pub mod spill;
+pub mod stats;
+pub mod stream;
+#[cfg(target_os = "linux")]
+pub mod timestamp;
+pub mod tpacket2;
 pub mod tpacket3;
+#[cfg(feature = "trace")]
+pub mod trace;
+#[cfg(target_os = "linux")]
 pub mod tx;
+#[cfg(not(target_os = "linux"))]
+mod unsupported;
+pub mod vlan;
+mod wire;
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+pub mod xdp;
+
+#[cfg(target_os = "linux")]
+pub use privileges::check_privileges;
+#[cfg(not(target_os = "linux"))]
+pub use unsupported::{rx_v2, simple, tx};
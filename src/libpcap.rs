@@ -0,0 +1,326 @@
+//!Capture through libpcap, for machines where AF_PACKET rings are unavailable or unusable. It
+//!implements `capture::Capture` so processing loops written against the rings run unchanged, but
+//!copies each packet through libpcap's own buffer and only understands Ethernet links as well as
+//!the rings do
+
+use std::ffi::{CStr, CString};
+use std::io::{self, Error, ErrorKind};
+use std::os::raw::{c_char, c_int, c_uchar, c_uint};
+use std::time::{Duration, UNIX_EPOCH};
+
+use libc::timeval;
+
+use capture::{Capture, CaptureStats};
+use filter::SockFilter;
+use rx::{RawPacket, RingLabel, RingTotals};
+
+const PCAP_ERRBUF_SIZE: usize = 256;
+
+///DLT_EN10MB, the only link type whose frames `RawPacket` parses fully
+pub const DLT_EN10MB: c_int = 1;
+
+#[allow(non_camel_case_types)]
+enum pcap_t {}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct pcap_pkthdr {
+    ts: timeval,
+    caplen: c_uint,
+    len: c_uint,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Default)]
+struct pcap_stat {
+    ps_recv: c_uint,
+    ps_drop: c_uint,
+    ps_ifdrop: c_uint,
+}
+
+//struct bpf_program, whose instructions are laid out like struct sock_filter
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct bpf_program {
+    bf_len: c_uint,
+    bf_insns: *const SockFilter,
+}
+
+type PcapHandler = extern "C" fn(*mut c_uchar, *const pcap_pkthdr, *const c_uchar);
+
+#[link(name = "pcap")]
+extern "C" {
+    fn pcap_create(source: *const c_char, errbuf: *mut c_char) -> *mut pcap_t;
+    fn pcap_set_snaplen(p: *mut pcap_t, snaplen: c_int) -> c_int;
+    fn pcap_set_promisc(p: *mut pcap_t, promisc: c_int) -> c_int;
+    fn pcap_set_timeout(p: *mut pcap_t, to_ms: c_int) -> c_int;
+    fn pcap_set_buffer_size(p: *mut pcap_t, buffer_size: c_int) -> c_int;
+    fn pcap_activate(p: *mut pcap_t) -> c_int;
+    fn pcap_geterr(p: *mut pcap_t) -> *const c_char;
+    fn pcap_close(p: *mut pcap_t);
+    fn pcap_datalink(p: *mut pcap_t) -> c_int;
+    fn pcap_dispatch(
+        p: *mut pcap_t,
+        cnt: c_int,
+        callback: PcapHandler,
+        user: *mut c_uchar,
+    ) -> c_int;
+    fn pcap_next_ex(
+        p: *mut pcap_t,
+        header: *mut *const pcap_pkthdr,
+        data: *mut *const c_uchar,
+    ) -> c_int;
+    fn pcap_stats(p: *mut pcap_t, ps: *mut pcap_stat) -> c_int;
+    fn pcap_setfilter(p: *mut pcap_t, fp: *const bpf_program) -> c_int;
+}
+
+///Settings to be used to open a libpcap handle
+#[derive(Clone, Debug)]
+pub struct PcapSettings {
+    ///Interface name
+    pub if_name: String,
+    ///Bytes captured of each packet
+    pub snaplen: i32,
+    pub promisc: bool,
+    ///How long libpcap buffers packets before delivering them, in milliseconds
+    pub timeout_ms: i32,
+    ///Size of libpcap's capture buffer in bytes, 0 keeps libpcap's default
+    pub buffer_size: i32,
+    ///Most packets handed to `next_block` at once
+    pub batch_size: i32,
+    ///Optional user tag used alongside the interface to identify the handle
+    pub tag: Option<String>,
+}
+
+impl Default for PcapSettings {
+    fn default() -> PcapSettings {
+        PcapSettings {
+            if_name: String::from("eth0"),
+            snaplen: 65535,
+            promisc: true,
+            timeout_ms: 100,
+            buffer_size: 0,
+            batch_size: 64,
+            tag: None,
+        }
+    }
+}
+
+///Counters kept by libpcap, which are cumulative unlike PACKET_STATISTICS
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PcapStats {
+    pub received: u32,
+    pub dropped: u32,
+    ///Packets dropped by the interface or its driver, not supported everywhere
+    pub if_dropped: u32,
+}
+
+///An activated libpcap capture handle
+#[derive(Debug)]
+pub struct Handle {
+    pcap: *mut pcap_t,
+    label: RingLabel,
+    batch_size: i32,
+    totals: RingTotals,
+    last_stats: PcapStats,
+}
+
+impl Handle {
+    ///Opens a libpcap handle on the specified interface name in promiscuous mode
+    pub fn from_if_name(if_name: &str) -> io::Result<Handle> {
+        Handle::new(PcapSettings {
+            if_name: String::from(if_name),
+            ..PcapSettings::default()
+        })
+    }
+
+    ///Opens and activates a libpcap handle from the supplied PcapSettings struct
+    pub fn new(settings: PcapSettings) -> io::Result<Handle> {
+        let label = RingLabel {
+            if_name: settings.if_name.clone(),
+            index: 0,
+            tag: settings.tag.clone(),
+        };
+        let device = CString::new(settings.if_name.as_str())?;
+        let mut errbuf = [0 as c_char; PCAP_ERRBUF_SIZE];
+        let pcap = unsafe { pcap_create(device.as_ptr(), errbuf.as_mut_ptr()) };
+        if pcap.is_null() {
+            let msg = unsafe { CStr::from_ptr(errbuf.as_ptr()) }.to_string_lossy();
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("{}: pcap_create: {}", label, msg),
+            ));
+        }
+        //closed by Drop if activation fails
+        let handle = Handle {
+            pcap,
+            label,
+            batch_size: settings.batch_size.max(1),
+            totals: RingTotals::default(),
+            last_stats: PcapStats::default(),
+        };
+        unsafe {
+            pcap_set_snaplen(pcap, settings.snaplen);
+            pcap_set_promisc(pcap, settings.promisc as c_int);
+            pcap_set_timeout(pcap, settings.timeout_ms);
+            if settings.buffer_size > 0 {
+                pcap_set_buffer_size(pcap, settings.buffer_size);
+            }
+        }
+        match unsafe { pcap_activate(pcap) } {
+            status if status < 0 => Err(handle.error("pcap_activate")),
+            status => {
+                if status > 0 {
+                    trace_event!(
+                        Warn,
+                        "pcap handle {} activated with warning {}",
+                        handle.label,
+                        status
+                    );
+                }
+                trace_event!(Info, "pcap handle {} up", handle.label);
+                Ok(handle)
+            }
+        }
+    }
+
+    ///Returns the label identifying this handle
+    pub fn label(&self) -> &RingLabel {
+        &self.label
+    }
+
+    ///Returns running totals of what this handle has delivered to the application
+    pub fn totals(&self) -> RingTotals {
+        self.totals
+    }
+
+    ///Returns the link type of the interface, `DLT_EN10MB` for Ethernet
+    pub fn datalink(&self) -> c_int {
+        unsafe { pcap_datalink(self.pcap) }
+    }
+
+    pub fn stats(&self) -> io::Result<PcapStats> {
+        let mut stat = pcap_stat::default();
+        if unsafe { pcap_stats(self.pcap, &mut stat) } < 0 {
+            return Err(self.error("pcap_stats"));
+        }
+        Ok(PcapStats {
+            received: stat.ps_recv,
+            dropped: stat.ps_drop,
+            if_dropped: stat.ps_ifdrop,
+        })
+    }
+
+    ///Returns the next packet, or `None` if the read timeout expired first. The packet points
+    ///into libpcap's buffer and is only valid until the next call
+    pub fn next_packet(&mut self) -> io::Result<Option<RawPacket<'_>>> {
+        let mut header: *const pcap_pkthdr = std::ptr::null();
+        let mut data: *const c_uchar = std::ptr::null();
+        match unsafe { pcap_next_ex(self.pcap, &mut header, &mut data) } {
+            1 => {
+                let packet = unsafe { to_raw_packet(&*header, data) };
+                self.totals.packets += 1;
+                self.totals.bytes += u64::from(packet.captured_len());
+                Ok(Some(packet))
+            }
+            0 => Ok(None),
+            _ => Err(self.error("pcap_next_ex")),
+        }
+    }
+
+    fn error(&self, step: &str) -> Error {
+        let msg = unsafe { CStr::from_ptr(pcap_geterr(self.pcap)) }.to_string_lossy();
+        Error::new(
+            ErrorKind::Other,
+            format!("{}: {}: {}", self.label, step, msg),
+        )
+    }
+}
+
+impl Capture for Handle {
+    fn next_block(&mut self, f: &mut dyn FnMut(&RawPacket<'_>)) -> io::Result<usize> {
+        struct Dispatch<'f> {
+            f: &'f mut dyn FnMut(&RawPacket<'_>),
+            bytes: u64,
+        }
+
+        extern "C" fn handler(
+            user: *mut c_uchar,
+            header: *const pcap_pkthdr,
+            data: *const c_uchar,
+        ) {
+            let dispatch = unsafe { &mut *(user as *mut Dispatch) };
+            let packet = unsafe { to_raw_packet(&*header, data) };
+            dispatch.bytes += u64::from(packet.captured_len());
+            (dispatch.f)(&packet);
+        }
+
+        let mut dispatch = Dispatch { f, bytes: 0 };
+        let count = unsafe {
+            pcap_dispatch(
+                self.pcap,
+                self.batch_size,
+                handler,
+                &mut dispatch as *mut Dispatch as *mut c_uchar,
+            )
+        };
+        if count < 0 {
+            return Err(self.error("pcap_dispatch"));
+        }
+        //a timeout with nothing captured isn't a block
+        if count > 0 {
+            self.totals.blocks += 1;
+        }
+        self.totals.packets += count as u64;
+        self.totals.bytes += dispatch.bytes;
+        Ok(count as usize)
+    }
+
+    fn label(&self) -> &RingLabel {
+        Handle::label(self)
+    }
+
+    fn totals(&self) -> RingTotals {
+        Handle::totals(self)
+    }
+
+    fn stats(&mut self) -> io::Result<CaptureStats> {
+        let stats = Handle::stats(self)?;
+        let last = self.last_stats;
+        self.last_stats = stats;
+        Ok(CaptureStats {
+            packets: u64::from(stats.received.wrapping_sub(last.received)),
+            drops: u64::from(stats.dropped.wrapping_sub(last.dropped)),
+        })
+    }
+
+    fn attach_filter(&mut self, prog: &[SockFilter]) -> io::Result<()> {
+        let program = bpf_program {
+            bf_len: prog.len() as c_uint,
+            bf_insns: prog.as_ptr(),
+        };
+        match unsafe { pcap_setfilter(self.pcap, &program) } {
+            0 => Ok(()),
+            _ => Err(self.error("pcap_setfilter")),
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { pcap_close(self.pcap) }
+    }
+}
+
+unsafe impl Send for Handle {}
+
+unsafe fn to_raw_packet<'a>(header: &pcap_pkthdr, data: *const c_uchar) -> RawPacket<'a> {
+    let frame = std::slice::from_raw_parts(data, header.caplen as usize);
+    let timestamp = UNIX_EPOCH
+        + Duration::new(
+            header.ts.tv_sec as u64,
+            (header.ts.tv_usec as u32).saturating_mul(1000),
+        );
+    RawPacket::from_frame(frame, header.len, timestamp)
+}
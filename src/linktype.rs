@@ -0,0 +1,61 @@
+//!pcap link types (LINKTYPE_*), the values pcap and pcapng files use to say what each record
+//!starts with. These match the DLT_* values libpcap reports on Linux except for raw IP, where the
+//!DLT value differs between platforms and files always use LINKTYPE_RAW.
+
+#[cfg(target_os = "linux")]
+use socket::{Socket, SOCK_DGRAM};
+
+//Linux's ARPHRD_* hardware types, spelled out so packets can be inspected on other platforms
+pub const ARPHRD_ETHER: u16 = 1;
+pub const ARPHRD_PPP: u16 = 512;
+pub const ARPHRD_RAWIP: u16 = 519;
+pub const ARPHRD_TUNNEL: u16 = 768;
+pub const ARPHRD_TUNNEL6: u16 = 769;
+pub const ARPHRD_LOOPBACK: u16 = 772;
+pub const ARPHRD_SIT: u16 = 776;
+pub const ARPHRD_IEEE80211: u16 = 801;
+pub const ARPHRD_IEEE80211_RADIOTAP: u16 = 803;
+pub const ARPHRD_NONE: u16 = 0xfffe;
+
+pub const LINKTYPE_ETHERNET: u32 = 1;
+///Bare IPv4 or IPv6 packets without a link-layer header
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_IEEE802_11: u32 = 105;
+///Linux "cooked" capture, records start with a 16 byte header built from the sockaddr_ll
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_IEEE802_11_RADIOTAP: u32 = 127;
+
+///Maps an ARPHRD_* hardware type to the link type of the frames captured from it on a SOCK_RAW
+///socket, or `None` for hardware types this crate doesn't know the framing of
+pub fn from_arphrd(hatype: u16) -> Option<u32> {
+    match hatype {
+        ARPHRD_ETHER | ARPHRD_LOOPBACK => Some(LINKTYPE_ETHERNET),
+        ARPHRD_IEEE80211 => Some(LINKTYPE_IEEE802_11),
+        ARPHRD_IEEE80211_RADIOTAP => Some(LINKTYPE_IEEE802_11_RADIOTAP),
+        //tun, ppp and IP tunnels hand over packets starting at the IP header
+        ARPHRD_NONE | ARPHRD_PPP | ARPHRD_RAWIP | ARPHRD_TUNNEL | ARPHRD_TUNNEL6 | ARPHRD_SIT => {
+            Some(LINKTYPE_RAW)
+        }
+        _ => None,
+    }
+}
+
+///Returns true if frames of the ARPHRD_* hardware type start with an Ethernet header. Loopback
+///frames carry a zeroed Ethernet header, while tun and ppp frames start at the network header
+#[inline]
+pub fn has_ethernet_header(hatype: u16) -> bool {
+    hatype == ARPHRD_ETHER || hatype == ARPHRD_LOOPBACK
+}
+
+///Link type of the packets a socket captures. Cooked sockets are LINKTYPE_LINUX_SLL, and raw
+///sockets on `socket::ANY_INTERFACE` mix the framing of every interface so they have none
+#[cfg(target_os = "linux")]
+pub(crate) fn for_socket(sock: &Socket) -> Option<u32> {
+    if sock.kind == SOCK_DGRAM {
+        return Some(LINKTYPE_LINUX_SLL);
+    }
+    if sock.is_any() {
+        return None;
+    }
+    from_arphrd(sock.hatype)
+}
@@ -0,0 +1,41 @@
+//Internal instrumentation macros. These compile to nothing unless the `trace` feature is enabled,
+//but the arguments are still type-checked so they don't rot or trip unused-variable lints. Off
+//Linux the backends that use them may not be built at all
+
+#[cfg(feature = "trace")]
+#[allow(unused_macros)]
+macro_rules! trace_event {
+    ($lvl:ident, $($arg:tt)+) => {
+        if $crate::trace::enabled($crate::trace::Level::$lvl) {
+            $crate::trace::dispatch(
+                $crate::trace::Level::$lvl,
+                module_path!(),
+                format_args!($($arg)+),
+            );
+        }
+    };
+}
+
+#[cfg(not(feature = "trace"))]
+#[allow(unused_macros)]
+macro_rules! trace_event {
+    ($lvl:ident, $($arg:tt)+) => {
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    };
+}
+
+#[cfg(feature = "trace")]
+#[allow(unused_macros)]
+macro_rules! trace_span {
+    ($guard:ident, $name:expr) => {
+        let $guard = $crate::trace::Span::enter($name);
+    };
+}
+
+#[cfg(not(feature = "trace"))]
+#[allow(unused_macros)]
+macro_rules! trace_span {
+    ($guard:ident, $name:expr) => {};
+}
@@ -0,0 +1,212 @@
+//!Puts packets from several fanout rings back in timestamp order. Fanout shards traffic by flow,
+//!so no single ring sees the whole sequence. Merging holds packets for a reordering window and
+//!releases them oldest first once nothing older can still arrive within that window.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+#[cfg(target_os = "linux")]
+use std::io;
+#[cfg(target_os = "linux")]
+use std::time::Instant;
+use std::time::{Duration, SystemTime};
+
+use rx::OwnedPacket;
+#[cfg(target_os = "linux")]
+use rx::Ring;
+
+//heap entry, ordered so the oldest packet is at the top of the max-heap
+struct Pending {
+    ts: SystemTime,
+    //arrival order, keeping packets with equal timestamps in the order they were pushed
+    seq: u64,
+    packet: OwnedPacket,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Pending) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Pending) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Pending) -> Ordering {
+        (other.ts, other.seq).cmp(&(self.ts, self.seq))
+    }
+}
+
+///Reorders packets pushed from any number of sources by timestamp. A packet is released once a
+///packet at least `window` newer has been pushed, so sources may lag each other by up to `window`
+pub struct TimeMerge {
+    window: Duration,
+    heap: BinaryHeap<Pending>,
+    newest: Option<SystemTime>,
+    last_released: Option<SystemTime>,
+    seq: u64,
+    late: u64,
+}
+
+impl TimeMerge {
+    pub fn new(window: Duration) -> TimeMerge {
+        TimeMerge {
+            window,
+            heap: BinaryHeap::new(),
+            newest: None,
+            last_released: None,
+            seq: 0,
+            late: 0,
+        }
+    }
+
+    ///Adds a packet. One older than a packet already released is counted in `late` and released
+    ///next, out of order, rather than dropped
+    pub fn push(&mut self, packet: OwnedPacket) {
+        let ts = packet.timestamp();
+        if self.last_released.is_some_and(|last| ts < last) {
+            self.late += 1;
+        }
+        if self.newest.map_or(true, |newest| ts > newest) {
+            self.newest = Some(ts);
+        }
+        self.seq += 1;
+        self.heap.push(Pending {
+            ts,
+            seq: self.seq,
+            packet,
+        });
+    }
+
+    ///Returns the oldest packet if it is more than the window older than the newest one pushed
+    pub fn pop_ready(&mut self) -> Option<OwnedPacket> {
+        let newest = self.newest?;
+        self.pop_older_than(newest)
+    }
+
+    ///Like `pop_ready`, but also measures the window against `now`, so packets are released when
+    ///the sources go quiet. Packet timestamps are wall clock time, so `now` is `SystemTime::now()`
+    pub fn pop_ready_by(&mut self, now: SystemTime) -> Option<OwnedPacket> {
+        let newest = self.newest.map_or(now, |newest| newest.max(now));
+        self.pop_older_than(newest)
+    }
+
+    fn pop_older_than(&mut self, newest: SystemTime) -> Option<OwnedPacket> {
+        let oldest = self.heap.peek()?.ts;
+        if newest.duration_since(oldest).unwrap_or_default() < self.window
+            && self.last_released.map_or(true, |last| oldest >= last)
+        {
+            return None;
+        }
+        self.release()
+    }
+
+    ///Returns the oldest packet regardless of the window, to drain the merge at shutdown
+    pub fn pop(&mut self) -> Option<OwnedPacket> {
+        self.release()
+    }
+
+    ///Returns the number of packets held
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    ///Returns true if no packets are held
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    ///Returns the number of packets that arrived after a newer packet had been released. If this
+    ///climbs, the window is shorter than the lag between sources
+    pub fn late(&self) -> u64 {
+        self.late
+    }
+
+    fn release(&mut self) -> Option<OwnedPacket> {
+        let p = self.heap.pop()?;
+        if self.last_released.map_or(true, |last| p.ts > last) {
+            self.last_released = Some(p.ts);
+        }
+        Some(p.packet)
+    }
+}
+
+///Drains several rings from one thread and yields their packets in timestamp order
+#[cfg(target_os = "linux")]
+pub struct RingMerge {
+    rings: Vec<Ring>,
+    merge: TimeMerge,
+    //ring to poll first next time, so no ring is favoured
+    next: usize,
+}
+
+#[cfg(target_os = "linux")]
+impl RingMerge {
+    ///Merges `rings`, usually all the rings of one fanout group. `window` should be at least
+    ///the rings' block retire timeout, as a ring holds packets back until its block retires
+    pub fn new(rings: Vec<Ring>, window: Duration) -> RingMerge {
+        RingMerge {
+            rings,
+            merge: TimeMerge::new(window),
+            next: 0,
+        }
+    }
+
+    ///Returns the next packet in timestamp order, waiting up to `timeout` for the rings to
+    ///retire enough blocks to release one
+    pub fn next_packet(&mut self, timeout: Duration) -> io::Result<Option<OwnedPacket>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(p) = self.merge.pop_ready() {
+                return Ok(Some(p));
+            }
+            if self.rings.is_empty() {
+                return Ok(self.merge.pop());
+            }
+            //take whatever is ready everywhere before waiting on a single ring
+            let mut took = false;
+            for n in 0..self.rings.len() {
+                let i = (self.next + n) % self.rings.len();
+                if let Some(block) = self.rings[i].get_block_timeout(Duration::from_millis(0)) {
+                    for packet in block.copy_packets() {
+                        self.merge.push(packet);
+                    }
+                    took = true;
+                }
+            }
+            self.next = (self.next + 1) % self.rings.len();
+            if took {
+                continue;
+            }
+            if let Some(p) = self.merge.pop_ready_by(SystemTime::now()) {
+                return Ok(Some(p));
+            }
+            let left = match deadline.checked_duration_since(Instant::now()) {
+                Some(left) => left,
+                None => return Ok(None),
+            };
+            let i = self.next;
+            let wait = left.min(Duration::from_millis(10));
+            if let Some(block) = self.rings[i].get_block_timeout(wait) {
+                for packet in block.copy_packets() {
+                    self.merge.push(packet);
+                }
+            }
+        }
+    }
+
+    ///Returns the underlying merge, e.g. to check `late` or drain it with `pop`
+    pub fn merge(&mut self) -> &mut TimeMerge {
+        &mut self.merge
+    }
+
+    ///Returns the merged rings
+    pub fn rings(&mut self) -> &mut [Ring] {
+        &mut self.rings
+    }
+}
@@ -0,0 +1,86 @@
+//!Opening rings inside another network namespace, so an agent on the host can capture in a
+//!container without running anything in it. A packet socket belongs to the namespace it was
+//!created in for the rest of its life, so only socket(2) and the interface lookup need to happen
+//!in there: the thread enters the namespace with setns(2), creates the socket and goes back.
+//!Entering one needs CAP_SYS_ADMIN.
+
+use std::path::PathBuf;
+
+#[cfg(target_os = "linux")]
+use libc::{close, open, setns, CLONE_NEWNET, O_CLOEXEC, O_RDONLY};
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+#[cfg(target_os = "linux")]
+use std::io::{self, Error, ErrorKind};
+#[cfg(target_os = "linux")]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::RawFd;
+#[cfg(target_os = "linux")]
+use std::path::Path;
+
+///A network namespace to open a ring in
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NetNs {
+    ///A namespace file, e.g. one `ip netns add` bound under /run/netns
+    Path(PathBuf),
+    ///The namespace a process is in, e.g. a container's init
+    Pid(u32),
+}
+
+impl NetNs {
+    ///Returns the file to open for the namespace
+    pub fn path(&self) -> PathBuf {
+        match self {
+            NetNs::Path(path) => path.clone(),
+            NetNs::Pid(pid) => PathBuf::from(format!("/proc/{}/ns/net", pid)),
+        }
+    }
+}
+
+///Moves the calling thread back to the namespace it was in when dropped
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct NetNsGuard {
+    home: RawFd,
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for NetNsGuard {
+    fn drop(&mut self) {
+        if unsafe { setns(self.home, CLONE_NEWNET) } != 0 {
+            trace_event!(
+                Error,
+                "returning to the original network namespace failed: {}",
+                Error::last_os_error()
+            );
+        }
+        unsafe { close(self.home) };
+    }
+}
+
+///Moves the calling thread into `ns` until the returned guard is dropped. Only this thread
+///changes namespace, others in the process stay where they are
+#[cfg(target_os = "linux")]
+pub fn enter(ns: &NetNs) -> io::Result<NetNsGuard> {
+    let home = open_ns(Path::new("/proc/thread-self/ns/net"))?;
+    let guard = NetNsGuard { home };
+    let target = open_ns(&ns.path())?;
+    let ret = unsafe { setns(target, CLONE_NEWNET) };
+    let err = Error::last_os_error();
+    unsafe { close(target) };
+    match ret {
+        0 => Ok(guard),
+        _ => Err(err),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_ns(path: &Path) -> io::Result<RawFd> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "namespace path contains a NUL"))?;
+    match unsafe { open(c_path.as_ptr(), O_RDONLY | O_CLOEXEC) } {
+        -1 => Err(Error::last_os_error()),
+        fd => Ok(fd),
+    }
+}
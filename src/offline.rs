@@ -0,0 +1,746 @@
+//!Reads pcap and pcapng files back as `RawPacket`s, so analysis code written against a live ring
+//!runs unchanged against capture files. Each record gets a synthesized tpacket3 header and
+//!sockaddr_ll: timestamps, lengths and the tp_mac/tp_net offsets are filled in from the record,
+//!the hardware type from the link type, and the packet type from pcapng's direction flags, the
+//!cooked header, or the destination MAC. Files of either byte order and any pcapng timestamp
+//!resolution are read.
+
+use std::fs::File;
+use std::io::{self, BufReader, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use capture::{Capture, CaptureStats};
+use filter::SockFilter;
+use linktype::{
+    ARPHRD_ETHER, ARPHRD_IEEE80211, ARPHRD_IEEE80211_RADIOTAP, ARPHRD_NONE, LINKTYPE_ETHERNET,
+    LINKTYPE_IEEE802_11, LINKTYPE_IEEE802_11_RADIOTAP, LINKTYPE_LINUX_SLL, LINKTYPE_RAW,
+};
+use rx::{
+    RawPacket, RingLabel, RingTotals, PACKET_BROADCAST, PACKET_HOST, PACKET_MULTICAST,
+    PACKET_OTHERHOST, PACKET_OUTGOING,
+};
+use tpacket3::{TpStatus, Tpacket3Hdr, TpacketHdrVariant1};
+use vlan::VlanStack;
+
+const PCAP_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_NANOS: u32 = 0xa1b2_3c4d;
+const PCAPNG_SHB: u32 = 0x0a0d_0d0a;
+const PCAPNG_BYTE_ORDER: u32 = 0x1a2b_3c4d;
+
+const PB: u32 = 2;
+const IDB: u32 = 1;
+const SPB: u32 = 3;
+const EPB: u32 = 6;
+
+const IF_NAME: u16 = 2;
+const IF_TSRESOL: u16 = 9;
+const EPB_FLAGS: u16 = 2;
+
+//records longer than this are taken as a sign of a corrupt file rather than allocated
+const MAX_RECORD: usize = 16 << 20;
+//sockaddr_ll as the kernel lays it out ahead of the frame
+const SLL_LEN: usize = 20;
+const SLL_HDR_LEN: usize = 16;
+//hardware type given to packets of link types the crate doesn't know the framing of
+const ARPHRD_VOID: u16 = 0xffff;
+const AF_PACKET: u16 = 17;
+
+///Packets handed out per call to `Capture::next_block`
+pub const DEFAULT_BATCH_SIZE: usize = 64;
+
+///The kind of capture file being read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    Pcap,
+    Pcapng,
+}
+
+#[derive(Clone, Debug)]
+struct Interface {
+    link_type: u32,
+    name: Option<String>,
+    //(units per second, true if a power of two exponent)
+    resolution: (u8, bool),
+}
+
+//what a record says about the frame read into `FileCapture::record`
+struct Record {
+    timestamp: SystemTime,
+    wire_len: u32,
+    link_type: u32,
+    //PACKET_* type, when the file records it
+    pkttype: Option<u8>,
+}
+
+#[derive(Debug)]
+enum State {
+    Pcap { link_type: u32, nanos: bool },
+    Pcapng { interfaces: Vec<Interface> },
+}
+
+///Reads a pcap or pcapng file one record at a time
+#[derive(Debug)]
+pub struct FileCapture<R: Read> {
+    input: R,
+    format: FileFormat,
+    state: State,
+    //true if the file's byte order is not the host's
+    swapped: bool,
+    label: RingLabel,
+    totals: RingTotals,
+    delivered: u64,
+    batch_size: usize,
+    link_type: Option<u32>,
+    record: Vec<u8>,
+    packet: Vec<u8>,
+}
+
+impl FileCapture<BufReader<File>> {
+    ///Opens a capture file, labelled with its path
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileCapture<BufReader<File>>> {
+        let path = path.as_ref();
+        let file = BufReader::new(File::open(path)?);
+        let mut capture = FileCapture::new(file)?;
+        capture.label.if_name = path.display().to_string();
+        Ok(capture)
+    }
+}
+
+impl<R: Read + Seek> FileCapture<R> {
+    ///Continues reading at `offset`, which has to be the start of a record, e.g. one from a
+    ///`rotate::CaptureIndex`. A pcapng file's interfaces are read first if no packet has been yet,
+    ///so they have to be described ahead of the packets, as `PcapngWriter` does
+    pub fn seek_to(&mut self, offset: u64) -> io::Result<()> {
+        let described = match self.state {
+            State::Pcapng { ref interfaces } => !interfaces.is_empty(),
+            State::Pcap { .. } => true,
+        };
+        if !described {
+            self.read_pcapng_record()?;
+        }
+        self.input.seek(SeekFrom::Start(offset))?;
+        Ok(())
+    }
+}
+impl<R: Read> FileCapture<R> {
+    ///Reads the file header from `input`, telling pcap and pcapng apart by its magic number
+    pub fn new(mut input: R) -> io::Result<FileCapture<R>> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        let mut capture = FileCapture {
+            input,
+            format: FileFormat::Pcap,
+            state: State::Pcapng {
+                interfaces: Vec::new(),
+            },
+            swapped: false,
+            label: RingLabel {
+                if_name: String::from("file"),
+                index: 0,
+                tag: None,
+            },
+            totals: RingTotals::default(),
+            delivered: 0,
+            batch_size: DEFAULT_BATCH_SIZE,
+            link_type: None,
+            record: Vec::new(),
+            packet: Vec::new(),
+        };
+        let ne = u32::from_ne_bytes(magic);
+        if ne == PCAPNG_SHB {
+            capture.format = FileFormat::Pcapng;
+            capture.read_section_header()?;
+            return Ok(capture);
+        }
+        let (nanos, swapped) = match (ne, ne.swap_bytes()) {
+            (PCAP_MICROS, _) => (false, false),
+            (PCAP_NANOS, _) => (true, false),
+            (_, PCAP_MICROS) => (false, true),
+            (_, PCAP_NANOS) => (true, true),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("not a pcap or pcapng file, magic {:#010x}", ne),
+                ))
+            }
+        };
+        capture.swapped = swapped;
+        let mut hdr = [0; 20];
+        capture.input.read_exact(&mut hdr)?;
+        //the upper bits of the link type field carry FCS information
+        let link_type = capture.u32_at(&hdr, 16) & 0xffff;
+        capture.state = State::Pcap { link_type, nanos };
+        capture.link_type = Some(link_type);
+        Ok(capture)
+    }
+
+    ///Returns the kind of file being read
+    pub fn format(&self) -> FileFormat {
+        self.format
+    }
+
+    ///Returns the link type (`linktype::LINKTYPE_*`) of the last packet read, or of the file for
+    ///pcap files
+    pub fn link_type(&self) -> Option<u32> {
+        self.link_type
+    }
+
+    ///Returns the name a pcapng file gives interface `id`, if any
+    pub fn interface_name(&self, id: u32) -> Option<&str> {
+        match self.state {
+            State::Pcapng { ref interfaces } => interfaces.get(id as usize)?.name.as_deref(),
+            State::Pcap { .. } => None,
+        }
+    }
+
+    pub fn label(&self) -> &RingLabel {
+        &self.label
+    }
+
+    pub fn totals(&self) -> RingTotals {
+        self.totals
+    }
+
+    ///Sets how many packets `Capture::next_block` hands out per call
+    pub fn set_batch_size(&mut self, batch_size: usize) {
+        self.batch_size = batch_size.max(1);
+    }
+
+    ///Returns the next packet, or `None` at the end of the file. The packet points into the
+    ///reader's buffer and is only valid until the next call
+    pub fn next_packet(&mut self) -> io::Result<Option<RawPacket<'_>>> {
+        let record = match self.format {
+            FileFormat::Pcap => self.read_pcap_record()?,
+            FileFormat::Pcapng => self.read_pcapng_record()?,
+        };
+        let record = match record {
+            Some(r) => r,
+            None => return Ok(None),
+        };
+        self.link_type = Some(record.link_type);
+        let hdr = synthesize(&record, &self.record, &mut self.packet);
+        self.totals.packets += 1;
+        self.totals.bytes += u64::from(hdr.tp_snaplen);
+        self.delivered += 1;
+        Ok(Some(RawPacket::with_sll_offset(hdr, &self.packet, 0)))
+    }
+
+    //reads a record's frame into `record`, returning what is known about it
+    fn read_pcap_record(&mut self) -> io::Result<Option<Record>> {
+        let (link_type, nanos) = match self.state {
+            State::Pcap { link_type, nanos } => (link_type, nanos),
+            State::Pcapng { .. } => unreachable!(),
+        };
+        let mut hdr = [0; 16];
+        if !read_or_eof(&mut self.input, &mut hdr)? {
+            return Ok(None);
+        }
+        let secs = self.u32_at(&hdr, 0);
+        let frac = self.u32_at(&hdr, 4);
+        let incl = self.u32_at(&hdr, 8) as usize;
+        let orig = self.u32_at(&hdr, 12);
+        if incl > MAX_RECORD {
+            return Err(corrupt(format!("pcap record of {} bytes", incl)));
+        }
+        self.record.resize(incl, 0);
+        self.input.read_exact(&mut self.record)?;
+        let nanos = if nanos {
+            frac
+        } else {
+            frac.saturating_mul(1000)
+        };
+        let timestamp = UNIX_EPOCH + Duration::new(u64::from(secs), nanos.min(999_999_999));
+        Ok(Some(Record {
+            timestamp,
+            wire_len: orig,
+            link_type,
+            pkttype: None,
+        }))
+    }
+
+    //reads blocks until a packet block, keeping track of sections and interfaces on the way
+    fn read_pcapng_record(&mut self) -> io::Result<Option<Record>> {
+        loop {
+            let mut hdr = [0; 8];
+            if !read_or_eof(&mut self.input, &mut hdr)? {
+                return Ok(None);
+            }
+            let kind = u32::from_ne_bytes([hdr[0], hdr[1], hdr[2], hdr[3]]);
+            if kind == PCAPNG_SHB {
+                //the length is in the new section's byte order, which comes after it
+                self.read_section_header_after(&hdr[4..])?;
+                continue;
+            }
+            let kind = self.u32_at(&hdr, 0);
+            let len = self.u32_at(&hdr, 4) as usize;
+            if len < 12 || len & 3 != 0 || len > MAX_RECORD {
+                return Err(corrupt(format!("pcapng block of {} bytes", len)));
+            }
+            let mut body = vec![0; len - 8];
+            self.input.read_exact(&mut body)?;
+            body.truncate(len - 12);
+            match kind {
+                IDB => self.add_interface(&body)?,
+                EPB | PB => return self.packet_block(kind, &body).map(Some),
+                SPB => {
+                    if body.len() < 4 {
+                        return Err(corrupt(String::from("short simple packet block")));
+                    }
+                    let orig = self.u32_at(&body, 0);
+                    let data = &body[4..];
+                    let incl = data.len().min(orig as usize);
+                    self.record.clear();
+                    self.record.extend_from_slice(&data[..incl]);
+                    let link_type = self.interface(0)?.link_type;
+                    return Ok(Some(Record {
+                        timestamp: UNIX_EPOCH,
+                        wire_len: orig,
+                        link_type,
+                        pkttype: None,
+                    }));
+                }
+                //statistics, name resolution and anything newer carry no packets
+                _ => {}
+            }
+        }
+    }
+
+    fn packet_block(&mut self, kind: u32, body: &[u8]) -> io::Result<Record> {
+        if body.len() < 20 {
+            return Err(corrupt(String::from("short packet block")));
+        }
+        //the obsolete packet block has a 16 bit interface id followed by a drop count
+        let id = match kind {
+            EPB => self.u32_at(body, 0),
+            _ => u32::from(self.u16_at(body, 0)),
+        };
+        let ts = u64::from(self.u32_at(body, 4)) << 32 | u64::from(self.u32_at(body, 8));
+        let incl = self.u32_at(body, 12) as usize;
+        let orig = self.u32_at(body, 16);
+        let data = body.get(20..20 + incl).ok_or_else(|| {
+            corrupt(format!(
+                "packet block with {} of {} bytes",
+                body.len(),
+                incl
+            ))
+        })?;
+        self.record.clear();
+        self.record.extend_from_slice(data);
+        let options = body.get(20 + ((incl + 3) & !3)..).unwrap_or(&[]);
+        let pkttype = match kind {
+            EPB => self
+                .option(options, EPB_FLAGS)
+                .filter(|v| v.len() >= 4)
+                .and_then(|v| pkttype_from_flags(self.u32_at(v, 0))),
+            _ => None,
+        };
+        let iface = self.interface(id)?;
+        let timestamp = to_system_time(ts, iface.resolution)?;
+        Ok(Record {
+            timestamp,
+            wire_len: orig,
+            link_type: iface.link_type,
+            pkttype,
+        })
+    }
+
+    fn add_interface(&mut self, body: &[u8]) -> io::Result<()> {
+        if body.len() < 8 {
+            return Err(corrupt(String::from("short interface description block")));
+        }
+        let link_type = u32::from(self.u16_at(body, 0));
+        let options = &body[8..];
+        let name = self.option(options, IF_NAME).map(|v| {
+            String::from_utf8_lossy(v)
+                .trim_end_matches('\0')
+                .to_string()
+        });
+        //microseconds unless the interface says otherwise
+        let resolution = match self.option(options, IF_TSRESOL).and_then(|v| v.first()) {
+            Some(&r) => (r & 0x7f, r & 0x80 != 0),
+            None => (6, false),
+        };
+        if let State::Pcapng { ref mut interfaces } = self.state {
+            interfaces.push(Interface {
+                link_type,
+                name,
+                resolution,
+            });
+        }
+        Ok(())
+    }
+
+    fn interface(&self, id: u32) -> io::Result<&Interface> {
+        match self.state {
+            State::Pcapng { ref interfaces } => interfaces
+                .get(id as usize)
+                .ok_or_else(|| corrupt(format!("packet for undeclared interface {}", id))),
+            State::Pcap { .. } => unreachable!(),
+        }
+    }
+
+    //finds the first option with `code` in an options list
+    fn option<'o>(&self, mut options: &'o [u8], code: u16) -> Option<&'o [u8]> {
+        while options.len() >= 4 {
+            let c = self.u16_at(options, 0);
+            let len = self.u16_at(options, 2) as usize;
+            if c == 0 {
+                return None;
+            }
+            let value = options.get(4..4 + len)?;
+            if c == code {
+                return Some(value);
+            }
+            options = options.get(4 + ((len + 3) & !3)..)?;
+        }
+        None
+    }
+
+    fn read_section_header(&mut self) -> io::Result<()> {
+        let mut len = [0; 4];
+        self.input.read_exact(&mut len)?;
+        self.read_section_header_after(&len)
+    }
+
+    //reads the rest of a section header block whose type and length were already read. A new
+    //section starts over with no interfaces and may switch byte order
+    fn read_section_header_after(&mut self, len: &[u8]) -> io::Result<()> {
+        let mut magic = [0; 4];
+        self.input.read_exact(&mut magic)?;
+        self.swapped = match u32::from_ne_bytes(magic) {
+            PCAPNG_BYTE_ORDER => false,
+            m if m.swap_bytes() == PCAPNG_BYTE_ORDER => true,
+            m => return Err(corrupt(format!("pcapng byte order magic {:#010x}", m))),
+        };
+        let len = self.u32_at(len, 0) as usize;
+        if len < 28 || len & 3 != 0 || len > MAX_RECORD {
+            return Err(corrupt(format!("pcapng section header of {} bytes", len)));
+        }
+        let mut rest = vec![0; len - 12];
+        self.input.read_exact(&mut rest)?;
+        self.state = State::Pcapng {
+            interfaces: Vec::new(),
+        };
+        Ok(())
+    }
+
+    fn u16_at(&self, b: &[u8], at: usize) -> u16 {
+        let v = u16::from_ne_bytes([b[at], b[at + 1]]);
+        if self.swapped {
+            v.swap_bytes()
+        } else {
+            v
+        }
+    }
+
+    fn u32_at(&self, b: &[u8], at: usize) -> u32 {
+        let v = u32::from_ne_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]]);
+        if self.swapped {
+            v.swap_bytes()
+        } else {
+            v
+        }
+    }
+}
+
+impl<R: Read> Capture for FileCapture<R> {
+    ///Hands out up to the batch size of packets. Returns 0 once the end of the file is reached
+    fn next_block(&mut self, f: &mut dyn FnMut(&RawPacket<'_>)) -> io::Result<usize> {
+        let mut count = 0;
+        while count < self.batch_size {
+            match self.next_packet()? {
+                Some(packet) => f(&packet),
+                None => break,
+            }
+            count += 1;
+        }
+        if count > 0 {
+            self.totals.blocks += 1;
+        }
+        Ok(count)
+    }
+
+    ///Hands out the next record. Returns 0 once the end of the file is reached
+    fn next_packet(&mut self, f: &mut dyn FnMut(&RawPacket<'_>)) -> io::Result<usize> {
+        match FileCapture::next_packet(self)? {
+            Some(packet) => {
+                f(&packet);
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn label(&self) -> &RingLabel {
+        &self.label
+    }
+
+    fn totals(&self) -> RingTotals {
+        self.totals
+    }
+
+    ///Counts the packets read since the last call. Files record no drops
+    fn stats(&mut self) -> io::Result<CaptureStats> {
+        let packets = self.delivered;
+        self.delivered = 0;
+        Ok(CaptureStats { packets, drops: 0 })
+    }
+
+    fn attach_filter(&mut self, _prog: &[SockFilter]) -> io::Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "socket filters can't be attached to capture files",
+        ))
+    }
+}
+
+//fills a buffer laid out like a ring frame from the sockaddr_ll on, and returns a header whose
+//offsets point into it
+fn synthesize(record: &Record, frame: &[u8], out: &mut Vec<u8>) -> Tpacket3Hdr {
+    let pkttype = record.pkttype;
+    //cooked, raw IP and 802.11 packets have no Ethernet header, their tp_net is tp_mac
+    let mut net = SLL_LEN;
+    let mut wire_len = record.wire_len;
+    let mut data = frame;
+    let (protocol, hatype, halen, addr, pkttype) = match record.link_type {
+        LINKTYPE_ETHERNET => {
+            let mut stack = VlanStack::new(frame, None);
+            while stack.next().is_some() {}
+            net = SLL_LEN + (stack.ethertype_offset() + 2).min(frame.len());
+            let protocol = be_u16(frame, 12);
+            let src = frame.get(6..12).unwrap_or(&[0; 6]);
+            let pkttype = pkttype.unwrap_or_else(|| match frame.first() {
+                Some(_) if frame[..6.min(frame.len())].iter().all(|&b| b == 0xff) => {
+                    PACKET_BROADCAST
+                }
+                Some(&b) if b & 1 != 0 => PACKET_MULTICAST,
+                _ => PACKET_HOST,
+            });
+            (protocol, ARPHRD_ETHER, 6, src, pkttype)
+        }
+        LINKTYPE_LINUX_SLL if frame.len() >= SLL_HDR_LEN => {
+            data = &frame[SLL_HDR_LEN..];
+            wire_len = wire_len.saturating_sub(SLL_HDR_LEN as u32);
+            let halen = (be_u16(frame, 4) as u8).min(8);
+            (
+                be_u16(frame, 14),
+                be_u16(frame, 2),
+                halen,
+                &frame[6..14],
+                be_u16(frame, 0) as u8,
+            )
+        }
+        LINKTYPE_RAW => {
+            let protocol = match frame.first().map(|b| b >> 4) {
+                Some(6) => 0x86dd,
+                _ => 0x0800,
+            };
+            (
+                protocol,
+                ARPHRD_NONE,
+                0,
+                &[][..],
+                pkttype.unwrap_or(PACKET_HOST),
+            )
+        }
+        LINKTYPE_IEEE802_11 => (
+            0,
+            ARPHRD_IEEE80211,
+            0,
+            &[][..],
+            pkttype.unwrap_or(PACKET_HOST),
+        ),
+        LINKTYPE_IEEE802_11_RADIOTAP => (
+            0,
+            ARPHRD_IEEE80211_RADIOTAP,
+            0,
+            &[][..],
+            pkttype.unwrap_or(PACKET_HOST),
+        ),
+        _ => (0, ARPHRD_VOID, 0, &[][..], pkttype.unwrap_or(PACKET_HOST)),
+    };
+    out.clear();
+    out.extend_from_slice(&AF_PACKET.to_ne_bytes());
+    out.extend_from_slice(&protocol.to_be_bytes());
+    out.extend_from_slice(&0i32.to_ne_bytes());
+    out.extend_from_slice(&hatype.to_ne_bytes());
+    out.push(pkttype);
+    out.push(halen);
+    let mut sll_addr = [0; 8];
+    sll_addr[..addr.len().min(8)].copy_from_slice(&addr[..addr.len().min(8)]);
+    out.extend_from_slice(&sll_addr);
+    out.extend_from_slice(data);
+    let since_epoch = record
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::from_secs(0));
+    Tpacket3Hdr {
+        tp_next_offset: 0,
+        tp_sec: since_epoch.as_secs() as u32,
+        tp_nsec: since_epoch.subsec_nanos(),
+        tp_snaplen: data.len() as u32,
+        tp_len: wire_len.max(data.len() as u32),
+        tp_status: TpStatus::USER.bits(),
+        tp_mac: SLL_LEN as u16,
+        tp_net: net as u16,
+        hv1: TpacketHdrVariant1::new(0, 0, 0),
+    }
+}
+
+//direction in bits 0-1 and reception type in bits 2-4, 0 where the writer didn't know
+fn pkttype_from_flags(flags: u32) -> Option<u8> {
+    if flags & 3 == 2 {
+        return Some(PACKET_OUTGOING);
+    }
+    match (flags >> 2) & 7 {
+        1 => Some(PACKET_HOST),
+        2 => Some(PACKET_MULTICAST),
+        3 => Some(PACKET_BROADCAST),
+        4 => Some(PACKET_OTHERHOST),
+        _ => None,
+    }
+}
+
+//fails for a timestamp past what SystemTime holds, which a coarse resolution makes easy to craft
+fn to_system_time(ts: u64, (exp, binary): (u8, bool)) -> io::Result<SystemTime> {
+    let per_sec: u128 = if binary {
+        1u128 << exp.min(63)
+    } else {
+        10u128.pow(u32::from(exp.min(19)))
+    };
+    let ts = u128::from(ts);
+    let secs = (ts / per_sec) as u64;
+    let nanos = ((ts % per_sec) * 1_000_000_000 / per_sec) as u32;
+    UNIX_EPOCH
+        .checked_add(Duration::new(secs, nanos))
+        .ok_or_else(|| corrupt(format!("timestamp of {} seconds is out of range", secs)))
+}
+
+fn be_u16(b: &[u8], at: usize) -> u16 {
+    match b.get(at..at + 2) {
+        Some(v) => u16::from_be_bytes([v[0], v[1]]),
+        None => 0,
+    }
+}
+
+//fills `buf`, returning false if the input ended before the first byte
+fn read_or_eof<R: Read>(input: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match input.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "capture file ends in the middle of a record",
+                ))
+            }
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+fn corrupt(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pcap::PcapWriter;
+    use pcapng::PcapngWriter;
+    use std::io::Cursor;
+
+    //broadcast to an IPv4 ethertype, padded to the Ethernet minimum
+    fn frame(n: u8) -> Vec<u8> {
+        let mut frame = vec![0xff; 6];
+        frame.extend_from_slice(&[0x02, 0, 0, 0, 0, n, 0x08, 0x00]);
+        frame.resize(60, n);
+        frame
+    }
+
+    fn read_all<R: Read>(capture: &mut FileCapture<R>) -> Vec<(SystemTime, u32, Vec<u8>)> {
+        let mut packets = Vec::new();
+        while let Some(p) = capture.next_packet().unwrap() {
+            packets.push((p.timestamp(), p.wire_len(), p.l2().to_vec()));
+        }
+        packets
+    }
+
+    fn written() -> Vec<(SystemTime, u32, Vec<u8>)> {
+        (0..3)
+            .map(|n| {
+                let ts = UNIX_EPOCH + Duration::new(1_600_000_000 + u64::from(n), 123_456_789);
+                (ts, 60 + u32::from(n), frame(n))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_back_a_pcap_file() {
+        let mut writer = PcapWriter::new(Vec::new(), LINKTYPE_ETHERNET).unwrap();
+        for (ts, len, frame) in &written() {
+            writer.write_raw(*ts, *len, frame).unwrap();
+        }
+        let mut capture = FileCapture::new(Cursor::new(writer.into_inner().unwrap())).unwrap();
+        assert_eq!(capture.format(), FileFormat::Pcap);
+        assert_eq!(read_all(&mut capture), written());
+        assert_eq!(capture.link_type(), Some(LINKTYPE_ETHERNET));
+        assert_eq!(capture.totals().packets, 3);
+    }
+
+    #[test]
+    fn reads_back_a_pcapng_file() {
+        let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+        let id = writer
+            .add_interface("eth0", LINKTYPE_ETHERNET, 65535)
+            .unwrap();
+        for (ts, len, frame) in &written() {
+            writer.write_raw(id, *ts, *len, frame).unwrap();
+        }
+        let mut capture = FileCapture::new(Cursor::new(writer.into_inner().unwrap())).unwrap();
+        assert_eq!(capture.format(), FileFormat::Pcapng);
+        assert_eq!(read_all(&mut capture), written());
+        assert_eq!(capture.interface_name(id), Some("eth0"));
+    }
+
+    #[test]
+    fn hands_out_one_packet_at_a_time() {
+        let mut writer = PcapWriter::new(Vec::new(), LINKTYPE_ETHERNET).unwrap();
+        for (ts, len, frame) in &written() {
+            writer.write_raw(*ts, *len, frame).unwrap();
+        }
+        let mut capture = FileCapture::new(Cursor::new(writer.into_inner().unwrap())).unwrap();
+        let mut seen = Vec::new();
+        let capture: &mut dyn Capture = &mut capture;
+        while capture.next_packet(&mut |p| seen.push(p.l2()[11])).unwrap() == 1 {}
+        assert_eq!(seen, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn rejects_a_timestamp_past_system_time() {
+        let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+        let id = writer
+            .add_interface("eth0", LINKTYPE_ETHERNET, 65535)
+            .unwrap();
+        let far = UNIX_EPOCH + Duration::from_secs(10_000_000_000);
+        writer.write_raw(id, far, 60, &frame(0)).unwrap();
+        let mut file = writer.into_inner().unwrap();
+        //turn the nanosecond if_tsresol into whole seconds, so the timestamp is 1e19 seconds
+        let mut tsresol = IF_TSRESOL.to_ne_bytes().to_vec();
+        tsresol.extend_from_slice(&1u16.to_ne_bytes());
+        tsresol.push(9);
+        let at = file
+            .windows(tsresol.len())
+            .position(|w| w == &tsresol[..])
+            .unwrap();
+        file[at + 4] = 0;
+        let mut capture = FileCapture::new(Cursor::new(file)).unwrap();
+        let err = capture.next_packet().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
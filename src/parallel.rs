@@ -0,0 +1,71 @@
+//!Data parallelism within a block, for per-packet work heavy enough (decryption, DPI) that one
+//!thread can't keep up with one ring. Each retired block's packets are split across scoped
+//!threads and the block is only handed back to the kernel once all of them are done, so packets
+//!are never read after their block is reused and blocks go back in the order they were retired.
+
+use std::cmp;
+use std::thread;
+use std::time::Duration;
+
+use rx::{RawPacket, Ring};
+
+///Calls `f` on every packet of `packets`, split into `threads` contiguous chunks processed at once
+pub fn par_for_each<F>(packets: &[RawPacket<'_>], threads: usize, f: &F)
+where
+    F: Fn(&RawPacket<'_>) + Sync,
+{
+    let threads = cmp::max(threads, 1);
+    if threads == 1 || packets.len() < 2 {
+        packets.iter().for_each(f);
+        return;
+    }
+    let chunk = (packets.len() + threads - 1) / threads;
+    thread::scope(|scope| {
+        let mut chunks = packets.chunks(chunk);
+        //the calling thread takes the first chunk itself
+        let first = chunks.next();
+        for c in chunks {
+            scope.spawn(move || c.iter().for_each(f));
+        }
+        if let Some(c) = first {
+            c.iter().for_each(f);
+        }
+    });
+}
+
+///Processes a ring's blocks with `threads` threads each
+pub struct ParallelRing {
+    ring: Ring,
+    threads: usize,
+}
+
+impl ParallelRing {
+    pub fn new(ring: Ring, threads: usize) -> ParallelRing {
+        ParallelRing { ring, threads }
+    }
+
+    ///Waits up to `timeout` for a block, calls `f` on its packets in parallel and consumes the
+    ///block. Returns the number of packets, or `None` if no block was retired in time
+    pub fn next_block<F>(&mut self, timeout: Duration, f: &F) -> Option<usize>
+    where
+        F: Fn(&RawPacket<'_>) + Sync,
+    {
+        let mut block = self.ring.get_block_timeout(timeout)?;
+        let packets = block.get_raw_packets();
+        par_for_each(&packets, self.threads, f);
+        let count = packets.len();
+        drop(packets);
+        block.mark_as_consumed();
+        Some(count)
+    }
+
+    ///Returns the ring
+    pub fn ring(&mut self) -> &mut Ring {
+        &mut self.ring
+    }
+
+    ///Returns the ring, giving up the adapter
+    pub fn into_inner(self) -> Ring {
+        self.ring
+    }
+}
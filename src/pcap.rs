@@ -0,0 +1,249 @@
+//!Writes packets to classic pcap files. Records carry the packet's timestamp at nanosecond
+//!resolution, its captured and wire lengths, and are framed for the file's link type: VLAN tags
+//!the kernel stripped are put back into Ethernet frames, and cooked packets get the
+//!LINKTYPE_LINUX_SLL header built from their sockaddr_ll. Wrap the output in a `BufWriter`,
+//!every record is one `write_all`.
+
+use std::io::{self, Write};
+#[cfg(target_os = "linux")]
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anonymize::Anonymizer;
+use linktype::{LINKTYPE_ETHERNET, LINKTYPE_LINUX_SLL};
+#[cfg(target_os = "linux")]
+use rx::Ring;
+use rx::{OwnedPacket, RawPacket};
+use tpacket3::SockaddrLl;
+use vlan::{self, VlanTag};
+
+const MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const VERSION_MAJOR: u16 = 2;
+const VERSION_MINOR: u16 = 4;
+///Snaplen written in the file header, larger than any frame a ring hands out
+pub const DEFAULT_SNAPLEN: u32 = 262_144;
+//pkttype, hatype, halen, 8 bytes of address and protocol
+const SLL_HDR_LEN: usize = 16;
+
+///Resolution of the record timestamps, which sets the file's magic number
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimestampPrecision {
+    ///0xa1b2c3d4 files, readable by every pcap tool
+    Micros,
+    ///0xa1b23c4d files, keeping the kernel's full resolution
+    Nanos,
+}
+
+///Writes a pcap file header and then one record per packet
+#[derive(Debug)]
+pub struct PcapWriter<W: Write> {
+    out: W,
+    link_type: u32,
+    snaplen: u32,
+    precision: TimestampPrecision,
+    records: u64,
+    anonymizer: Option<Anonymizer>,
+    frame: Vec<u8>,
+    record: Vec<u8>,
+}
+
+impl<W: Write> PcapWriter<W> {
+    ///Writes the header of a nanosecond file with `link_type` (`linktype::LINKTYPE_*`) to `out`
+    pub fn new(out: W, link_type: u32) -> io::Result<PcapWriter<W>> {
+        PcapWriter::with_options(out, link_type, DEFAULT_SNAPLEN, TimestampPrecision::Nanos)
+    }
+
+    ///Like `new` but with the snaplen and timestamp resolution given. Packets are cut to
+    ///`snaplen` as they are written
+    pub fn with_options(
+        mut out: W,
+        link_type: u32,
+        snaplen: u32,
+        precision: TimestampPrecision,
+    ) -> io::Result<PcapWriter<W>> {
+        let magic = match precision {
+            TimestampPrecision::Micros => MAGIC_MICROS,
+            TimestampPrecision::Nanos => MAGIC_NANOS,
+        };
+        let mut hdr = Vec::with_capacity(24);
+        hdr.extend_from_slice(&magic.to_ne_bytes());
+        hdr.extend_from_slice(&VERSION_MAJOR.to_ne_bytes());
+        hdr.extend_from_slice(&VERSION_MINOR.to_ne_bytes());
+        //thiszone and sigfigs, always 0
+        hdr.extend_from_slice(&[0; 8]);
+        hdr.extend_from_slice(&snaplen.to_ne_bytes());
+        hdr.extend_from_slice(&link_type.to_ne_bytes());
+        out.write_all(&hdr)?;
+        Ok(PcapWriter {
+            out,
+            link_type,
+            snaplen,
+            precision,
+            records: 0,
+            anonymizer: None,
+            frame: Vec::new(),
+            record: Vec::new(),
+        })
+    }
+
+    ///Writes a nanosecond file for `ring`'s packets. Fails with `InvalidInput` if the ring has no
+    ///single link type, as on a raw socket bound to `socket::ANY_INTERFACE`; capture those cooked
+    #[cfg(target_os = "linux")]
+    pub fn for_ring(out: W, ring: &Ring) -> io::Result<PcapWriter<W>> {
+        match ring.link_type() {
+            Some(link_type) => PcapWriter::new(out, link_type),
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "ring {} has no single link type, capture it cooked to write it to pcap",
+                    ring.label()
+                ),
+            )),
+        }
+    }
+
+    ///Writes a record for a packet from a ring or another backend
+    pub fn write_packet(&mut self, packet: &RawPacket<'_>) -> io::Result<()> {
+        let sll = packet.sockaddr_ll();
+        self.write_frame(
+            packet.timestamp(),
+            packet.wire_len(),
+            packet.l2(),
+            packet.vlan_tag(),
+            sll.as_ref(),
+        )
+    }
+
+    ///Writes a record for a packet copied out of the ring
+    pub fn write_owned(&mut self, packet: &OwnedPacket) -> io::Result<()> {
+        self.write_frame(
+            packet.timestamp(),
+            packet.wire_len(),
+            packet.l2(),
+            packet.vlan_tag(),
+            packet.sockaddr_ll.as_ref(),
+        )
+    }
+
+    ///Writes a record for a frame that is already framed for the file's link type
+    pub fn write_raw(
+        &mut self,
+        timestamp: SystemTime,
+        wire_len: u32,
+        frame: &[u8],
+    ) -> io::Result<()> {
+        self.write_frame(timestamp, wire_len, frame, None, None)
+    }
+
+    fn write_frame(
+        &mut self,
+        timestamp: SystemTime,
+        wire_len: u32,
+        l2: &[u8],
+        vlan_tag: Option<VlanTag>,
+        sll: Option<&SockaddrLl>,
+    ) -> io::Result<()> {
+        let mut frame = std::mem::take(&mut self.frame);
+        let added = frame_for_link(self.link_type, l2, vlan_tag, sll, &mut frame);
+        if let Some(ref anonymizer) = self.anonymizer {
+            anonymizer.apply(self.link_type, &mut frame);
+        }
+        let result = self.write_record(timestamp, wire_len.saturating_add(added), &frame);
+        self.frame = frame;
+        result
+    }
+
+    fn write_record(
+        &mut self,
+        timestamp: SystemTime,
+        orig_len: u32,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let since_epoch = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        let fraction = match self.precision {
+            TimestampPrecision::Micros => since_epoch.subsec_micros(),
+            TimestampPrecision::Nanos => since_epoch.subsec_nanos(),
+        };
+        let incl = data.len().min(self.snaplen as usize);
+        self.record.clear();
+        self.record
+            .extend_from_slice(&(since_epoch.as_secs() as u32).to_ne_bytes());
+        self.record.extend_from_slice(&fraction.to_ne_bytes());
+        self.record.extend_from_slice(&(incl as u32).to_ne_bytes());
+        self.record
+            .extend_from_slice(&orig_len.max(incl as u32).to_ne_bytes());
+        self.record.extend_from_slice(&data[..incl]);
+        self.out.write_all(&self.record)?;
+        self.records += 1;
+        Ok(())
+    }
+
+    ///Scrubs every record written from now on with `anonymizer`, or stops scrubbing with `None`
+    pub fn set_anonymizer(&mut self, anonymizer: Option<Anonymizer>) {
+        self.anonymizer = anonymizer;
+    }
+
+    ///Returns the file's link type
+    pub fn link_type(&self) -> u32 {
+        self.link_type
+    }
+
+    ///Returns the number of records written
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    ///Flushes and returns the output
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+//frames `l2` for `link_type` into `out`, returning how many bytes were added. The kernel's
+//lengths leave those out, so they are added to the record's wire length too
+pub(crate) fn frame_for_link(
+    link_type: u32,
+    l2: &[u8],
+    vlan_tag: Option<VlanTag>,
+    sll: Option<&SockaddrLl>,
+    out: &mut Vec<u8>,
+) -> u32 {
+    out.clear();
+    match (link_type, vlan_tag) {
+        (LINKTYPE_ETHERNET, Some(tag)) => vlan::reinsert_tag(l2, tag, out),
+        (LINKTYPE_LINUX_SLL, _) => {
+            sll_header(sll, out);
+            out.extend_from_slice(l2);
+        }
+        _ => out.extend_from_slice(l2),
+    }
+    out.len().saturating_sub(l2.len()) as u32
+}
+
+//the LINKTYPE_LINUX_SLL header: packet type, ARPHRD_* type, address length, the address padded
+//to 8 bytes and the protocol, all big-endian. Packets without a sockaddr_ll get a zeroed one
+fn sll_header(sll: Option<&SockaddrLl>, out: &mut Vec<u8>) {
+    let start = out.len();
+    match sll {
+        Some(sll) => {
+            out.extend_from_slice(&u16::from(sll.sll_pkttype).to_be_bytes());
+            out.extend_from_slice(&sll.sll_hatype.to_be_bytes());
+            out.extend_from_slice(&u16::from(sll.sll_halen).to_be_bytes());
+            out.extend_from_slice(&sll.sll_addr);
+            out.extend_from_slice(&sll.sll_protocol.to_be_bytes());
+        }
+        None => out.resize(start + SLL_HDR_LEN, 0),
+    }
+}
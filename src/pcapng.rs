@@ -0,0 +1,310 @@
+//!Writes packets to pcapng files. Each capture interface gets an Interface Description Block
+//!with nanosecond timestamp resolution, packets are Enhanced Packet Blocks carrying tp_nsec in
+//!full and their direction, and the kernel's drop counts can be recorded as Interface
+//!Statistics Blocks. Packets are framed as in `pcap`. Wrap the output in a `BufWriter`, every
+//!block is one `write_all`.
+
+use std::io::{self, Write};
+#[cfg(target_os = "linux")]
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anonymize::Anonymizer;
+use pcap;
+#[cfg(target_os = "linux")]
+use rx::Ring;
+use rx::{OwnedPacket, PacketType, RawPacket};
+use tpacket3::{SockaddrLl, TpacketStatsV3};
+use vlan::VlanTag;
+
+const SHB: u32 = 0x0a0d_0d0a;
+const IDB: u32 = 1;
+const ISB: u32 = 5;
+const EPB: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+const OPT_END: u16 = 0;
+const SHB_USERAPPL: u16 = 4;
+const IF_NAME: u16 = 2;
+const IF_TSRESOL: u16 = 9;
+const EPB_FLAGS: u16 = 2;
+const ISB_STARTTIME: u16 = 2;
+const ISB_IFRECV: u16 = 4;
+const ISB_IFDROP: u16 = 5;
+
+//if_tsresol of 9, timestamps count nanoseconds
+const TSRESOL_NANOS: u8 = 9;
+
+#[derive(Debug)]
+struct Interface {
+    link_type: u32,
+    snaplen: u32,
+    //PACKET_STATISTICS is reset on every read, the blocks want totals since the capture began
+    received: u64,
+    dropped: u64,
+    added: SystemTime,
+}
+
+///Writes a pcapng Section Header Block, then interfaces, packets and statistics as they come
+#[derive(Debug)]
+pub struct PcapngWriter<W: Write> {
+    out: W,
+    interfaces: Vec<Interface>,
+    packets: u64,
+    anonymizer: Option<Anonymizer>,
+    frame: Vec<u8>,
+    block: Vec<u8>,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    ///Writes the section header to `out`. Add interfaces before writing their packets
+    pub fn new(mut out: W) -> io::Result<PcapngWriter<W>> {
+        let mut block = Vec::new();
+        let mut body = Vec::new();
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_ne_bytes());
+        body.extend_from_slice(&1u16.to_ne_bytes());
+        body.extend_from_slice(&0u16.to_ne_bytes());
+        //section length, -1 as it isn't known up front
+        body.extend_from_slice(&(-1i64).to_ne_bytes());
+        option(&mut body, SHB_USERAPPL, b"af_packet");
+        option(&mut body, OPT_END, &[]);
+        frame_block(SHB, &body, &mut block);
+        out.write_all(&block)?;
+        Ok(PcapngWriter {
+            out,
+            interfaces: Vec::new(),
+            packets: 0,
+            anonymizer: None,
+            frame: Vec::new(),
+            block,
+        })
+    }
+
+    ///Writes an Interface Description Block and returns the interface id to write its packets
+    ///with. `link_type` is one of `linktype::LINKTYPE_*`
+    pub fn add_interface(&mut self, name: &str, link_type: u32, snaplen: u32) -> io::Result<u32> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&(link_type as u16).to_ne_bytes());
+        body.extend_from_slice(&0u16.to_ne_bytes());
+        body.extend_from_slice(&snaplen.to_ne_bytes());
+        option(&mut body, IF_NAME, name.as_bytes());
+        option(&mut body, IF_TSRESOL, &[TSRESOL_NANOS]);
+        option(&mut body, OPT_END, &[]);
+        self.write_block(IDB, &body)?;
+        self.interfaces.push(Interface {
+            link_type,
+            snaplen,
+            received: 0,
+            dropped: 0,
+            added: SystemTime::now(),
+        });
+        Ok(self.interfaces.len() as u32 - 1)
+    }
+
+    ///Adds `ring`'s interface with the ring's link type. Fails with `InvalidInput` if the ring
+    ///has no single link type, as on a raw socket bound to `socket::ANY_INTERFACE`
+    #[cfg(target_os = "linux")]
+    pub fn add_ring(&mut self, ring: &Ring) -> io::Result<u32> {
+        match ring.link_type() {
+            Some(link_type) => {
+                self.add_interface(&ring.label().if_name, link_type, pcap::DEFAULT_SNAPLEN)
+            }
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "ring {} has no single link type, capture it cooked to write it to pcapng",
+                    ring.label()
+                ),
+            )),
+        }
+    }
+
+    ///Writes an Enhanced Packet Block for a packet from a ring or another backend
+    pub fn write_packet(&mut self, interface: u32, packet: &RawPacket<'_>) -> io::Result<()> {
+        let sll = packet.sockaddr_ll();
+        self.write_frame(
+            interface,
+            packet.timestamp(),
+            packet.wire_len(),
+            packet.l2(),
+            packet.vlan_tag(),
+            sll.as_ref(),
+        )
+    }
+
+    ///Writes an Enhanced Packet Block for a packet copied out of the ring
+    pub fn write_owned(&mut self, interface: u32, packet: &OwnedPacket) -> io::Result<()> {
+        self.write_frame(
+            interface,
+            packet.timestamp(),
+            packet.wire_len(),
+            packet.l2(),
+            packet.vlan_tag(),
+            packet.sockaddr_ll.as_ref(),
+        )
+    }
+
+    ///Writes an Enhanced Packet Block for a frame already framed for the interface's link type
+    pub fn write_raw(
+        &mut self,
+        interface: u32,
+        timestamp: SystemTime,
+        wire_len: u32,
+        frame: &[u8],
+    ) -> io::Result<()> {
+        self.write_frame(interface, timestamp, wire_len, frame, None, None)
+    }
+
+    ///Adds kernel statistics read with `get_rx_statistics` to the interface's totals and writes
+    ///them as an Interface Statistics Block. The counts in the block are totals since the
+    ///interface was added
+    pub fn write_statistics(&mut self, interface: u32, stats: &TpacketStatsV3) -> io::Result<()> {
+        let (start, received, dropped) = {
+            let iface = self.interface(interface)?;
+            iface.received += u64::from(stats.tp_packets);
+            iface.dropped += u64::from(stats.tp_drops);
+            (iface.added, iface.received, iface.dropped)
+        };
+        let mut body = Vec::new();
+        body.extend_from_slice(&interface.to_ne_bytes());
+        push_timestamp(&mut body, SystemTime::now());
+        let mut starttime = Vec::with_capacity(8);
+        push_timestamp(&mut starttime, start);
+        option(&mut body, ISB_STARTTIME, &starttime);
+        option(&mut body, ISB_IFRECV, &received.to_ne_bytes());
+        option(&mut body, ISB_IFDROP, &dropped.to_ne_bytes());
+        option(&mut body, OPT_END, &[]);
+        self.write_block(ISB, &body)
+    }
+
+    ///Reads `ring`'s kernel statistics, resetting them, and writes them for `interface`
+    #[cfg(target_os = "linux")]
+    pub fn write_ring_statistics(&mut self, interface: u32, ring: &Ring) -> io::Result<()> {
+        let stats = ring.get_rx_statistics()?;
+        self.write_statistics(interface, &stats.stats)
+    }
+
+    fn write_frame(
+        &mut self,
+        interface: u32,
+        timestamp: SystemTime,
+        wire_len: u32,
+        l2: &[u8],
+        vlan_tag: Option<VlanTag>,
+        sll: Option<&SockaddrLl>,
+    ) -> io::Result<()> {
+        let (link_type, snaplen) = {
+            let iface = self.interface(interface)?;
+            (iface.link_type, iface.snaplen)
+        };
+        let mut frame = std::mem::take(&mut self.frame);
+        let added = pcap::frame_for_link(link_type, l2, vlan_tag, sll, &mut frame);
+        if let Some(ref anonymizer) = self.anonymizer {
+            anonymizer.apply(link_type, &mut frame);
+        }
+        let incl = frame.len().min(snaplen as usize);
+        let orig = wire_len.saturating_add(added).max(incl as u32);
+        let mut body = Vec::with_capacity(32 + incl);
+        body.extend_from_slice(&interface.to_ne_bytes());
+        push_timestamp(&mut body, timestamp);
+        body.extend_from_slice(&(incl as u32).to_ne_bytes());
+        body.extend_from_slice(&orig.to_ne_bytes());
+        body.extend_from_slice(&frame[..incl]);
+        pad(&mut body);
+        self.frame = frame;
+        if let Some(flags) = sll.map(epb_flags) {
+            option(&mut body, EPB_FLAGS, &flags.to_ne_bytes());
+            option(&mut body, OPT_END, &[]);
+        }
+        self.write_block(EPB, &body)?;
+        self.packets += 1;
+        Ok(())
+    }
+
+    fn interface(&mut self, interface: u32) -> io::Result<&mut Interface> {
+        self.interfaces.get_mut(interface as usize).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no pcapng interface {}", interface),
+            )
+        })
+    }
+
+    fn write_block(&mut self, kind: u32, body: &[u8]) -> io::Result<()> {
+        frame_block(kind, body, &mut self.block);
+        self.out.write_all(&self.block)
+    }
+
+    ///Scrubs every packet written from now on with `anonymizer`, or stops scrubbing with `None`
+    pub fn set_anonymizer(&mut self, anonymizer: Option<Anonymizer>) {
+        self.anonymizer = anonymizer;
+    }
+
+    ///Returns the number of interfaces added
+    pub fn interfaces(&self) -> usize {
+        self.interfaces.len()
+    }
+
+    ///Returns the number of packets written
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    ///Flushes and returns the output
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+//block type, total length, body and total length again. Bodies are already padded to 32 bits
+fn frame_block(kind: u32, body: &[u8], out: &mut Vec<u8>) {
+    let len = (12 + body.len()) as u32;
+    out.clear();
+    out.extend_from_slice(&kind.to_ne_bytes());
+    out.extend_from_slice(&len.to_ne_bytes());
+    out.extend_from_slice(body);
+    out.extend_from_slice(&len.to_ne_bytes());
+}
+
+fn option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_ne_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_ne_bytes());
+    body.extend_from_slice(value);
+    pad(body);
+}
+
+fn pad(body: &mut Vec<u8>) {
+    let padded = (body.len() + 3) & !3;
+    body.resize(padded, 0);
+}
+
+//nanoseconds since the epoch, high 32 bits first as the format wants
+fn push_timestamp(body: &mut Vec<u8>, timestamp: SystemTime) {
+    let nanos = timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::from_secs(0))
+        .as_nanos() as u64;
+    body.extend_from_slice(&((nanos >> 32) as u32).to_ne_bytes());
+    body.extend_from_slice(&(nanos as u32).to_ne_bytes());
+}
+
+//direction in bits 0-1 and reception type in bits 2-4, from the sockaddr_ll packet type
+fn epb_flags(sll: &SockaddrLl) -> u32 {
+    match PacketType::from(sll.sll_pkttype) {
+        PacketType::Host => 1 | 1 << 2,
+        PacketType::Multicast => 1 | 2 << 2,
+        PacketType::Broadcast => 1 | 3 << 2,
+        PacketType::OtherHost => 1 | 4 << 2,
+        PacketType::Outgoing => 2,
+        PacketType::Other(_) => 0,
+    }
+}
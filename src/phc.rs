@@ -0,0 +1,166 @@
+//!Reading a NIC's PTP hardware clock (PHC), the clock its raw hardware timestamps come from, and
+//!turning those timestamps into system time. A PHC is a character device /dev/ptpN whose
+//!descriptor doubles as a dynamic clock id for clock_gettime. PTP_SYS_OFFSET reads it between
+//!pairs of system clock readings, and the tightest pair gives the offset between the two clocks.
+//!Unless ptp4l and phc2sys keep them in step, the clocks drift apart, so the offset should be
+//!measured again every second or so while converting.
+
+use libc::{c_ulong, clock_gettime, clockid_t, ioctl, timespec};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Error, ErrorKind};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ethtool;
+use socket::Socket;
+
+const PTP_MAX_SAMPLES: usize = 25;
+//_IOW('=', 5, struct ptp_sys_offset), the 0x340 being its size
+const PTP_SYS_OFFSET: c_ulong = 0x4340_3d05;
+
+//struct ptp_clock_time
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct PtpClockTime {
+    sec: i64,
+    nsec: u32,
+    reserved: u32,
+}
+
+//struct ptp_sys_offset: system, PHC, system, ... readings, n_samples PHC ones in all
+#[repr(C)]
+struct PtpSysOffset {
+    n_samples: u32,
+    rsv: [u32; 3],
+    ts: [PtpClockTime; 2 * PTP_MAX_SAMPLES + 1],
+}
+
+impl PtpClockTime {
+    fn nanos(&self) -> i128 {
+        i128::from(self.sec) * 1_000_000_000 + i128::from(self.nsec)
+    }
+}
+
+///An open PTP hardware clock
+#[derive(Debug)]
+pub struct PhcClock {
+    file: File,
+}
+
+///How far a PHC was ahead of the system clock at one moment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PhcOffset {
+    ///PHC time minus system time, in nanoseconds
+    pub offset_ns: i64,
+    ///How long the system clock readings around the PHC reading were apart, which bounds the
+    ///offset's error
+    pub uncertainty: Duration,
+    ///The system time the offset was measured at
+    pub measured_at: SystemTime,
+}
+
+impl PhcOffset {
+    ///Converts a time read from the PHC, such as a raw hardware timestamp, to system time
+    pub fn to_system_time(&self, phc: Duration) -> SystemTime {
+        let nanos = phc.as_nanos() as i128 - i128::from(self.offset_ns);
+        from_nanos(nanos)
+    }
+
+    ///Converts a raw hardware packet timestamp, as `RawPacket::timestamp` returns it, to system
+    ///time
+    pub fn correct(&self, hw: SystemTime) -> SystemTime {
+        self.to_system_time(hw.duration_since(UNIX_EPOCH).unwrap_or_default())
+    }
+}
+
+impl PhcClock {
+    ///Opens /dev/ptp`index`
+    pub fn open(index: u32) -> io::Result<PhcClock> {
+        PhcClock::open_path(format!("/dev/ptp{}", index))
+    }
+
+    ///Opens a PHC device by path
+    pub fn open_path<P: AsRef<Path>>(path: P) -> io::Result<PhcClock> {
+        let file = OpenOptions::new().read(true).open(path)?;
+        Ok(PhcClock { file })
+    }
+
+    ///Opens the PHC of the socket's interface, found with ETHTOOL_GET_TS_INFO. Fails with
+    ///`NotFound` if the interface has none
+    pub fn for_socket(sock: &Socket) -> io::Result<PhcClock> {
+        let info = ethtool::get_ts_info(sock)?;
+        if info.phc_index < 0 {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{} has no PTP hardware clock", sock.if_name),
+            ));
+        }
+        PhcClock::open(info.phc_index as u32)
+    }
+
+    ///Returns the clock id to pass to clock_gettime and friends, valid while the clock is open
+    pub fn clock_id(&self) -> clockid_t {
+        //FD_TO_CLOCKID
+        ((!self.file.as_raw_fd()) << 3) | 3
+    }
+
+    ///Reads the PHC's time, counted from its own epoch
+    pub fn now(&self) -> io::Result<Duration> {
+        let mut ts = timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        match unsafe { clock_gettime(self.clock_id(), &mut ts) } {
+            0 => Ok(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)),
+            _ => Err(Error::last_os_error()),
+        }
+    }
+
+    ///Measures the PHC's offset from the system clock with PTP_SYS_OFFSET, keeping the best of
+    ///`samples` readings, up to 25
+    pub fn sys_offset(&self, samples: u32) -> io::Result<PhcOffset> {
+        let n = (samples as usize).clamp(1, PTP_MAX_SAMPLES);
+        let mut req = PtpSysOffset {
+            n_samples: n as u32,
+            rsv: [0; 3],
+            ts: [PtpClockTime::default(); 2 * PTP_MAX_SAMPLES + 1],
+        };
+        if unsafe { ioctl(self.file.as_raw_fd(), PTP_SYS_OFFSET as _, &mut req) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        let best = (0..n)
+            .map(|i| (&req.ts[2 * i], &req.ts[2 * i + 1], &req.ts[2 * i + 2]))
+            .min_by_key(|(before, _, after)| after.nanos() - before.nanos())
+            .expect("at least one sample");
+        let (before, phc, after) = best;
+        let sys = (before.nanos() + after.nanos()) / 2;
+        Ok(PhcOffset {
+            offset_ns: (phc.nanos() - sys) as i64,
+            uncertainty: Duration::from_nanos((after.nanos() - before.nanos()).max(0) as u64),
+            measured_at: from_nanos(sys),
+        })
+    }
+
+    ///Converts a time read from the PHC to system time, measuring the offset afresh. For many
+    ///timestamps at once, measure it with `sys_offset` and convert with that
+    pub fn to_system_time(&self, phc: Duration) -> io::Result<SystemTime> {
+        Ok(self.sys_offset(PTP_MAX_SAMPLES as u32)?.to_system_time(phc))
+    }
+}
+
+impl AsRawFd for PhcClock {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+fn from_nanos(nanos: i128) -> SystemTime {
+    let secs = nanos.div_euclid(1_000_000_000);
+    let sub = nanos.rem_euclid(1_000_000_000) as u32;
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::new(secs as u64, sub)
+    } else {
+        UNIX_EPOCH - Duration::new((-secs) as u64, 0) + Duration::new(0, sub)
+    }
+}
@@ -0,0 +1,277 @@
+//!Checks the process' effective capabilities up front, so a daemon missing one fails at startup
+//!with a message naming it rather than on the first ring it opens, and gives them up once the
+//!rings are open with `drop_privileges`.
+
+use libc::{
+    c_char, c_int, geteuid, getpwnam_r, getuid, passwd, prctl, setgroups, setresgid, setresuid,
+    syscall, SYS_capset, PACKET_VERSION, PR_CAP_AMBIENT, PR_CAP_AMBIENT_CLEAR_ALL, PR_SET_KEEPCAPS,
+    PR_SET_NO_NEW_PRIVS,
+};
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+
+use rx::RingSettings;
+use socket::get_sock_opt;
+
+///A capability rings may need
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    ///Opening any AF_PACKET socket, joining multicast groups and PACKET_MR_PROMISC
+    NetRaw,
+    ///Changing offloads with ethtool and setting interface flags with SIOCSIFFLAGS
+    NetAdmin,
+}
+
+impl Capability {
+    //bit in the capability sets, from linux/capability.h
+    fn bit(self) -> u32 {
+        match self {
+            Capability::NetAdmin => 12,
+            Capability::NetRaw => 13,
+        }
+    }
+
+    ///Says what the capability is needed for
+    pub fn needed_for(self) -> &'static str {
+        match self {
+            Capability::NetRaw => "opening AF_PACKET sockets",
+            Capability::NetAdmin => "disabling offloads and changing interface flags",
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Capability::NetRaw => f.write_str("CAP_NET_RAW"),
+            Capability::NetAdmin => f.write_str("CAP_NET_ADMIN"),
+        }
+    }
+}
+
+///The process' effective capability set
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Privileges {
+    effective: u64,
+}
+
+impl Privileges {
+    ///Reads the process' effective set from /proc/self/status
+    pub fn current() -> io::Result<Privileges> {
+        let status = fs::read_to_string("/proc/self/status")?;
+        let effective = status
+            .lines()
+            .find_map(|l| l.strip_prefix("CapEff:"))
+            .and_then(|v| u64::from_str_radix(v.trim(), 16).ok())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "no CapEff in /proc/self/status"))?;
+        Ok(Privileges { effective })
+    }
+
+    ///Returns true if `cap` is in the effective set
+    pub fn has(&self, cap: Capability) -> bool {
+        self.effective & (1 << cap.bit()) != 0
+    }
+
+    ///Returns the capabilities of `needed` that are missing, in the same order
+    pub fn missing(&self, needed: &[Capability]) -> Vec<Capability> {
+        needed.iter().copied().filter(|&c| !self.has(c)).collect()
+    }
+
+    ///Fails with `PermissionDenied` naming each capability of `needed` that is missing and what
+    ///it is for
+    pub fn require(&self, needed: &[Capability]) -> io::Result<()> {
+        let missing = self.missing(needed);
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let list: Vec<String> = missing
+            .iter()
+            .map(|c| format!("{} (for {})", c, c.needed_for()))
+            .collect();
+        Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "missing {}. Run as root, or grant the missing capabilities with setcap or the \
+                 container's --cap-add",
+                list.join(", ")
+            ),
+        ))
+    }
+}
+
+///Checks that the process has CAP_NET_RAW and CAP_NET_ADMIN, failing with a message naming the
+///ones it lacks
+pub fn check_privileges() -> io::Result<()> {
+    Privileges::current()?.require(&[Capability::NetRaw, Capability::NetAdmin])
+}
+
+///Checks for the capabilities rings opened with `settings` need: CAP_NET_RAW, and CAP_NET_ADMIN
+///if `disable_offloads` is set
+pub fn check_privileges_for(settings: &RingSettings) -> io::Result<()> {
+    let mut needed = vec![Capability::NetRaw];
+    if settings.disable_offloads {
+        needed.push(Capability::NetAdmin);
+    }
+    Privileges::current()?.require(&needed)
+}
+
+///What `drop_privileges` gives up once the rings are open
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DropConfig {
+    ///User to switch to, real, effective and saved
+    pub uid: Option<u32>,
+    ///Group to switch to. Supplementary groups are cleared whenever either id changes
+    pub gid: Option<u32>,
+    ///Capabilities to keep, e.g. CAP_NET_ADMIN for a sensor that reopens rings when interfaces
+    ///come back. Every other one is dropped from the effective, permitted and inheritable sets
+    pub keep: Vec<Capability>,
+    ///Sets PR_SET_NO_NEW_PRIVS, so nothing the process executes can gain privileges back
+    pub no_new_privs: bool,
+}
+
+impl DropConfig {
+    ///Switches to the named user and its primary group, dropping every capability
+    pub fn for_user(name: &str) -> io::Result<DropConfig> {
+        let c_name = CString::new(name)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "user name contains a NUL"))?;
+        let mut pwd: passwd = unsafe { mem::zeroed() };
+        let mut buf = vec![0 as c_char; 4096];
+        let mut found: *mut passwd = ptr::null_mut();
+        let ret = unsafe {
+            getpwnam_r(
+                c_name.as_ptr(),
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut found,
+            )
+        };
+        if ret != 0 {
+            return Err(Error::from_raw_os_error(ret));
+        }
+        if found.is_null() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("no user named {}", name),
+            ));
+        }
+        Ok(DropConfig {
+            uid: Some(pwd.pw_uid),
+            gid: Some(pwd.pw_gid),
+            keep: Vec::new(),
+            no_new_privs: true,
+        })
+    }
+
+    fn keep_mask(&self) -> u64 {
+        self.keep.iter().fold(0, |mask, cap| mask | 1 << cap.bit())
+    }
+}
+
+//struct __user_cap_header_struct and __user_cap_data_struct, for capset(2)
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+///Gives up the uid, gid and capabilities `config` says once `rings` are open, then checks it
+///worked: that only the kept capabilities are left, the ids are the new ones, and every ring's
+///socket still answers. Rings keep capturing without privileges, the checks were only needed to
+///open them. Steps before a failing one stay done, so treat an error as fatal
+pub fn drop_privileges<R: AsRawFd>(config: &DropConfig, rings: &[R]) -> io::Result<()> {
+    let keep = config.keep_mask();
+    if config.uid.is_some() || config.gid.is_some() {
+        check(unsafe { setgroups(0, ptr::null()) }, "setgroups")?;
+    }
+    if let Some(gid) = config.gid {
+        check(unsafe { setresgid(gid, gid, gid) }, "setresgid")?;
+    }
+    if let Some(uid) = config.uid {
+        //otherwise leaving uid 0 clears the permitted set, and the kept ones with it
+        if keep != 0 {
+            check(
+                unsafe { prctl(PR_SET_KEEPCAPS, 1, 0, 0, 0) },
+                "PR_SET_KEEPCAPS",
+            )?;
+        }
+        check(unsafe { setresuid(uid, uid, uid) }, "setresuid")?;
+    }
+    let mut header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapData::default(); 2];
+    for (i, d) in data.iter_mut().enumerate() {
+        let word = (keep >> (32 * i)) as u32;
+        d.effective = word;
+        d.permitted = word;
+    }
+    check(
+        unsafe { syscall(SYS_capset, &mut header as *mut CapHeader, data.as_ptr()) } as c_int,
+        "capset",
+    )?;
+    //kernels before 4.3 have no ambient set, there is nothing to clear then
+    unsafe { prctl(PR_CAP_AMBIENT, PR_CAP_AMBIENT_CLEAR_ALL, 0, 0, 0) };
+    if config.no_new_privs {
+        check(
+            unsafe { prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) },
+            "PR_SET_NO_NEW_PRIVS",
+        )?;
+    }
+    verify(config, keep, rings)
+}
+
+fn verify<R: AsRawFd>(config: &DropConfig, keep: u64, rings: &[R]) -> io::Result<()> {
+    let left = Privileges::current()?.effective & !keep;
+    if left != 0 {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("capabilities {:#x} are still effective", left),
+        ));
+    }
+    if let Some(uid) = config.uid {
+        if unsafe { getuid() } != uid || unsafe { geteuid() } != uid {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("still not running as uid {}", uid),
+            ));
+        }
+    }
+    for ring in rings {
+        let mut version: c_int = 0;
+        get_sock_opt(ring.as_raw_fd(), PACKET_VERSION, &mut version).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("ring socket {} unusable: {}", ring.as_raw_fd(), e),
+            )
+        })?;
+    }
+    trace_event!(Info, "privileges dropped, {} rings still open", rings.len());
+    Ok(())
+}
+
+fn check(ret: c_int, what: &str) -> io::Result<()> {
+    match ret {
+        0 => Ok(()),
+        _ => {
+            let e = Error::last_os_error();
+            Err(Error::new(e.kind(), format!("{}: {}", what, e)))
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//!Probes what the running kernel supports before a ring is committed to, so unsupported settings
+//!fail with a descriptive error instead of a bare EINVAL from setsockopt
+
+use std::io::{self, Error, ErrorKind};
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+use libc::{c_int, close, getpid, ARPHRD_LOOPBACK, EINVAL};
+
+use error::ConfigError;
+use rx::{self, RingSettings, PACKET_FANOUT, PACKET_VERSION};
+use simple::SimpleCapture;
+use socket::{self, Socket};
+use tpacket2::TPACKET_V2;
+use tpacket3::TPACKET_V3;
+
+pub const PACKET_FANOUT_CPU: c_int = 2;
+pub const PACKET_FANOUT_ROLLOVER: c_int = 3;
+pub const PACKET_FANOUT_RND: c_int = 4;
+pub const PACKET_FANOUT_QM: c_int = 5;
+pub const PACKET_FANOUT_CBPF: c_int = 6;
+pub const PACKET_FANOUT_EBPF: c_int = 7;
+
+pub const PACKET_FANOUT_FLAG_ROLLOVER: c_int = 0x1000;
+pub const PACKET_FANOUT_FLAG_UNIQUEID: c_int = 0x2000;
+pub const PACKET_FANOUT_FLAG_DEFRAG: c_int = 0x8000;
+
+const FANOUT_MODES: [c_int; 8] = [
+    rx::PACKET_FANOUT_HASH,
+    rx::PACKET_FANOUT_LB,
+    PACKET_FANOUT_CPU,
+    PACKET_FANOUT_ROLLOVER,
+    PACKET_FANOUT_RND,
+    PACKET_FANOUT_QM,
+    PACKET_FANOUT_CBPF,
+    PACKET_FANOUT_EBPF,
+];
+
+///What the running kernel supports on a given interface
+#[derive(Clone, Debug, Default)]
+pub struct KernelSupport {
+    pub tpacket_v3: bool,
+    pub tpacket_v2: bool,
+    ///Fanout modes (PACKET_FANOUT_HASH, PACKET_FANOUT_LB, ...) that could be joined
+    pub fanout_modes: Vec<c_int>,
+    pub fanout_flag_rollover: bool,
+    pub fanout_flag_defrag: bool,
+}
+
+impl KernelSupport {
+    #[inline]
+    pub fn supports_fanout(&self, method: c_int) -> bool {
+        self.fanout_modes.contains(&(method & 0xff))
+    }
+}
+
+///Returns a readable name for a fanout mode
+pub fn fanout_mode_name(method: c_int) -> &'static str {
+    match method & 0xff {
+        rx::PACKET_FANOUT_HASH => "PACKET_FANOUT_HASH",
+        rx::PACKET_FANOUT_LB => "PACKET_FANOUT_LB",
+        PACKET_FANOUT_CPU => "PACKET_FANOUT_CPU",
+        PACKET_FANOUT_ROLLOVER => "PACKET_FANOUT_ROLLOVER",
+        PACKET_FANOUT_RND => "PACKET_FANOUT_RND",
+        PACKET_FANOUT_QM => "PACKET_FANOUT_QM",
+        PACKET_FANOUT_CBPF => "PACKET_FANOUT_CBPF",
+        PACKET_FANOUT_EBPF => "PACKET_FANOUT_EBPF",
+        _ => "unknown fanout mode",
+    }
+}
+
+///Probes the kernel using throwaway sockets on `if_name`. This needs the same privileges as
+///opening a ring
+pub fn probe(if_name: &str) -> io::Result<KernelSupport> {
+    let mut support = KernelSupport {
+        tpacket_v3: version_supported(if_name, TPACKET_V3)?,
+        tpacket_v2: version_supported(if_name, TPACKET_V2)?,
+        ..KernelSupport::default()
+    };
+    for &mode in FANOUT_MODES.iter() {
+        if fanout_supported(if_name, mode)? {
+            support.fanout_modes.push(mode);
+        }
+    }
+    support.fanout_flag_rollover = fanout_supported(
+        if_name,
+        rx::PACKET_FANOUT_HASH | PACKET_FANOUT_FLAG_ROLLOVER,
+    )?;
+    support.fanout_flag_defrag =
+        fanout_supported(if_name, rx::PACKET_FANOUT_HASH | PACKET_FANOUT_FLAG_DEFRAG)?;
+    Ok(support)
+}
+
+///Returns true if PACKET_VERSION accepts `version`
+pub fn version_supported(if_name: &str, version: c_int) -> io::Result<bool> {
+    with_probe_socket(if_name, |sock| {
+        is_supported(sock.setsockopt(PACKET_VERSION, version))
+    })
+}
+
+///Returns true if a socket bound to `if_name` can join a new fanout group with `method` (mode
+///and flags)
+pub fn fanout_supported(if_name: &str, method: c_int) -> io::Result<bool> {
+    with_probe_socket(if_name, |sock| {
+        rx::bind_ring(sock)?;
+        //a kernel-assigned group is used so the probe can't collide with a real group, falling
+        //back to an arbitrary id on kernels that predate PACKET_FANOUT_FLAG_UNIQUEID
+        let unique = (method | PACKET_FANOUT_FLAG_UNIQUEID) << 16;
+        if is_supported(sock.setsockopt(PACKET_FANOUT, unique))? {
+            return Ok(true);
+        }
+        let fallback = (rx::fanout_word(method) ^ 0x5a5a) & 0xFFFF | (method << 16);
+        is_supported(sock.setsockopt(PACKET_FANOUT, fallback))
+    })
+}
+
+///Rewrites an error from setting PACKET_VERSION to TPACKET_V3 into one that points at the fallback
+pub fn explain_version_error(e: Error) -> Error {
+    if e.raw_os_error() != Some(EINVAL) {
+        return e;
+    }
+    Error::new(
+        ErrorKind::Unsupported,
+        format!(
+            "TPACKET_V3 is not supported by this kernel ({}), rx_v2::Ring can be used instead",
+            e
+        ),
+    )
+}
+
+///Rewrites an error from joining a fanout group into one saying whether the mode is unsupported
+///or the group already exists with different settings
+pub fn explain_fanout_error(if_name: &str, method: c_int, e: Error) -> Error {
+    if e.raw_os_error() != Some(EINVAL) {
+        return e;
+    }
+    match fanout_supported(if_name, method) {
+        Ok(false) => Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "{} with flags {:#x} is not supported by this kernel ({})",
+                fanout_mode_name(method),
+                method & !0xff,
+                e
+            ),
+        ),
+        Ok(true) => Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "this process' fanout group already exists with a different mode or flags than {} ({})",
+                fanout_mode_name(method),
+                e
+            ),
+        ),
+        Err(_) => e,
+    }
+}
+
+///Finds why joining the fanout group `settings` describe failed with EINVAL: a method the crate
+///can tell is bad, one this kernel lacks, or a group that already exists with another method
+pub(crate) fn check_fanout(settings: &RingSettings) -> Result<(), ConfigError> {
+    settings.validate_fanout()?;
+    let method = settings.fanout_method;
+    let mode = fanout_mode_name(method);
+    match fanout_supported(&settings.if_name, method) {
+        Ok(false) => Err(ConfigError::FanoutUnsupported {
+            mode,
+            flags: method & !0xff,
+        }),
+        Ok(true) => Err(ConfigError::FanoutModeMismatch {
+            mode,
+            group: (rx::settings_fanout_word(settings) & 0xFFFF) as u16,
+        }),
+        Err(_) => Ok(()),
+    }
+}
+
+///Sends a UDP datagram to itself over `lo` and returns how many copies of it a capture on `lo`
+///saw, checking that each reported the loopback hardware type. Loopback packets are seen leaving
+///and arriving, so this is 2 without `ignore_outgoing` and 1 with it. Needs CAP_NET_RAW
+pub fn loopback_self_check(ignore_outgoing: bool) -> io::Result<usize> {
+    let mut capture = SimpleCapture::new(RingSettings {
+        if_name: String::from("lo"),
+        ignore_outgoing,
+        ..RingSettings::default()
+    })?;
+    let result = count_loopback_copies(&mut capture);
+    unsafe {
+        close(capture.socket.fd);
+    }
+    result
+}
+
+fn count_loopback_copies(capture: &mut SimpleCapture) -> io::Result<usize> {
+    let marker = format!("af_packet loopback self-check {}", unsafe { getpid() });
+    let udp = UdpSocket::bind("127.0.0.1:0")?;
+    capture.set_read_timeout(Some(Duration::from_millis(100)))?;
+    udp.send_to(marker.as_bytes(), udp.local_addr()?)?;
+
+    //other loopback traffic is skipped until the capture goes quiet or the deadline passes
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let mut buf = [0u8; 2048];
+    let mut copies = 0;
+    while Instant::now() < deadline {
+        let packet = match capture.recv(&mut buf) {
+            Ok(packet) => packet,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                break
+            }
+            Err(e) => return Err(e),
+        };
+        if !packet
+            .data
+            .windows(marker.len())
+            .any(|w| w == marker.as_bytes())
+        {
+            continue;
+        }
+        if packet.sockaddr_ll.sll_hatype != ARPHRD_LOOPBACK {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "loopback packet reported hardware type {}",
+                    packet.sockaddr_ll.sll_hatype
+                ),
+            ));
+        }
+        copies += 1;
+    }
+    Ok(copies)
+}
+
+fn is_supported(result: io::Result<()>) -> io::Result<bool> {
+    match result {
+        Ok(()) => Ok(true),
+        Err(ref e) if e.raw_os_error() == Some(EINVAL) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn with_probe_socket<T, F>(if_name: &str, f: F) -> io::Result<T>
+where
+    F: FnOnce(&mut Socket) -> io::Result<T>,
+{
+    let mut sock = Socket::from_if_name(if_name, socket::PF_PACKET)?;
+    let result = f(&mut sock);
+    unsafe {
+        close(sock.fd);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs CAP_NET_RAW"]
+    fn loopback_copies_follow_ignore_outgoing() {
+        assert_eq!(loopback_self_check(false).unwrap(), 2);
+        assert_eq!(loopback_self_check(true).unwrap(), 1);
+    }
+}
@@ -0,0 +1,186 @@
+//!Prometheus text exposition of ring statistics, for sensors scraped as part of a fleet
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rx;
+use tpacket3::TpacketStatsV3;
+
+//scrapes are answered one at a time, so a client that stalls may only hold up the others this long
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+///Running totals for a single ring. The kernel resets its counters every time they are read, so
+///these accumulate them into the monotonic counters Prometheus expects
+#[derive(Clone, Debug, Default)]
+pub struct RingMetrics {
+    ///Value of the `ring` label on every series for this ring
+    pub label: String,
+    ///Frames seen by the kernel, including those that were dropped
+    pub packets: u64,
+    ///Frames dropped because the ring was full
+    pub drops: u64,
+    ///Number of times the queue was frozen
+    pub freeze_q_cnt: u64,
+}
+
+impl RingMetrics {
+    pub fn new(label: &str) -> RingMetrics {
+        RingMetrics {
+            label: String::from(label),
+            ..RingMetrics::default()
+        }
+    }
+
+    ///Adds a set of kernel counters to the running totals
+    pub fn record(&mut self, stats: &TpacketStatsV3) {
+        self.packets += u64::from(stats.tp_packets);
+        self.drops += u64::from(stats.tp_drops);
+        self.freeze_q_cnt += u64::from(stats.tp_freeze_q_cnt);
+    }
+}
+
+///Statistics for a group of rings, usually the fanout members on one interface
+#[derive(Clone, Debug, Default)]
+pub struct GroupMetrics {
+    rings: Vec<(i32, RingMetrics)>,
+}
+
+impl GroupMetrics {
+    pub fn new() -> GroupMetrics {
+        GroupMetrics::default()
+    }
+
+    ///Adds a ring to the group, labelled with its `RingLabel`
+    pub fn add_ring(&mut self, ring: &rx::Ring) {
+        self.add_fd(ring.socket.fd, &ring.label().to_string());
+    }
+
+    ///Adds a ring to the group by its file descriptor
+    pub fn add_fd(&mut self, fd: i32, label: &str) {
+        self.rings.push((fd, RingMetrics::new(label)));
+    }
+
+    ///Reads the kernel counters of every ring in the group and adds them to the totals
+    pub fn collect(&mut self) -> io::Result<()> {
+        for &mut (fd, ref mut metrics) in &mut self.rings {
+            metrics.record(&rx::get_rx_statistics(fd)?);
+        }
+        Ok(())
+    }
+
+    pub fn rings(&self) -> impl Iterator<Item = &RingMetrics> {
+        self.rings.iter().map(|(_, metrics)| metrics)
+    }
+
+    ///Renders the group in the Prometheus text exposition format
+    pub fn encode(&self) -> String {
+        let rings: Vec<&RingMetrics> = self.rings().collect();
+        encode(&rings)
+    }
+}
+
+///Renders a set of ring totals in the Prometheus text exposition format
+pub fn encode(rings: &[&RingMetrics]) -> String {
+    let mut out = String::new();
+    write_family(
+        &mut out,
+        "af_packet_packets_total",
+        "Frames seen by the kernel, including drops",
+        rings,
+        |m| m.packets,
+    );
+    write_family(
+        &mut out,
+        "af_packet_drops_total",
+        "Frames dropped because the ring was full",
+        rings,
+        |m| m.drops,
+    );
+    write_family(
+        &mut out,
+        "af_packet_freeze_queue_total",
+        "Times the ring queue was frozen",
+        rings,
+        |m| m.freeze_q_cnt,
+    );
+    out
+}
+
+fn write_family<F>(out: &mut String, name: &str, help: &str, rings: &[&RingMetrics], value: F)
+where
+    F: Fn(&RingMetrics) -> u64,
+{
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for ring in rings {
+        let _ = writeln!(
+            out,
+            "{}{{ring=\"{}\"}} {}",
+            name,
+            escape_label(&ring.label),
+            value(ring)
+        );
+    }
+}
+
+fn escape_label(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+///Serves the group on `addr` from a background thread. Counters are collected from the kernel
+///on every scrape, so nothing else should be reading statistics from these rings
+pub fn serve<A: ToSocketAddrs>(
+    addr: A,
+    group: Arc<Mutex<GroupMetrics>>,
+) -> io::Result<thread::JoinHandle<()>> {
+    let listener = TcpListener::bind(addr)?;
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+            if stream.set_read_timeout(Some(CLIENT_TIMEOUT)).is_err()
+                || stream.set_write_timeout(Some(CLIENT_TIMEOUT)).is_err()
+            {
+                continue;
+            }
+            //we answer every request the same way, so only drain what the client sent
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf);
+
+            let body = {
+                let mut group = match group.lock() {
+                    Ok(g) => g,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                match group.collect() {
+                    Ok(()) => group.encode(),
+                    Err(e) => {
+                        let _ = write!(stream, "HTTP/1.0 500 Internal Server Error\r\n\r\n{}\n", e);
+                        continue;
+                    }
+                }
+            };
+            let _ = write!(
+                stream,
+                "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+        }
+    }))
+}
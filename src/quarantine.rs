@@ -0,0 +1,159 @@
+//!Keeps copies of the frames a ring found malformed, for looking into NIC and driver bugs after
+//!the fact. The buffer is bounded: once full, each new frame evicts the oldest.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use rx::{BlockAnomaly, FrameAnomaly, RingLabel};
+
+///What was wrong with a quarantined frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anomaly {
+    ///The frame's header doesn't fit the frame, the packet was still handed out
+    Frame(FrameAnomaly),
+    ///The block walk stopped at this frame, it and the rest of the block were lost
+    Block(BlockAnomaly),
+}
+
+///A copy of a malformed frame
+#[derive(Clone, Debug)]
+pub struct QuarantinedFrame {
+    pub label: RingLabel,
+    ///Sequence number of the block the frame was in
+    pub seq: u64,
+    ///Position of the frame in the block
+    pub index: u32,
+    pub anomaly: Anomaly,
+    ///The frame from the start of its tpacket header, cut to the quarantine's `max_frame_len`
+    pub bytes: Vec<u8>,
+    pub at: SystemTime,
+}
+
+struct Inner {
+    frames: VecDeque<QuarantinedFrame>,
+    capacity: usize,
+    max_frame_len: usize,
+    evicted: u64,
+}
+
+///A handle to a bounded buffer of malformed frames. Clones share the buffer, so the one given to
+///a ring in `RingSettings::quarantine` can be read from another thread
+#[derive(Clone)]
+pub struct Quarantine {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Quarantine {
+    ///Holds up to `capacity` frames, each cut to `max_frame_len` bytes
+    pub fn new(capacity: usize, max_frame_len: usize) -> Quarantine {
+        Quarantine {
+            inner: Arc::new(Mutex::new(Inner {
+                frames: VecDeque::with_capacity(capacity),
+                capacity,
+                max_frame_len,
+                evicted: 0,
+            })),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn admit(
+        &self,
+        label: &RingLabel,
+        seq: u64,
+        index: u32,
+        anomaly: Anomaly,
+        bytes: &[u8],
+    ) {
+        let mut inner = self.lock();
+        if inner.capacity == 0 {
+            inner.evicted += 1;
+            return;
+        }
+        if inner.frames.len() == inner.capacity {
+            inner.frames.pop_front();
+            inner.evicted += 1;
+        }
+        let len = bytes.len().min(inner.max_frame_len);
+        inner.frames.push_back(QuarantinedFrame {
+            label: label.clone(),
+            seq,
+            index,
+            anomaly,
+            bytes: bytes[..len].to_vec(),
+            at: SystemTime::now(),
+        });
+    }
+
+    ///Returns the length frames are cut to
+    pub fn max_frame_len(&self) -> usize {
+        self.lock().max_frame_len
+    }
+
+    ///Returns the number of frames held
+    pub fn len(&self) -> usize {
+        self.lock().frames.len()
+    }
+
+    ///Returns true if no frames are held
+    pub fn is_empty(&self) -> bool {
+        self.lock().frames.is_empty()
+    }
+
+    ///Returns the number of frames dropped to make room, or because the capacity is 0
+    pub fn evicted(&self) -> u64 {
+        self.lock().evicted
+    }
+
+    ///Returns copies of the frames held, oldest first
+    pub fn snapshot(&self) -> Vec<QuarantinedFrame> {
+        self.lock().frames.iter().cloned().collect()
+    }
+
+    ///Removes and returns the frames held, oldest first
+    pub fn drain(&self) -> Vec<QuarantinedFrame> {
+        self.lock().frames.drain(..).collect()
+    }
+
+    ///Writes the frames held as a line describing each followed by a hex dump of its bytes
+    pub fn dump<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for frame in self.snapshot() {
+            writeln!(
+                w,
+                "{} block {} frame {}: {:?}, {} bytes",
+                frame.label,
+                frame.seq,
+                frame.index,
+                frame.anomaly,
+                frame.bytes.len()
+            )?;
+            for (n, line) in frame.bytes.chunks(16).enumerate() {
+                write!(w, "  {:04x}:", n * 16)?;
+                for b in line {
+                    write!(w, " {:02x}", b)?;
+                }
+                writeln!(w)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        //the buffer stays consistent if a holder panicked, each change is a single push or pop
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for Quarantine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let inner = self.lock();
+        f.debug_struct("Quarantine")
+            .field("len", &inner.frames.len())
+            .field("capacity", &inner.capacity)
+            .field("evicted", &inner.evicted)
+            .finish()
+    }
+}
@@ -0,0 +1,69 @@
+//!Radiotap headers, which prefix every frame captured on a wireless interface in monitor mode
+//!(ARPHRD_IEEE80211_RADIOTAP). The header carries per-frame radio metadata and is followed by the
+//!802.11 frame itself. Unlike everything else on the wire, radiotap fields are little-endian.
+
+pub use linktype::ARPHRD_IEEE80211_RADIOTAP;
+
+///Fixed part of the header: version, pad, length and the first presence word
+const MIN_LEN: usize = 8;
+
+//set in a presence word when another presence word follows it
+const PRESENT_EXT: u32 = 1 << 31;
+
+///Bits of the first presence word
+pub const PRESENT_TSFT: u32 = 1;
+pub const PRESENT_FLAGS: u32 = 1 << 1;
+pub const PRESENT_RATE: u32 = 1 << 2;
+pub const PRESENT_CHANNEL: u32 = 1 << 3;
+pub const PRESENT_DBM_ANTSIGNAL: u32 = 1 << 5;
+pub const PRESENT_DBM_ANTNOISE: u32 = 1 << 6;
+
+///The fixed part of a radiotap header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RadiotapHeader {
+    ///Always 0 so far
+    pub version: u8,
+    ///Length of the whole header including its fields, where the 802.11 frame starts
+    pub len: u16,
+    ///First presence word, saying which fields follow. Further words are only present if
+    ///`is_extended` is true
+    pub present: u32,
+}
+
+impl RadiotapHeader {
+    ///Parses the header at the start of `frame`, checking that its length fits the frame
+    pub fn parse(frame: &[u8]) -> Option<RadiotapHeader> {
+        if frame.len() < MIN_LEN || frame[0] != 0 {
+            return None;
+        }
+        let len = u16::from_le_bytes([frame[2], frame[3]]);
+        if (len as usize) < MIN_LEN || len as usize > frame.len() {
+            return None;
+        }
+        Some(RadiotapHeader {
+            version: frame[0],
+            len,
+            present: u32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]]),
+        })
+    }
+
+    ///Returns true if the first presence word has every bit of `field` set
+    #[inline]
+    pub fn has(&self, field: u32) -> bool {
+        self.present & field == field
+    }
+
+    ///Returns true if more presence words follow the first one
+    #[inline]
+    pub fn is_extended(&self) -> bool {
+        self.present & PRESENT_EXT != 0
+    }
+}
+
+///Returns the 802.11 frame following the radiotap header, or `None` if `frame` doesn't start with
+///a valid one
+#[inline]
+pub fn skip_radiotap(frame: &[u8]) -> Option<&[u8]> {
+    let hdr = RadiotapHeader::parse(frame)?;
+    frame.get(hdr.len as usize..)
+}
@@ -0,0 +1,397 @@
+//!Splits long running captures across files. A new segment is started once the current one
+//!reaches a size or an age, each named from a pattern, and only the newest few can be kept.
+//!Segments are opened with a closure, so any of the writers works, e.g.
+//!`RotatingWriter::new(pattern, policy, |f| PcapWriter::new(f, LINKTYPE_ETHERNET))`, and a
+//!pcapng opener adds its interfaces again to every segment.
+//!
+//!With `index_interval` set, every segment gets an index next to it, named after the segment
+//!with `.idx` appended, so tools can seek to a time rather than read the file from the start.
+//!The index is the magic `AFPIDX01` followed by 16 byte entries, each the timestamp of a packet
+//!in nanoseconds since the epoch and the offset of its record in the segment, both little
+//!endian u64s. An entry is made for the first packet at least `index_interval` after the last
+//!entry, so entries are in time order. Packets need to be written with `writer_at` to be indexed.
+//!Offsets are into the segment as written, so they mean nothing if the segment is compressed.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use pcap::PcapWriter;
+use pcapng::PcapngWriter;
+
+///When to start a new segment and how many to keep. Limits that are `None` don't apply
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RotationPolicy {
+    ///Start a new segment once the current one holds this many bytes
+    pub max_bytes: Option<u64>,
+    ///Start a new segment once the current one has been open this long
+    pub max_age: Option<Duration>,
+    ///Delete the oldest segments this writer made so no more than this many are left
+    pub keep: Option<usize>,
+    ///Write an index for each segment with an entry at most this far apart
+    pub index_interval: Option<Duration>,
+}
+
+const INDEX_MAGIC: &[u8; 8] = b"AFPIDX01";
+
+///A point in a segment: the first packet at or after `timestamp` is at `offset` or later
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub timestamp: SystemTime,
+    pub offset: u64,
+}
+
+///A segment's index, read back
+#[derive(Clone, Debug)]
+pub struct CaptureIndex {
+    entries: Vec<IndexEntry>,
+}
+
+impl CaptureIndex {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<CaptureIndex> {
+        let mut input = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a capture index"));
+        }
+        let mut entries = Vec::new();
+        let mut entry = [0; 16];
+        loop {
+            match input.read_exact(&mut entry) {
+                Ok(()) => {}
+                //an index cut short by a crash is still good up to there
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            let mut nanos = [0; 8];
+            let mut offset = [0; 8];
+            nanos.copy_from_slice(&entry[..8]);
+            offset.copy_from_slice(&entry[8..]);
+            entries.push(IndexEntry {
+                timestamp: UNIX_EPOCH + Duration::from_nanos(u64::from_le_bytes(nanos)),
+                offset: u64::from_le_bytes(offset),
+            });
+        }
+        Ok(CaptureIndex { entries })
+    }
+
+    ///Reads the index written next to `segment`
+    pub fn for_segment<P: AsRef<Path>>(segment: P) -> io::Result<CaptureIndex> {
+        CaptureIndex::open(index_path(segment.as_ref()))
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    ///Returns the offset to start reading at for packets from `timestamp` on: that of the last
+    ///entry at or before it, or of the first entry if there is none
+    pub fn offset_for(&self, timestamp: SystemTime) -> Option<u64> {
+        let after = self.entries.partition_point(|e| e.timestamp <= timestamp);
+        self.entries
+            .get(after.saturating_sub(1))
+            .map(|entry| entry.offset)
+    }
+}
+
+//the segment's path with .idx appended to the file name
+fn index_path(segment: &Path) -> PathBuf {
+    let mut name = segment.as_os_str().to_owned();
+    name.push(".idx");
+    PathBuf::from(name)
+}
+
+struct Index {
+    out: BufWriter<File>,
+    last: Option<SystemTime>,
+}
+
+impl Index {
+    fn create(path: &Path) -> io::Result<Index> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(INDEX_MAGIC)?;
+        Ok(Index { out, last: None })
+    }
+
+    fn offer(&mut self, interval: Duration, timestamp: SystemTime, offset: u64) -> io::Result<()> {
+        let due = match self.last {
+            Some(last) => timestamp >= last + interval,
+            None => true,
+        };
+        if !due {
+            return Ok(());
+        }
+        let nanos = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::from_secs(0))
+            .as_nanos() as u64;
+        self.out.write_all(&nanos.to_le_bytes())?;
+        self.out.write_all(&offset.to_le_bytes())?;
+        self.last = Some(timestamp);
+        Ok(())
+    }
+}
+
+///A writer that can be asked to flush its output when its segment is closed
+pub trait Segment {
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+impl<W: Write> Segment for PcapWriter<W> {
+    fn flush(&mut self) -> io::Result<()> {
+        PcapWriter::flush(self)
+    }
+}
+
+impl<W: Write> Segment for PcapngWriter<W> {
+    fn flush(&mut self) -> io::Result<()> {
+        PcapngWriter::flush(self)
+    }
+}
+
+///A buffered segment file that counts the bytes written to it
+#[derive(Debug)]
+pub struct SegmentFile {
+    out: BufWriter<File>,
+    written: Arc<AtomicU64>,
+}
+
+impl Write for SegmentFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.out.write(buf)?;
+        self.written.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+///Writes segments named after `pattern`, in which `{seq}` is replaced by the segment number,
+///`{time}` by the UTC time it was opened as `YYYYMMDDTHHMMSS` and `{unix}` by the same time in
+///seconds since the epoch. Patterns without `{seq}` get `.N` appended after the first segment so
+///names never collide
+pub struct RotatingWriter<W: Segment> {
+    pattern: String,
+    policy: RotationPolicy,
+    open: Box<dyn FnMut(SegmentFile) -> io::Result<W> + Send>,
+    current: Option<Current<W>>,
+    seq: u64,
+    segments: VecDeque<PathBuf>,
+}
+
+struct Current<W> {
+    writer: W,
+    written: Arc<AtomicU64>,
+    opened: Instant,
+    index: Option<Index>,
+}
+
+impl<W: Segment> RotatingWriter<W> {
+    ///Opens the first segment right away, so a bad pattern or directory fails here
+    pub fn new<F>(pattern: &str, policy: RotationPolicy, open: F) -> io::Result<RotatingWriter<W>>
+    where
+        F: FnMut(SegmentFile) -> io::Result<W> + Send + 'static,
+    {
+        let mut writer = RotatingWriter {
+            pattern: String::from(pattern),
+            policy,
+            open: Box::new(open),
+            current: None,
+            seq: 0,
+            segments: VecDeque::new(),
+        };
+        writer.rotate()?;
+        Ok(writer)
+    }
+
+    ///Returns the current segment's writer, first starting a new segment if the policy says the
+    ///current one is done. Call it for every packet
+    pub fn writer(&mut self) -> io::Result<&mut W> {
+        if self.due() {
+            self.rotate()?;
+        }
+        match self.current {
+            Some(ref mut current) => Ok(&mut current.writer),
+            None => unreachable!(),
+        }
+    }
+
+    ///Like `writer`, for writing a packet captured at `timestamp`, which is indexed if an index
+    ///entry is due
+    pub fn writer_at(&mut self, timestamp: SystemTime) -> io::Result<&mut W> {
+        if self.due() {
+            self.rotate()?;
+        }
+        let interval = self.policy.index_interval;
+        match self.current {
+            Some(ref mut current) => {
+                if let (Some(index), Some(interval)) = (current.index.as_mut(), interval) {
+                    let offset = current.written.load(Ordering::Relaxed);
+                    index.offer(interval, timestamp, offset)?;
+                }
+                Ok(&mut current.writer)
+            }
+            None => unreachable!(),
+        }
+    }
+
+    ///Closes the current segment and starts the next one
+    pub fn rotate(&mut self) -> io::Result<()> {
+        self.close()?;
+        let path = self.next_path();
+        let written = Arc::new(AtomicU64::new(0));
+        let file = SegmentFile {
+            out: BufWriter::new(File::create(&path)?),
+            written: written.clone(),
+        };
+        let writer = (self.open)(file)?;
+        let index = match self.policy.index_interval {
+            Some(_) => Some(Index::create(&index_path(&path))?),
+            None => None,
+        };
+        trace_event!(Info, "capture segment {:?} opened", path);
+        self.current = Some(Current {
+            writer,
+            written,
+            opened: Instant::now(),
+            index,
+        });
+        self.seq += 1;
+        self.segments.push_back(path);
+        self.prune();
+        Ok(())
+    }
+
+    ///Returns the path of the segment being written
+    pub fn current_path(&self) -> Option<&PathBuf> {
+        self.segments.back()
+    }
+
+    ///Returns the number of segments opened so far
+    pub fn segments_opened(&self) -> u64 {
+        self.seq
+    }
+
+    ///Returns the bytes written to the current segment
+    pub fn current_size(&self) -> u64 {
+        match self.current {
+            Some(ref current) => current.written.load(Ordering::Relaxed),
+            None => 0,
+        }
+    }
+
+    ///Flushes and closes the current segment
+    pub fn finish(mut self) -> io::Result<()> {
+        self.close()
+    }
+
+    fn due(&self) -> bool {
+        let current = match self.current {
+            Some(ref current) => current,
+            None => return true,
+        };
+        let full = self
+            .policy
+            .max_bytes
+            .is_some_and(|max| current.written.load(Ordering::Relaxed) >= max);
+        let old = self
+            .policy
+            .max_age
+            .is_some_and(|max| current.opened.elapsed() >= max);
+        full || old
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        match self.current.take() {
+            Some(mut current) => {
+                current.writer.flush()?;
+                match current.index {
+                    Some(mut index) => index.out.flush(),
+                    None => Ok(()),
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    //deletes the oldest segments beyond the retention count. Files already gone are fine
+    fn prune(&mut self) {
+        let keep = match self.policy.keep {
+            Some(keep) => keep.max(1),
+            None => return,
+        };
+        while self.segments.len() > keep {
+            if let Some(path) = self.segments.pop_front() {
+                match fs::remove_file(&path) {
+                    Ok(()) => trace_event!(Info, "capture segment {:?} deleted", path),
+                    Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+                    Err(e) => {
+                        trace_event!(Warn, "deleting capture segment {:?} failed: {}", path, e)
+                    }
+                }
+                if self.policy.index_interval.is_some() {
+                    let _ = fs::remove_file(index_path(&path));
+                }
+            }
+        }
+    }
+
+    fn next_path(&self) -> PathBuf {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::from_secs(0))
+            .as_secs();
+        let mut name = self
+            .pattern
+            .replace("{seq}", &format!("{:06}", self.seq))
+            .replace("{time}", &utc_timestamp(now))
+            .replace("{unix}", &now.to_string());
+        if !self.pattern.contains("{seq}") && self.seq > 0 {
+            name = format!("{}.{}", name, self.seq);
+        }
+        PathBuf::from(name)
+    }
+}
+
+impl<W: Segment> fmt::Debug for RotatingWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RotatingWriter")
+            .field("pattern", &self.pattern)
+            .field("policy", &self.policy)
+            .field("seq", &self.seq)
+            .field("current_path", &self.current_path())
+            .finish()
+    }
+}
+
+//YYYYMMDDTHHMMSS in UTC, converting days since the epoch to a civil date
+fn utc_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
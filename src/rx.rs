@@ -1,45 +1,225 @@
+#[cfg(target_os = "linux")]
 use std;
-use std::io::{self, Error};
-use std::mem;
+use std::fmt;
+#[cfg(any(target_os = "linux", feature = "bpf"))]
+use std::io;
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use libc::{
-    bind, c_int, c_void, getpid, mmap, poll, pollfd, sockaddr, sockaddr_ll, socklen_t, AF_PACKET,
-    ETH_ALEN, ETH_P_IP, MAP_LOCKED, MAP_NORESERVE, MAP_SHARED, POLLERR, POLLIN, PROT_READ,
-    PROT_WRITE,
-};
+use libc::c_int;
 
-use socket::{self, Socket, IFF_PROMISC};
+use blockdump::BlockDump;
+use error::ConfigError;
+#[cfg(any(target_os = "linux", feature = "bpf"))]
+use error::{Operation, RingError};
+use flow::FiveTuple;
+#[cfg(feature = "headers")]
+use headers::Headers;
+use linktype;
+#[cfg(target_os = "linux")]
+use netns;
+use netns::NetNs;
+#[cfg(target_os = "linux")]
+use quarantine::Anomaly;
+use quarantine::Quarantine;
+use radiotap::{self, RadiotapHeader};
 
 use tpacket3;
+use vlan::{self, VlanStack, VlanTag};
+
+#[cfg(target_os = "linux")]
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+#[cfg(target_os = "linux")]
+use std::{cell::Cell, collections::VecDeque, io::Error, mem, time::Instant};
+
+#[cfg(target_os = "linux")]
+use libc::{
+    bind, c_void, close, getpid, mmap, munmap, poll, pollfd, sockaddr, sockaddr_ll, socklen_t,
+    AF_PACKET, ENOPROTOOPT, ETH_ALEN, ETH_P_ALL, ETH_P_IP, MAP_LOCKED, MAP_NORESERVE, MAP_SHARED,
+    POLLERR, POLLHUP, POLLIN, PROT_READ, PROT_WRITE,
+};
+
+#[cfg(not(target_os = "linux"))]
+pub use unsupported::Ring;
+//the ring itself is Linux only, the packets and statistics it hands out can be built anywhere
+#[cfg(target_os = "linux")]
+use {
+    ethtool::{self, OffloadGuard, Offloads, TsInfo},
+    filter,
+    interface::{self, InterfaceInfo, OperState},
+    phc::PhcClock,
+    probe,
+    shutdown::ShutdownToken,
+    socket::{self, PromiscGuard, Socket},
+    stats::{BlockLatency, HoldHistogram},
+    timestamp::{self, HwTimestampGuard, HwTstampConfig, Timestamping},
+};
 
 //Used digits for these consts, if they were defined differently in C headers I have added that definition in the comments beside them
 
-const PACKET_RX_RING: c_int = 5;
+#[cfg(target_os = "linux")]
+pub(crate) const PACKET_RX_RING: c_int = 5;
+#[cfg(target_os = "linux")]
 const PACKET_STATISTICS: c_int = 6;
-const PACKET_VERSION: c_int = 10;
-const PACKET_FANOUT: c_int = 18;
+#[cfg(target_os = "linux")]
+pub(crate) const PACKET_VERSION: c_int = 10;
+#[cfg(target_os = "linux")]
+pub(crate) const PACKET_FANOUT: c_int = 18;
+#[cfg(target_os = "linux")]
+const PACKET_IGNORE_OUTGOING: c_int = 23;
 
 /* https://stackoverflow.com/questions/43193889/sending-data-with-packet-mmap-and-packet-tx-ring-is-slower-than-normal-withou */
 
 pub const PACKET_FANOUT_HASH: c_int = 0;
 pub const PACKET_FANOUT_LB: c_int = 1;
 
-const PACKET_HOST: u8 = 0;
-const PACKET_BROADCAST: u8 = 1;
-const PACKET_MULTICAST: u8 = 2;
-const PACKET_OTHERHOST: u8 = 3;
-const PACKET_OUTGOING: u8 = 4;
+pub(crate) const PACKET_HOST: u8 = 0;
+pub(crate) const PACKET_BROADCAST: u8 = 1;
+pub(crate) const PACKET_MULTICAST: u8 = 2;
+pub(crate) const PACKET_OTHERHOST: u8 = 3;
+pub(crate) const PACKET_OUTGOING: u8 = 4;
 
 ///Settings to be used to bring up each ring
 #[derive(Clone, Debug)]
 pub struct RingSettings {
-    ///Interface name
+    ///Interface name, or `socket::ANY_INTERFACE` to capture from every interface. Rings on every
+    ///interface are not put into promiscuous mode and usually want `cooked` set, as the
+    ///interfaces may not share a link-layer type
     pub if_name: String,
     ///PACKET_FANOUT_HASH will pin flows to individual threads, PACKET_FANOUT_LB will distribute
     ///them across multiple threads
     pub fanout_method: c_int,
     ///Lower-level settings including block size, also enable/disable filling RXHASH in packet data
     pub ring_settings: tpacket3::TpacketReq3,
+    ///Fanout group id, shared by the rings that split an interface's traffic. Defaults to the
+    ///process id. A group belongs to one interface, so rings on different interfaces in the same
+    ///process need different ids
+    pub fanout_group: Option<u16>,
+    ///Position of this ring among the rings opened on the same interface
+    pub index: u32,
+    ///Optional user tag used alongside the interface and index to identify the ring
+    pub tag: Option<String>,
+    ///Opens the socket as SOCK_DGRAM, so the kernel strips the link-layer header and packets
+    ///start at the network header. Needed for interfaces without Ethernet framing (ppp, tun) and
+    ///for the "any" device. The link-layer protocol is then only available from `sockaddr_ll`
+    pub cooked: bool,
+    ///Don't capture packets sent by this host. On loopback every packet is otherwise seen twice,
+    ///once leaving and once arriving
+    pub ignore_outgoing: bool,
+    ///Puts the interface into promiscuous mode for the life of the capture and turns it back off
+    ///afterwards, unless it was already on
+    pub promisc: bool,
+    ///Extra MAC addresses for the interface to accept, for capturing e.g. LLDP without `promisc`
+    pub memberships: Vec<Membership>,
+    ///Has the interface accept every multicast frame, a lighter alternative to `promisc` when
+    ///only multicast traffic matters. Same as adding `Membership::AllMulticast`
+    pub allmulti: bool,
+    ///Turns GRO and LRO off for the life of the capture, so packets are seen as they were on the
+    ///wire rather than merged. Needs CAP_NET_ADMIN. See `ethtool` for rx VLAN offload
+    pub disable_offloads: bool,
+    ///Where packet timestamps come from. Anything but `Software` has the interface's NIC
+    ///timestamp every received packet for the life of the capture, which needs CAP_NET_ADMIN and
+    ///a driver that supports it, see `Ring::timestamp_info`. `HardwareSystem` is only honoured by
+    ///kernels before 4.20, later ones give software timestamps for it; convert `HardwareRaw`
+    ///ones with `phc` there instead
+    pub timestamps: tpacket3::TimestampSource,
+    ///Waits up to this long for the interface to appear and come up before opening the socket,
+    ///for services that may start before their NIC does. `None` fails right away
+    pub wait_for_interface: Option<Duration>,
+    ///Opens the socket in this network namespace rather than the process' own, so `if_name`
+    ///names an interface in there. The wait for the interface checks sysfs, which shows the
+    ///namespace it was mounted in, so setting `wait_for_interface` as well is refused
+    pub netns: Option<NetNs>,
+    ///What to do with blocks whose packet offsets are inconsistent
+    pub parse_policy: ParsePolicy,
+    ///Where to keep copies of malformed frames, shared with the application
+    pub quarantine: Option<Quarantine>,
+    ///Where to copy retired blocks while the dump is armed, for debugging the parsers
+    pub block_dump: Option<BlockDump>,
+}
+
+///What a ring does with a malformed block, one whose packet offsets are inconsistent (see
+///`BlockAnomaly`). Every policy counts the block in `RingTotals::malformed_blocks`
+#[derive(Clone, Default)]
+pub enum ParsePolicy {
+    ///Hand out the packets before the problem, as `get_raw_packets` always does
+    #[default]
+    Skip,
+    ///Have `try_get_block` fail with `RingError::MalformedBlock`, handing the block back to the
+    ///kernel. `get_block` and `get_block_timeout` skip as with `Skip`
+    Error,
+    ///Call the function with the ring's label, the anomaly and the bytes the kernel wrote to the
+    ///block, block header included, then skip. It runs on the thread that got the block
+    Callback(Arc<ParseCallback>),
+}
+
+///Called with a malformed block under `ParsePolicy::Callback`
+pub type ParseCallback = dyn Fn(&RingLabel, &BlockAnomaly, &[u8]) + Send + Sync;
+
+impl fmt::Debug for ParsePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParsePolicy::Skip => f.write_str("Skip"),
+            ParsePolicy::Error => f.write_str("Error"),
+            ParsePolicy::Callback(_) => f.write_str("Callback(..)"),
+        }
+    }
+}
+
+///A MAC address added to an interface's receive filter for as long as the socket is open
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Membership {
+    ///A multicast group, e.g. `LLDP_MULTICAST`
+    Multicast([u8; 6]),
+    ///A unicast address other than the interface's own
+    Unicast([u8; 6]),
+    ///Every multicast group, like IFF_ALLMULTI but counted per socket
+    AllMulticast,
+}
+
+///Destination of LLDP frames, 01:80:c2:00:00:0e
+pub const LLDP_MULTICAST: [u8; 6] = [0x01, 0x80, 0xc2, 0x00, 0x00, 0x0e];
+///Destination of VRRP advertisements, 01:00:5e:00:00:12
+pub const VRRP_MULTICAST: [u8; 6] = [0x01, 0x00, 0x5e, 0x00, 0x00, 0x12];
+
+impl RingSettings {
+    //`memberships` plus the one `allmulti` stands for
+    #[cfg(target_os = "linux")]
+    pub(crate) fn all_memberships(&self) -> Vec<Membership> {
+        let mut all = self.memberships.clone();
+        if self.allmulti && !all.contains(&Membership::AllMulticast) {
+            all.push(Membership::AllMulticast);
+        }
+        all
+    }
+
+    ///Checks the ring geometry and fanout method against the constraints the kernel enforces,
+    ///catching settings that opening a ring would fail on with a bare EINVAL
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.ring_settings.validate()?;
+        self.validate_fanout()
+    }
+
+    //the checks on the fanout argument that need no kernel
+    pub(crate) fn validate_fanout(&self) -> Result<(), ConfigError> {
+        let mode = self.fanout_method & 0xff;
+        let flags = self.fanout_method & !0xff;
+        if !(0..=7).contains(&mode) {
+            return Err(ConfigError::UnknownFanoutMode(mode));
+        }
+        //ROLLOVER, UNIQUEID, IGNORE_OUTGOING and DEFRAG
+        if flags & !0xf000 != 0 {
+            return Err(ConfigError::UnknownFanoutFlags(flags & !0xf000));
+        }
+        //rings always join a given group, the process id by default
+        if flags & 0x2000 != 0 {
+            return Err(ConfigError::UniqueIdWithGroup {
+                group: self.fanout_group.unwrap_or(process::id() as u16),
+            });
+        }
+        Ok(())
+    }
 }
 
 impl Default for RingSettings {
@@ -47,25 +227,352 @@ impl Default for RingSettings {
         RingSettings {
             if_name: String::from("eth0"),
             fanout_method: PACKET_FANOUT_HASH,
+            fanout_group: None,
             ring_settings: tpacket3::TpacketReq3::default(),
+            index: 0,
+            tag: None,
+            cooked: false,
+            ignore_outgoing: false,
+            promisc: true,
+            memberships: Vec::new(),
+            allmulti: false,
+            disable_offloads: false,
+            timestamps: tpacket3::TimestampSource::Software,
+            wait_for_interface: None,
+            netns: None,
+            parse_policy: ParsePolicy::Skip,
+            quarantine: None,
+            block_dump: None,
+        }
+    }
+}
+
+///Identifies a ring in Debug output, statistics, and errors. Displays as `eth0#2` or
+///`eth0#2(tag)`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RingLabel {
+    pub if_name: String,
+    pub index: u32,
+    pub tag: Option<String>,
+}
+
+impl RingLabel {
+    pub(crate) fn from_settings(settings: &RingSettings) -> RingLabel {
+        RingLabel {
+            if_name: settings.if_name.clone(),
+            index: settings.index,
+            tag: settings.tag.clone(),
+        }
+    }
+}
+
+impl fmt::Display for RingLabel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}#{}", self.if_name, self.index)?;
+        if let Some(ref tag) = self.tag {
+            write!(f, "({})", tag)?;
+        }
+        Ok(())
+    }
+}
+
+///Packets and bytes the ring has handed to the application, unlike the kernel statistics these
+///never include drops and are not reset when read
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RingTotals {
+    ///Blocks returned by `get_block`
+    pub blocks: u64,
+    ///Packets in those blocks
+    pub packets: u64,
+    ///Captured bytes of those packets, not including tpacket headers
+    pub bytes: u64,
+    ///Blocks the kernel retired and then reused before `get_block` returned them, detected from
+    ///gaps in the block sequence numbers
+    pub missed_blocks: u64,
+    ///Blocks whose packet offsets were inconsistent, see `BlockAnomaly`
+    pub malformed_blocks: u64,
+    ///Packets whose header doesn't fit their frame (see `FrameAnomaly`), plus the packets lost
+    ///in malformed blocks
+    pub malformed_frames: u64,
+}
+
+///Kernel statistics for a ring, along with the ring they were read from
+#[derive(Clone, Debug)]
+pub struct RingStatistics {
+    pub label: RingLabel,
+    pub stats: tpacket3::TpacketStatsV3,
+}
+
+///A snapshot of a ring's configuration and state from `Ring::diagnostics`, for bug reports and
+///health endpoints. `Display` writes it as one `key: value` line per field
+#[derive(Clone, Debug)]
+pub struct RingDiagnostics {
+    pub label: RingLabel,
+    pub if_index: u32,
+    ///SOCK_RAW, or SOCK_DGRAM for cooked capture
+    pub kind: c_int,
+    ///ARPHRD_* hardware type of the interface
+    pub hardware_type: u16,
+    ///Ethertype the socket is bound to
+    pub protocol: u16,
+    ///PACKET_FANOUT argument: the group id in the low 16 bits, the mode and flags above
+    pub fanout_word: u32,
+    pub block_size: u32,
+    pub block_nr: u32,
+    pub frame_size: u32,
+    pub frame_nr: u32,
+    pub retire_timeout: Duration,
+    ///Bytes mapped for the ring
+    pub mmap_len: usize,
+    ///Index of the next block `get_block` looks at
+    pub cursor: u32,
+    ///Sequence number of the last block handed out
+    pub last_seq: Option<u64>,
+    ///Blocks owned by userspace, whether handed out yet or not
+    pub ready_blocks: usize,
+    ///Blocks returned by `get_block` and not yet marked as consumed
+    pub outstanding: usize,
+    ///Longest a block was held before being consumed since the ring was created
+    pub longest_hold: Duration,
+    pub started: SystemTime,
+    pub totals: RingTotals,
+}
+
+impl fmt::Display for RingDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "label: {}", self.label)?;
+        writeln!(f, "if_index: {}", self.if_index)?;
+        writeln!(f, "kind: {}", self.kind)?;
+        writeln!(f, "hardware_type: {}", self.hardware_type)?;
+        writeln!(f, "protocol: {:#06x}", self.protocol)?;
+        writeln!(f, "fanout_word: {:#010x}", self.fanout_word)?;
+        writeln!(f, "block_size: {}", self.block_size)?;
+        writeln!(f, "block_nr: {}", self.block_nr)?;
+        writeln!(f, "frame_size: {}", self.frame_size)?;
+        writeln!(f, "frame_nr: {}", self.frame_nr)?;
+        writeln!(f, "retire_timeout: {:?}", self.retire_timeout)?;
+        writeln!(f, "mmap_len: {}", self.mmap_len)?;
+        writeln!(f, "cursor: {}", self.cursor)?;
+        match self.last_seq {
+            Some(seq) => writeln!(f, "last_seq: {}", seq)?,
+            None => writeln!(f, "last_seq: none")?,
         }
+        writeln!(f, "ready_blocks: {}", self.ready_blocks)?;
+        writeln!(f, "outstanding: {}", self.outstanding)?;
+        writeln!(f, "longest_hold: {:?}", self.longest_hold)?;
+        let uptime = self.started.elapsed().unwrap_or_default();
+        writeln!(f, "uptime: {:?}", uptime)?;
+        let t = &self.totals;
+        writeln!(f, "blocks: {}", t.blocks)?;
+        writeln!(f, "packets: {}", t.packets)?;
+        writeln!(f, "bytes: {}", t.bytes)?;
+        writeln!(f, "missed_blocks: {}", t.missed_blocks)?;
+        writeln!(f, "malformed_blocks: {}", t.malformed_blocks)?;
+        writeln!(f, "malformed_frames: {}", t.malformed_frames)
     }
 }
 
+///The system calls a locked ring makes while receiving: poll (ppoll where libc implements poll
+///with it) to wait for blocks, getsockopt for SO_ERROR and PACKET_STATISTICS, clock_gettime for
+///timeouts where the vDSO doesn't answer it, and munmap and close when it is closed. A seccomp
+///profile for a capture thread needs these plus whatever the application makes itself, and
+///write when a `BlockDump` or `Quarantine` is set
+pub const LOCKED_SYSCALLS: &[&str] = &[
+    "poll",
+    "ppoll",
+    "getsockopt",
+    "clock_gettime",
+    "munmap",
+    "close",
+];
+
 ///References a single mmaped ring buffer. Normally one per thread.
+#[cfg(target_os = "linux")]
 #[derive(Clone, Debug)]
 pub struct Ring {
     pub socket: Socket,
+    label: RingLabel,
     mmap: Option<*mut u8>,
     opts: tpacket3::TpacketReq3,
+    fanout: c_int,
+    totals: RingTotals,
+    started: SystemTime,
+    cursor: u32,
+    last_seq: Option<u64>,
+    leases: VecDeque<Lease>,
+    hold_times: HoldHistogram,
+    latency: BlockLatency,
+    hold_warning: Option<HoldWarningHook>,
+    //longest hold recorded since the kernel statistics were last read
+    hold_peak: Cell<Duration>,
+    freeze_hook: Option<FreezeHook>,
+    //shared by clones, so promiscuous mode is restored when the last of them is dropped
+    promisc: Option<Arc<PromiscGuard>>,
+    offloads: Option<Arc<OffloadGuard>>,
+    hw_timestamps: Option<Arc<HwTimestampGuard>>,
+    shutdown: Option<ShutdownToken>,
+    parse_policy: ParsePolicy,
+    quarantine: Option<Quarantine>,
+    block_dump: Option<BlockDump>,
+    locked: bool,
+}
+
+//A block handed to the application by get_block, outstanding until it is marked as consumed
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug)]
+struct Lease {
+    seq: u64,
+    index: u32,
+    leased_at: Instant,
+    //capture time of the block's first packet, None for an empty block
+    first_packet: Option<SystemTime>,
+}
+
+///Passed to the hold warning callback when a block was held for a large part of the retire
+///timeout. Holding blocks that long is what makes the kernel freeze the queue, which shows up as
+///tp_freeze_q_cnt climbing
+#[derive(Clone, Debug)]
+pub struct HoldWarning {
+    pub label: RingLabel,
+    ///Sequence number of the block
+    pub seq: u64,
+    ///How long the block was held before being consumed
+    pub held: Duration,
+    ///The ring's tp_retire_blk_tov
+    pub retire_timeout: Duration,
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Clone)]
+struct HoldWarningHook {
+    threshold: Duration,
+    callback: Arc<dyn Fn(&HoldWarning) + Send + Sync>,
+}
+
+#[cfg(target_os = "linux")]
+impl fmt::Debug for HoldWarningHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HoldWarningHook")
+            .field("threshold", &self.threshold)
+            .finish()
+    }
+}
+
+///Passed to the freeze callback, and traced, when reading the kernel statistics shows the queue
+///froze since they were last read. The kernel freezes the queue when the next block it wants to
+///fill is still owned by userspace, and drops packets until it is handed back
+#[derive(Clone, Debug)]
+pub struct FreezeEvent {
+    pub label: RingLabel,
+    ///tp_freeze_q_cnt, the number of times the queue froze since the statistics were last read
+    pub freezes: u32,
+    ///Blocks returned by `get_block` and not yet marked as consumed
+    pub outstanding: usize,
+    ///Blocks owned by userspace, whether handed out yet or not, out of `block_nr`
+    pub ready: usize,
+    pub block_nr: u32,
+    ///How long the oldest outstanding block has been held so far
+    pub oldest_held: Option<Duration>,
+    ///Longest a block was held before being consumed since the statistics were last read
+    pub longest_hold: Duration,
+    ///The ring's tp_retire_blk_tov
+    pub retire_timeout: Duration,
+}
+
+///What a `FreezeEvent` points at
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FreezeCause {
+    ///Blocks were held for a retire timeout or more, or every block was waiting to be read.
+    ///The consumer needs to be faster or to release blocks sooner
+    Consumer,
+    ///The consumer released blocks well within the retire timeout, so the ring ran out of
+    ///blocks because the timeout retires them faster than they are read. A longer
+    ///tp_retire_blk_tov fills each block further, more blocks give more headroom
+    RetireTimeout,
+}
+
+impl FreezeEvent {
+    ///Guesses the culprit from the hold times and the number of ready blocks
+    pub fn cause(&self) -> FreezeCause {
+        let held = self.longest_hold.max(self.oldest_held.unwrap_or_default());
+        if held >= self.retire_timeout || self.ready >= self.block_nr as usize {
+            FreezeCause::Consumer
+        } else {
+            FreezeCause::RetireTimeout
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Clone)]
+struct FreezeHook {
+    callback: Arc<dyn Fn(&FreezeEvent) + Send + Sync>,
+}
+
+#[cfg(target_os = "linux")]
+impl fmt::Debug for FreezeHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("FreezeHook")
+    }
 }
 
 ///Contains a reference to a block as it exists in the ring buffer, its block descriptor, and a Vec of individual packets in that block.
+#[cfg(target_os = "linux")]
 #[derive(Debug)]
 pub struct Block<'a> {
     block_desc: tpacket3::TpacketBlockDesc,
-    packets: Vec<RawPacket<'a>>,
     raw_data: &'a mut [u8],
+    fd: c_int,
+}
+
+///How full a retired block was and why the kernel retired it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockFill {
+    ///Packets in the block
+    pub num_pkts: u32,
+    ///Bytes of the block used, including the block header
+    pub blk_len: u32,
+    ///Size of the block as configured by tp_block_size
+    pub block_size: u32,
+    ///True if the block was retired because tp_retire_blk_tov expired rather than being full
+    pub timed_out: bool,
+}
+
+impl BlockFill {
+    ///Fraction of the block that was used, between 0 and 1
+    pub fn ratio(&self) -> f64 {
+        if self.block_size == 0 {
+            return 0.0;
+        }
+        f64::from(self.blk_len) / f64::from(self.block_size)
+    }
+}
+
+///Which way a packet was travelling, from sll_pkttype
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PacketType {
+    ///Addressed to this host
+    Host,
+    Broadcast,
+    Multicast,
+    ///Addressed to another host, seen because of promiscuous mode
+    OtherHost,
+    ///Sent by this host
+    Outgoing,
+    Other(u8),
+}
+
+impl From<u8> for PacketType {
+    fn from(pkttype: u8) -> PacketType {
+        match pkttype {
+            PACKET_HOST => PacketType::Host,
+            PACKET_BROADCAST => PacketType::Broadcast,
+            PACKET_MULTICAST => PacketType::Multicast,
+            PACKET_OTHERHOST => PacketType::OtherHost,
+            PACKET_OUTGOING => PacketType::Outgoing,
+            other => PacketType::Other(other),
+        }
+    }
 }
 
 ///Contains a reference to an individual packet in a block, as well as details about that packet
@@ -75,221 +582,2122 @@ pub struct RawPacket<'a> {
     pub tpacket3_hdr: tpacket3::Tpacket3Hdr,
     ///Raw packet data including any encapsulations
     pub data: &'a [u8],
+    //where the kernel put the sockaddr_ll, which depends on the TPACKET version
+    sll_offset: usize,
 }
 
-impl<'a> Block<'a> {
-    ///Marks a block as free to be destroyed by the kernel
+impl<'a> RawPacket<'a> {
+    ///Wraps a TPACKET_V3 header and the packet data that starts with it
     #[inline]
-    pub fn mark_as_consumed(&mut self) {
-        self.raw_data[tpacket3::TP_BLK_STATUS_OFFSET] = tpacket3::TP_STATUS_KERNEL;
-        self.raw_data[tpacket3::TP_BLK_STATUS_OFFSET + 1] = 0;
-        self.raw_data[tpacket3::TP_BLK_STATUS_OFFSET + 2] = 0;
-        self.raw_data[tpacket3::TP_BLK_STATUS_OFFSET + 3] = 0;
+    pub fn new(tpacket3_hdr: tpacket3::Tpacket3Hdr, data: &'a [u8]) -> RawPacket<'a> {
+        RawPacket::with_sll_offset(tpacket3_hdr, data, tpacket3::TP_SOCKADDR_LL_OFFSET)
     }
 
     #[inline]
-    fn is_ready(&self) -> bool {
-        (self.raw_data[tpacket3::TP_BLK_STATUS_OFFSET] & tpacket3::TP_STATUS_USER) != 0
+    pub(crate) fn with_sll_offset(
+        tpacket3_hdr: tpacket3::Tpacket3Hdr,
+        data: &'a [u8],
+        sll_offset: usize,
+    ) -> RawPacket<'a> {
+        RawPacket {
+            tpacket3_hdr,
+            data,
+            sll_offset,
+        }
+    }
+    ///Wraps a bare Ethernet frame from a backend without tpacket headers (AF_XDP, libpcap). The
+    ///header is synthesized from what is known, and there is no sockaddr_ll
+    pub fn from_frame(frame: &'a [u8], wire_len: u32, timestamp: SystemTime) -> RawPacket<'a> {
+        let mut stack = VlanStack::new(frame, None);
+        while stack.next().is_some() {}
+        let since_epoch = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        let tpacket3_hdr = tpacket3::Tpacket3Hdr {
+            tp_next_offset: 0,
+            tp_sec: since_epoch.as_secs() as u32,
+            tp_nsec: since_epoch.subsec_nanos(),
+            tp_snaplen: frame.len() as u32,
+            tp_len: wire_len,
+            tp_status: tpacket3::TpStatus::USER.bits(),
+            tp_mac: 0,
+            tp_net: (stack.ethertype_offset() + 2).min(frame.len()) as u16,
+            hv1: tpacket3::TpacketHdrVariant1::new(0, 0, 0),
+        };
+        RawPacket::with_sll_offset(tpacket3_hdr, frame, usize::MAX)
     }
 
-    ///Returns a `Vec` of details and references to raw packets that can be read from the ring buffer
+    ///Returns the captured frame starting at the link-layer (Ethernet) header. For cooked
+    ///captures this is the same as `l3`
     #[inline]
-    pub fn get_raw_packets(&self) -> Vec<RawPacket> {
-        //standard block header is 48b
+    pub fn l2(&self) -> &'a [u8] {
+        self.slice_from(self.tpacket3_hdr.tp_mac as usize)
+    }
 
-        let mut packets = Vec::<RawPacket>::new();
-        let mut next_offset = 48;
+    ///Returns the captured frame starting at the network-layer (IP) header
+    #[inline]
+    pub fn l3(&self) -> &'a [u8] {
+        self.slice_from(self.tpacket3_hdr.tp_net as usize)
+    }
 
-        let count = self.block_desc.hdr.num_pkts;
-        for x in 0..count {
-            let this_offset = next_offset;
+    ///Returns the status flags the kernel set for this packet
+    #[inline]
+    pub fn status(&self) -> tpacket3::TpStatus {
+        self.tpacket3_hdr.status()
+    }
 
-            let mut tpacket3_hdr = match tpacket3::get_tpacket3_hdr(&self.raw_data[next_offset..]) {
-                Ok(x) => x,
-                Err(_) => {
-                    continue;
-                }
-            };
+    ///Returns the length of the packet as it was on the wire (tp_len)
+    #[inline]
+    pub fn wire_len(&self) -> u32 {
+        self.tpacket3_hdr.tp_len
+    }
 
-            if x < count - 1 {
-                next_offset = this_offset + tpacket3_hdr.1.tp_next_offset as usize;
-            } else {
-                next_offset = self.raw_data.len();
-                tpacket3_hdr.1.tp_next_offset = 0;
-            }
-            packets.push(RawPacket {
-                tpacket3_hdr: tpacket3_hdr.1,
-                data: &self.raw_data[this_offset..next_offset],
-            });
-        }
+    ///Returns the number of bytes of the packet that were captured (tp_snaplen)
+    #[inline]
+    pub fn captured_len(&self) -> u32 {
+        self.tpacket3_hdr.tp_snaplen
+    }
 
-        packets
+    ///Returns true if the snaplen or frame size cut the packet short
+    #[inline]
+    pub fn is_truncated(&self) -> bool {
+        self.captured_len() < self.wire_len()
     }
-}
 
-impl Ring {
-    ///Creates a new ring buffer on the specified interface name and puts the interface into promiscuous mode
-    pub fn from_if_name(if_name: &str) -> io::Result<Ring> {
-        let mut ring = Ring {
-            socket: Socket::from_if_name(if_name, socket::PF_PACKET)?,
-            mmap: None,
-            opts: tpacket3::TpacketReq3::default(),
-        };
+    ///Returns the time the packet was captured
+    #[inline]
+    pub fn timestamp(&self) -> SystemTime {
+        self.tpacket3_hdr.timestamp()
+    }
 
-        ring.socket.set_flag(IFF_PROMISC as u64)?;
-        ring.socket
-            .setsockopt(PACKET_VERSION, tpacket3::TPACKET_V3)?;
-        ring.socket.setsockopt(PACKET_RX_RING, ring.opts.clone())?;
-        ring.mmap_rx_ring()?;
-        ring.bind_rx_ring()?;
-        let fanout = (unsafe { getpid() } & 0xFFFF) | (PACKET_FANOUT_HASH << 16);
-        ring.socket.setsockopt(PACKET_FANOUT, fanout)?;
-        Ok(ring)
+    ///Returns whether the timestamp is the kernel's or the NIC's
+    #[inline]
+    pub fn timestamp_source(&self) -> Option<tpacket3::TimestampSource> {
+        self.status().timestamp_source()
     }
 
-    ///Creates a new ring buffer from the supplied RingSettings struct
-    pub fn new(settings: RingSettings) -> io::Result<Ring> {
-        //this typecasting sucks :(
-        let mut ring = Ring {
-            socket: Socket::from_if_name(&settings.if_name, socket::PF_PACKET)?,
-            mmap: None,
-            opts: settings.ring_settings,
-        };
+    ///Returns how long after `start` (usually `Ring::started`) the packet was captured, or zero
+    ///if it was captured before it
+    #[inline]
+    pub fn time_since(&self, start: SystemTime) -> Duration {
+        self.timestamp()
+            .duration_since(start)
+            .unwrap_or_else(|_| Duration::from_secs(0))
+    }
 
-        ring.socket.set_flag(IFF_PROMISC as u64)?;
-        ring.socket
-            .setsockopt(PACKET_VERSION, tpacket3::TPACKET_V3)?;
-        ring.socket.setsockopt(PACKET_RX_RING, ring.opts.clone())?;
-        ring.mmap_rx_ring()?;
-        ring.bind_rx_ring()?;
-        let fanout = (unsafe { getpid() } & 0xFFFF) | (settings.fanout_method << 16);
-        ring.socket.setsockopt(PACKET_FANOUT, fanout)?;
-        Ok(ring)
+    ///Returns the kernel's flow hash for this packet. This is only filled in when RXHASH is
+    ///requested in tp_feature_req_word, and 0 means none was computed
+    #[inline]
+    pub fn rxhash(&self) -> Option<u32> {
+        self.tpacket3_hdr.rxhash()
     }
 
-    ///Waits for a block to be added to the ring buffer and returns it
-    //We're allowing unused_mut here because apps that include this crate may need to control
-    //marking blocks as consumed for performance reasons to avoid copies
-    #[allow(unused_mut)]
+    ///Parses the IP addresses, ports and protocol of this packet
     #[inline]
-    pub fn get_block(&mut self) -> Block {
-        loop {
-            self.wait_for_block();
-            //check all blocks in memory space
-            for i in 0..self.opts.tp_block_nr {
-                if let Some(mut block) = self.get_single_block(i) {
-                    if block.is_ready() {
-                        return block;
-                    }
-                }
-            }
-        }
+    pub fn five_tuple(&self) -> Option<FiveTuple> {
+        FiveTuple::from_l3(self.l3())
     }
 
-    fn mmap_rx_ring(&mut self) -> io::Result<()> {
-        match unsafe {
-            mmap(
-                std::ptr::null_mut(),
-                (self.opts.tp_block_size * self.opts.tp_block_nr) as usize,
-                PROT_READ | PROT_WRITE,
-                MAP_SHARED | MAP_LOCKED | MAP_NORESERVE,
-                self.socket.fd,
-                0,
-            )
-        } as isize
-        {
-            -1 => Err(io::Error::last_os_error()),
-            map => {
-                self.mmap = Some(map as *mut u8);
-                Ok(())
+    ///Returns a flow hash that is the same for both directions of a flow, computed in userspace
+    #[inline]
+    pub fn symmetric_hash(&self) -> Option<u32> {
+        self.five_tuple().map(|t| t.symmetric_hash())
+    }
+
+    ///Returns the VLAN tag the kernel stripped from this frame, if any. Frames captured on a VLAN
+    ///subinterface never have one, see `interface::InterfaceInfo::vlan_tag`
+    #[inline]
+    pub fn vlan_tag(&self) -> Option<VlanTag> {
+        self.tpacket3_hdr.vlan_tag()
+    }
+
+    ///Iterates every VLAN tag of the frame from the outside in, both the one the kernel stripped
+    ///and any still present in the captured bytes, e.g. the inner tag of a QinQ frame
+    #[inline]
+    pub fn vlan_stack(&self) -> VlanStack<'a> {
+        //only the stripped tag can be known without an Ethernet header
+        let frame = if self.has_ethernet_header() {
+            self.l2()
+        } else {
+            &[]
+        };
+        VlanStack::new(frame, self.vlan_tag())
+    }
+
+    ///Writes the frame into `out` as it was on the wire, putting back the VLAN tag the kernel
+    ///stripped. Frames without a stripped tag are copied unchanged
+    pub fn wire_frame(&self, out: &mut Vec<u8>) {
+        match self.vlan_tag() {
+            Some(tag) => vlan::reinsert_tag(self.l2(), tag, out),
+            None => {
+                out.clear();
+                out.extend_from_slice(self.l2());
             }
         }
     }
 
-    fn bind_rx_ring(&mut self) -> io::Result<()> {
-        let mut sa = sockaddr_ll {
-            sll_family: AF_PACKET as u16,
-            sll_protocol: ETH_P_IP.to_be() as u16,
-            sll_ifindex: self.socket.if_index as c_int,
-            sll_hatype: 519,
-            sll_pkttype: (PACKET_HOST //can we just use 255 here lol
-                | PACKET_BROADCAST
-                | PACKET_MULTICAST
-                | PACKET_OTHERHOST
-                | PACKET_OUTGOING),
-            sll_halen: ETH_ALEN as u8,
-            sll_addr: [0; 8],
-        };
+    ///Returns the link-layer address information the kernel stored with this packet
+    #[inline]
+    pub fn sockaddr_ll(&self) -> Option<tpacket3::SockaddrLl> {
+        let raw = self.data.get(self.sll_offset..)?;
+        tpacket3::get_sockaddr_ll(raw).ok().map(|x| x.1)
+    }
+
+    ///Returns whether the packet was inbound, outbound, broadcast, etc.
+    #[inline]
+    pub fn packet_type(&self) -> Option<PacketType> {
+        self.sockaddr_ll()
+            .map(|sll| PacketType::from(sll.sll_pkttype))
+    }
+
+    ///Returns the index of the interface the packet was seen on
+    #[inline]
+    pub fn if_index(&self) -> Option<i32> {
+        self.sockaddr_ll().map(|sll| sll.sll_ifindex)
+    }
+
+    ///Looks up the name of the interface the packet was seen on, mostly useful for rings opened
+    ///on `socket::ANY_INTERFACE`. This is a syscall per call, so cache it by `if_index` on hot paths
+    #[cfg(target_os = "linux")]
+    pub fn if_name(&self) -> Option<String> {
+        socket::get_if_name(self.if_index()? as u32).ok()
+    }
+
+    ///Returns true if the kernel stripped the link-layer header, as it does for SOCK_DGRAM sockets
+    #[inline]
+    pub fn is_cooked(&self) -> bool {
+        self.tpacket3_hdr.tp_mac == self.tpacket3_hdr.tp_net
+    }
+
+    ///Returns the link-layer protocol (ethertype) of the packet as reported by the kernel, which
+    ///is the only way to learn it for cooked captures
+    #[inline]
+    pub fn protocol(&self) -> Option<u16> {
+        self.sockaddr_ll().map(|sll| sll.sll_protocol)
+    }
+
+    ///Returns the ARPHRD_* hardware type of the interface the packet was seen on
+    #[inline]
+    pub fn hardware_type(&self) -> Option<u16> {
+        self.sockaddr_ll().map(|sll| sll.sll_hatype)
+    }
 
-        //get the size before we change the pointer type
-        let size = mem::size_of_val(&sa);
-        //we have to do this transmute or similar because Linux uses multiple sockaddr_ 
-        //family structs and casts them to sockaddr after populating them
-        let addr_ptr = unsafe { mem::transmute::<*mut sockaddr_ll, *mut sockaddr>(&mut sa) };
+    ///Returns true if the captured frame starts with an Ethernet header. Packets from backends
+    ///without a sockaddr_ll are always Ethernet
+    #[inline]
+    pub fn has_ethernet_header(&self) -> bool {
+        !self.is_cooked()
+            && self
+                .hardware_type()
+                .map_or(true, linktype::has_ethernet_header)
+    }
+
+    ///Returns true if the frame was captured in monitor mode and starts with a radiotap header
+    #[inline]
+    pub fn is_radiotap(&self) -> bool {
+        self.hardware_type() == Some(radiotap::ARPHRD_IEEE80211_RADIOTAP)
+    }
 
-        match unsafe { bind(self.socket.fd, addr_ptr, size as socklen_t) } {
-            0 => Ok(()),
-            _ => Err(io::Error::last_os_error()),
+    ///Parses the radiotap header of a monitor mode frame
+    #[inline]
+    pub fn radiotap(&self) -> Option<RadiotapHeader> {
+        if !self.is_radiotap() {
+            return None;
         }
+        RadiotapHeader::parse(self.l2())
     }
 
+    ///Returns the 802.11 frame of a monitor mode capture, after its radiotap header
     #[inline]
-    fn wait_for_block(&self) {
-        let mut pfd = pollfd {
-            fd: self.socket.fd,
-            events: POLLIN | POLLERR,
-            revents: 0,
-        };
+    pub fn ieee80211_frame(&self) -> Option<&'a [u8]> {
+        if !self.is_radiotap() {
+            return None;
+        }
+        radiotap::skip_radiotap(self.l2())
+    }
+
+    ///Parses the Ethernet, IP, and TCP/UDP headers of the packet. Cooked captures and interfaces
+    ///without Ethernet framing (tun, ppp) have no Ethernet header, so parsing starts at the IP
+    ///header
+    #[cfg(feature = "headers")]
+    #[inline]
+    pub fn headers(&self) -> Headers<'a> {
+        if !self.has_ethernet_header() {
+            return Headers::from_ip(self.l3());
+        }
+        Headers::from_ethernet(self.l2())
+    }
 
-        unsafe {
-            poll(&mut pfd, 1, -1);
+    ///Copies the packet out of the ring so it can be kept after the block is consumed
+    pub fn to_owned(&self) -> OwnedPacket {
+        OwnedPacket {
+            tpacket3_hdr: self.tpacket3_hdr.clone(),
+            sockaddr_ll: self.sockaddr_ll(),
+            data: self.l2().to_vec(),
         }
     }
 
+    //tp_mac and tp_net are offsets from the start of the tpacket3 header, and the captured data
+    //ends tp_snaplen bytes after tp_mac
     #[inline]
-    fn get_single_block<'a>(&mut self, count: u32) -> Option<Block<'a>> {
-        //TODO: clean up all this typecasting
-        let offset = count as isize * self.opts.tp_block_size as isize;
+    fn slice_from(&self, start: usize) -> &'a [u8] {
+        let end = self.tpacket3_hdr.tp_mac as usize + self.tpacket3_hdr.tp_snaplen as usize;
+        let end = end.min(self.data.len());
+        self.data.get(start..end).unwrap_or(&[])
+    }
+}
 
-        let block = unsafe {
-            std::slice::from_raw_parts_mut(
-                self.mmap?.offset(offset),
-                self.opts.tp_block_size as usize,
-            )
-        };
+///A packet copied out of the ring with `RawPacket::to_owned`. It doesn't borrow the block, so it
+///can be queued, kept after the block is consumed, or sent to another thread
+#[derive(Clone, Debug)]
+pub struct OwnedPacket {
+    ///Contains packet details, tp_mac and tp_net still refer to offsets in the ring
+    pub tpacket3_hdr: tpacket3::Tpacket3Hdr,
+    pub sockaddr_ll: Option<tpacket3::SockaddrLl>,
+    ///Captured frame starting at the link-layer header
+    pub data: Vec<u8>,
+}
 
-        let block_desc = match tpacket3::get_tpacket_block_desc(&block[..]) {
-            Ok(x) => x,
-            Err(_) => {
-                return None;
-            }
-        };
+impl OwnedPacket {
+    ///Returns the captured frame starting at the link-layer (Ethernet) header
+    #[inline]
+    pub fn l2(&self) -> &[u8] {
+        &self.data
+    }
 
-        let blk = Block {
-            block_desc: block_desc.1,
-            packets: Vec::new(),
-            raw_data: &mut block[..],
-        };
+    ///Returns the captured frame starting at the network-layer (IP) header
+    #[inline]
+    pub fn l3(&self) -> &[u8] {
+        let hdr = &self.tpacket3_hdr;
+        let offset = hdr.tp_net.saturating_sub(hdr.tp_mac) as usize;
+        self.data.get(offset..).unwrap_or(&[])
+    }
 
-        Some(blk)
+    #[inline]
+    pub fn status(&self) -> tpacket3::TpStatus {
+        self.tpacket3_hdr.status()
     }
-}
 
-unsafe impl Send for Ring {}
+    #[inline]
+    pub fn wire_len(&self) -> u32 {
+        self.tpacket3_hdr.tp_len
+    }
 
-///This is very easy because the Linux kernel has its own counters that are reset every time
-///getsockopt() is called
-#[inline]
-pub fn get_rx_statistics(fd: i32) -> Result<tpacket3::TpacketStatsV3, Error> {
-    let mut optval = tpacket3::TpacketStatsV3 {
-        tp_packets: 0,
-        tp_drops: 0,
-        tp_freeze_q_cnt: 0,
-    };
-    socket::get_sock_opt(
-        fd,
-        PACKET_STATISTICS,
-        &(&mut optval as *mut _ as *mut c_void),
-    )?;
-    Ok(optval)
+    #[inline]
+    pub fn captured_len(&self) -> u32 {
+        self.tpacket3_hdr.tp_snaplen
+    }
+
+    #[inline]
+    pub fn is_truncated(&self) -> bool {
+        self.captured_len() < self.wire_len()
+    }
+
+    #[inline]
+    pub fn timestamp(&self) -> SystemTime {
+        self.tpacket3_hdr.timestamp()
+    }
+
+    #[inline]
+    pub fn timestamp_source(&self) -> Option<tpacket3::TimestampSource> {
+        self.status().timestamp_source()
+    }
+
+    #[inline]
+    pub fn rxhash(&self) -> Option<u32> {
+        self.tpacket3_hdr.rxhash()
+    }
+
+    #[inline]
+    pub fn five_tuple(&self) -> Option<FiveTuple> {
+        FiveTuple::from_l3(self.l3())
+    }
+
+    #[inline]
+    pub fn symmetric_hash(&self) -> Option<u32> {
+        self.five_tuple().map(|t| t.symmetric_hash())
+    }
+
+    #[inline]
+    pub fn vlan_tag(&self) -> Option<VlanTag> {
+        self.tpacket3_hdr.vlan_tag()
+    }
+
+    #[inline]
+    pub fn vlan_stack(&self) -> VlanStack<'_> {
+        let frame = if self.has_ethernet_header() {
+            self.l2()
+        } else {
+            &[]
+        };
+        VlanStack::new(frame, self.vlan_tag())
+    }
+
+    ///Writes the frame into `out` as it was on the wire, see `RawPacket::wire_frame`
+    pub fn wire_frame(&self, out: &mut Vec<u8>) {
+        match self.vlan_tag() {
+            Some(tag) => vlan::reinsert_tag(self.l2(), tag, out),
+            None => {
+                out.clear();
+                out.extend_from_slice(self.l2());
+            }
+        }
+    }
+
+    ///Parses the Ethernet, IP, and TCP/UDP headers of the packet
+    #[cfg(feature = "headers")]
+    #[inline]
+    pub fn headers(&self) -> Headers<'_> {
+        if !self.has_ethernet_header() {
+            return Headers::from_ip(self.l3());
+        }
+        Headers::from_ethernet(self.l2())
+    }
+
+    #[inline]
+    pub fn packet_type(&self) -> Option<PacketType> {
+        self.sockaddr_ll
+            .as_ref()
+            .map(|sll| PacketType::from(sll.sll_pkttype))
+    }
+
+    #[inline]
+    pub fn if_index(&self) -> Option<i32> {
+        self.sockaddr_ll.as_ref().map(|sll| sll.sll_ifindex)
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn if_name(&self) -> Option<String> {
+        socket::get_if_name(self.if_index()? as u32).ok()
+    }
+
+    #[inline]
+    pub fn is_cooked(&self) -> bool {
+        self.tpacket3_hdr.tp_mac == self.tpacket3_hdr.tp_net
+    }
+
+    #[inline]
+    pub fn protocol(&self) -> Option<u16> {
+        self.sockaddr_ll.as_ref().map(|sll| sll.sll_protocol)
+    }
+
+    #[inline]
+    pub fn hardware_type(&self) -> Option<u16> {
+        self.sockaddr_ll.as_ref().map(|sll| sll.sll_hatype)
+    }
+
+    #[inline]
+    pub fn has_ethernet_header(&self) -> bool {
+        !self.is_cooked()
+            && self
+                .hardware_type()
+                .map_or(true, linktype::has_ethernet_header)
+    }
+
+    #[inline]
+    pub fn is_radiotap(&self) -> bool {
+        self.hardware_type() == Some(radiotap::ARPHRD_IEEE80211_RADIOTAP)
+    }
+
+    #[inline]
+    pub fn radiotap(&self) -> Option<RadiotapHeader> {
+        if !self.is_radiotap() {
+            return None;
+        }
+        RadiotapHeader::parse(self.l2())
+    }
+
+    #[inline]
+    pub fn ieee80211_frame(&self) -> Option<&[u8]> {
+        if !self.is_radiotap() {
+            return None;
+        }
+        radiotap::skip_radiotap(self.l2())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl<'a> Block<'a> {
+    ///Wraps a block read back from a `blockdump` file, or built by hand, so the crate's parsers
+    ///can be run over it offline. Returns `None` if `data` is too short for a block descriptor.
+    ///The block isn't tied to a socket: `mark_as_consumed` only rewrites the status in `data`
+    ///and `kernel_dropped_since_last` fails
+    pub fn from_bytes(data: &'a mut [u8]) -> Option<Block<'a>> {
+        let (_, block_desc) = tpacket3::get_tpacket_block_desc(&data[..]).ok()?;
+        Some(Block {
+            block_desc,
+            raw_data: data,
+            fd: -1,
+        })
+    }
+
+    ///Marks a block as free to be destroyed by the kernel
+    #[inline]
+    pub fn mark_as_consumed(&mut self) {
+        trace_event!(
+            Trace,
+            "block with {} packets released",
+            self.block_desc.hdr.num_pkts
+        );
+        tpacket3::write_block_status(self.raw_data, u32::from(tpacket3::TP_STATUS_KERNEL));
+    }
+
+    ///Returns the block's sequence number, which the kernel increments for every block it retires
+    #[inline]
+    pub fn seq_num(&self) -> u64 {
+        self.block_desc.hdr.seq_num
+    }
+
+    ///Returns the number of bytes of the block in use, including the block header
+    #[inline]
+    pub fn blk_len(&self) -> u32 {
+        self.block_desc.hdr.blk_len
+    }
+
+    ///Returns the offset of the first packet from the start of the block
+    #[inline]
+    pub fn offset_to_first_pkt(&self) -> u32 {
+        self.block_desc.hdr.offset_to_first_pkt
+    }
+
+    ///Returns the raw block status word
+    #[inline]
+    pub fn status(&self) -> u32 {
+        self.block_desc.hdr.block_status
+    }
+
+    ///Returns true if the kernel flagged this block with TP_STATUS_LOSING, meaning packets were
+    ///dropped since the ring's statistics were last read
+    #[inline]
+    pub fn is_losing(&self) -> bool {
+        self.block_desc.hdr.block_status & tpacket3::TpStatus::LOSING.bits() != 0
+    }
+
+    ///Returns how many packets the kernel dropped in the window leading up to this block. This
+    ///reads, and so resets, the ring's kernel statistics when the block is flagged as losing, and
+    ///returns 0 without a syscall otherwise
+    pub fn kernel_dropped_since_last(&self) -> io::Result<u32> {
+        if !self.is_losing() {
+            return Ok(0);
+        }
+        Ok(get_rx_statistics(self.fd)?.tp_drops)
+    }
+
+    ///Returns the block descriptor as parsed from the ring
+    #[inline]
+    pub fn descriptor(&self) -> &tpacket3::TpacketBlockDesc {
+        &self.block_desc
+    }
+
+    ///Returns the time the first packet in this block was captured
+    #[inline]
+    pub fn first_packet_time(&self) -> SystemTime {
+        self.block_desc.hdr.first_packet_time()
+    }
+
+    ///Returns the time the last packet in this block was captured
+    #[inline]
+    pub fn last_packet_time(&self) -> SystemTime {
+        self.block_desc.hdr.last_packet_time()
+    }
+
+    ///Returns how full this block is and whether it was retired by the timeout
+    #[inline]
+    pub fn fill(&self) -> BlockFill {
+        let hdr = &self.block_desc.hdr;
+        BlockFill {
+            num_pkts: hdr.num_pkts,
+            blk_len: hdr.blk_len,
+            block_size: self.raw_data.len() as u32,
+            timed_out: hdr.block_status & tpacket3::TP_STATUS_BLK_TMO != 0,
+        }
+    }
+
+    #[inline]
+    fn is_ready(&self) -> bool {
+        tpacket3::read_block_status(self.raw_data) & u32::from(tpacket3::TP_STATUS_USER) != 0
+    }
+
+    ///Returns a `Vec` of details and references to raw packets that can be read from the ring buffer.
+    ///If the block's offsets are inconsistent, only the packets before the problem are returned
+    ///and `anomaly` describes what went wrong
+    #[inline]
+    pub fn get_raw_packets(&self) -> Vec<RawPacket<'_>> {
+        let mut packets = Vec::<RawPacket>::with_capacity(self.block_desc.hdr.num_pkts as usize);
+        let mut walk = self.walk();
+        for (start, end, tpacket3_hdr) in &mut walk {
+            packets.push(RawPacket::new(tpacket3_hdr, &self.raw_data[start..end]));
+        }
+        if let Some(anomaly) = walk.anomaly {
+            trace_event!(
+                Warn,
+                "stopped walking block {}: {:?}",
+                self.seq_num(),
+                anomaly
+            );
+        }
+        packets
+    }
+
+    ///Copies every packet out of the block and immediately hands the block back to the kernel.
+    ///This is for consumers whose per-packet work is slow enough that holding the block while
+    ///processing would freeze the queue
+    pub fn copy_packets(mut self) -> Vec<OwnedPacket> {
+        let packets = self
+            .get_raw_packets()
+            .iter()
+            .map(|p| p.to_owned())
+            .collect();
+        self.mark_as_consumed();
+        packets
+    }
+
+    ///Returns the number of packets in the block, straight from the block descriptor
+    #[inline]
+    pub fn packet_count(&self) -> u32 {
+        self.block_desc.hdr.num_pkts
+    }
+
+    ///Returns the number of bytes the packets take up in the block, straight from the block
+    ///descriptor. This includes each packet's tpacket3 header and alignment padding, so it is an
+    ///upper bound on captured bytes that is cheap enough for lightweight accounting
+    #[inline]
+    pub fn byte_count(&self) -> u32 {
+        let hdr = &self.block_desc.hdr;
+        hdr.blk_len.saturating_sub(hdr.offset_to_first_pkt)
+    }
+
+    ///Returns true if the block holds no packets, which happens when it is retired by
+    ///tp_retire_blk_tov on a quiet interface. Empty blocks still need to be marked as consumed
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.block_desc.hdr.num_pkts == 0
+    }
+
+    ///Walks the block and returns the first inconsistency in its packet offsets, if any
+    pub fn anomaly(&self) -> Option<BlockAnomaly> {
+        let mut walk = self.walk();
+        for _ in &mut walk {}
+        walk.anomaly
+    }
+
+    //the block header is 48 bytes on current kernels, but it is followed by tp_sizeof_priv bytes
+    //of private data and the kernel tells us where the packets really start
+    #[inline]
+    fn first_packet_offset(&self) -> usize {
+        self.block_desc.hdr.offset_to_first_pkt as usize
+    }
+
+    ///Returns the part of the block the kernel wrote, block descriptor included, as
+    ///`blockdump::BlockWriter` stores it
+    pub fn as_bytes(&self) -> &[u8] {
+        let end = (self.block_desc.hdr.blk_len as usize).min(self.raw_data.len());
+        &self.raw_data[..end]
+    }
+
+    #[inline]
+    fn walk(&self) -> PacketWalk<'_> {
+        //blk_len covers what the kernel wrote, anything after it is left over from earlier use of
+        //the block and must never be handed out as packet data
+        let end = (self.block_desc.hdr.blk_len as usize).min(self.raw_data.len());
+        PacketWalk {
+            data: self.raw_data,
+            end,
+            offset: self.first_packet_offset(),
+            index: 0,
+            count: self.block_desc.hdr.num_pkts,
+            anomaly: None,
+        }
+    }
+
+    //sums tp_snaplen across the block and counts its malformed frames, passing each to `bad`
+    //with its index and bytes, along with any problem that stopped the walk. Frames after that
+    //problem are lost and counted as malformed too
+    #[inline]
+    fn scan<F>(&self, mut bad: F) -> (u64, u64, Option<BlockAnomaly>)
+    where
+        F: FnMut(u32, Anomaly, &[u8]),
+    {
+        let mut walk = self.walk();
+        let mut bytes = 0;
+        let mut bad_frames = 0;
+        while let Some((start, end, hdr)) = walk.next() {
+            bytes += u64::from(hdr.tp_snaplen);
+            if let Some(a) = FrameAnomaly::check(&hdr, end - start) {
+                bad_frames += 1;
+                bad(
+                    walk.index - 1,
+                    Anomaly::Frame(a),
+                    &self.raw_data[start..end],
+                );
+            }
+        }
+        if let Some(a) = walk.anomaly {
+            bad_frames += u64::from(walk.count - walk.index);
+            let rest = self.raw_data.get(a.offset()..walk.end).unwrap_or(&[]);
+            bad(walk.index, Anomaly::Block(a), rest);
+        }
+        (bytes, bad_frames, walk.anomaly)
+    }
+}
+
+///Why a walk over a block's packets stopped before reaching num_pkts. These come from buggy
+///drivers or corrupted memory, never from a healthy kernel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockAnomaly {
+    ///The packet header at `offset` runs past the end of the block
+    HeaderTruncated { index: u32, offset: usize },
+    ///The packet at `offset` has a tp_next_offset that is zero, overlaps its own header, or points
+    ///past the end of the block
+    BadNextOffset {
+        index: u32,
+        offset: usize,
+        next_offset: u32,
+    },
+}
+
+impl BlockAnomaly {
+    ///Returns the offset in the block of the packet the walk stopped at
+    pub fn offset(&self) -> usize {
+        match *self {
+            BlockAnomaly::HeaderTruncated { offset, .. }
+            | BlockAnomaly::BadNextOffset { offset, .. } => offset,
+        }
+    }
+}
+
+///Why a packet's header doesn't fit its frame. The packet is still handed out, with its data cut
+///to what lies inside the frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameAnomaly {
+    ///tp_mac points into the tpacket header or sockaddr_ll
+    MacInHeader { mac: u16 },
+    ///tp_net is before tp_mac
+    NetBeforeMac { mac: u16, net: u16 },
+    ///The captured data, tp_snaplen bytes from tp_mac, runs past the end of the frame
+    SnaplenOverrun {
+        mac: u16,
+        snaplen: u32,
+        frame_len: usize,
+    },
+}
+
+impl FrameAnomaly {
+    ///Checks a TPACKET_V3 header against the length of the frame it starts
+    pub fn check(hdr: &tpacket3::Tpacket3Hdr, frame_len: usize) -> Option<FrameAnomaly> {
+        let (mac, net, snaplen) = (hdr.tp_mac, hdr.tp_net, hdr.tp_snaplen);
+        if (mac as usize) < tpacket3::TP_SOCKADDR_LL_OFFSET + SOCKADDR_LL_LEN {
+            return Some(FrameAnomaly::MacInHeader { mac });
+        }
+        if net < mac {
+            return Some(FrameAnomaly::NetBeforeMac { mac, net });
+        }
+        if mac as usize + snaplen as usize > frame_len {
+            return Some(FrameAnomaly::SnaplenOverrun {
+                mac,
+                snaplen,
+                frame_len,
+            });
+        }
+        None
+    }
+}
+
+//size of struct sockaddr_ll, which follows the tpacket header
+const SOCKADDR_LL_LEN: usize = 20;
+
+//Walks the packets of a block, yielding the start and end of each packet along with its header
+//and stopping at the first inconsistent offset
+#[cfg(target_os = "linux")]
+struct PacketWalk<'b> {
+    data: &'b [u8],
+    end: usize,
+    offset: usize,
+    index: u32,
+    count: u32,
+    anomaly: Option<BlockAnomaly>,
+}
+
+#[cfg(target_os = "linux")]
+impl<'b> Iterator for PacketWalk<'b> {
+    type Item = (usize, usize, tpacket3::Tpacket3Hdr);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.count || self.anomaly.is_some() {
+            return None;
+        }
+        let start = self.offset;
+        let mut hdr = match self
+            .data
+            .get(start..self.end)
+            .and_then(|d| tpacket3::get_tpacket3_hdr(d).ok())
+        {
+            Some(x) => x.1,
+            None => {
+                self.anomaly = Some(BlockAnomaly::HeaderTruncated {
+                    index: self.index,
+                    offset: start,
+                });
+                return None;
+            }
+        };
+
+        let end = if self.index < self.count - 1 {
+            let next = hdr.tp_next_offset as usize;
+            if next < tpacket3::TP_SOCKADDR_LL_OFFSET || start + next > self.end {
+                self.anomaly = Some(BlockAnomaly::BadNextOffset {
+                    index: self.index,
+                    offset: start,
+                    next_offset: hdr.tp_next_offset,
+                });
+                return None;
+            }
+            start + next
+        } else {
+            hdr.tp_next_offset = 0;
+            self.end
+        };
+
+        self.offset = end;
+        self.index += 1;
+        Some((start, end, hdr))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Ring {
+    ///Creates a new ring buffer on the specified interface name and puts the interface into promiscuous mode
+    pub fn from_if_name(if_name: &str) -> Result<Ring, RingError> {
+        Ring::new(RingSettings {
+            if_name: String::from(if_name),
+            ..RingSettings::default()
+        })
+    }
+
+    ///Creates a new ring buffer from the supplied RingSettings struct
+    pub fn new(settings: RingSettings) -> Result<Ring, RingError> {
+        trace_span!(_span, "ring_setup");
+        let label = RingLabel::from_settings(&settings);
+        let socket = open_socket(&settings, &label)?;
+        Ring::set_up(socket, settings)
+    }
+
+    ///Creates a ring on a packet socket opened elsewhere, e.g. by a privileged parent or passed
+    ///in by whatever runs the process, taking it over. `if_name` in the settings names the
+    ///interface the ring binds to, and `cooked` is ignored for the socket's own kind
+    pub fn from_raw_fd(fd: RawFd, settings: RingSettings) -> Result<Ring, RingError> {
+        trace_span!(_span, "ring_setup");
+        let label = RingLabel::from_settings(&settings);
+        let socket = setup_step(
+            &label,
+            Operation::Other(String::from("adopting the socket")),
+            Socket::from_fd(fd, &settings.if_name),
+        )?;
+        Ring::set_up(socket, settings)
+    }
+
+    ///Creates a ring on an already open packet socket, taking it over. Only the ring is set up,
+    ///mapped and bound, with the settings' promiscuous mode, offloads, memberships and fanout.
+    ///The socket's interface is used whatever `if_name` in the settings says
+    pub fn from_socket(socket: Socket, settings: RingSettings) -> Result<Ring, RingError> {
+        trace_span!(_span, "ring_setup");
+        Ring::set_up(socket, settings)
+    }
+
+    //everything after socket(2)
+    fn set_up(socket: Socket, settings: RingSettings) -> Result<Ring, RingError> {
+        let mut label = RingLabel::from_settings(&settings);
+        label.if_name = socket.if_name.clone();
+        let mut ring = Ring::with_socket(socket, label, settings.ring_settings.clone());
+        ring.parse_policy = settings.parse_policy.clone();
+        ring.quarantine = settings.quarantine.clone();
+        ring.block_dump = settings.block_dump.clone();
+        //a ring that fails part way has no clones yet, so its socket and mapping can go with it
+        if let Err(e) = ring.configure(&settings) {
+            ring.close();
+            return Err(e);
+        }
+        trace_event!(
+            Info,
+            "ring {} up (fd {}): {} blocks of {} bytes, fanout {:#x}",
+            ring.label,
+            ring.socket.fd,
+            ring.opts.tp_block_nr,
+            ring.opts.tp_block_size,
+            ring.fanout
+        );
+        Ok(ring)
+    }
+
+    //each step of setting up the socket and ring, in the order the kernel needs them
+    fn configure(&mut self, settings: &RingSettings) -> Result<(), RingError> {
+        //there is no device to put into promiscuous mode when capturing on every interface
+        if settings.promisc && !self.socket.is_any() {
+            self.setup(Operation::Promisc, |r| {
+                r.promisc = Some(Arc::new(PromiscGuard::new(&r.socket)?));
+                Ok(())
+            })?;
+        }
+        if settings.disable_offloads && !self.socket.is_any() {
+            self.setup(Operation::Offloads, |r| {
+                r.offloads = Some(Arc::new(OffloadGuard::disable(
+                    &r.socket,
+                    Offloads::MERGING,
+                )?));
+                Ok(())
+            })?;
+        }
+        if settings.timestamps != tpacket3::TimestampSource::Software {
+            let source = settings.timestamps;
+            self.setup(Operation::Timestamping(source), |r| {
+                check_timestamp_support(&r.socket, source)?;
+                r.set_timestamp_source(source).map(|_| ())
+            })?;
+        }
+        self.setup(Operation::Version(tpacket3::TPACKET_V3), |r| {
+            r.socket
+                .setsockopt(PACKET_VERSION, tpacket3::TPACKET_V3)
+                .map_err(probe::explain_version_error)
+        })?;
+        let opts = &self.opts;
+        let rx_ring = Operation::RxRing {
+            block_size: opts.tp_block_size,
+            block_nr: opts.tp_block_nr,
+            frame_size: opts.tp_frame_size,
+            frame_nr: opts.tp_frame_nr,
+        };
+        self.setup(rx_ring, |r| {
+            r.socket.setsockopt(PACKET_RX_RING, r.opts.clone())
+        })
+        .map_err(|e| e.explain(|| self.opts.validate()))?;
+        //the kernel took the geometry, so it fits
+        let len = self.map_len().unwrap_or(0);
+        self.setup(Operation::Mmap { len }, |r| r.mmap_rx_ring())?;
+        let protocol = ring_protocol(&self.socket);
+        self.setup(Operation::Bind { protocol }, |r| r.bind_rx_ring())?;
+        if settings.ignore_outgoing {
+            self.setup(Operation::IgnoreOutgoing, |r| {
+                ignore_outgoing(&mut r.socket)
+            })?;
+        }
+        for m in settings.all_memberships() {
+            self.setup(Operation::AddMembership(m), |r| r.socket.add_membership(m))?;
+        }
+        let fanout = settings_fanout_word(settings);
+        let word = fanout as u32;
+        self.setup(Operation::Fanout { word }, |r| {
+            r.socket.setsockopt(PACKET_FANOUT, fanout)
+        })
+        .map_err(|e| e.explain(|| probe::check_fanout(settings)))?;
+        self.fanout = fanout;
+        Ok(())
+    }
+
+    //a ring around `socket` with nothing mapped or set up yet
+    fn with_socket(socket: Socket, label: RingLabel, opts: tpacket3::TpacketReq3) -> Ring {
+        Ring {
+            socket,
+            label,
+            mmap: None,
+            opts,
+            fanout: 0,
+            totals: RingTotals::default(),
+            started: SystemTime::now(),
+            cursor: 0,
+            last_seq: None,
+            leases: VecDeque::new(),
+            hold_times: HoldHistogram::new(),
+            latency: BlockLatency::new(),
+            hold_warning: None,
+            hold_peak: Cell::new(Duration::from_secs(0)),
+            freeze_hook: None,
+            promisc: None,
+            offloads: None,
+            hw_timestamps: None,
+            shutdown: None,
+            parse_policy: ParsePolicy::Skip,
+            quarantine: None,
+            block_dump: None,
+            locked: false,
+        }
+    }
+
+    //maps the ring of a socket that already has its PACKET_RX_RING set up and is bound, like one
+    //passed over from another process. Promiscuous mode and offloads stay with whoever set them
+    pub(crate) fn attach(
+        socket: Socket,
+        label: RingLabel,
+        opts: tpacket3::TpacketReq3,
+        fanout: c_int,
+    ) -> io::Result<Ring> {
+        let mut ring = Ring::with_socket(socket, label, opts);
+        ring.fanout = fanout;
+        ring.mmap_rx_ring()?;
+        trace_event!(
+            Info,
+            "ring {} attached (fd {}): {} blocks of {} bytes",
+            ring.label,
+            ring.socket.fd,
+            ring.opts.tp_block_nr,
+            ring.opts.tp_block_size
+        );
+        Ok(ring)
+    }
+
+    ///Returns the PACKET_FANOUT argument the ring joined its fanout group with
+    pub fn fanout(&self) -> c_int {
+        self.fanout
+    }
+
+    ///Returns when this ring was created, for use with `RawPacket::time_since`
+    pub fn started(&self) -> SystemTime {
+        self.started
+    }
+
+    ///Returns running totals of what this ring has delivered to the application
+    pub fn totals(&self) -> RingTotals {
+        self.totals
+    }
+
+    ///Returns the number of blocks that were lost because the application fell too far behind,
+    ///as detected from gaps in the block sequence numbers
+    pub fn missed_blocks(&self) -> u64 {
+        self.totals.missed_blocks
+    }
+
+    ///Returns the label identifying this ring
+    pub fn label(&self) -> &RingLabel {
+        &self.label
+    }
+
+    ///Returns the guard restoring promiscuous mode, or `None` if `promisc` was off in the settings
+    #[inline]
+    pub fn promisc(&self) -> Option<&PromiscGuard> {
+        self.promisc.as_deref()
+    }
+
+    ///Returns the guard restoring offloads, or `None` if `disable_offloads` was off in the settings
+    #[inline]
+    pub fn offloads(&self) -> Option<&OffloadGuard> {
+        self.offloads.as_deref()
+    }
+
+    ///Has the ring's interface timestamp received packets in hardware and the ring report those
+    ///timestamps, until the ring and its clones are dropped. Returns the setup the driver
+    ///applied. Needs CAP_NET_ADMIN, and fails with EOPNOTSUPP on interfaces without hardware
+    ///timestamping, such as most virtual ones
+    pub fn enable_hardware_timestamps(&mut self) -> io::Result<HwTstampConfig> {
+        self.set_timestamp_source(tpacket3::TimestampSource::HardwareRaw)
+            .map(|applied| applied.unwrap_or_default())
+    }
+
+    ///Switches where the ring's packet timestamps come from, like `timestamps` in the settings.
+    ///Returns the setup the driver applied for the hardware sources. Going back to `Software`
+    ///puts the interface's timestamping back as the ring found it
+    pub fn set_timestamp_source(
+        &mut self,
+        source: tpacket3::TimestampSource,
+    ) -> io::Result<Option<HwTstampConfig>> {
+        self.check_unlocked("set_timestamp_source")?;
+        let flags = match source {
+            tpacket3::TimestampSource::Software => {
+                timestamp::set_packet_timestamp(&mut self.socket, Timestamping::empty())?;
+                self.hw_timestamps.take();
+                return Ok(None);
+            }
+            tpacket3::TimestampSource::HardwareRaw => Timestamping::RAW_HARDWARE,
+            tpacket3::TimestampSource::HardwareSystem => Timestamping::SYS_HARDWARE,
+        };
+        let applied = match &self.hw_timestamps {
+            Some(guard) => guard.applied(),
+            None => {
+                let guard = HwTimestampGuard::enable_rx(&self.socket)?;
+                let applied = guard.applied();
+                self.hw_timestamps = Some(Arc::new(guard));
+                applied
+            }
+        };
+        timestamp::set_packet_timestamp(&mut self.socket, flags)?;
+        trace_event!(
+            Info,
+            "ring {} reports {:?} timestamps, rx_filter {}",
+            self.label,
+            source,
+            applied.rx_filter
+        );
+        Ok(Some(applied))
+    }
+
+    ///Reads what timestamping the ring's interface supports
+    pub fn timestamp_info(&self) -> io::Result<TsInfo> {
+        self.check_unlocked("timestamp_info")?;
+        ethtool::get_ts_info(&self.socket)
+    }
+
+    ///Returns the guard restoring the interface's timestamping, or `None` if
+    ///`enable_hardware_timestamps` wasn't called
+    #[inline]
+    pub fn hardware_timestamps(&self) -> Option<&HwTimestampGuard> {
+        self.hw_timestamps.as_deref()
+    }
+
+    ///Opens the PTP hardware clock of the ring's interface, which its raw hardware timestamps
+    ///come from, for converting them to system time
+    pub fn phc(&self) -> io::Result<PhcClock> {
+        self.check_unlocked("phc")?;
+        PhcClock::for_socket(&self.socket)
+    }
+
+    ///Returns the timestamp source in effect for packets on this ring: a hardware one if the
+    ///socket asks for it and the interface's NIC is timestamping received packets, the kernel's
+    ///clock otherwise. Packets the NIC left unstamped still fall back to the kernel's clock, which
+    ///`RawPacket::timestamp_source` tells apart
+    pub fn timestamp_source(&self) -> io::Result<tpacket3::TimestampSource> {
+        let flags = timestamp::get_packet_timestamp(self.socket.fd)?;
+        if !flags.intersects(Timestamping::RAW_HARDWARE | Timestamping::SYS_HARDWARE) {
+            return Ok(tpacket3::TimestampSource::Software);
+        }
+        let rx_enabled = match timestamp::get_hwtstamp(&self.socket) {
+            Ok(config) => config.rx_enabled(),
+            //drivers that can't report their setup are only on if this ring turned them on
+            Err(ref e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => {
+                self.hw_timestamps.is_some()
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(match rx_enabled {
+            false => tpacket3::TimestampSource::Software,
+            true if flags.contains(Timestamping::RAW_HARDWARE) => {
+                tpacket3::TimestampSource::HardwareRaw
+            }
+            true => tpacket3::TimestampSource::HardwareSystem,
+        })
+    }
+
+    ///Returns the ARPHRD_* hardware type of the ring's interface, ARPHRD_VOID on
+    ///`socket::ANY_INTERFACE` where every packet reports its own
+    pub fn hardware_type(&self) -> u16 {
+        self.socket.hatype
+    }
+
+    ///Reads the MAC address of the ring's interface
+    pub fn mac_address(&self) -> io::Result<[u8; 6]> {
+        self.check_unlocked("mac_address")?;
+        self.socket.get_mac_address()
+    }
+
+    ///Reads the MTU of the ring's interface, which with the link-layer header bounds the frame
+    ///size the ring needs
+    pub fn mtu(&self) -> io::Result<u32> {
+        self.check_unlocked("mtu")?;
+        self.socket.get_mtu()
+    }
+
+    ///Reads whether the ring's interface is up
+    pub fn oper_state(&self) -> io::Result<OperState> {
+        self.check_unlocked("oper_state")?;
+        self.socket.get_oper_state()
+    }
+
+    ///Reads how the ring's interface is related to other interfaces, e.g. the parent of a VLAN
+    ///subinterface or the slaves of a bond
+    pub fn interface_info(&self) -> io::Result<InterfaceInfo> {
+        self.check_unlocked("interface_info")?;
+        InterfaceInfo::from_if_name(&self.socket.if_name)
+    }
+
+    ///Returns the pcap link type (`linktype::LINKTYPE_*`) to write in the header of a capture
+    ///file of this ring's packets, or `None` if the interface's framing isn't known
+    pub fn link_type(&self) -> Option<u32> {
+        linktype::for_socket(&self.socket)
+    }
+
+    ///Locks the ring for a tight seccomp profile: from now on `mac_address`, `mtu`, `oper_state`
+    ///and `interface_info`, which need ioctls or sysfs reads, fail with `PermissionDenied`, so
+    ///receiving makes only the calls in `LOCKED_SYSCALLS`. Only this handle is locked, not its
+    ///clones, and `socket` is still there for anyone who reaches for it
+    pub fn lock(&mut self) {
+        self.locked = true;
+        trace_event!(Debug, "ring {} locked", self.label);
+    }
+
+    ///Returns true once `lock` has been called
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    fn check_unlocked(&self, what: &str) -> io::Result<()> {
+        if !self.locked {
+            return Ok(());
+        }
+        Err(Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("ring {} is locked, {} isn't available", self.label, what),
+        ))
+    }
+
+    ///Returns the block and frame layout the ring was created with
+    pub fn ring_settings(&self) -> &tpacket3::TpacketReq3 {
+        &self.opts
+    }
+
+    ///Reads the kernel statistics for this ring, resetting the kernel counters. If the queue froze
+    ///since they were last read, a `FreezeEvent` is traced and passed to the `on_freeze` callback
+    pub fn get_rx_statistics(&self) -> io::Result<RingStatistics> {
+        let stats = match read_statistics(self.socket.fd) {
+            Ok(s) => s,
+            Err(e) => return Err(label_error(&self.label, "PACKET_STATISTICS", e)),
+        };
+        let longest_hold = self.hold_peak.replace(Duration::from_secs(0));
+        if stats.tp_freeze_q_cnt > 0 {
+            self.report_freeze(stats.tp_freeze_q_cnt, longest_hold);
+        }
+        Ok(RingStatistics {
+            label: self.label.clone(),
+            stats,
+        })
+    }
+
+    fn setup<T, F>(&mut self, op: Operation, f: F) -> Result<T, RingError>
+    where
+        F: FnOnce(&mut Ring) -> io::Result<T>,
+    {
+        let result = f(self);
+        setup_step(&self.label, op, result)
+    }
+
+    ///Waits for a block to be added to the ring buffer and returns it
+    //We're allowing unused_mut here because apps that include this crate may need to control
+    //marking blocks as consumed for performance reasons to avoid copies
+    #[allow(unused_mut)]
+    #[inline]
+    pub fn get_block(&mut self) -> Block<'_> {
+        self.prune_leases();
+        loop {
+            self.wait_for_block();
+            if let Some((block, _)) = self.next_ready_block() {
+                return block;
+            }
+        }
+    }
+
+    ///Like `get_block`, but gives up and returns `None` if no block is retired within `timeout`,
+    ///or as soon as the ring's shutdown token is triggered. Socket errors are traced and waiting
+    ///goes on, `try_get_block` returns them
+    pub fn get_block_timeout(&mut self, timeout: Duration) -> Option<Block<'_>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.wait_block(Some(deadline)) {
+                Ok(ready) => return ready.map(|(block, _)| block),
+                Err(_e) => trace_event!(Warn, "ring {}: socket error: {}", self.label, _e),
+            }
+        }
+    }
+
+    ///Like `get_block_timeout`, waiting forever if `timeout` is `None`, but fails with the
+    ///socket's error if there is one, e.g. ENETDOWN once the interface goes down (see
+    ///`RingError::is_interface_down`). The error is cleared, so calling this again waits on.
+    ///Under `ParsePolicy::Error` it also fails on a malformed block
+    pub fn try_get_block(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Block<'_>>, RingError> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let label = self.label.clone();
+        match self.wait_block(deadline) {
+            Ok(Some((mut block, Some(anomaly))))
+                if matches!(self.parse_policy, ParsePolicy::Error) =>
+            {
+                let seq = block.seq_num();
+                block.mark_as_consumed();
+                Err(RingError::malformed(&label, seq, anomaly))
+            }
+            Ok(ready) => Ok(ready.map(|(block, _)| block)),
+            Err(e) => Err(RingError::new(&label, Operation::Receive, e)),
+        }
+    }
+
+    fn wait_block<'a>(
+        &mut self,
+        deadline: Option<Instant>,
+    ) -> io::Result<Option<(Block<'a>, Option<BlockAnomaly>)>> {
+        self.prune_leases();
+        loop {
+            if let Some(ready) = self.next_ready_block() {
+                return Ok(Some(ready));
+            }
+            if self.is_shut_down() {
+                return Ok(None);
+            }
+            let left = match deadline {
+                Some(d) => match d.checked_duration_since(Instant::now()) {
+                    Some(left) => Some(left),
+                    None => return Ok(None),
+                },
+                None => None,
+            };
+            let wake = self.shutdown.as_ref().map(ShutdownToken::fd);
+            if !wait_readable_for(&self.socket, &self.label, left, wake)? {
+                return Ok(None);
+            }
+        }
+    }
+
+    ///Has `get_block_timeout` return early once `token` is triggered
+    pub fn set_shutdown(&mut self, token: ShutdownToken) {
+        self.shutdown = Some(token);
+    }
+
+    ///Returns true if the ring's shutdown token was triggered
+    #[inline]
+    pub fn is_shut_down(&self) -> bool {
+        self.shutdown
+            .as_ref()
+            .is_some_and(ShutdownToken::is_triggered)
+    }
+
+    //check all blocks in memory space, starting from where the last one was found so they come
+    //back in the order the kernel retired them
+    //returns the next retired block, with the problem found walking it if it is malformed
+    fn next_ready_block<'a>(&mut self) -> Option<(Block<'a>, Option<BlockAnomaly>)> {
+        let nr = self.opts.tp_block_nr;
+        for n in 0..nr {
+            let i = (self.cursor + n) % nr;
+            if let Some(block) = self.get_single_block(i) {
+                if block.is_ready() && !self.is_leased(i, block.seq_num()) {
+                    trace_event!(
+                        Trace,
+                        "ring {}: block {} retired with {} packets",
+                        self.label,
+                        i,
+                        block.block_desc.hdr.num_pkts
+                    );
+                    self.cursor = (i + 1) % nr;
+                    let anomaly = self.account_block(&block);
+                    let first_packet = match block.block_desc.hdr.num_pkts {
+                        0 => None,
+                        _ => {
+                            let first = block.first_packet_time();
+                            let last = block.last_packet_time();
+                            self.latency.record_delivery(first, last, SystemTime::now());
+                            Some(first)
+                        }
+                    };
+                    self.leases.push_back(Lease {
+                        seq: block.seq_num(),
+                        index: i,
+                        leased_at: Instant::now(),
+                        first_packet,
+                    });
+                    return Some((block, anomaly));
+                }
+            }
+        }
+        None
+    }
+
+    ///Returns the number of blocks returned by `get_block` that haven't been marked as consumed.
+    ///Applications that hold several blocks at once (zero-copy pipelines) can drop the `Block`
+    ///without consuming it and release it later with `consume_up_to`
+    pub fn outstanding(&mut self) -> usize {
+        self.prune_leases();
+        self.leases.len()
+    }
+
+    ///Returns the sequence number of the oldest outstanding block
+    pub fn oldest_outstanding(&mut self) -> Option<u64> {
+        self.prune_leases();
+        self.leases.front().map(|l| l.seq)
+    }
+
+    ///Hands every outstanding block with a sequence number up to and including `seq` back to the
+    ///kernel, oldest first, and returns how many were released
+    pub fn consume_up_to(&mut self, seq: u64) -> usize {
+        self.prune_leases();
+        let mut released = 0;
+        while let Some(lease) = self.leases.front().cloned() {
+            if lease.seq > seq {
+                break;
+            }
+            self.leases.pop_front();
+            if let Some(mut block) = self.get_single_block(lease.index) {
+                if block.seq_num() == lease.seq {
+                    block.mark_as_consumed();
+                    released += 1;
+                }
+            }
+            self.record_hold(&lease);
+        }
+        released
+    }
+
+    ///Returns how long blocks were held between `get_block` and being marked as consumed. The
+    ///release is noticed on the next call into the ring, which in a normal receive loop is the
+    ///next `get_block`
+    pub fn hold_times(&self) -> &HoldHistogram {
+        &self.hold_times
+    }
+
+    ///Returns how old blocks' packets were when the blocks were handed out and consumed. Like
+    ///hold times, consumption is noticed on the next call into the ring
+    pub fn block_latency(&self) -> &BlockLatency {
+        &self.latency
+    }
+
+    ///Calls `callback` from `get_rx_statistics` whenever the statistics show the queue froze
+    pub fn on_freeze<F>(&mut self, callback: F)
+    where
+        F: Fn(&FreezeEvent) + Send + Sync + 'static,
+    {
+        self.freeze_hook = Some(FreezeHook {
+            callback: Arc::new(callback),
+        });
+    }
+
+    ///Returns a snapshot of the ring's settings, position and totals. It only reads the ring, the
+    ///kernel statistics are left alone so their counters aren't reset
+    pub fn diagnostics(&self) -> RingDiagnostics {
+        let opts = &self.opts;
+        RingDiagnostics {
+            label: self.label.clone(),
+            if_index: self.socket.if_index,
+            kind: self.socket.kind,
+            hardware_type: self.socket.hatype,
+            protocol: ring_protocol(&self.socket),
+            fanout_word: self.fanout as u32,
+            block_size: opts.tp_block_size,
+            block_nr: opts.tp_block_nr,
+            frame_size: opts.tp_frame_size,
+            frame_nr: opts.tp_frame_nr,
+            retire_timeout: self.retire_timeout(),
+            mmap_len: match self.mmap {
+                Some(_) => self.map_len().unwrap_or(0),
+                None => 0,
+            },
+            cursor: self.cursor,
+            last_seq: self.last_seq,
+            ready_blocks: self.ready_blocks(),
+            outstanding: self.live_leases().0,
+            longest_hold: self.hold_times.max(),
+            started: self.started,
+            totals: self.totals,
+        }
+    }
+
+    //counts the leases whose blocks are still unconsumed, without pruning the others, and how
+    //long the oldest of them has been held
+    fn live_leases(&self) -> (usize, Option<Duration>) {
+        let mut outstanding = 0;
+        let mut oldest_held = None;
+        for lease in &self.leases {
+            if self.peek_block(lease.index) == Some((true, lease.seq)) {
+                outstanding += 1;
+                oldest_held = oldest_held.or_else(|| Some(lease.leased_at.elapsed()));
+            }
+        }
+        (outstanding, oldest_held)
+    }
+
+    fn ready_blocks(&self) -> usize {
+        (0..self.opts.tp_block_nr)
+            .filter(|&i| self.peek_block(i).is_some_and(|(ready, _)| ready))
+            .count()
+    }
+
+    fn report_freeze(&self, freezes: u32, longest_hold: Duration) {
+        let (outstanding, oldest_held) = self.live_leases();
+        let event = FreezeEvent {
+            label: self.label.clone(),
+            freezes,
+            outstanding,
+            ready: self.ready_blocks(),
+            block_nr: self.opts.tp_block_nr,
+            oldest_held,
+            longest_hold,
+            retire_timeout: self.retire_timeout(),
+        };
+        trace_event!(
+            Warn,
+            "ring {}: queue froze {} times, {} of {} blocks ready, {} outstanding, oldest held {:?}, \
+             longest hold {:?}, retire timeout {:?}, likely cause {:?}",
+            event.label,
+            event.freezes,
+            event.ready,
+            event.block_nr,
+            event.outstanding,
+            event.oldest_held,
+            event.longest_hold,
+            event.retire_timeout,
+            event.cause()
+        );
+        if let Some(ref hook) = self.freeze_hook {
+            (hook.callback)(&event);
+        }
+    }
+
+    ///Calls `callback` whenever a block was held for more than `fraction` (e.g. 0.8) of
+    ///tp_retire_blk_tov before being consumed
+    pub fn on_hold_warning<F>(&mut self, fraction: f64, callback: F)
+    where
+        F: Fn(&HoldWarning) + Send + Sync + 'static,
+    {
+        self.hold_warning = Some(HoldWarningHook {
+            threshold: self.retire_timeout().mul_f64(fraction.max(0.0)),
+            callback: Arc::new(callback),
+        });
+    }
+
+    #[inline]
+    fn retire_timeout(&self) -> Duration {
+        Duration::from_millis(u64::from(self.opts.tp_retire_blk_tov))
+    }
+
+    //drops leases on blocks the application has since marked as consumed. A block the kernel has
+    //since refilled has a new sequence number, so it no longer matches its lease either
+    fn prune_leases(&mut self) {
+        let mut i = 0;
+        while i < self.leases.len() {
+            let lease = self.leases[i];
+            let outstanding = match self.get_single_block(lease.index) {
+                Some(block) => block.is_ready() && block.seq_num() == lease.seq,
+                None => false,
+            };
+            if outstanding {
+                i += 1;
+            } else {
+                self.leases.remove(i);
+                self.record_hold(&lease);
+            }
+        }
+    }
+
+    fn record_hold(&mut self, lease: &Lease) {
+        let held = lease.leased_at.elapsed();
+        self.hold_times.record(held);
+        if let Some(first) = lease.first_packet {
+            self.latency.record_consumed(first, SystemTime::now());
+        }
+        if held > self.hold_peak.get() {
+            self.hold_peak.set(held);
+        }
+        if let Some(ref hook) = self.hold_warning {
+            if held >= hook.threshold {
+                trace_event!(
+                    Warn,
+                    "ring {}: block {} held for {:?}",
+                    self.label,
+                    lease.seq,
+                    held
+                );
+                (hook.callback)(&HoldWarning {
+                    label: self.label.clone(),
+                    seq: lease.seq,
+                    held,
+                    retire_timeout: self.retire_timeout(),
+                });
+            }
+        }
+    }
+
+    #[inline]
+    fn is_leased(&self, index: u32, seq: u64) -> bool {
+        self.leases
+            .iter()
+            .any(|lease| lease.index == index && lease.seq == seq)
+    }
+
+    #[inline]
+    fn account_block(&mut self, block: &Block) -> Option<BlockAnomaly> {
+        let seq = block.seq_num();
+        if let Some(last) = self.last_seq {
+            if seq > last + 1 {
+                let missed = seq - last - 1;
+                trace_event!(
+                    Warn,
+                    "ring {}: missed {} blocks before sequence {}",
+                    self.label,
+                    missed,
+                    seq
+                );
+                self.totals.missed_blocks += missed;
+            }
+        }
+        self.last_seq = Some(seq);
+        if let Some(ref dump) = self.block_dump {
+            dump.offer(&self.label, seq, block.as_bytes());
+        }
+        self.totals.blocks += 1;
+        self.totals.packets += u64::from(block.block_desc.hdr.num_pkts);
+        let label = &self.label;
+        let quarantine = &self.quarantine;
+        let (bytes, bad_frames, anomaly) = block.scan(|index, anomaly, data| {
+            trace_event!(
+                Debug,
+                "ring {}: malformed frame {} in block {}: {:?}",
+                label,
+                index,
+                seq,
+                anomaly
+            );
+            if let Some(q) = quarantine {
+                q.admit(label, seq, index, anomaly, data);
+            }
+        });
+        self.totals.bytes += bytes;
+        self.totals.malformed_frames += bad_frames;
+        if let Some(ref anomaly) = anomaly {
+            trace_event!(
+                Warn,
+                "ring {}: malformed block {}: {:?}",
+                self.label,
+                seq,
+                anomaly
+            );
+            self.totals.malformed_blocks += 1;
+            if let ParsePolicy::Callback(ref f) = self.parse_policy {
+                f(&self.label, anomaly, block.as_bytes());
+            }
+        }
+        anomaly
+    }
+
+    //unmaps the ring and closes the socket. Rings don't do this on drop as clones share both, so
+    //only owners that know there are no clones left call it
+    pub(crate) fn close(self) {
+        unsafe { close(self.into_raw_fd()) };
+    }
+
+    //the size of the mapping. The geometry of an attached ring comes from its peer rather than
+    //PACKET_RX_RING, so the product is checked rather than trusted
+    fn map_len(&self) -> io::Result<usize> {
+        let (size, nr) = (self.opts.tp_block_size, self.opts.tp_block_nr);
+        (size as usize).checked_mul(nr as usize).ok_or_else(|| {
+            Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} blocks of {} bytes is too large to map", nr, size),
+            )
+        })
+    }
+
+    fn mmap_rx_ring(&mut self) -> io::Result<()> {
+        let size = self.map_len()?;
+        self.mmap = Some(mmap_ring(&self.socket, size)?);
+        Ok(())
+    }
+
+    fn bind_rx_ring(&mut self) -> io::Result<()> {
+        bind_ring(&self.socket)
+    }
+
+    #[inline]
+    fn wait_for_block(&self) {
+        wait_readable(&self.socket, &self.label)
+    }
+
+    //reads whether a block is owned by userspace and its sequence number, without handing it out
+    fn peek_block(&self, index: u32) -> Option<(bool, u64)> {
+        let offset = index as isize * self.opts.tp_block_size as isize;
+        let block = unsafe {
+            std::slice::from_raw_parts(self.mmap?.offset(offset), self.opts.tp_block_size as usize)
+        };
+        let (_, desc) = tpacket3::get_tpacket_block_desc(block).ok()?;
+        let ready = tpacket3::read_block_status(block) & u32::from(tpacket3::TP_STATUS_USER) != 0;
+        Some((ready, desc.hdr.seq_num))
+    }
+
+    #[inline]
+    fn get_single_block<'a>(&mut self, count: u32) -> Option<Block<'a>> {
+        //TODO: clean up all this typecasting
+        let offset = count as isize * self.opts.tp_block_size as isize;
+
+        let block = unsafe {
+            std::slice::from_raw_parts_mut(
+                self.mmap?.offset(offset),
+                self.opts.tp_block_size as usize,
+            )
+        };
+
+        let block_desc = match tpacket3::get_tpacket_block_desc(&block[..]) {
+            Ok(x) => x,
+            Err(_) => {
+                return None;
+            }
+        };
+
+        let blk = Block {
+            block_desc: block_desc.1,
+            raw_data: &mut block[..],
+            fd: self.socket.fd,
+        };
+
+        Some(blk)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for Ring {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.fd
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl IntoRawFd for Ring {
+    ///Unmaps the ring and gives up its socket, which keeps its ring, binding and fanout group.
+    ///This ring's promiscuous mode, offload and timestamping guards are dropped, so clones must be
+    ///gone for the socket to be the only thing left using it
+    fn into_raw_fd(mut self) -> RawFd {
+        //the guards need the socket to undo their changes
+        self.promisc.take();
+        self.offloads.take();
+        self.hw_timestamps.take();
+        if let Some(map) = self.mmap.take() {
+            //the ring was only mapped if its size fit
+            let size = self.map_len().unwrap_or(0);
+            unsafe { munmap(map as *mut c_void, size) };
+        }
+        self.socket.fd
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe impl Send for Ring {}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn mmap_ring(sock: &Socket, size: usize) -> io::Result<*mut u8> {
+    match unsafe {
+        mmap(
+            std::ptr::null_mut(),
+            size,
+            PROT_READ | PROT_WRITE,
+            MAP_SHARED | MAP_LOCKED | MAP_NORESERVE,
+            sock.fd,
+            0,
+        )
+    } as isize
+    {
+        -1 => Err(io::Error::last_os_error()),
+        map => Ok(map as *mut u8),
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn bind_ring(sock: &Socket) -> io::Result<()> {
+    bind_protocol(sock, ring_protocol(sock))
+}
+
+///Returns the ethertype a ring on `sock` binds to
+#[cfg(target_os = "linux")]
+pub(crate) fn ring_protocol(sock: &Socket) -> u16 {
+    //monitor mode frames are 802.11 rather than IP, binding to ETH_P_IP would see none of them
+    if sock.hatype == radiotap::ARPHRD_IEEE80211_RADIOTAP {
+        return ETH_P_ALL as u16;
+    }
+    ETH_P_IP as u16
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn bind_protocol(sock: &Socket, protocol: u16) -> io::Result<()> {
+    let mut sa = sockaddr_ll {
+        sll_family: AF_PACKET as u16,
+        sll_protocol: protocol.to_be(),
+        sll_ifindex: sock.if_index as c_int,
+        sll_hatype: sock.hatype,
+        sll_pkttype: (PACKET_HOST //can we just use 255 here lol
+            | PACKET_BROADCAST
+            | PACKET_MULTICAST
+            | PACKET_OTHERHOST
+            | PACKET_OUTGOING),
+        sll_halen: ETH_ALEN as u8,
+        sll_addr: [0; 8],
+    };
+
+    //get the size before we change the pointer type
+    let size = mem::size_of_val(&sa);
+    //we have to do this transmute or similar because Linux uses multiple sockaddr_
+    //family structs and casts them to sockaddr after populating them
+    let addr_ptr = unsafe { mem::transmute::<*mut sockaddr_ll, *mut sockaddr>(&mut sa) };
+
+    match unsafe { bind(sock.fd, addr_ptr, size as socklen_t) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+///Waits up to `timeout`, or forever if `None`, for the socket or `wake` to become readable.
+///Returns false on timeout, and the socket's pending error if poll reports one, which reading
+///clears so the next wait blocks again
+#[cfg(target_os = "linux")]
+pub(crate) fn wait_readable_for(
+    sock: &Socket,
+    label: &RingLabel,
+    timeout: Option<Duration>,
+    wake: Option<c_int>,
+) -> io::Result<bool> {
+    let mut pfds = [
+        pollfd {
+            fd: sock.fd,
+            events: POLLIN | POLLERR,
+            revents: 0,
+        },
+        pollfd {
+            //poll ignores negative descriptors
+            fd: wake.unwrap_or(-1),
+            events: POLLIN,
+            revents: 0,
+        },
+    ];
+    let ms = timeout.map_or(-1, |t| t.as_millis().min(c_int::MAX as u128) as c_int);
+    match unsafe { poll(pfds.as_mut_ptr(), 2, ms) } {
+        0 => Ok(false),
+        n if n < 0 => {
+            trace_event!(
+                Warn,
+                "ring {}: poll failed: {}",
+                label,
+                io::Error::last_os_error()
+            );
+            //let the caller rescan rather than report a timeout early, as on EINTR
+            Ok(true)
+        }
+        _ if pfds[0].revents & (POLLERR | POLLHUP) != 0 => match sock.take_error()? {
+            Some(e) => Err(e),
+            None if pfds[0].revents & POLLHUP != 0 => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the socket was shut down",
+            )),
+            //POLLERR for a queued error message rather than a socket error
+            None => Ok(true),
+        },
+        _ => Ok(true),
+    }
+}
+
+///Waits for the socket to become readable. Socket errors are traced and cleared rather than
+///returned, so a loop around this doesn't spin on a downed interface
+#[cfg(target_os = "linux")]
+#[inline]
+pub(crate) fn wait_readable(sock: &Socket, label: &RingLabel) {
+    if let Err(_e) = wait_readable_for(sock, label, None, None) {
+        trace_event!(Warn, "ring {}: socket error: {}", label, _e);
+    }
+}
+
+///Stops the socket from seeing packets sent by this host. Kernels before 4.20 lack
+///PACKET_IGNORE_OUTGOING, so `filter::DROP_OUTGOING` is attached instead, which a filter attached
+///later replaces
+#[cfg(target_os = "linux")]
+pub(crate) fn ignore_outgoing(sock: &mut Socket) -> io::Result<()> {
+    match sock.setsockopt(PACKET_IGNORE_OUTGOING, 1 as c_int) {
+        Err(ref e) if e.raw_os_error() == Some(ENOPROTOOPT) => {
+            trace_event!(
+                Debug,
+                "{}: no PACKET_IGNORE_OUTGOING, filtering instead",
+                sock.if_name
+            );
+            filter::attach_filter(sock.fd, &filter::DROP_OUTGOING)
+        }
+        result => result,
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn socket_kind(settings: &RingSettings) -> c_int {
+    if settings.cooked {
+        socket::SOCK_DGRAM
+    } else {
+        socket::SOCK_RAW
+    }
+}
+
+///Computes the PACKET_FANOUT argument joining this process' fanout group with `method`
+#[cfg(target_os = "linux")]
+#[inline]
+pub(crate) fn fanout_word(method: c_int) -> c_int {
+    (unsafe { getpid() } & 0xFFFF) | (method << 16)
+}
+
+//the explicit group in the settings, or this process' group
+#[cfg(target_os = "linux")]
+#[inline]
+pub(crate) fn settings_fanout_word(settings: &RingSettings) -> c_int {
+    match settings.fanout_group {
+        Some(id) => c_int::from(id) | (settings.fanout_method << 16),
+        None => fanout_word(settings.fanout_method),
+    }
+}
+
+//waits for the interface if the settings ask to, before anything is opened on it
+#[cfg(target_os = "linux")]
+fn wait_for_interface(settings: &RingSettings, label: &RingLabel) -> Result<(), RingError> {
+    match settings.wait_for_interface {
+        //sysfs shows the caller's namespace, so the wait would watch the wrong interface
+        Some(timeout) if settings.netns.is_some() => Err(RingError::invalid(
+            label,
+            Operation::WaitForInterface { timeout },
+            ConfigError::WaitInNetns,
+        )),
+        Some(timeout) if settings.if_name != socket::ANY_INTERFACE => setup_step(
+            label,
+            Operation::WaitForInterface { timeout },
+            interface::wait_for(&settings.if_name, timeout),
+        ),
+        _ => Ok(()),
+    }
+}
+
+//fails early with a clearer error than the ioctls give, if the driver says it can't
+#[cfg(target_os = "linux")]
+pub(crate) fn check_timestamp_support(
+    sock: &Socket,
+    source: tpacket3::TimestampSource,
+) -> io::Result<()> {
+    let info = match ethtool::get_ts_info(sock) {
+        Ok(info) => info,
+        //leave it to SIOCSHWTSTAMP to say
+        Err(_) => return Ok(()),
+    };
+    if info.supports(source) {
+        return Ok(());
+    }
+    Err(Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "{} can't give {:?} timestamps, only {:?}",
+            sock.if_name,
+            source,
+            info.rx_sources()
+        ),
+    ))
+}
+
+//waits for the interface, then opens the packet socket in the settings' namespace
+#[cfg(target_os = "linux")]
+pub(crate) fn open_socket(settings: &RingSettings, label: &RingLabel) -> Result<Socket, RingError> {
+    wait_for_interface(settings, label)?;
+    //this typecasting sucks :(
+    let kind = socket_kind(settings);
+    let netns = match settings.netns {
+        Some(ref ns) => Some(setup_step(
+            label,
+            Operation::Netns(ns.clone()),
+            netns::enter(ns),
+        )?),
+        None => None,
+    };
+    let socket = setup_step(
+        label,
+        Operation::Socket { kind },
+        Socket::with_kind(&settings.if_name, socket::PF_PACKET, kind),
+    )?;
+    //the socket stays in the namespace, nothing after this needs to be in there
+    drop(netns);
+    Ok(socket)
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn setup_step<T>(
+    label: &RingLabel,
+    op: Operation,
+    result: io::Result<T>,
+) -> Result<T, RingError> {
+    result.map_err(|e| {
+        trace_event!(Error, "ring {} setup failed at {}: {}", label, op, e);
+        RingError::new(label, op, e)
+    })
+}
+
+///Keeps the error kind but prefixes the message with the ring and the step that failed. The
+///`RingError` inside can be had back with `RingError::from`
+#[cfg(any(target_os = "linux", feature = "bpf"))]
+pub(crate) fn label_error(label: &RingLabel, step: &str, e: io::Error) -> io::Error {
+    RingError::new(label, Operation::Other(String::from(step)), e).into()
+}
+
+///This is very easy because the Linux kernel has its own counters that are reset every time
+///getsockopt() is called
+#[cfg(target_os = "linux")]
+#[inline]
+pub fn get_rx_statistics(fd: i32) -> Result<tpacket3::TpacketStatsV3, Error> {
+    let optval = read_statistics(fd)?;
+    if optval.tp_freeze_q_cnt > 0 {
+        trace_event!(
+            Warn,
+            "queue on fd {} froze {} times, consumer is not keeping up",
+            fd,
+            optval.tp_freeze_q_cnt
+        );
+    }
+    Ok(optval)
+}
+
+#[cfg(target_os = "linux")]
+fn read_statistics(fd: i32) -> Result<tpacket3::TpacketStatsV3, Error> {
+    let mut optval = tpacket3::TpacketStatsV3 {
+        tp_packets: 0,
+        tp_drops: 0,
+        tp_freeze_q_cnt: 0,
+    };
+    socket::get_sock_opt(fd, PACKET_STATISTICS, &mut optval)?;
+    trace_event!(
+        Debug,
+        "fd {}: {} packets, {} drops",
+        fd,
+        optval.tp_packets,
+        optval.tp_drops
+    );
+    Ok(optval)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    const PRIV_LEN: usize = 16;
+    //where the frame starts in each packet, after the tpacket3 header and the sockaddr_ll
+    const MAC_OFFSET: usize = 80;
+
+    //a packet laid out the way the kernel writes it, taking up `next_offset` bytes if nonzero
+    fn packet(next_offset: u32, frame: &[u8], wire_len: u32) -> Vec<u8> {
+        let mut p = Vec::new();
+        for word in &[next_offset, 10, 20, frame.len() as u32, wire_len, 1] {
+            p.extend_from_slice(&word.to_ne_bytes());
+        }
+        p.extend_from_slice(&(MAC_OFFSET as u16).to_ne_bytes());
+        p.extend_from_slice(&(MAC_OFFSET as u16 + 14).to_ne_bytes());
+        p.resize(tpacket3::TP_SOCKADDR_LL_OFFSET, 0);
+        p.extend_from_slice(&17u16.to_ne_bytes());
+        p.extend_from_slice(&0x0800u16.to_be_bytes());
+        p.resize(MAC_OFFSET, 0);
+        p.extend_from_slice(frame);
+        let len = match next_offset {
+            0 => (p.len() + 15) & !15,
+            n => n as usize,
+        };
+        p.resize(len, 0);
+        p
+    }
+
+    //a block of `packets` after a PRIV_LEN private area, with `blk_len` and `num_pkts` as given
+    //or taken from the packets, followed by stale bytes to the block size
+    fn block_bytes(packets: &[Vec<u8>], num_pkts: Option<u32>, blk_len: Option<u32>) -> Vec<u8> {
+        let first = 48 + PRIV_LEN;
+        let used = first + packets.iter().map(Vec::len).sum::<usize>();
+        let mut b = Vec::new();
+        let num_pkts = num_pkts.unwrap_or(packets.len() as u32);
+        let blk_len = blk_len.unwrap_or(used as u32);
+        for word in &[3u32, 48, 1, num_pkts, first as u32, blk_len] {
+            b.extend_from_slice(&word.to_ne_bytes());
+        }
+        b.extend_from_slice(&7u64.to_ne_bytes());
+        b.resize(48, 0);
+        //private area the walk has to skip
+        b.resize(first, 0xee);
+        for p in packets {
+            b.extend_from_slice(p);
+        }
+        b.resize(4096, 0xaa);
+        b
+    }
+
+    fn block(raw: &mut [u8]) -> Block<'_> {
+        let (_, block_desc) = tpacket3::get_tpacket_block_desc(raw).unwrap();
+        Block {
+            block_desc,
+            raw_data: raw,
+            fd: -1,
+        }
+    }
+
+    fn frame(fill: u8, len: usize) -> Vec<u8> {
+        vec![fill; len]
+    }
+
+    #[test]
+    fn walks_packets_after_the_private_area() {
+        let frames = [frame(1, 60), frame(2, 100), frame(3, 42)];
+        let packets = vec![
+            packet(160, &frames[0], 60),
+            packet(192, &frames[1], 1514),
+            packet(0, &frames[2], 42),
+        ];
+        let mut raw = block_bytes(&packets, None, None);
+        let block = block(&mut raw);
+        assert_eq!(block.first_packet_offset(), 48 + PRIV_LEN);
+        assert_eq!(block.anomaly(), None);
+        let got = block.get_raw_packets();
+        assert_eq!(got.len(), 3);
+        for (p, f) in got.iter().zip(&frames) {
+            assert_eq!(p.l2(), &f[..]);
+            assert_eq!(p.captured_len() as usize, f.len());
+            assert_eq!(p.l3(), &f[14..]);
+        }
+        assert_eq!(got[1].wire_len(), 1514);
+        assert!(got[1].is_truncated());
+        assert_eq!(got[0].sockaddr_ll().unwrap().sll_protocol, 0x0800);
+        //the last packet ends where the kernel stopped writing, not at the end of the block
+        let last = &got[2];
+        assert_eq!(last.tpacket3_hdr.tp_next_offset, 0);
+        assert_eq!(last.data.len(), packets[2].len());
+    }
+
+    #[test]
+    fn stops_at_a_truncated_header() {
+        let packets = vec![
+            packet(160, &frame(1, 60), 60),
+            packet(160, &frame(2, 60), 60),
+        ];
+        let blk_len = (48 + PRIV_LEN + 320) as u32;
+        //a third packet is claimed but blk_len ends after the second
+        let mut raw = block_bytes(&packets, Some(3), Some(blk_len));
+        let block = block(&mut raw);
+        assert_eq!(
+            block.anomaly(),
+            Some(BlockAnomaly::HeaderTruncated {
+                index: 2,
+                offset: blk_len as usize,
+            })
+        );
+        let got = block.get_raw_packets();
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[1].l2(), &frame(2, 60)[..]);
+    }
+
+    #[test]
+    fn stops_at_a_next_offset_past_blk_len() {
+        let packets = vec![
+            packet(160, &frame(1, 60), 60),
+            packet(160, &frame(2, 60), 60),
+            packet(0, &frame(3, 60), 60),
+        ];
+        let mut raw = block_bytes(&packets, None, None);
+        //the second packet points into the stale bytes after blk_len
+        let second = 48 + PRIV_LEN + 160;
+        raw[second..second + 4].copy_from_slice(&2048u32.to_ne_bytes());
+        let block = block(&mut raw);
+        assert_eq!(
+            block.anomaly(),
+            Some(BlockAnomaly::BadNextOffset {
+                index: 1,
+                offset: second,
+                next_offset: 2048,
+            })
+        );
+        let got = block.get_raw_packets();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].l2(), &frame(1, 60)[..]);
+    }
+
+    #[test]
+    fn rejects_a_next_offset_inside_the_header() {
+        let packets = vec![packet(16, &[], 0), packet(0, &frame(2, 60), 60)];
+        let mut raw = block_bytes(&packets, None, None);
+        let block = block(&mut raw);
+        assert!(matches!(
+            block.anomaly(),
+            Some(BlockAnomaly::BadNextOffset { index: 0, .. })
+        ));
+        assert!(block.get_raw_packets().is_empty());
+    }
 }
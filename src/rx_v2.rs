@@ -0,0 +1,495 @@
+//!Frame-based TPACKET_V2 ring for kernels where TPACKET_V3 is missing or unusable. It mirrors the
+//!`rx` API: `get_block` returns a batch of ready frames, which hands out the same `RawPacket`s and
+//!is released with `mark_as_consumed`, so receive loops work unchanged on either ring.
+
+use std::io;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use libc::{c_int, c_void, close, munmap, MSG_DONTWAIT};
+
+use error::{ConfigError, Operation, RingError};
+use ethtool::{OffloadGuard, Offloads};
+use interface::{InterfaceInfo, OperState};
+use linktype;
+use probe;
+use rx::{
+    self, OwnedPacket, RawPacket, RingLabel, RingSettings, RingStatistics, RingTotals,
+    PACKET_FANOUT, PACKET_RX_RING, PACKET_VERSION,
+};
+use simple::{self, SimplePacket};
+use socket::{PromiscGuard, Socket};
+use timestamp::{self, HwTimestampGuard, Timestamping};
+use tpacket2::{self, TpacketReq};
+use tpacket3::TimestampSource;
+
+const PACKET_COPY_THRESH: c_int = 7;
+
+///References a single mmaped TPACKET_V2 ring buffer. Normally one per thread.
+#[derive(Clone, Debug)]
+pub struct Ring {
+    pub socket: Socket,
+    label: RingLabel,
+    mmap: Option<*mut u8>,
+    req: TpacketReq,
+    frames_per_block: u32,
+    //index of the next frame the kernel will fill
+    cursor: u32,
+    next_seq: u64,
+    totals: RingTotals,
+    //shared by clones, so promiscuous mode is restored when the last of them is dropped
+    promisc: Option<Arc<PromiscGuard>>,
+    offloads: Option<Arc<OffloadGuard>>,
+    hw_timestamps: Option<Arc<HwTimestampGuard>>,
+}
+
+///A run of consecutive ready frames, handed out by `Ring::get_block` in place of a TPACKET_V3
+///block. The frames are returned to the kernel together by `mark_as_consumed`
+#[derive(Debug)]
+pub struct Block<'a> {
+    raw_data: &'a mut [u8],
+    frame_size: usize,
+    frames: u32,
+    seq: u64,
+}
+
+impl<'a> Block<'a> {
+    ///Marks every frame in the batch as free to be reused by the kernel
+    #[inline]
+    pub fn mark_as_consumed(&mut self) {
+        for i in 0..self.frames as usize {
+            let status = i * self.frame_size;
+            self.raw_data[status..status + 4]
+                .copy_from_slice(&tpacket2::TP_STATUS_KERNEL.to_ne_bytes());
+        }
+    }
+
+    ///Returns a `Vec` of details and references to raw packets in the batch
+    #[inline]
+    pub fn get_raw_packets(&self) -> Vec<RawPacket<'_>> {
+        let mut packets = Vec::with_capacity(self.frames as usize);
+        for i in 0..self.frames as usize {
+            let frame = &self.raw_data[i * self.frame_size..(i + 1) * self.frame_size];
+            if let Ok((_, hdr)) = tpacket2::get_tpacket2_hdr(frame) {
+                packets.push(RawPacket::with_sll_offset(
+                    hdr.to_tpacket3_hdr(),
+                    frame,
+                    tpacket2::TP2_SOCKADDR_LL_OFFSET,
+                ));
+            }
+        }
+        packets
+    }
+
+    ///Copies every packet out of the batch and immediately hands the frames back to the kernel
+    pub fn copy_packets(mut self) -> Vec<OwnedPacket> {
+        let packets = self
+            .get_raw_packets()
+            .iter()
+            .map(|p| p.to_owned())
+            .collect();
+        self.mark_as_consumed();
+        packets
+    }
+
+    ///Returns the number of frames in the batch
+    #[inline]
+    pub fn packet_count(&self) -> u32 {
+        self.frames
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+
+    ///Returns a sequence number counted by the ring, as TPACKET_V2 has no block sequence numbers
+    #[inline]
+    pub fn seq_num(&self) -> u64 {
+        self.seq
+    }
+
+    ///Returns the time the first packet in the batch was captured
+    pub fn first_packet_time(&self) -> Option<SystemTime> {
+        self.get_raw_packets().first().map(|p| p.timestamp())
+    }
+
+    ///Returns the time the last packet in the batch was captured
+    pub fn last_packet_time(&self) -> Option<SystemTime> {
+        self.get_raw_packets().last().map(|p| p.timestamp())
+    }
+}
+
+impl Ring {
+    ///Creates a new ring buffer on the specified interface name and puts the interface into promiscuous mode
+    pub fn from_if_name(if_name: &str) -> Result<Ring, RingError> {
+        Ring::new(RingSettings {
+            if_name: String::from(if_name),
+            ..RingSettings::default()
+        })
+    }
+
+    ///Creates a new ring buffer from the supplied RingSettings struct. Only the block and frame
+    ///geometry of `ring_settings` applies to TPACKET_V2, and the block parsing settings
+    ///`parse_policy`, `quarantine` and `block_dump` are refused unless left at their defaults
+    pub fn new(settings: RingSettings) -> Result<Ring, RingError> {
+        trace_span!(_span, "ring_setup");
+        let label = RingLabel::from_settings(&settings);
+        if let Some(setting) = v3_only_setting(&settings) {
+            return Err(RingError::invalid(
+                &label,
+                Operation::Version(tpacket2::TPACKET_V2),
+                ConfigError::NotOnTpacketV2(setting),
+            ));
+        }
+        let socket = rx::open_socket(&settings, &label)?;
+        let req = TpacketReq::from(&settings.ring_settings);
+        let frames_per_block = req
+            .tp_block_size
+            .checked_div(req.tp_frame_size)
+            .unwrap_or(0);
+        let mut ring = Ring {
+            socket,
+            label,
+            mmap: None,
+            req,
+            frames_per_block,
+            cursor: 0,
+            next_seq: 0,
+            totals: RingTotals::default(),
+            promisc: None,
+            offloads: None,
+            hw_timestamps: None,
+        };
+
+        //a ring that fails part way has no clones yet, so its socket and mapping can go with it
+        if let Err(e) = ring.configure(&settings) {
+            ring.close();
+            return Err(e);
+        }
+        trace_event!(
+            Info,
+            "TPACKET_V2 ring {} up (fd {}): {} frames of {} bytes",
+            ring.label,
+            ring.socket.fd,
+            ring.req.tp_frame_nr,
+            ring.req.tp_frame_size
+        );
+        Ok(ring)
+    }
+
+    //has the NIC timestamp received packets and the socket report them, as `timestamps` asks
+    fn enable_timestamps(&mut self, source: TimestampSource) -> io::Result<()> {
+        let flags = match source {
+            TimestampSource::Software => return Ok(()),
+            TimestampSource::HardwareRaw => Timestamping::RAW_HARDWARE,
+            TimestampSource::HardwareSystem => Timestamping::SYS_HARDWARE,
+        };
+        self.hw_timestamps = Some(Arc::new(HwTimestampGuard::enable_rx(&self.socket)?));
+        timestamp::set_packet_timestamp(&mut self.socket, flags)
+    }
+
+    //each step of setting up the socket and ring, in the order the kernel needs them
+    fn configure(&mut self, settings: &RingSettings) -> Result<(), RingError> {
+        //there is no device to put into promiscuous mode when capturing on every interface
+        if settings.promisc && !self.socket.is_any() {
+            self.setup(Operation::Promisc, |r| {
+                r.promisc = Some(Arc::new(PromiscGuard::new(&r.socket)?));
+                Ok(())
+            })?;
+        }
+        if settings.disable_offloads && !self.socket.is_any() {
+            self.setup(Operation::Offloads, |r| {
+                r.offloads = Some(Arc::new(OffloadGuard::disable(
+                    &r.socket,
+                    Offloads::MERGING,
+                )?));
+                Ok(())
+            })?;
+        }
+        if settings.timestamps != TimestampSource::Software {
+            let source = settings.timestamps;
+            self.setup(Operation::Timestamping(source), |r| {
+                rx::check_timestamp_support(&r.socket, source)?;
+                r.enable_timestamps(source)
+            })?;
+        }
+        self.setup(Operation::Version(tpacket2::TPACKET_V2), |r| {
+            r.socket.setsockopt(PACKET_VERSION, tpacket2::TPACKET_V2)
+        })?;
+        let rx_ring = Operation::RxRing {
+            block_size: self.req.tp_block_size,
+            block_nr: self.req.tp_block_nr,
+            frame_size: self.req.tp_frame_size,
+            frame_nr: self.req.tp_frame_nr,
+        };
+        self.setup(rx_ring, |r| {
+            r.socket.setsockopt(PACKET_RX_RING, r.req.clone())
+        })
+        .map_err(|e| e.explain(|| self.req.validate()))?;
+        let len = self.map_len();
+        self.setup(Operation::Mmap { len }, |r| {
+            r.mmap = Some(rx::mmap_ring(&r.socket, len)?);
+            Ok(())
+        })?;
+        let protocol = rx::ring_protocol(&self.socket);
+        self.setup(Operation::Bind { protocol }, |r| rx::bind_ring(&r.socket))?;
+        if settings.ignore_outgoing {
+            self.setup(Operation::IgnoreOutgoing, |r| {
+                rx::ignore_outgoing(&mut r.socket)
+            })?;
+        }
+        for m in settings.all_memberships() {
+            self.setup(Operation::AddMembership(m), |r| r.socket.add_membership(m))?;
+        }
+        let fanout = rx::settings_fanout_word(settings);
+        let word = fanout as u32;
+        self.setup(Operation::Fanout { word }, |r| {
+            r.socket.setsockopt(PACKET_FANOUT, fanout)
+        })
+        .map_err(|e| e.explain(|| probe::check_fanout(settings)))?;
+        Ok(())
+    }
+
+    ///Returns the label identifying this ring
+    pub fn label(&self) -> &RingLabel {
+        &self.label
+    }
+
+    ///Returns the guard restoring promiscuous mode, or `None` if `promisc` was off in the settings
+    #[inline]
+    pub fn promisc(&self) -> Option<&PromiscGuard> {
+        self.promisc.as_deref()
+    }
+
+    ///Returns the guard restoring offloads, or `None` if `disable_offloads` was off in the settings
+    #[inline]
+    pub fn offloads(&self) -> Option<&OffloadGuard> {
+        self.offloads.as_deref()
+    }
+
+    ///Returns the guard restoring the interface's timestamping, or `None` if `timestamps` was
+    ///`Software` in the settings
+    #[inline]
+    pub fn hardware_timestamps(&self) -> Option<&HwTimestampGuard> {
+        self.hw_timestamps.as_deref()
+    }
+
+    ///Returns running totals of what this ring has delivered to the application
+    pub fn totals(&self) -> RingTotals {
+        self.totals
+    }
+
+    ///Returns the ARPHRD_* hardware type of the ring's interface, see `rx::Ring::hardware_type`
+    pub fn hardware_type(&self) -> u16 {
+        self.socket.hatype
+    }
+
+    ///Reads the MAC address of the ring's interface
+    pub fn mac_address(&self) -> io::Result<[u8; 6]> {
+        self.socket.get_mac_address()
+    }
+
+    ///Reads the MTU of the ring's interface, which with the link-layer header bounds the frame
+    ///size the ring needs
+    pub fn mtu(&self) -> io::Result<u32> {
+        self.socket.get_mtu()
+    }
+
+    ///Reads whether the ring's interface is up
+    pub fn oper_state(&self) -> io::Result<OperState> {
+        self.socket.get_oper_state()
+    }
+
+    ///Reads how the ring's interface is related to other interfaces, see
+    ///`rx::Ring::interface_info`
+    pub fn interface_info(&self) -> io::Result<InterfaceInfo> {
+        InterfaceInfo::from_if_name(&self.socket.if_name)
+    }
+
+    ///Returns the pcap link type of this ring's packets, see `rx::Ring::link_type`
+    pub fn link_type(&self) -> Option<u32> {
+        linktype::for_socket(&self.socket)
+    }
+
+    ///Reads the kernel statistics for this ring, resetting the kernel counters. TPACKET_V2 has
+    ///no freeze count, so tp_freeze_q_cnt is always 0
+    pub fn get_rx_statistics(&self) -> io::Result<RingStatistics> {
+        let stats = match rx::get_rx_statistics(self.socket.fd) {
+            Ok(s) => s,
+            Err(e) => return Err(rx::label_error(&self.label, "PACKET_STATISTICS", e)),
+        };
+        Ok(RingStatistics {
+            label: self.label.clone(),
+            stats,
+        })
+    }
+
+    ///Sets PACKET_COPY_THRESH. While it is non-zero, a packet too large for a frame still gets a
+    ///truncated copy in the ring, flagged with `TpStatus::COPY`, and the whole packet is also
+    ///queued on the socket to be read with `recv_copied`. TPACKET_V3 rings ignore this option,
+    ///which is why it is only offered here
+    pub fn set_copy_thresh(&mut self, thresh: u32) -> io::Result<()> {
+        self.socket
+            .setsockopt(PACKET_COPY_THRESH, thresh as c_int)
+            .map_err(|e| rx::label_error(&self.label, "PACKET_COPY_THRESH", e))
+    }
+
+    ///Reads the next whole packet queued on the socket because of `set_copy_thresh`, if any,
+    ///without blocking. Returns `WouldBlock` when the queue is empty
+    pub fn recv_copied<'b>(&mut self, buf: &'b mut [u8]) -> io::Result<SimplePacket<'b>> {
+        simple::recv_packet_flags(&self.socket, buf, MSG_DONTWAIT)
+    }
+
+    ///Waits for frames to be added to the ring buffer and returns the ready ones as a batch, up
+    ///to one kernel block's worth
+    #[inline]
+    pub fn get_block(&mut self) -> Block<'_> {
+        loop {
+            if let Some(block) = self.ready_frames() {
+                return block;
+            }
+            rx::wait_readable(&self.socket, &self.label);
+        }
+    }
+
+    ///Like `get_block`, but returns `None` if no frames are ready within `timeout`, if given, and
+    ///fails with the socket's error if there is one, e.g. ENETDOWN once the interface goes down.
+    ///The error is cleared, so calling this again waits on
+    pub fn try_get_block(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Block<'_>>, RingError> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            if let Some(block) = self.ready_frames() {
+                return Ok(Some(block));
+            }
+            let left = match deadline {
+                Some(d) => match d.checked_duration_since(Instant::now()) {
+                    Some(left) => Some(left),
+                    None => return Ok(None),
+                },
+                None => None,
+            };
+            match rx::wait_readable_for(&self.socket, &self.label, left, None) {
+                Ok(true) => {}
+                Ok(false) => return Ok(None),
+                Err(e) => return Err(RingError::new(&self.label, Operation::Receive, e)),
+            }
+        }
+    }
+
+    ///Unmaps the ring and closes the socket. Clones share both, so only call this once no
+    ///clones are left
+    pub fn close(self) {
+        unsafe { close(self.into_raw_fd()) };
+    }
+
+    //the size of the mapping, worked out in usize as the product can pass u32::MAX
+    fn map_len(&self) -> usize {
+        self.req.tp_block_size as usize * self.req.tp_block_nr as usize
+    }
+
+    fn setup<T, F>(&mut self, op: Operation, f: F) -> Result<T, RingError>
+    where
+        F: FnOnce(&mut Ring) -> io::Result<T>,
+    {
+        let result = f(self);
+        rx::setup_step(&self.label, op, result)
+    }
+
+    #[inline]
+    fn frame_offset(&self, frame: u32) -> usize {
+        let block = frame / self.frames_per_block;
+        let within = frame % self.frames_per_block;
+        block as usize * self.req.tp_block_size as usize
+            + within as usize * self.req.tp_frame_size as usize
+    }
+
+    #[inline]
+    fn frame_ready(&self, map: *mut u8, frame: u32) -> bool {
+        let status = unsafe { std::slice::from_raw_parts(map.add(self.frame_offset(frame)), 4) };
+        u32::from_ne_bytes([status[0], status[1], status[2], status[3]]) & tpacket2::TP_STATUS_USER
+            != 0
+    }
+
+    //collects the ready frames from the cursor onwards, stopping at the end of the kernel block
+    //so the batch is contiguous in memory
+    fn ready_frames<'a>(&mut self) -> Option<Block<'a>> {
+        let map = self.mmap?;
+        if self.frames_per_block == 0 || self.req.tp_frame_nr == 0 {
+            return None;
+        }
+        let first = self.cursor;
+        let block_end = (first / self.frames_per_block + 1) * self.frames_per_block;
+        let mut frames = 0;
+        while first + frames < block_end && self.frame_ready(map, first + frames) {
+            frames += 1;
+        }
+        if frames == 0 {
+            return None;
+        }
+
+        let frame_size = self.req.tp_frame_size as usize;
+        let raw_data = unsafe {
+            std::slice::from_raw_parts_mut(
+                map.add(self.frame_offset(first)),
+                frames as usize * frame_size,
+            )
+        };
+        self.cursor = (first + frames) % self.req.tp_frame_nr;
+        let block = Block {
+            raw_data,
+            frame_size,
+            frames,
+            seq: self.next_seq,
+        };
+        self.next_seq += 1;
+
+        self.totals.blocks += 1;
+        for p in block.get_raw_packets() {
+            self.totals.packets += 1;
+            self.totals.bytes += u64::from(p.captured_len());
+        }
+        Some(block)
+    }
+}
+
+impl AsRawFd for Ring {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.fd
+    }
+}
+
+impl IntoRawFd for Ring {
+    ///Unmaps the ring and gives up its socket, which keeps its ring, binding and fanout group.
+    ///This ring's promiscuous mode, offload and timestamping guards are dropped, so clones must be gone for the
+    ///socket to be the only thing left using it
+    fn into_raw_fd(mut self) -> RawFd {
+        //the guards need the socket to undo their changes
+        self.promisc.take();
+        self.offloads.take();
+        self.hw_timestamps.take();
+        if let Some(map) = self.mmap.take() {
+            unsafe { munmap(map as *mut c_void, self.map_len()) };
+        }
+        self.socket.fd
+    }
+}
+
+//names the first setting that only a TPACKET_V3 ring's block parsing can honour
+fn v3_only_setting(settings: &RingSettings) -> Option<&'static str> {
+    if !matches!(settings.parse_policy, rx::ParsePolicy::Skip) {
+        Some("parse_policy")
+    } else if settings.quarantine.is_some() {
+        Some("quarantine")
+    } else if settings.block_dump.is_some() {
+        Some("block_dump")
+    } else {
+        None
+    }
+}
+
+unsafe impl Send for Ring {}
@@ -0,0 +1,62 @@
+//!A shutdown signal that wakes rings blocked waiting for blocks. It is an eventfd that becomes
+//!readable once triggered and stays that way, so every ring polling it alongside its socket
+//!returns at once rather than at its next retire timeout.
+
+use std::io::{self, Error};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use libc::{c_int, c_void, close, eventfd, write, EFD_CLOEXEC, EFD_NONBLOCK};
+
+#[derive(Debug)]
+struct Inner {
+    fd: c_int,
+    triggered: AtomicBool,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        unsafe { close(self.fd) };
+    }
+}
+
+///Cloned handles share one signal. Give one to each ring with `Ring::set_shutdown`
+#[derive(Clone, Debug)]
+pub struct ShutdownToken {
+    inner: Arc<Inner>,
+}
+
+impl ShutdownToken {
+    pub fn new() -> io::Result<ShutdownToken> {
+        let fd = unsafe { eventfd(0, EFD_CLOEXEC | EFD_NONBLOCK) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(ShutdownToken {
+            inner: Arc::new(Inner {
+                fd,
+                triggered: AtomicBool::new(false),
+            }),
+        })
+    }
+
+    ///Signals shutdown. Only an atomic store and a write(2), so it can be called from a SIGTERM
+    ///handler
+    pub fn trigger(&self) {
+        self.inner.triggered.store(true, Ordering::SeqCst);
+        let one: u64 = 1;
+        unsafe { write(self.inner.fd, &one as *const u64 as *const c_void, 8) };
+    }
+
+    ///Returns true once `trigger` was called on any clone
+    #[inline]
+    pub fn is_triggered(&self) -> bool {
+        self.inner.triggered.load(Ordering::SeqCst)
+    }
+
+    ///Returns the eventfd, readable once triggered, for polling alongside other descriptors
+    #[inline]
+    pub fn fd(&self) -> c_int {
+        self.inner.fd
+    }
+}
@@ -0,0 +1,409 @@
+//!Plain socket capture that reads one packet at a time with recvfrom, for low-rate tools (ARP
+//!watchers, LLDP listeners) where setting up a ring's worth of memory is overkill, or a batch at a
+//!time with recvmmsg where mmap rings are unavailable (restricted containers)
+
+use std::io;
+use std::mem;
+use std::sync::Arc;
+use std::time::Duration;
+
+use libc::{
+    c_int, c_uint, c_void, cmsghdr, iovec, mmsghdr, msghdr, recvmmsg, recvmsg, setsockopt,
+    sockaddr_ll, socklen_t, timeval, CMSG_DATA, CMSG_FIRSTHDR, CMSG_NXTHDR, CMSG_SPACE, ETH_P_ALL,
+    MSG_TRUNC, MSG_WAITFORONE, SOL_PACKET, SOL_SOCKET, SO_RCVTIMEO,
+};
+
+use error::{Operation, RingError};
+use ethtool::{OffloadGuard, Offloads};
+use rx::{self, PacketType, RingLabel, RingSettings, RingStatistics};
+use socket::{PromiscGuard, Socket};
+use tpacket3::{SockaddrLl, TpStatus};
+use vlan::{self, VlanTag};
+
+const PACKET_AUXDATA: c_int = 8;
+
+///Per-packet details the kernel attaches as a control message when PACKET_AUXDATA is enabled,
+///standing in for the tpacket header a ring would provide
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TpacketAuxdata {
+    pub tp_status: u32,
+    pub tp_len: u32,
+    pub tp_snaplen: u32,
+    pub tp_mac: u16,
+    pub tp_net: u16,
+    pub tp_vlan_tci: u16,
+    pub tp_vlan_tpid: u16,
+}
+
+impl TpacketAuxdata {
+    #[inline]
+    pub fn status(&self) -> TpStatus {
+        TpStatus::from_bits_truncate(self.tp_status)
+    }
+
+    ///Returns the VLAN tag the NIC stripped from the frame, if any
+    pub fn vlan_tag(&self) -> Option<VlanTag> {
+        let status = self.status();
+        if !status.vlan_valid() {
+            return None;
+        }
+        Some(VlanTag {
+            tpid: if status.vlan_tpid_valid() {
+                self.tp_vlan_tpid
+            } else {
+                vlan::ETH_P_8021Q
+            },
+            tci: self.tp_vlan_tci,
+        })
+    }
+}
+
+///A packet socket without a ring. Packets are copied into buffers supplied by the caller
+#[derive(Clone, Debug)]
+pub struct SimpleCapture {
+    pub socket: Socket,
+    label: RingLabel,
+    //shared by clones, so promiscuous mode is restored when the last of them is dropped
+    promisc: Option<Arc<PromiscGuard>>,
+    offloads: Option<Arc<OffloadGuard>>,
+}
+
+///A packet received by `SimpleCapture::recv`, borrowing the caller's buffer
+#[derive(Debug)]
+pub struct SimplePacket<'a> {
+    ///Captured bytes, starting at the link-layer header unless the socket is cooked
+    pub data: &'a [u8],
+    ///Length of the packet as it was on the wire, which is more than `data.len()` if the buffer
+    ///was too small
+    pub wire_len: usize,
+    pub sockaddr_ll: SockaddrLl,
+    ///Missing only if the kernel didn't attach it
+    pub auxdata: Option<TpacketAuxdata>,
+}
+
+impl<'a> SimplePacket<'a> {
+    #[inline]
+    pub fn is_truncated(&self) -> bool {
+        self.data.len() < self.wire_len
+    }
+
+    #[inline]
+    pub fn packet_type(&self) -> PacketType {
+        PacketType::from(self.sockaddr_ll.sll_pkttype)
+    }
+
+    #[inline]
+    pub fn if_index(&self) -> i32 {
+        self.sockaddr_ll.sll_ifindex
+    }
+
+    ///Link-layer protocol (ethertype) of the packet
+    #[inline]
+    pub fn protocol(&self) -> u16 {
+        self.sockaddr_ll.sll_protocol
+    }
+
+    #[inline]
+    pub fn status(&self) -> TpStatus {
+        self.auxdata
+            .map(|a| a.status())
+            .unwrap_or_else(TpStatus::empty)
+    }
+
+    #[inline]
+    pub fn vlan_tag(&self) -> Option<VlanTag> {
+        self.auxdata.and_then(|a| a.vlan_tag())
+    }
+}
+
+///Preallocated buffers for `SimpleCapture::recv_batch`, reused from one call to the next
+pub struct RecvBatch {
+    bufs: Vec<Vec<u8>>,
+    addrs: Vec<sockaddr_ll>,
+    controls: Vec<Control>,
+    iovecs: Vec<iovec>,
+    msgs: Vec<mmsghdr>,
+    received: usize,
+}
+
+impl RecvBatch {
+    ///Allocates room for `count` packets of up to `buf_size` bytes each
+    pub fn new(count: usize, buf_size: usize) -> RecvBatch {
+        RecvBatch {
+            bufs: vec![vec![0u8; buf_size]; count],
+            addrs: vec![unsafe { mem::zeroed() }; count],
+            controls: vec![Control::default(); count],
+            iovecs: Vec::with_capacity(count),
+            msgs: Vec::with_capacity(count),
+            received: 0,
+        }
+    }
+
+    ///Number of packets the last `recv_batch` call received
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.received
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.received == 0
+    }
+
+    ///Number of packets the batch has room for
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.bufs.len()
+    }
+
+    ///Returns the packets received by the last `recv_batch` call
+    pub fn packets(&self) -> Vec<SimplePacket<'_>> {
+        (0..self.received)
+            .map(|i| {
+                let wire_len = self.msgs[i].msg_len as usize;
+                SimplePacket {
+                    data: &self.bufs[i][..wire_len.min(self.bufs[i].len())],
+                    wire_len,
+                    sockaddr_ll: SockaddrLl::from(&self.addrs[i]),
+                    auxdata: find_auxdata(&self.msgs[i].msg_hdr),
+                }
+            })
+            .collect()
+    }
+
+    //the headers point into the buffers, so they are rebuilt before every call rather than kept
+    //across moves of the batch
+    fn prepare(&mut self) {
+        self.iovecs.clear();
+        for buf in self.bufs.iter_mut() {
+            self.iovecs.push(iovec {
+                iov_base: buf.as_mut_ptr() as *mut c_void,
+                iov_len: buf.len(),
+            });
+        }
+        self.msgs.clear();
+        for ((iov, addr), control) in self
+            .iovecs
+            .iter_mut()
+            .zip(self.addrs.iter_mut())
+            .zip(self.controls.iter_mut())
+        {
+            let mut msg: mmsghdr = unsafe { mem::zeroed() };
+            msg.msg_hdr = message(iov, addr, control);
+            self.msgs.push(msg);
+        }
+        self.received = 0;
+    }
+}
+
+impl SimpleCapture {
+    ///Opens a capture socket on the specified interface name and puts the interface into
+    ///promiscuous mode
+    pub fn from_if_name(if_name: &str) -> Result<SimpleCapture, RingError> {
+        SimpleCapture::new(RingSettings {
+            if_name: String::from(if_name),
+            ..RingSettings::default()
+        })
+    }
+
+    ///Opens a capture socket from the supplied RingSettings struct. Only `if_name`, `index`,
+    ///`tag`, `cooked`, `ignore_outgoing`, `promisc`, `memberships`, `allmulti`,
+    ///`disable_offloads`, `wait_for_interface` and `netns` apply; the socket doesn't join a
+    ///fanout group
+    pub fn new(settings: RingSettings) -> Result<SimpleCapture, RingError> {
+        let label = RingLabel::from_settings(&settings);
+        let mut socket = rx::open_socket(&settings, &label)?;
+        let promisc = if settings.promisc && !socket.is_any() {
+            let guard = rx::setup_step(&label, Operation::Promisc, PromiscGuard::new(&socket))?;
+            Some(Arc::new(guard))
+        } else {
+            None
+        };
+        let offloads = if settings.disable_offloads && !socket.is_any() {
+            let guard = rx::setup_step(
+                &label,
+                Operation::Offloads,
+                OffloadGuard::disable(&socket, Offloads::MERGING),
+            )?;
+            Some(Arc::new(guard))
+        } else {
+            None
+        };
+        //unlike a ring, a capture like this is usually after non-IP traffic such as ARP or LLDP
+        let protocol = ETH_P_ALL as u16;
+        rx::setup_step(
+            &label,
+            Operation::Bind { protocol },
+            rx::bind_protocol(&socket, protocol),
+        )?;
+        if settings.ignore_outgoing {
+            rx::setup_step(
+                &label,
+                Operation::IgnoreOutgoing,
+                rx::ignore_outgoing(&mut socket),
+            )?;
+        }
+        for m in settings.all_memberships() {
+            rx::setup_step(
+                &label,
+                Operation::AddMembership(m),
+                socket.add_membership(m),
+            )?;
+        }
+        rx::setup_step(
+            &label,
+            Operation::Other(String::from("PACKET_AUXDATA")),
+            socket.setsockopt(PACKET_AUXDATA, 1 as c_int),
+        )?;
+        trace_event!(Info, "simple capture {} up (fd {})", label, socket.fd);
+        Ok(SimpleCapture {
+            socket,
+            label,
+            promisc,
+            offloads,
+        })
+    }
+
+    ///Returns the label identifying this capture
+    pub fn label(&self) -> &RingLabel {
+        &self.label
+    }
+
+    ///Returns the guard restoring promiscuous mode, or `None` if `promisc` was off in the settings
+    #[inline]
+    pub fn promisc(&self) -> Option<&PromiscGuard> {
+        self.promisc.as_deref()
+    }
+
+    ///Returns the guard restoring offloads, or `None` if `disable_offloads` was off in the settings
+    #[inline]
+    pub fn offloads(&self) -> Option<&OffloadGuard> {
+        self.offloads.as_deref()
+    }
+
+    ///Reads the kernel statistics for this socket, resetting the kernel counters
+    pub fn get_rx_statistics(&self) -> io::Result<RingStatistics> {
+        match rx::get_rx_statistics(self.socket.fd) {
+            Ok(stats) => Ok(RingStatistics {
+                label: self.label.clone(),
+                stats,
+            }),
+            Err(e) => Err(rx::label_error(&self.label, "PACKET_STATISTICS", e)),
+        }
+    }
+
+    ///Sets how long `recv` blocks before failing with `WouldBlock`, `None` blocks forever
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        let tv = match timeout {
+            Some(t) => timeval {
+                tv_sec: t.as_secs() as _,
+                tv_usec: t.subsec_micros() as _,
+            },
+            None => timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+        };
+        match unsafe {
+            setsockopt(
+                self.socket.fd,
+                SOL_SOCKET,
+                SO_RCVTIMEO,
+                &tv as *const _ as *const c_void,
+                mem::size_of_val(&tv) as socklen_t,
+            )
+        } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    ///Blocks until a packet arrives and copies as much of it as fits into `buf`
+    pub fn recv<'b>(&mut self, buf: &'b mut [u8]) -> io::Result<SimplePacket<'b>> {
+        recv_packet(&self.socket, buf)
+    }
+
+    ///Blocks until at least one packet arrives, then fills as much of `batch` as is already
+    ///queued with a single recvmmsg call. Returns the number of packets received
+    pub fn recv_batch(&mut self, batch: &mut RecvBatch) -> io::Result<usize> {
+        batch.prepare();
+        //MSG_TRUNC makes msg_len the length on the wire rather than what was copied. musl takes
+        //the flags as an unsigned int
+        let n = unsafe {
+            recvmmsg(
+                self.socket.fd,
+                batch.msgs.as_mut_ptr(),
+                batch.msgs.len() as c_uint,
+                (MSG_TRUNC | MSG_WAITFORONE) as _,
+                std::ptr::null_mut(),
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        batch.received = n as usize;
+        Ok(batch.received)
+    }
+}
+
+pub(crate) fn recv_packet<'b>(sock: &Socket, buf: &'b mut [u8]) -> io::Result<SimplePacket<'b>> {
+    recv_packet_flags(sock, buf, 0)
+}
+
+pub(crate) fn recv_packet_flags<'b>(
+    sock: &Socket,
+    buf: &'b mut [u8],
+    flags: c_int,
+) -> io::Result<SimplePacket<'b>> {
+    let mut sll: sockaddr_ll = unsafe { mem::zeroed() };
+    let mut control = Control::default();
+    let mut iov = iovec {
+        iov_base: buf.as_mut_ptr() as *mut c_void,
+        iov_len: buf.len(),
+    };
+    let mut msg = message(&mut iov, &mut sll, &mut control);
+    //MSG_TRUNC makes recvmsg return the length on the wire rather than what was copied
+    let len = unsafe { recvmsg(sock.fd, &mut msg, MSG_TRUNC | flags) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let wire_len = len as usize;
+    let auxdata = find_auxdata(&msg);
+    Ok(SimplePacket {
+        data: &buf[..wire_len.min(buf.len())],
+        wire_len,
+        sockaddr_ll: SockaddrLl::from(&sll),
+        auxdata,
+    })
+}
+
+//room for one cmsghdr carrying a TpacketAuxdata, u64s to keep the cmsghdr aligned
+#[derive(Clone, Copy, Default)]
+struct Control([u64; 8]);
+
+fn message(iov: &mut iovec, addr: &mut sockaddr_ll, control: &mut Control) -> msghdr {
+    let mut msg: msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = addr as *mut sockaddr_ll as *mut c_void;
+    msg.msg_namelen = mem::size_of::<sockaddr_ll>() as socklen_t;
+    msg.msg_iov = iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.0.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = unsafe { CMSG_SPACE(mem::size_of::<TpacketAuxdata>() as u32) } as _;
+    msg
+}
+
+fn find_auxdata(msg: &msghdr) -> Option<TpacketAuxdata> {
+    unsafe {
+        let mut cmsg: *mut cmsghdr = CMSG_FIRSTHDR(msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == SOL_PACKET && (*cmsg).cmsg_type == PACKET_AUXDATA {
+                return Some(std::ptr::read_unaligned(
+                    CMSG_DATA(cmsg) as *const TpacketAuxdata
+                ));
+            }
+            cmsg = CMSG_NXTHDR(msg, cmsg);
+        }
+    }
+    None
+}
@@ -0,0 +1,48 @@
+//!Hands packets from ring threads to channels the crate doesn't know about, such as tokio's mpsc
+//!and broadcast channels, so async processing stages can be fed without this crate depending on
+//!an async runtime. A `PacketSink` is anything that takes an owned packet and says whether it was
+//!queued. Closures are sinks, so wrapping a tokio `mpsc::Sender` is a closure calling `try_send`
+//!that maps `Full` and `Closed` across, or `blocking_send` to wait instead, as ring threads aren't
+//!runtime threads. A `broadcast::Sender` is a closure calling `send`, which only fails once
+//!every receiver is gone; receivers that fall behind lose packets on their side and learn it
+//!from `RecvError::Lagged`. `Dispatcher::spawn_sinks` runs a ring group feeding a set of sinks
+//!and counts what they turn away.
+
+use std::sync::mpsc::{SyncSender, TrySendError};
+
+use rx::OwnedPacket;
+
+///What a sink did with a packet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Offer {
+    Sent,
+    ///The channel was full and the packet dropped
+    Full,
+    ///Nothing is receiving any more and the packet dropped
+    Closed,
+}
+
+///Takes packets from ring threads. It is shared by all of them, and shouldn't block unless
+///holding up the rings is what's wanted
+pub trait PacketSink: Send + Sync + 'static {
+    fn offer(&self, packet: OwnedPacket) -> Offer;
+}
+
+impl<F> PacketSink for F
+where
+    F: Fn(OwnedPacket) -> Offer + Send + Sync + 'static,
+{
+    fn offer(&self, packet: OwnedPacket) -> Offer {
+        self(packet)
+    }
+}
+
+impl PacketSink for SyncSender<OwnedPacket> {
+    fn offer(&self, packet: OwnedPacket) -> Offer {
+        match self.try_send(packet) {
+            Ok(()) => Offer::Sent,
+            Err(TrySendError::Full(_)) => Offer::Full,
+            Err(TrySendError::Disconnected(_)) => Offer::Closed,
+        }
+    }
+}
@@ -1,21 +1,83 @@
 extern crate libc;
 
+pub use interface::OperState;
 use libc::{
-    c_char, c_int, c_short, c_uint, c_ulong, c_void, getsockopt, if_nametoindex, ioctl, setsockopt,
-    socket, socklen_t, ETH_P_ALL, SOCK_RAW, SOL_PACKET, IF_NAMESIZE
+    c_char, c_int, c_short, c_uint, c_ulong, c_void, close, getsockname, getsockopt,
+    if_indextoname, if_nametoindex, ioctl, setsockopt, sockaddr, sockaddr_ll, socket, socklen_t,
+    ARPHRD_VOID, ETH_P_ALL, IF_NAMESIZE, SIOCGIFFLAGS, SIOCGIFHWADDR, SIOCGIFMTU, SIOCSIFFLAGS,
+    SOL_PACKET, SOL_SOCKET, SO_DOMAIN, SO_ERROR, SO_TYPE,
 };
-pub use libc::{AF_PACKET, IFF_PROMISC, PF_PACKET};
+pub use libc::{AF_PACKET, IFF_PROMISC, PF_PACKET, SOCK_DGRAM, SOCK_RAW};
+pub use linktype::has_ethernet_header;
+pub use rx::Membership;
+
+use interface;
 
 use std::ffi::CString;
 use std::io::{self, Error, ErrorKind};
 use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 
+//the union is 24 bytes on 64-bit targets and 16 on 32-bit ones, the larger size is safe for both
 const IFREQUNIONSIZE: usize = 24;
 
-const SIOCGIFFLAGS: c_ulong = 35091; //0x00008913;
-const SIOCSIFFLAGS: c_ulong = 35092; //0x00008914;
+//glibc declares ioctl's request as an unsigned long, musl and bionic as an int
+#[cfg(any(target_env = "musl", target_os = "android"))]
+type IoctlRequest = c_int;
+#[cfg(not(any(target_env = "musl", target_os = "android")))]
+type IoctlRequest = libc::c_ulong;
+
+bitflags! {
+    ///Interface flags (IFF_*) read and written with SIOCGIFFLAGS and SIOCSIFFLAGS
+    pub struct IfFlags: u16 {
+        ///The interface is administratively up
+        const UP = libc::IFF_UP as u16;
+        const BROADCAST = libc::IFF_BROADCAST as u16;
+        const DEBUG = libc::IFF_DEBUG as u16;
+        const LOOPBACK = libc::IFF_LOOPBACK as u16;
+        const POINTOPOINT = libc::IFF_POINTOPOINT as u16;
+        const NOTRAILERS = libc::IFF_NOTRAILERS as u16;
+        ///The interface has carrier, read only
+        const RUNNING = libc::IFF_RUNNING as u16;
+        const NOARP = libc::IFF_NOARP as u16;
+        ///Receive every frame, not only those addressed to the interface
+        const PROMISC = libc::IFF_PROMISC as u16;
+        ///Receive every multicast frame
+        const ALLMULTI = libc::IFF_ALLMULTI as u16;
+        ///Bond master
+        const MASTER = libc::IFF_MASTER as u16;
+        ///Bond slave
+        const SLAVE = libc::IFF_SLAVE as u16;
+        const MULTICAST = libc::IFF_MULTICAST as u16;
+        const PORTSEL = libc::IFF_PORTSEL as u16;
+        const AUTOMEDIA = libc::IFF_AUTOMEDIA as u16;
+        const DYNAMIC = libc::IFF_DYNAMIC as u16;
+    }
+}
 
 pub const PACKET_FANOUT: c_int = 18;
+const PACKET_ADD_MEMBERSHIP: c_int = 1;
+const PACKET_DROP_MEMBERSHIP: c_int = 2;
+const PACKET_ORIGDEV: c_int = 9;
+
+const PACKET_MR_MULTICAST: u16 = 0;
+const PACKET_MR_PROMISC: u16 = 1;
+const PACKET_MR_ALLMULTI: u16 = 2;
+const PACKET_MR_UNICAST: u16 = 3;
+
+//struct packet_mreq
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct PacketMreq {
+    mr_ifindex: c_int,
+    mr_type: u16,
+    mr_alen: u16,
+    mr_address: [u8; 8],
+}
+
+///Pseudo-interface name that binds to ifindex 0 and captures from every interface, like
+///`tcpdump -i any`
+pub const ANY_INTERFACE: &str = "any";
 
 #[repr(C)]
 struct IfReq {
@@ -26,13 +88,13 @@ struct IfReq {
 
 impl IfReq {
     fn as_short(&self) -> c_short {
-        c_short::from_be((self.data[0] as c_short) << 8 | (self.data[1] as c_short))
+        c_short::from_ne_bytes([self.data[0], self.data[1]])
     }
 
     fn from_short(i: c_short) -> IfReq {
         let mut req = IfReq::default();
         //TODO: find a better way to do this
-        let bytes: [u8; 2] = unsafe { mem::transmute(i) };
+        let bytes: [u8; 2] = i.to_ne_bytes();
         req.data[0] = bytes[0];
         req.data[1] = bytes[1];
         req
@@ -47,12 +109,16 @@ impl IfReq {
 
         // basically a memcpy
         for (a, c) in if_req.ifr_name.iter_mut().zip(if_name.bytes()) {
-            *a = c as i8;
+            *a = c as c_char;
         }
 
         Ok(if_req)
     }
 
+    fn as_int(&self) -> c_int {
+        c_int::from_ne_bytes([self.data[0], self.data[1], self.data[2], self.data[3]])
+    }
+
     fn ifr_flags(&self) -> c_short {
         self.as_short()
     }
@@ -75,25 +141,72 @@ pub struct Socket {
     pub if_name: String,
     pub if_index: c_uint,
     pub sock_type: c_int,
+    ///SOCK_RAW, or SOCK_DGRAM for cooked capture
+    pub kind: c_int,
+    ///ARPHRD_* hardware type of the interface, ARPHRD_VOID for `ANY_INTERFACE`
+    pub hatype: u16,
 }
 
 impl Socket {
     pub fn from_if_name(if_name: &str, socket_type: c_int) -> io::Result<Socket> {
+        Socket::with_kind(if_name, socket_type, SOCK_RAW)
+    }
+
+    ///Opens a socket of the given kind. With SOCK_DGRAM the kernel strips the link-layer header
+    ///and reports it in the packet's sockaddr_ll instead
+    pub fn with_kind(if_name: &str, socket_type: c_int, kind: c_int) -> io::Result<Socket> {
+        let if_index = get_if_index(if_name)?;
         //this typecasting sucks :(
-        let fd = unsafe { socket(socket_type, SOCK_RAW, (ETH_P_ALL as u16).to_be() as i32) };
+        let fd = unsafe { socket(socket_type, kind, (ETH_P_ALL as u16).to_be() as i32) };
         if fd < 0 {
             return Err(Error::last_os_error());
         }
 
-        Ok(Socket {
+        let mut sock = Socket {
             if_name: String::from(if_name),
-            if_index: get_if_index(if_name)?,
+            if_index,
             sock_type: socket_type,
+            kind,
             fd,
-        })
+            hatype: ARPHRD_VOID,
+        };
+        if !sock.is_any() {
+            match sock.get_hardware_type() {
+                Ok(hatype) => sock.hatype = hatype,
+                Err(e) => {
+                    unsafe { close(fd) };
+                    return Err(e);
+                }
+            }
+        }
+        Ok(sock)
     }
 
-    fn ioctl(&self, ident: c_ulong, if_req: IfReq) -> io::Result<IfReq> {
+    ///Wraps a packet socket opened elsewhere, e.g. by a privileged parent, reading its kind and
+    ///the hardware type of `if_name`. The socket isn't bound or otherwise changed
+    pub fn from_fd(fd: c_int, if_name: &str) -> io::Result<Socket> {
+        let domain = sol_socket_int(fd, SO_DOMAIN)?;
+        if domain != AF_PACKET {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("fd {} is not a packet socket (domain {})", fd, domain),
+            ));
+        }
+        let mut sock = Socket {
+            if_name: String::from(if_name),
+            if_index: get_if_index(if_name)?,
+            sock_type: PF_PACKET,
+            kind: sol_socket_int(fd, SO_TYPE)?,
+            fd,
+            hatype: ARPHRD_VOID,
+        };
+        if !sock.is_any() {
+            sock.hatype = sock.get_hardware_type()?;
+        }
+        Ok(sock)
+    }
+
+    fn ioctl(&self, ident: IoctlRequest, if_req: IfReq) -> io::Result<IfReq> {
         let mut req: Box<IfReq> = Box::new(if_req);
         match unsafe { ioctl(self.fd, ident, &mut *req) } {
             -1 => Err(Error::last_os_error()),
@@ -102,15 +215,117 @@ impl Socket {
     }
 
     fn get_flags(&self) -> io::Result<IfReq> {
-        self.ioctl(SIOCGIFFLAGS, IfReq::with_if_name(&self.if_name)?)
+        self.ioctl(
+            SIOCGIFFLAGS as IoctlRequest,
+            IfReq::with_if_name(&self.if_name)?,
+        )
+    }
+
+    ///Reads the interface's ARPHRD_* hardware type, which says what link-layer header its frames
+    ///start with
+    pub fn get_hardware_type(&self) -> io::Result<u16> {
+        let req = self.ioctl(
+            SIOCGIFHWADDR as IoctlRequest,
+            IfReq::with_if_name(&self.if_name)?,
+        )?;
+        //ifr_hwaddr is a sockaddr, and sa_family holds the hardware type
+        Ok(req.as_short() as u16)
+    }
+
+    ///Reads the interface's hardware address, the source MAC for frames sent on it
+    pub fn get_mac_address(&self) -> io::Result<[u8; 6]> {
+        let req = self.ioctl(
+            SIOCGIFHWADDR as IoctlRequest,
+            IfReq::with_if_name(&self.if_name)?,
+        )?;
+        //sa_data follows the two byte sa_family
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(&req.data[2..8]);
+        Ok(mac)
     }
 
+    ///Reads the interface's MTU, the largest network-layer packet it carries
+    pub fn get_mtu(&self) -> io::Result<u32> {
+        let req = self.ioctl(
+            SIOCGIFMTU as IoctlRequest,
+            IfReq::with_if_name(&self.if_name)?,
+        )?;
+        Ok(req.as_int() as u32)
+    }
+
+    ///Reads and clears the socket's pending error, set e.g. to ENETDOWN when the interface goes
+    ///down and to ENODEV when it is removed
+    pub fn take_error(&self) -> io::Result<Option<Error>> {
+        let mut err: c_int = 0;
+        let mut len = mem::size_of::<c_int>() as socklen_t;
+        let ret = unsafe {
+            getsockopt(
+                self.fd,
+                SOL_SOCKET,
+                SO_ERROR,
+                &mut err as *mut c_int as *mut c_void,
+                &mut len,
+            )
+        };
+        match (ret, err) {
+            (0, 0) => Ok(None),
+            (0, err) => Ok(Some(Error::from_raw_os_error(err))),
+            _ => Err(Error::last_os_error()),
+        }
+    }
+
+    ///Reads the interface's RFC 2863 operational state
+    pub fn get_oper_state(&self) -> io::Result<OperState> {
+        interface::oper_state(&self.if_name)
+    }
+
+    ///Returns true if frames on this interface start with an Ethernet header
+    #[inline]
+    pub fn has_ethernet_header(&self) -> bool {
+        has_ethernet_header(self.hatype)
+    }
+
+    ///Sets one of the `IFF_*` interface flags. Fails with `InvalidInput` for bits past the 16
+    ///that SIOCSIFFLAGS takes
     pub fn set_flag(&mut self, flag: c_ulong) -> io::Result<()> {
-        let flags = &self.get_flags()?.ifr_flags();
-        let new_flags = flags | flag as c_short;
+        if flag > 0xffff {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("interface flag {:#x} doesn't fit in ifr_flags", flag),
+            ));
+        }
+        let flags = self.get_flags()?.ifr_flags() | flag as u16 as c_short;
+        let mut if_req = IfReq::with_if_name(&self.if_name)?;
+        if_req.data = IfReq::from_short(flags).data;
+        self.ioctl(SIOCSIFFLAGS as IoctlRequest, if_req)?;
+        Ok(())
+    }
+
+    ///Reads the interface's flags
+    pub fn get_if_flags(&self) -> io::Result<IfFlags> {
+        Ok(IfFlags::from_bits_truncate(
+            self.get_flags()?.ifr_flags() as u16
+        ))
+    }
+
+    ///Turns `flags` on, leaving the others as they are. Changing flags needs CAP_NET_ADMIN
+    pub fn set_if_flags(&mut self, flags: IfFlags) -> io::Result<()> {
+        let current = self.get_if_flags()?;
+        self.replace_if_flags(current | flags)
+    }
+
+    ///Turns `flags` off, leaving the others as they are, e.g. `IfFlags::PROMISC` to leave
+    ///promiscuous mode
+    pub fn clear_if_flags(&mut self, flags: IfFlags) -> io::Result<()> {
+        let current = self.get_if_flags()?;
+        self.replace_if_flags(current - flags)
+    }
+
+    ///Writes the interface's flags as given
+    pub fn replace_if_flags(&mut self, flags: IfFlags) -> io::Result<()> {
         let mut if_req = IfReq::with_if_name(&self.if_name)?;
-        if_req.data = IfReq::from_short(new_flags).data;
-        self.ioctl(SIOCSIFFLAGS, if_req)?;
+        if_req.data = IfReq::from_short(flags.bits() as c_short).data;
+        self.ioctl(SIOCSIFFLAGS as IoctlRequest, if_req)?;
         Ok(())
     }
 
@@ -129,21 +344,240 @@ impl Socket {
         }
     }
 
-    pub fn getsockopt(&mut self, opt: c_int, opt_val: &*mut c_void) -> io::Result<()> {
+    pub fn getsockopt<T>(&mut self, opt: c_int, opt_val: &mut T) -> io::Result<()> {
         get_sock_opt(self.fd, opt, opt_val)
     }
+
+    ///With `on`, packets received through a bond or other stacked device report the ifindex of
+    ///the device they arrived on (e.g. the bond slave) in sockaddr_ll rather than the one the
+    ///socket is bound to
+    pub fn set_orig_dev(&mut self, on: bool) -> io::Result<()> {
+        self.setsockopt(PACKET_ORIGDEV, on as c_int)
+    }
+
+    ///Adds a MAC address to the interface's receive filter until it is dropped again or the
+    ///socket closes. Memberships are counted per interface, like promiscuous mode
+    pub fn add_membership(&mut self, m: Membership) -> io::Result<()> {
+        let (mr_type, addr) = mreq_for(&m);
+        self.membership(PACKET_ADD_MEMBERSHIP, mr_type, addr)
+    }
+
+    ///Drops a membership added with `add_membership`
+    pub fn drop_membership(&mut self, m: Membership) -> io::Result<()> {
+        let (mr_type, addr) = mreq_for(&m);
+        self.membership(PACKET_DROP_MEMBERSHIP, mr_type, addr)
+    }
+
+    //adds or drops a packet_mreq membership of `mr_type` on the socket's interface
+    fn membership(&mut self, opt: c_int, mr_type: u16, address: &[u8]) -> io::Result<()> {
+        let mut mreq = PacketMreq {
+            mr_ifindex: self.if_index as c_int,
+            mr_type,
+            mr_alen: address.len() as u16,
+            mr_address: [0; 8],
+        };
+        if address.len() > mreq.mr_address.len() {
+            return Err(Error::new(
+                io::ErrorKind::InvalidInput,
+                "membership address longer than 8 bytes",
+            ));
+        }
+        mreq.mr_address[..address.len()].copy_from_slice(address);
+        self.setsockopt(opt, mreq)
+    }
+
+    ///Returns true if the socket captures from every interface rather than a single one
+    #[inline]
+    pub fn is_any(&self) -> bool {
+        self.if_index == 0
+    }
+}
+
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl IntoRawFd for Socket {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd
+    }
+}
+
+impl FromRawFd for Socket {
+    ///Wraps a packet socket, finding its interface from the address it is bound to. One that
+    ///isn't bound yet is taken to be on `ANY_INTERFACE`, use `Socket::from_fd` to name it
+    unsafe fn from_raw_fd(fd: RawFd) -> Socket {
+        let mut sll: sockaddr_ll = mem::zeroed();
+        let mut len = mem::size_of::<sockaddr_ll>() as socklen_t;
+        let bound = getsockname(fd, &mut sll as *mut sockaddr_ll as *mut sockaddr, &mut len) == 0
+            && sll.sll_ifindex > 0;
+        let if_index = if bound { sll.sll_ifindex as c_uint } else { 0 };
+        Socket {
+            fd,
+            if_name: match if_index {
+                0 => String::from(ANY_INTERFACE),
+                index => get_if_name(index).unwrap_or_else(|_| index.to_string()),
+            },
+            if_index,
+            sock_type: PF_PACKET,
+            kind: sol_socket_int(fd, SO_TYPE).unwrap_or(SOCK_RAW),
+            hatype: if bound { sll.sll_hatype } else { ARPHRD_VOID },
+        }
+    }
+}
+
+///Keeps an interface in promiscuous mode while it is held, through a PACKET_MR_PROMISC membership
+///on the socket. The kernel counts these memberships per interface and only leaves promiscuous
+///mode once the last one is dropped, either with the guard or when the socket closes, so rings
+///sharing an interface don't undo each other and a crashed process doesn't leave it on
+#[derive(Debug)]
+pub struct PromiscGuard {
+    sock: Socket,
+    was_promisc: bool,
+}
+
+impl PromiscGuard {
+    ///Records whether the socket's interface is promiscuous and adds the socket's membership
+    pub fn new(sock: &Socket) -> io::Result<PromiscGuard> {
+        let mut sock = sock.clone();
+        let was_promisc = sock.get_if_flags()?.contains(IfFlags::PROMISC);
+        sock.membership(PACKET_ADD_MEMBERSHIP, PACKET_MR_PROMISC, &[])?;
+        Ok(PromiscGuard { sock, was_promisc })
+    }
+
+    ///Returns true if IFF_PROMISC was already set, e.g. by `ip link set promisc on`. The flag
+    ///doesn't reflect memberships, its own or other sockets', and dropping the guard leaves it set
+    #[inline]
+    pub fn was_promisc(&self) -> bool {
+        self.was_promisc
+    }
+}
+
+impl Drop for PromiscGuard {
+    fn drop(&mut self) {
+        if let Err(_e) = self
+            .sock
+            .membership(PACKET_DROP_MEMBERSHIP, PACKET_MR_PROMISC, &[])
+        {
+            trace_event!(
+                Warn,
+                "failed to drop promiscuous membership on {}: {}",
+                self.sock.if_name,
+                _e
+            );
+        }
+    }
 }
 
-pub fn get_sock_opt(fd: i32, opt: c_int, opt_val: &*mut c_void) -> io::Result<()> {
-    let mut optlen = mem::size_of_val(&opt_val) as socklen_t;
-    match unsafe { getsockopt(fd, SOL_PACKET, opt, *opt_val, &mut optlen) } {
+fn mreq_for(m: &Membership) -> (u16, &[u8]) {
+    match *m {
+        Membership::Multicast(ref addr) => (PACKET_MR_MULTICAST, addr),
+        Membership::Unicast(ref addr) => (PACKET_MR_UNICAST, addr),
+        Membership::AllMulticast => (PACKET_MR_ALLMULTI, &[]),
+    }
+}
+
+//reads an int SOL_SOCKET option
+pub(crate) fn sol_socket_int(fd: c_int, opt: c_int) -> io::Result<c_int> {
+    let mut val: c_int = 0;
+    let mut len = mem::size_of::<c_int>() as socklen_t;
+    match unsafe {
+        getsockopt(
+            fd,
+            SOL_SOCKET,
+            opt,
+            &mut val as *mut c_int as *mut c_void,
+            &mut len,
+        )
+    } {
+        0 => Ok(val),
+        _ => Err(Error::last_os_error()),
+    }
+}
+
+pub fn get_sock_opt<T>(fd: i32, opt: c_int, opt_val: &mut T) -> io::Result<()> {
+    let mut optlen = mem::size_of::<T>() as socklen_t;
+    match unsafe {
+        getsockopt(
+            fd,
+            SOL_PACKET,
+            opt,
+            opt_val as *mut _ as *mut c_void,
+            &mut optlen,
+        )
+    } {
         0 => Ok(()),
         _ => Err(io::Error::last_os_error()),
     }
 }
 
+///Returns the index of the named interface, or 0 for `ANY_INTERFACE`
 pub fn get_if_index(name: &str) -> io::Result<c_uint> {
-    let name = CString::new(name)?;
-    let index = unsafe { if_nametoindex(name.as_ptr()) };
-    Ok(index)
+    if name == ANY_INTERFACE {
+        return Ok(0);
+    }
+    let c_name = CString::new(name)?;
+    match unsafe { if_nametoindex(c_name.as_ptr()) } {
+        0 => Err(Error::new(
+            io::ErrorKind::NotFound,
+            format!("no such interface: {}", name),
+        )),
+        index => Ok(index),
+    }
+}
+
+///Returns the name of the interface with the given index, for labelling packets captured on
+///`ANY_INTERFACE`
+pub fn get_if_name(index: c_uint) -> io::Result<String> {
+    let mut buf = [0 as c_char; IF_NAMESIZE];
+    if unsafe { if_indextoname(index, buf.as_mut_ptr()) }.is_null() {
+        return Err(Error::last_os_error());
+    }
+    let name: Vec<u8> = buf
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8)
+        .collect();
+    Ok(String::from_utf8_lossy(&name).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ifreq_packs_shorts_and_ints() {
+        let req = IfReq::from_short(0x1234);
+        assert_eq!(req.as_short(), 0x1234);
+        assert_eq!(req.ifr_flags(), 0x1234);
+        //the short takes the first two bytes of the union and the rest stay zero
+        assert_eq!(req.data[..2], 0x1234i16.to_ne_bytes());
+        assert!(req.data[2..].iter().all(|&b| b == 0));
+        assert!(req.ifr_name.iter().all(|&c| c == 0));
+
+        let req = IfReq::from_short(-1);
+        assert_eq!(req.as_short(), -1);
+        assert_eq!(req.data[..4], [0xff, 0xff, 0, 0]);
+
+        let flags = (IfFlags::UP | IfFlags::PROMISC).bits() as c_short;
+        assert_eq!(IfReq::from_short(flags).as_short(), flags);
+
+        let mut req = IfReq::default();
+        req.data[..4].copy_from_slice(&1500i32.to_ne_bytes());
+        assert_eq!(req.as_int(), 1500);
+    }
+
+    #[test]
+    fn ifreq_takes_the_interface_name() {
+        let req = IfReq::with_if_name("eth0").unwrap();
+        assert_eq!(
+            &req.ifr_name[..5],
+            &[b'e' as c_char, b't' as _, b'h' as _, b'0' as _, 0]
+        );
+        assert!(IfReq::with_if_name("an-interface-name-that-is-too-long").is_err());
+        assert!(IfReq::with_if_name(&"x".repeat(IF_NAMESIZE)).is_err());
+        assert!(IfReq::with_if_name(&"x".repeat(IF_NAMESIZE - 1)).is_ok());
+    }
 }
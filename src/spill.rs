@@ -0,0 +1,432 @@
+//!A handoff queue that spills to disk. Packets go to a bounded in-memory queue until it fills,
+//!then to an unlinked temporary file, and the consumer gets them back in the order they were
+//!sent once it catches up. A burst then costs disk space instead of drops, which suits analysis
+//!that is slower than the link for a while but keeps up on average. The file is reused from the
+//!start whenever it has been read to the end, so it only grows while the consumer stays behind
+//!and is bounded by `max_bytes`.
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use rx::OwnedPacket;
+use wire;
+
+static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+///Where and how much to spill
+#[derive(Clone, Debug)]
+pub struct SpillConfig {
+    ///Packets held in memory before later ones go to disk
+    pub capacity: usize,
+    ///Directory the spill file is created in. It is unlinked straight away, so nothing is left
+    ///behind if the process dies
+    pub dir: PathBuf,
+    ///Bytes the spill file may hold before packets are dropped, or senders wait, until the
+    ///consumer has read it to the end
+    pub max_bytes: u64,
+}
+
+impl Default for SpillConfig {
+    fn default() -> SpillConfig {
+        SpillConfig {
+            capacity: 4096,
+            dir: std::env::temp_dir(),
+            max_bytes: 1 << 30,
+        }
+    }
+}
+
+///Counters of a spill queue
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SpillStats {
+    ///Packets waiting in memory
+    pub queued: usize,
+    ///Packets waiting on disk
+    pub on_disk: u64,
+    ///Bytes of the spill file in use
+    pub disk_bytes: u64,
+    ///Packets written to disk so far
+    pub spilled: u64,
+    ///Packets read back from disk so far
+    pub replayed: u64,
+    ///Packets dropped because the spill file was full, a write failed or the receiver was gone
+    pub dropped: u64,
+}
+
+struct State {
+    memory: VecDeque<OwnedPacket>,
+    file: File,
+    write_pos: u64,
+    read_pos: u64,
+    stats: SpillStats,
+    senders: usize,
+    receiving: bool,
+}
+
+impl State {
+    fn stats(&self) -> SpillStats {
+        SpillStats {
+            queued: self.memory.len(),
+            ..self.stats
+        }
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    //signalled when a packet is queued or the last sender goes
+    filled: Condvar,
+    //signalled when the receiver takes a packet or goes
+    drained: Condvar,
+    capacity: usize,
+    max_bytes: u64,
+}
+
+///Creates a spill queue, returning its two ends. The sender can be cloned for several producers
+pub fn channel(config: &SpillConfig) -> io::Result<(SpillSender, SpillReceiver)> {
+    let path = config.dir.join(format!(
+        "af_packet-spill-{}-{}",
+        process::id(),
+        NEXT_FILE.fetch_add(1, Ordering::Relaxed)
+    ));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    fs::remove_file(&path)?;
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            memory: VecDeque::with_capacity(config.capacity),
+            file,
+            write_pos: 0,
+            read_pos: 0,
+            stats: SpillStats::default(),
+            senders: 1,
+            receiving: true,
+        }),
+        filled: Condvar::new(),
+        drained: Condvar::new(),
+        capacity: config.capacity,
+        max_bytes: config.max_bytes,
+    });
+    Ok((
+        SpillSender {
+            shared: shared.clone(),
+        },
+        SpillReceiver { shared },
+    ))
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    //queues `packet` in memory or on disk. Gives it back if the file has no room
+    fn push(&self, state: &mut State, packet: OwnedPacket) -> Result<(), OwnedPacket> {
+        //once anything is on disk, later packets go there too so they stay in order
+        if state.stats.on_disk == 0 && state.memory.len() < self.capacity {
+            state.memory.push_back(packet);
+            self.filled.notify_one();
+            return Ok(());
+        }
+        let mut record = Vec::new();
+        wire::encode(&packet, &mut record);
+        //waiting wouldn't help a packet that doesn't fit in an empty file
+        if record.len() as u64 > self.max_bytes {
+            trace_event!(
+                Warn,
+                "a {} byte packet is too large for the spill file",
+                record.len()
+            );
+            state.stats.dropped += 1;
+            return Ok(());
+        }
+        if state.write_pos + record.len() as u64 > self.max_bytes {
+            return Err(packet);
+        }
+        match state.file.write_all_at(&record, state.write_pos) {
+            Ok(()) => {
+                state.write_pos += record.len() as u64;
+                state.stats.on_disk += 1;
+                state.stats.spilled += 1;
+                state.stats.disk_bytes = state.write_pos - state.read_pos;
+                self.filled.notify_one();
+                Ok(())
+            }
+            Err(e) => {
+                trace_event!(Warn, "writing to the spill file failed: {}", e);
+                state.stats.dropped += 1;
+                Ok(())
+            }
+        }
+    }
+
+    fn pop(&self, state: &mut State) -> Option<OwnedPacket> {
+        let packet = match state.memory.pop_front() {
+            Some(packet) => Some(packet),
+            None if state.stats.on_disk > 0 => self.replay(state),
+            None => None,
+        };
+        if packet.is_some() {
+            self.drained.notify_all();
+        }
+        packet
+    }
+
+    //reads the oldest packet on disk, starting the file over once it is all read
+    fn replay(&self, state: &mut State) -> Option<OwnedPacket> {
+        let packet = match read_record(&state.file, state.read_pos) {
+            Ok((packet, len)) => {
+                state.read_pos += len;
+                state.stats.on_disk -= 1;
+                state.stats.replayed += 1;
+                Some(packet)
+            }
+            Err(e) => {
+                //the records after this one can't be found, so they are all lost
+                trace_event!(Warn, "reading the spill file failed: {}", e);
+                state.stats.dropped += state.stats.on_disk;
+                state.stats.on_disk = 0;
+                None
+            }
+        };
+        if state.stats.on_disk == 0 {
+            state.write_pos = 0;
+            state.read_pos = 0;
+            if let Err(e) = state.file.set_len(0) {
+                trace_event!(Warn, "truncating the spill file failed: {}", e);
+            }
+        }
+        state.stats.disk_bytes = state.write_pos - state.read_pos;
+        packet
+    }
+}
+
+///The sending end of a spill queue
+pub struct SpillSender {
+    shared: Arc<Shared>,
+}
+
+impl SpillSender {
+    ///Queues `packet`, waiting for the receiver to read the spill file to the end if it is full.
+    ///Returns false if the receiver is gone or the packet couldn't be written, including one too
+    ///large for `max_bytes`
+    pub fn send(&self, packet: OwnedPacket) -> bool {
+        let mut state = self.shared.lock();
+        let mut packet = packet;
+        loop {
+            if !state.receiving {
+                state.stats.dropped += 1;
+                return false;
+            }
+            let dropped = state.stats.dropped;
+            match self.shared.push(&mut state, packet) {
+                Ok(()) => return state.stats.dropped == dropped,
+                Err(back) => packet = back,
+            }
+            state = self
+                .shared
+                .drained
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    ///Queues `packet`, dropping it if the spill file is full. Returns false if it was dropped
+    pub fn try_send(&self, packet: OwnedPacket) -> bool {
+        let mut state = self.shared.lock();
+        let dropped = state.stats.dropped;
+        if state.receiving && self.shared.push(&mut state, packet).is_ok() {
+            return state.stats.dropped == dropped;
+        }
+        state.stats.dropped += 1;
+        false
+    }
+
+    pub fn stats(&self) -> SpillStats {
+        self.shared.lock().stats()
+    }
+}
+
+impl Clone for SpillSender {
+    fn clone(&self) -> SpillSender {
+        self.shared.lock().senders += 1;
+        SpillSender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for SpillSender {
+    fn drop(&mut self) {
+        self.shared.lock().senders -= 1;
+        self.shared.filled.notify_all();
+    }
+}
+
+///The receiving end of a spill queue. Packets come out in the order they were sent, from memory
+///first and then from disk
+pub struct SpillReceiver {
+    shared: Arc<Shared>,
+}
+
+impl SpillReceiver {
+    ///Waits for the next packet. Returns `None` once every sender is gone and everything queued
+    ///has been received
+    pub fn recv(&self) -> Option<OwnedPacket> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(packet) = self.shared.pop(&mut state) {
+                return Some(packet);
+            }
+            if state.senders == 0 {
+                return None;
+            }
+            state = self
+                .shared
+                .filled
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    ///Waits up to `timeout` for the next packet
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<OwnedPacket> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(packet) = self.shared.pop(&mut state) {
+                return Some(packet);
+            }
+            let now = Instant::now();
+            if state.senders == 0 || now >= deadline {
+                return None;
+            }
+            state = self
+                .shared
+                .filled
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    ///Returns the next packet if one is queued
+    pub fn try_recv(&self) -> Option<OwnedPacket> {
+        let mut state = self.shared.lock();
+        self.shared.pop(&mut state)
+    }
+
+    pub fn stats(&self) -> SpillStats {
+        self.shared.lock().stats()
+    }
+}
+
+impl Iterator for SpillReceiver {
+    type Item = OwnedPacket;
+
+    fn next(&mut self) -> Option<OwnedPacket> {
+        self.recv()
+    }
+}
+
+impl Drop for SpillReceiver {
+    fn drop(&mut self) {
+        self.shared.lock().receiving = false;
+        self.shared.drained.notify_all();
+    }
+}
+
+//returns the packet at `pos` and the length of its record
+fn read_record(file: &File, pos: u64) -> io::Result<(OwnedPacket, u64)> {
+    let mut len = [0; 4];
+    file.read_exact_at(&mut len, pos)?;
+    let mut record = vec![0; wire::record_len(len).max(4)];
+    file.read_exact_at(&mut record, pos)?;
+    let packet = wire::decode(&record)?;
+    Ok((packet, record.len() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rx::RawPacket;
+    use std::time::UNIX_EPOCH;
+
+    fn packet(n: u8) -> OwnedPacket {
+        RawPacket::from_frame(&[n; 10], 10, UNIX_EPOCH).to_owned()
+    }
+
+    fn record_len() -> u64 {
+        let mut record = Vec::new();
+        wire::encode(&packet(0), &mut record);
+        record.len() as u64
+    }
+
+    fn config(capacity: usize, max_bytes: u64) -> SpillConfig {
+        SpillConfig {
+            capacity,
+            max_bytes,
+            ..SpillConfig::default()
+        }
+    }
+
+    fn drain(rx: &SpillReceiver) -> Vec<u8> {
+        let mut seen = Vec::new();
+        while let Some(packet) = rx.try_recv() {
+            seen.push(packet.data[0]);
+        }
+        seen
+    }
+
+    #[test]
+    fn keeps_order_across_memory_and_disk() {
+        let (tx, rx) = channel(&config(2, 4 * record_len())).unwrap();
+        for n in 0..5 {
+            assert!(tx.try_send(packet(n)));
+        }
+        assert_eq!(rx.try_recv().map(|p| p.data[0]), Some(0));
+        //memory has room again, but 2 to 4 are still on disk
+        assert!(tx.try_send(packet(5)));
+        assert!(!tx.try_send(packet(6)));
+        let stats = rx.stats();
+        assert_eq!((stats.queued, stats.on_disk, stats.dropped), (1, 4, 1));
+        assert_eq!(stats.disk_bytes, 4 * record_len());
+        assert_eq!(drain(&rx), vec![1, 2, 3, 4, 5]);
+        assert_eq!(rx.stats().disk_bytes, 0);
+    }
+
+    #[test]
+    fn reuses_the_file_once_read_to_the_end() {
+        let (tx, rx) = channel(&config(1, 3 * record_len())).unwrap();
+        for round in 0..3 {
+            for n in 0..4 {
+                assert!(tx.try_send(packet(round * 10 + n)));
+            }
+            assert_eq!(
+                drain(&rx),
+                (0..4).map(|n| round * 10 + n).collect::<Vec<_>>()
+            );
+        }
+        let stats = rx.stats();
+        assert_eq!((stats.spilled, stats.replayed, stats.dropped), (9, 9, 0));
+    }
+
+    #[test]
+    fn drops_a_packet_larger_than_the_file() {
+        let (tx, rx) = channel(&config(0, record_len() - 1)).unwrap();
+        assert!(!tx.send(packet(0)));
+        assert!(!tx.try_send(packet(1)));
+        assert_eq!(rx.stats().dropped, 2);
+        assert!(rx.try_recv().is_none());
+    }
+}
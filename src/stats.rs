@@ -0,0 +1,218 @@
+//!Helpers for turning per-block telemetry into tuning advice
+
+use std::time::{Duration, SystemTime};
+
+use rx::BlockFill;
+
+const FILL_BUCKETS: usize = 10;
+
+///What a `FillHistogram` suggests about the ring's tp_block_size and tp_retire_blk_tov
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TuningHint {
+    ///Not enough blocks have been recorded to say anything
+    NotEnoughData,
+    ///Blocks are reasonably full and retire for a mix of reasons
+    Balanced,
+    ///Most blocks retire on the timeout while mostly empty, so the ring is holding much more
+    ///memory than the traffic needs. Consider smaller blocks or a shorter tp_retire_blk_tov
+    UnderFilled,
+    ///Blocks almost always fill before the timeout, so the consumer is handed large bursts.
+    ///Consider more blocks, or larger blocks if per-block overhead matters more than latency
+    AlwaysFull,
+}
+
+///Histogram of block fill levels, in buckets of 10%, along with why blocks were retired
+#[derive(Clone, Debug, Default)]
+pub struct FillHistogram {
+    buckets: [u64; FILL_BUCKETS],
+    timed_out: u64,
+    total: u64,
+    fill_sum: f64,
+}
+
+impl FillHistogram {
+    pub fn new() -> FillHistogram {
+        FillHistogram::default()
+    }
+
+    #[inline]
+    pub fn record(&mut self, fill: &BlockFill) {
+        let ratio = fill.ratio().clamp(0.0, 1.0);
+        let bucket = ((ratio * FILL_BUCKETS as f64) as usize).min(FILL_BUCKETS - 1);
+        self.buckets[bucket] += 1;
+        if fill.timed_out {
+            self.timed_out += 1;
+        }
+        self.total += 1;
+        self.fill_sum += ratio;
+    }
+
+    ///Counts of blocks per fill level, the first bucket is 0-10% full and the last 90-100%
+    pub fn buckets(&self) -> &[u64; FILL_BUCKETS] {
+        &self.buckets
+    }
+
+    ///Number of blocks recorded
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    ///Number of blocks that were retired by tp_retire_blk_tov rather than being full
+    pub fn timed_out(&self) -> u64 {
+        self.timed_out
+    }
+
+    ///Average fraction of a block that was used, between 0 and 1
+    pub fn mean_fill(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.fill_sum / self.total as f64
+    }
+
+    pub fn advice(&self) -> TuningHint {
+        //a ring's worth of blocks takes a while to settle after startup
+        if self.total < 100 {
+            return TuningHint::NotEnoughData;
+        }
+        let timeout_ratio = self.timed_out as f64 / self.total as f64;
+        let mean = self.mean_fill();
+        if timeout_ratio > 0.9 && mean < 0.25 {
+            TuningHint::UnderFilled
+        } else if timeout_ratio < 0.01 && mean > 0.9 {
+            TuningHint::AlwaysFull
+        } else {
+            TuningHint::Balanced
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = FillHistogram::default();
+    }
+}
+
+//buckets are powers of two microseconds, which covers up to ~35 minutes
+const HOLD_BUCKETS: usize = 32;
+
+///Histogram of how long blocks were held by the application before being marked as consumed,
+///and of other durations in `BlockLatency`
+#[derive(Clone, Debug, Default)]
+pub struct HoldHistogram {
+    buckets: [u64; HOLD_BUCKETS],
+    total: u64,
+    max: Duration,
+}
+
+impl HoldHistogram {
+    pub fn new() -> HoldHistogram {
+        HoldHistogram::default()
+    }
+
+    #[inline]
+    pub fn record(&mut self, held: Duration) {
+        let micros = held.as_micros().max(1) as u64;
+        let bucket = (63 - micros.leading_zeros() as usize).min(HOLD_BUCKETS - 1);
+        self.buckets[bucket] += 1;
+        self.total += 1;
+        if held > self.max {
+            self.max = held;
+        }
+    }
+
+    ///Number of hold times recorded
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    ///Longest hold time recorded
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    ///Returns an upper bound for the hold time at percentile `p` (0-100), accurate to within a
+    ///factor of two
+    pub fn percentile(&self, p: f64) -> Duration {
+        if self.total == 0 {
+            return Duration::from_secs(0);
+        }
+        let target = ((p.clamp(0.0, 100.0) / 100.0) * self.total as f64).ceil() as u64;
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target.max(1) {
+                let upper = Duration::from_micros(1u64 << (i + 1));
+                return upper.min(self.max);
+            }
+        }
+        self.max
+    }
+
+    pub fn reset(&mut self) {
+        *self = HoldHistogram::default();
+    }
+}
+
+///How old blocks' packets were by the time the application got to them, for keeping an eye on
+///capture-to-processing latency. Each block with packets is measured from the capture time of
+///its first and last packet, when it was handed out and when it was marked as consumed.
+///Timestamps from a NIC clock that isn't synchronised to the system clock make these meaningless
+#[derive(Clone, Debug, Default)]
+pub struct BlockLatency {
+    delivery_oldest: HoldHistogram,
+    delivery_newest: HoldHistogram,
+    consumed: HoldHistogram,
+    //blocks whose timestamps were ahead of the system clock
+    skipped: u64,
+}
+
+impl BlockLatency {
+    pub fn new() -> BlockLatency {
+        BlockLatency::default()
+    }
+
+    ///Records a block handed out at `now` with packets captured from `first` to `last`
+    pub fn record_delivery(&mut self, first: SystemTime, last: SystemTime, now: SystemTime) {
+        match (now.duration_since(first), now.duration_since(last)) {
+            (Ok(oldest), Ok(newest)) => {
+                self.delivery_oldest.record(oldest);
+                self.delivery_newest.record(newest);
+            }
+            _ => self.skipped += 1,
+        }
+    }
+
+    ///Records a block whose first packet was captured at `first` being consumed at `now`
+    pub fn record_consumed(&mut self, first: SystemTime, now: SystemTime) {
+        match now.duration_since(first) {
+            Ok(age) => self.consumed.record(age),
+            Err(_) => self.skipped += 1,
+        }
+    }
+
+    ///How long the first packet of each block waited before the block was handed out, which
+    ///includes the time the block took to fill or time out
+    pub fn delivery_oldest(&self) -> &HoldHistogram {
+        &self.delivery_oldest
+    }
+
+    ///How long the last packet of each block waited before the block was handed out, the time
+    ///spent in a retired block waiting for the application
+    pub fn delivery_newest(&self) -> &HoldHistogram {
+        &self.delivery_newest
+    }
+
+    ///How long the first packet of each block waited before the block was consumed, the
+    ///latency from capture to the end of processing
+    pub fn consumed(&self) -> &HoldHistogram {
+        &self.consumed
+    }
+
+    ///Number of measurements left out because a timestamp was ahead of the system clock
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    pub fn reset(&mut self) {
+        *self = BlockLatency::default();
+    }
+}
@@ -0,0 +1,261 @@
+//!Serves packets over a Unix domain socket, so a capture daemon holding CAP_NET_RAW can feed
+//!analysis processes that run without it. Every connected client gets every packet sent, as a
+//!stream that starts with the magic `AFPSTRM1` and continues with one length-prefixed record per
+//!packet carrying its tpacket3 header, sockaddr_ll and frame. Each client has its own bounded
+//!queue and writer thread, so one slow client drops its own packets and doesn't hold up the
+//!ring or the others. Who may connect is up to the socket file's permissions, set them after
+//!`bind`.
+
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use rx::{OwnedPacket, RawPacket};
+use wire;
+
+const MAGIC: &[u8; 8] = b"AFPSTRM1";
+
+///Packets queued for each client before its packets are dropped
+pub const DEFAULT_CLIENT_QUEUE: usize = 4096;
+
+struct Client {
+    queue: SyncSender<Arc<Vec<u8>>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    clients: Mutex<Vec<Client>>,
+    stop: AtomicBool,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+}
+
+///Listens on a Unix socket and sends packets to every client connected to it. Dropping it stops
+///listening, disconnects the clients once their queues are written and removes the socket file
+pub struct PacketServer {
+    path: PathBuf,
+    shared: Arc<Shared>,
+    accept: Option<JoinHandle<()>>,
+}
+
+impl PacketServer {
+    ///Binds to `path`, which must not exist yet, queueing up to `DEFAULT_CLIENT_QUEUE` packets
+    ///for each client
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<PacketServer> {
+        PacketServer::with_queue(path, DEFAULT_CLIENT_QUEUE)
+    }
+
+    ///Binds to `path`, queueing up to `queue` packets for each client
+    pub fn with_queue<P: AsRef<Path>>(path: P, queue: usize) -> io::Result<PacketServer> {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path)?;
+        let shared = Arc::new(Shared::default());
+        let accepting = shared.clone();
+        let accept = thread::Builder::new()
+            .name(String::from("af_packet-stream"))
+            .spawn(move || accept_clients(listener, accepting, queue))?;
+        Ok(PacketServer {
+            path,
+            shared,
+            accept: Some(accept),
+        })
+    }
+
+    ///Sends a packet from a ring to every client
+    pub fn send(&self, packet: &RawPacket<'_>) {
+        self.send_owned(&packet.to_owned())
+    }
+
+    ///Sends a packet to every client. Clients whose queue is full miss it, which is counted in
+    ///`dropped`
+    pub fn send_owned(&self, packet: &OwnedPacket) {
+        let mut clients = self
+            .shared
+            .clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if clients.is_empty() {
+            return;
+        }
+        let mut record = Vec::new();
+        wire::encode(packet, &mut record);
+        let record = Arc::new(record);
+        clients.retain(|client| match client.queue.try_send(record.clone()) {
+            Ok(()) => {
+                self.shared.sent.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(TrySendError::Full(_)) => {
+                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            //its writer thread has gone, the client disconnected
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+
+    ///Returns the number of clients connected
+    pub fn clients(&self) -> usize {
+        self.shared
+            .clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .len()
+    }
+
+    ///Returns the number of packets queued for clients, counting each client separately
+    pub fn sent(&self) -> u64 {
+        self.shared.sent.load(Ordering::Relaxed)
+    }
+
+    ///Returns the number of packets clients missed because their queue was full
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PacketServer {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        //accept doesn't return until someone connects
+        let _ = UnixStream::connect(&self.path);
+        if let Some(accept) = self.accept.take() {
+            let _ = accept.join();
+        }
+        self.shared
+            .clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn accept_clients(listener: UnixListener, shared: Arc<Shared>, queue: usize) {
+    for stream in listener.incoming() {
+        if shared.stop.load(Ordering::SeqCst) {
+            return;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                trace_event!(Warn, "accepting a stream client failed: {}", e);
+                continue;
+            }
+        };
+        let (tx, rx) = sync_channel::<Arc<Vec<u8>>>(queue);
+        let spawned = thread::Builder::new()
+            .name(String::from("af_packet-stream-client"))
+            .spawn(move || {
+                let mut out = BufWriter::new(stream);
+                if out.write_all(MAGIC).and_then(|()| out.flush()).is_err() {
+                    return;
+                }
+                while let Ok(record) = rx.recv() {
+                    if out.write_all(&record).is_err() {
+                        return;
+                    }
+                    //write in batches while packets are waiting, flush once the queue is empty
+                    while let Ok(record) = rx.try_recv() {
+                        if out.write_all(&record).is_err() {
+                            return;
+                        }
+                    }
+                    if out.flush().is_err() {
+                        return;
+                    }
+                }
+                let _ = out.flush();
+            });
+        match spawned {
+            Ok(_) => {
+                trace_event!(Info, "stream client connected");
+                shared
+                    .clients
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .push(Client { queue: tx });
+            }
+            Err(e) => trace_event!(Warn, "starting a stream client thread failed: {}", e),
+        }
+    }
+}
+
+///Receives the packets a `PacketServer` sends
+#[derive(Debug)]
+pub struct PacketClient {
+    input: BufReader<UnixStream>,
+    record: Vec<u8>,
+    received: u64,
+}
+
+impl PacketClient {
+    ///Connects to the server listening on `path`
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<PacketClient> {
+        PacketClient::from_stream(UnixStream::connect(path)?)
+    }
+
+    ///Reads the stream header from a connected socket
+    pub fn from_stream(stream: UnixStream) -> io::Result<PacketClient> {
+        let mut input = BufReader::new(stream);
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a packet stream"));
+        }
+        Ok(PacketClient {
+            input,
+            record: Vec::new(),
+            received: 0,
+        })
+    }
+
+    ///Waits for the next packet. Returns `None` once the server has gone
+    pub fn recv(&mut self) -> io::Result<Option<OwnedPacket>> {
+        let mut len = [0; 4];
+        match self.input.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let len = wire::record_len(len);
+        if !(4..=wire::MAX_RECORD).contains(&len) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("packet record of {} bytes", len),
+            ));
+        }
+        self.record.clear();
+        self.record.extend_from_slice(&(len as u32).to_le_bytes());
+        self.record.resize(len, 0);
+        self.input.read_exact(&mut self.record[4..])?;
+        self.received += 1;
+        wire::decode(&self.record).map(Some)
+    }
+
+    ///Returns the number of packets received
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    pub fn get_ref(&self) -> &UnixStream {
+        self.input.get_ref()
+    }
+}
+
+impl Iterator for PacketClient {
+    type Item = io::Result<OwnedPacket>;
+
+    fn next(&mut self) -> Option<io::Result<OwnedPacket>> {
+        self.recv().transpose()
+    }
+}
@@ -0,0 +1,318 @@
+//!Hardware and transmit timestamps. A NIC that can timestamp packets as they arrive only does so
+//!once it is told to with SIOCSHWTSTAMP, which applies to the whole interface and every socket on
+//!it. A packet socket then reports the NIC's time in tp_sec and tp_nsec rather than the kernel's
+//!once SOF_TIMESTAMPING_RAW_HARDWARE is set on it with PACKET_TIMESTAMP. Packets the NIC didn't
+//!stamp still get the kernel's time, so each packet's status says which one it carries.
+//!
+//!Sent frames are timestamped once SO_TIMESTAMPING asks for it on the sending socket. The
+//!kernel queues each timestamp on the socket's error queue, numbered by the order the frames
+//!were sent in, and the socket polls with POLLERR while any are waiting. `tx::Player` hands out
+//!the same numbers as it sends, so a timestamp can be matched to its frame.
+
+use libc::{
+    c_int, c_void, cmsghdr, iovec, msghdr, recvmsg, setsockopt, sock_extended_err, socklen_t,
+    timespec, CMSG_DATA, CMSG_FIRSTHDR, CMSG_NXTHDR, ENOMSG, MSG_DONTWAIT, MSG_ERRQUEUE,
+    SIOCGHWTSTAMP, SIOCSHWTSTAMP, SOL_PACKET, SOL_SOCKET, SO_EE_ORIGIN_TIMESTAMPING,
+    SO_TIMESTAMPING,
+};
+use std::io::{self, Error, ErrorKind};
+use std::mem;
+use std::ptr;
+use std::time::Duration;
+
+use ethtool;
+use socket::{self, Socket};
+use tpacket3::TimestampSource;
+
+pub(crate) const PACKET_TIMESTAMP: c_int = 17;
+const PACKET_TX_TIMESTAMP: c_int = 16;
+
+const SCM_TSTAMP_SND: u32 = 0;
+const SCM_TSTAMP_SCHED: u32 = 1;
+const SCM_TSTAMP_ACK: u32 = 2;
+
+///hwtstamp_config tx_type: the NIC doesn't timestamp sent packets
+pub const HWTSTAMP_TX_OFF: i32 = 0;
+///hwtstamp_config tx_type: the NIC timestamps sent packets that ask for it
+pub const HWTSTAMP_TX_ON: i32 = 1;
+///hwtstamp_config rx_filter: the NIC doesn't timestamp received packets
+pub const HWTSTAMP_FILTER_NONE: i32 = 0;
+///hwtstamp_config rx_filter: the NIC timestamps every received packet
+pub const HWTSTAMP_FILTER_ALL: i32 = 1;
+
+bitflags! {
+    ///SOF_TIMESTAMPING_* flags, as set with PACKET_TIMESTAMP or SO_TIMESTAMPING
+    pub struct Timestamping: u32 {
+        const TX_HARDWARE = 1 << 0;
+        const TX_SOFTWARE = 1 << 1;
+        const RX_HARDWARE = 1 << 2;
+        const RX_SOFTWARE = 1 << 3;
+        ///Report software timestamps
+        const SOFTWARE = 1 << 4;
+        ///Report hardware timestamps converted to system time, ignored since Linux 4.20
+        const SYS_HARDWARE = 1 << 5;
+        ///Report the NIC's own timestamps
+        const RAW_HARDWARE = 1 << 6;
+        const OPT_ID = 1 << 7;
+        const TX_SCHED = 1 << 8;
+        const TX_ACK = 1 << 9;
+        const OPT_CMSG = 1 << 10;
+        const OPT_TSONLY = 1 << 11;
+    }
+}
+
+///An interface's hardware timestamping setup (struct hwtstamp_config)
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HwTstampConfig {
+    pub flags: i32,
+    ///HWTSTAMP_TX_*
+    pub tx_type: i32,
+    ///HWTSTAMP_FILTER_*. Drivers may apply a broader filter than asked for
+    pub rx_filter: i32,
+}
+
+impl HwTstampConfig {
+    ///True if the NIC timestamps at least some received packets
+    #[inline]
+    pub fn rx_enabled(&self) -> bool {
+        self.rx_filter != HWTSTAMP_FILTER_NONE
+    }
+}
+
+///Reads the socket's interface's hardware timestamping setup. Fails with EOPNOTSUPP if the
+///driver can't timestamp or doesn't support reading it back
+pub fn get_hwtstamp(sock: &Socket) -> io::Result<HwTstampConfig> {
+    let mut config = HwTstampConfig::default();
+    ethtool::ifreq_data_ioctl(sock, SIOCGHWTSTAMP, &mut config)?;
+    Ok(config)
+}
+
+///Changes the socket's interface's hardware timestamping setup, returning what the driver
+///actually applied. Needs CAP_NET_ADMIN
+pub fn set_hwtstamp(sock: &Socket, config: HwTstampConfig) -> io::Result<HwTstampConfig> {
+    let mut config = config;
+    ethtool::ifreq_data_ioctl(sock, SIOCSHWTSTAMP, &mut config)?;
+    Ok(config)
+}
+
+///Reads the PACKET_TIMESTAMP flags of a packet socket
+pub fn get_packet_timestamp(fd: i32) -> io::Result<Timestamping> {
+    let mut flags: u32 = 0;
+    socket::get_sock_opt(fd, PACKET_TIMESTAMP, &mut flags)?;
+    Ok(Timestamping::from_bits_truncate(flags))
+}
+
+///Sets which timestamps a packet socket's ring reports. Only `RAW_HARDWARE` and `SYS_HARDWARE`
+///make a difference, the kernel's time is used when neither is set or the NIC gave none
+pub fn set_packet_timestamp(sock: &mut Socket, flags: Timestamping) -> io::Result<()> {
+    sock.setsockopt(PACKET_TIMESTAMP, flags.bits())
+}
+
+///Keeps receive timestamping on in an interface's NIC while it is held, and puts back the setup
+///it found when dropped
+#[derive(Debug)]
+pub struct HwTimestampGuard {
+    sock: Socket,
+    saved: HwTstampConfig,
+    applied: HwTstampConfig,
+}
+
+impl HwTimestampGuard {
+    ///Has the socket's interface timestamp every received packet, leaving sent packets as they
+    ///were. Fails with EOPNOTSUPP on interfaces that can't, and if the driver applied no receive
+    ///filter at all
+    pub fn enable_rx(sock: &Socket) -> io::Result<HwTimestampGuard> {
+        let guard = HwTimestampGuard::apply(sock, |saved| HwTstampConfig {
+            rx_filter: HWTSTAMP_FILTER_ALL,
+            ..saved
+        })?;
+        if !guard.applied.rx_enabled() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("{} kept receive timestamping off", sock.if_name),
+            ));
+        }
+        Ok(guard)
+    }
+
+    ///Has the socket's interface timestamp the packets it sends, leaving received packets as
+    ///they were. Fails with EOPNOTSUPP on interfaces that can't
+    pub fn enable_tx(sock: &Socket) -> io::Result<HwTimestampGuard> {
+        HwTimestampGuard::apply(sock, |saved| HwTstampConfig {
+            tx_type: HWTSTAMP_TX_ON,
+            ..saved
+        })
+    }
+
+    fn apply(
+        sock: &Socket,
+        change: impl FnOnce(HwTstampConfig) -> HwTstampConfig,
+    ) -> io::Result<HwTimestampGuard> {
+        //drivers that can't report their setup started out with it off
+        let saved = match get_hwtstamp(sock) {
+            Ok(config) => config,
+            Err(ref e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => HwTstampConfig::default(),
+            Err(e) => return Err(e),
+        };
+        let applied = set_hwtstamp(sock, change(saved))?;
+        Ok(HwTimestampGuard {
+            sock: sock.clone(),
+            saved,
+            applied,
+        })
+    }
+
+    ///Returns the setup the interface had before the guard was made
+    #[inline]
+    pub fn saved(&self) -> HwTstampConfig {
+        self.saved
+    }
+
+    ///Returns the setup the driver applied, which may differ from what was asked for
+    #[inline]
+    pub fn applied(&self) -> HwTstampConfig {
+        self.applied
+    }
+}
+
+impl Drop for HwTimestampGuard {
+    fn drop(&mut self) {
+        if let Err(_e) = set_hwtstamp(&self.sock, self.saved) {
+            trace_event!(
+                Warn,
+                "failed to restore hardware timestamping on {}: {}",
+                self.sock.if_name,
+                _e
+            );
+        }
+    }
+}
+
+///Where on its way out a sent frame was timestamped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxStage {
+    ///Queued for the interface, before any traffic shaping
+    Scheduled,
+    ///Handed to the NIC, or put on the wire for hardware timestamps
+    Sent,
+    ///Acknowledged, only for TCP
+    Acked,
+}
+
+///A sent frame's timestamp, read from the socket's error queue
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TxTimestamp {
+    ///The frame's number in send order, counting from 0 when timestamping was turned on
+    pub id: u32,
+    pub stage: TxStage,
+    ///How far the time is from its clock's epoch: the Unix epoch for software timestamps, the
+    ///NIC's own epoch for raw hardware ones
+    pub time: Duration,
+    ///`Software` or `HardwareRaw`
+    pub source: TimestampSource,
+}
+
+///Asks for timestamps of the frames sent on a packet socket, taken by the kernel and, with
+///`hardware`, by the NIC. The NIC only takes them once `HwTimestampGuard::enable_tx` turned that
+///on for the interface
+pub fn enable_tx_timestamps(sock: &Socket, hardware: bool) -> io::Result<()> {
+    let mut flags = Timestamping::TX_SOFTWARE
+        | Timestamping::TX_SCHED
+        | Timestamping::SOFTWARE
+        | Timestamping::OPT_ID
+        | Timestamping::OPT_TSONLY;
+    if hardware {
+        flags |= Timestamping::TX_HARDWARE | Timestamping::RAW_HARDWARE;
+    }
+    sol_socket_u32(sock, SO_TIMESTAMPING, flags.bits())
+}
+
+///Stops timestamping sent frames on a packet socket
+pub fn disable_tx_timestamps(sock: &Socket) -> io::Result<()> {
+    sol_socket_u32(sock, SO_TIMESTAMPING, 0)
+}
+
+///Takes the next timestamp off a packet socket's error queue, or returns `None` if there isn't
+///one yet. Doesn't block, polling the socket for POLLERR waits for one
+pub fn read_tx_timestamp(fd: c_int) -> io::Result<Option<TxTimestamp>> {
+    loop {
+        //room for a sock_extended_err and a scm_timestamping, each with its cmsghdr
+        let mut control = [0u64; 32];
+        let mut data = [0u8; 1];
+        let mut iov = iovec {
+            iov_base: data.as_mut_ptr() as *mut c_void,
+            iov_len: data.len(),
+        };
+        let mut msg: msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut c_void;
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+        if unsafe { recvmsg(fd, &mut msg, MSG_ERRQUEUE | MSG_DONTWAIT) } < 0 {
+            let err = Error::last_os_error();
+            return match err.kind() {
+                ErrorKind::WouldBlock => Ok(None),
+                _ => Err(err),
+            };
+        }
+        //anything else on the error queue, such as a send error, isn't a timestamp
+        if let Some(ts) = parse_tx_timestamp(&msg) {
+            return Ok(Some(ts));
+        }
+    }
+}
+
+fn parse_tx_timestamp(msg: &msghdr) -> Option<TxTimestamp> {
+    let mut err: Option<sock_extended_err> = None;
+    let mut times: Option<[timespec; 3]> = None;
+    let mut cmsg: *const cmsghdr = unsafe { CMSG_FIRSTHDR(msg) };
+    while !cmsg.is_null() {
+        let hdr = unsafe { &*cmsg };
+        let data = unsafe { CMSG_DATA(cmsg) };
+        if hdr.cmsg_level == SOL_PACKET && hdr.cmsg_type == PACKET_TX_TIMESTAMP {
+            err = Some(unsafe { ptr::read_unaligned(data as *const sock_extended_err) });
+        } else if hdr.cmsg_level == SOL_SOCKET && hdr.cmsg_type == SO_TIMESTAMPING {
+            times = Some(unsafe { ptr::read_unaligned(data as *const [timespec; 3]) });
+        }
+        cmsg = unsafe { CMSG_NXTHDR(msg, cmsg) };
+    }
+    let err = err?;
+    if err.ee_errno != ENOMSG as u32 || err.ee_origin != SO_EE_ORIGIN_TIMESTAMPING {
+        return None;
+    }
+    let times = times?;
+    //the software time is in the first slot and the raw hardware time in the third
+    let (time, source) = if times[2].tv_sec != 0 || times[2].tv_nsec != 0 {
+        (times[2], TimestampSource::HardwareRaw)
+    } else {
+        (times[0], TimestampSource::Software)
+    };
+    let stage = match err.ee_info {
+        SCM_TSTAMP_SND => TxStage::Sent,
+        SCM_TSTAMP_SCHED => TxStage::Scheduled,
+        SCM_TSTAMP_ACK => TxStage::Acked,
+        _ => return None,
+    };
+    Some(TxTimestamp {
+        id: err.ee_data,
+        stage,
+        time: Duration::new(time.tv_sec as u64, time.tv_nsec as u32),
+        source,
+    })
+}
+
+fn sol_socket_u32(sock: &Socket, opt: c_int, val: u32) -> io::Result<()> {
+    match unsafe {
+        setsockopt(
+            sock.fd,
+            SOL_SOCKET,
+            opt,
+            &val as *const u32 as *const c_void,
+            mem::size_of::<u32>() as socklen_t,
+        )
+    } {
+        0 => Ok(()),
+        _ => Err(Error::last_os_error()),
+    }
+}
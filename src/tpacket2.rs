@@ -0,0 +1,171 @@
+use endian::{ne_u16, ne_u32};
+use libc::{c_int, c_uint};
+
+use error::ConfigError;
+use tpacket3::{self, Tpacket3Hdr, TpacketHdrVariant1, TpacketReq3};
+
+pub const TPACKET_V2: c_int = 1;
+
+pub const TP_STATUS_KERNEL: u32 = 0;
+pub const TP_STATUS_USER: u32 = 1;
+
+///Offset of the sockaddr_ll from the start of each frame, TPACKET_ALIGN(sizeof(struct tpacket2_hdr))
+pub const TP2_SOCKADDR_LL_OFFSET: usize = 32;
+
+#[derive(Clone, Debug)]
+#[repr(C)]
+///Ring geometry for TPACKET_V2.
+///tp_frame_size * tp_frame_nr must equal tp_block_size * tp_block_nr
+pub struct TpacketReq {
+    pub tp_block_size: c_uint,
+    pub tp_block_nr: c_uint,
+    pub tp_frame_size: c_uint,
+    pub tp_frame_nr: c_uint,
+}
+
+impl From<&TpacketReq3> for TpacketReq {
+    fn from(req: &TpacketReq3) -> TpacketReq {
+        TpacketReq {
+            tp_block_size: req.tp_block_size,
+            tp_block_nr: req.tp_block_nr,
+            tp_frame_size: req.tp_frame_size,
+            tp_frame_nr: req.tp_frame_nr,
+        }
+    }
+}
+
+//TPACKET2_HDRLEN, the aligned tpacket2_hdr followed by a sockaddr_ll
+const TPACKET2_HDRLEN: u32 = 52;
+
+impl TpacketReq {
+    ///Checks the geometry against the constraints the kernel puts on a TPACKET_V2 ring
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        tpacket3::check_geometry(
+            [
+                self.tp_block_size,
+                self.tp_block_nr,
+                self.tp_frame_size,
+                self.tp_frame_nr,
+            ],
+            TPACKET2_HDRLEN,
+            u64::from(TPACKET2_HDRLEN),
+        )
+    }
+}
+
+///Header at the start of every TPACKET_V2 frame
+#[derive(Clone, Debug)]
+pub struct Tpacket2Hdr {
+    pub tp_status: u32,
+    pub tp_len: u32,
+    pub tp_snaplen: u32,
+    pub tp_mac: u16,
+    pub tp_net: u16,
+    pub tp_sec: u32,
+    pub tp_nsec: u32,
+    pub tp_vlan_tci: u16,
+    pub tp_vlan_tpid: u16,
+}
+
+impl Tpacket2Hdr {
+    ///Maps the header onto the TPACKET_V3 layout so frames can be handed out as `RawPacket`s.
+    ///There is no RX hash in V2, so tp_rxhash is always 0
+    pub fn to_tpacket3_hdr(&self) -> Tpacket3Hdr {
+        Tpacket3Hdr {
+            tp_next_offset: 0,
+            tp_sec: self.tp_sec,
+            tp_nsec: self.tp_nsec,
+            tp_snaplen: self.tp_snaplen,
+            tp_len: self.tp_len,
+            tp_status: self.tp_status,
+            tp_mac: self.tp_mac,
+            tp_net: self.tp_net,
+            hv1: TpacketHdrVariant1::new(0, u32::from(self.tp_vlan_tci), self.tp_vlan_tpid),
+        }
+    }
+}
+
+named!(
+    pub get_tpacket2_hdr<Tpacket2Hdr>,
+    do_parse!(
+        tp_status: ne_u32
+            >> tp_len: ne_u32
+            >> tp_snaplen: ne_u32
+            >> tp_mac: ne_u16
+            >> tp_net: ne_u16
+            >> tp_sec: ne_u32
+            >> tp_nsec: ne_u32
+            >> tp_vlan_tci: ne_u16
+            >> tp_vlan_tpid: ne_u16
+            >> (Tpacket2Hdr {
+                tp_status,
+                tp_len,
+                tp_snaplen,
+                tp_mac,
+                tp_net,
+                tp_sec,
+                tp_nsec,
+                tp_vlan_tci,
+                tp_vlan_tpid
+            })
+    )
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tpacket2_hdr() {
+        let mut b = Vec::new();
+        for word in &[TP_STATUS_USER, 1514, 60] {
+            b.extend_from_slice(&word.to_ne_bytes());
+        }
+        b.extend_from_slice(&52u16.to_ne_bytes());
+        b.extend_from_slice(&66u16.to_ne_bytes());
+        b.extend_from_slice(&10u32.to_ne_bytes());
+        b.extend_from_slice(&20u32.to_ne_bytes());
+        b.extend_from_slice(&0x0123u16.to_ne_bytes());
+        b.extend_from_slice(&0x88a8u16.to_ne_bytes());
+        let (rest, hdr) = get_tpacket2_hdr(&b).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(hdr.tp_status, TP_STATUS_USER);
+        assert_eq!((hdr.tp_len, hdr.tp_snaplen), (1514, 60));
+        assert_eq!((hdr.tp_mac, hdr.tp_net), (52, 66));
+        assert_eq!((hdr.tp_sec, hdr.tp_nsec), (10, 20));
+        assert_eq!((hdr.tp_vlan_tci, hdr.tp_vlan_tpid), (0x0123, 0x88a8));
+        assert!(get_tpacket2_hdr(&b[..b.len() - 1]).is_err());
+
+        let v3 = hdr.to_tpacket3_hdr();
+        assert_eq!(v3.tp_next_offset, 0);
+        assert_eq!(v3.hv1.tp_rxhash, 0);
+        assert_eq!(v3.hv1.tp_vlan_tci, 0x0123);
+        assert_eq!(v3.hv1.tp_vlan_tpid, 0x88a8);
+        assert_eq!((v3.tp_mac, v3.tp_net), (52, 66));
+    }
+
+    #[test]
+    fn validates_against_the_v2_header() {
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u32;
+        let req = TpacketReq {
+            tp_block_size: page,
+            tp_block_nr: 2,
+            tp_frame_size: 64,
+            tp_frame_nr: page / 64 * 2,
+        };
+        //too small for a V3 frame but fine for V2
+        assert_eq!(req.validate(), Ok(()));
+        let req = TpacketReq {
+            tp_frame_size: 48,
+            tp_frame_nr: page / 48 * 2,
+            ..req
+        };
+        assert_eq!(
+            req.validate(),
+            Err(ConfigError::FrameTooSmall {
+                frame_size: 48,
+                min: TPACKET2_HDRLEN,
+            })
+        );
+    }
+}
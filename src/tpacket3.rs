@@ -1,12 +1,97 @@
-use libc::{c_int, c_uint};
-use nom::number::complete::{le_u16, le_u32, le_u64};
+#[cfg(target_os = "linux")]
+use libc::sockaddr_ll;
+use libc::{c_int, c_uint, sysconf, _SC_PAGESIZE};
+use nom::number::complete::{be_u16, le_u8};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use endian::{ne_i32, ne_u16, ne_u32, ne_u64};
+use error::ConfigError;
+use vlan::{self, VlanTag};
 
 pub const TP_STATUS_KERNEL: u8 = 0;
 pub const TP_STATUS_USER: u8 = 1;
-//const TP_STATUS_COPY: u8 = 1 << 1;
-//const TP_STATUS_LOSING: u8 = 1 << 2;
-//const TP_STATUS_CSUMNOTREADY: u8 = 1 << 3;
-//const TP_STATUS_CSUM_VALID: u8 = 1 << 7;
+
+bitflags! {
+    ///Flags the kernel sets in a packet's tp_status
+    pub struct TpStatus: u32 {
+        const USER = 1;
+        const COPY = 1 << 1;
+        ///The ring dropped packets since the last time statistics were read
+        const LOSING = 1 << 2;
+        ///The checksum will be computed by hardware on the way out and is not valid yet
+        const CSUMNOTREADY = 1 << 3;
+        ///tp_vlan_tci holds the VLAN tag stripped from the frame
+        const VLAN_VALID = 1 << 4;
+        ///tp_vlan_tpid holds the TPID of the stripped VLAN tag
+        const VLAN_TPID_VALID = 1 << 6;
+        ///The checksum was validated by the NIC
+        const CSUM_VALID = 1 << 7;
+        const GSO_TCP = 1 << 8;
+        ///tp_sec and tp_nsec are the kernel's software timestamp
+        const TS_SOFTWARE = 1 << 29;
+        ///tp_sec and tp_nsec are a hardware timestamp converted to system time, only set by
+        ///kernels before 4.20
+        const TS_SYS_HARDWARE = 1 << 30;
+        ///tp_sec and tp_nsec are the NIC's raw hardware timestamp
+        const TS_RAW_HARDWARE = 1 << 31;
+    }
+}
+
+///Where a packet's timestamp came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimestampSource {
+    ///The kernel's clock when the packet reached it
+    Software,
+    ///The NIC's clock, as the NIC reported it
+    HardwareRaw,
+    ///The NIC's clock converted to system time by the driver
+    HardwareSystem,
+}
+
+impl TpStatus {
+    #[inline]
+    pub fn vlan_valid(self) -> bool {
+        self.contains(TpStatus::VLAN_VALID)
+    }
+
+    #[inline]
+    pub fn vlan_tpid_valid(self) -> bool {
+        self.contains(TpStatus::VLAN_TPID_VALID)
+    }
+
+    #[inline]
+    pub fn csum_valid(self) -> bool {
+        self.contains(TpStatus::CSUM_VALID)
+    }
+
+    #[inline]
+    pub fn csum_not_ready(self) -> bool {
+        self.contains(TpStatus::CSUMNOTREADY)
+    }
+
+    #[inline]
+    pub fn losing(self) -> bool {
+        self.contains(TpStatus::LOSING)
+    }
+
+    ///Returns where the timestamp came from, or `None` if the kernel took the time only when
+    ///it copied the packet into the ring
+    #[inline]
+    pub fn timestamp_source(self) -> Option<TimestampSource> {
+        if self.contains(TpStatus::TS_RAW_HARDWARE) {
+            Some(TimestampSource::HardwareRaw)
+        } else if self.contains(TpStatus::TS_SYS_HARDWARE) {
+            Some(TimestampSource::HardwareSystem)
+        } else if self.contains(TpStatus::TS_SOFTWARE) {
+            Some(TimestampSource::Software)
+        } else {
+            None
+        }
+    }
+}
+
+///Set in a block's status when it was retired because tp_retire_blk_tov expired
+pub const TP_STATUS_BLK_TMO: u32 = 1 << 5;
 
 pub const TPACKET_V3: c_int = 2;
 
@@ -14,6 +99,24 @@ const TP_FT_REQ_FILL_RXHASH: c_uint = 1; //0x1;
 
 pub const TP_BLK_STATUS_OFFSET: usize = 8;
 
+///Reads the status word of the block starting at `block`, which the kernel writes in native
+///byte order
+#[inline]
+pub fn read_block_status(block: &[u8]) -> u32 {
+    let mut word = [0u8; 4];
+    word.copy_from_slice(&block[TP_BLK_STATUS_OFFSET..TP_BLK_STATUS_OFFSET + 4]);
+    u32::from_ne_bytes(word)
+}
+
+///Hands the block starting at `block` back to the kernel
+#[inline]
+pub fn write_block_status(block: &mut [u8], status: u32) {
+    block[TP_BLK_STATUS_OFFSET..TP_BLK_STATUS_OFFSET + 4].copy_from_slice(&status.to_ne_bytes());
+}
+
+///Offset of the sockaddr_ll from the start of each tpacket3 header, TPACKET_ALIGN(sizeof(struct tpacket3_hdr))
+pub const TP_SOCKADDR_LL_OFFSET: usize = 48;
+
 #[derive(Clone, Debug)]
 #[repr(C)]
 pub struct TpacketStatsV3 {
@@ -43,6 +146,7 @@ pub struct TpacketReq3 {
     pub tp_feature_req_word: c_uint,
 }
 
+#[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub struct TpacketBlockDesc {
     version: u32,
@@ -52,11 +156,11 @@ pub struct TpacketBlockDesc {
 
 #[derive(Clone, Debug)]
 pub struct TpacketBDHeader {
-    block_status: u32,
+    pub block_status: u32,
     pub num_pkts: u32,
-    offset_to_first_pkt: u32,
-    blk_len: u32,
-    seq_num: u64,
+    pub offset_to_first_pkt: u32,
+    pub blk_len: u32,
+    pub seq_num: u64,
     ts_first_pkt: TpacketBDTS,
     ts_last_pkt: TpacketBDTS,
 }
@@ -82,7 +186,70 @@ pub struct Tpacket3Hdr {
     //pub tp_padding: [u8; 8],
 }
 
+impl TpacketBDTS {
+    #[inline]
+    fn to_system_time(&self) -> SystemTime {
+        to_system_time(self.ts_sec, self.ts_nsec)
+    }
+}
+
+impl TpacketBDHeader {
+    ///Time the first packet in the block was captured
+    #[inline]
+    pub fn first_packet_time(&self) -> SystemTime {
+        self.ts_first_pkt.to_system_time()
+    }
+
+    ///Time the last packet in the block was captured
+    #[inline]
+    pub fn last_packet_time(&self) -> SystemTime {
+        self.ts_last_pkt.to_system_time()
+    }
+}
+
+impl Tpacket3Hdr {
+    ///Time the packet was captured
+    #[inline]
+    pub fn timestamp(&self) -> SystemTime {
+        to_system_time(self.tp_sec, self.tp_nsec)
+    }
+
+    ///Returns the flow hash, only filled in when RXHASH is requested in tp_feature_req_word. 0
+    ///means none was computed
+    #[inline]
+    pub fn rxhash(&self) -> Option<u32> {
+        match self.hv1.tp_rxhash {
+            0 => None,
+            hash => Some(hash),
+        }
+    }
+
+    ///Returns the VLAN tag the kernel stripped from the frame, if any
+    #[inline]
+    pub fn vlan_tag(&self) -> Option<VlanTag> {
+        let status = self.status();
+        if !status.vlan_valid() {
+            return None;
+        }
+        Some(VlanTag {
+            tpid: if status.vlan_tpid_valid() {
+                self.hv1.tp_vlan_tpid
+            } else {
+                vlan::ETH_P_8021Q
+            },
+            tci: self.hv1.tp_vlan_tci as u16,
+        })
+    }
+
+    ///Returns tp_status as typed flags, ignoring any bits this crate doesn't know about
+    #[inline]
+    pub fn status(&self) -> TpStatus {
+        TpStatus::from_bits_truncate(self.tp_status)
+    }
+}
+
 ///Contains VLAN tags and RX Hash value (if enabled)
+#[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub struct TpacketHdrVariant1 {
     pub tp_rxhash: u32,
@@ -91,6 +258,56 @@ pub struct TpacketHdrVariant1 {
     tp_padding: u16,
 }
 
+///Link-layer address information the kernel stores after each packet header
+#[derive(Clone, Debug)]
+pub struct SockaddrLl {
+    pub sll_family: u16,
+    ///Ethertype in host byte order
+    pub sll_protocol: u16,
+    ///Index of the interface the packet was seen on
+    pub sll_ifindex: i32,
+    ///ARPHRD_* type of the interface
+    pub sll_hatype: u16,
+    ///PACKET_HOST, PACKET_OUTGOING, etc.
+    pub sll_pkttype: u8,
+    ///Number of bytes of sll_addr in use
+    pub sll_halen: u8,
+    ///Source link-layer address
+    pub sll_addr: [u8; 8],
+}
+
+#[cfg(target_os = "linux")]
+impl From<&sockaddr_ll> for SockaddrLl {
+    fn from(sll: &sockaddr_ll) -> SockaddrLl {
+        SockaddrLl {
+            sll_family: sll.sll_family,
+            sll_protocol: u16::from_be(sll.sll_protocol),
+            sll_ifindex: sll.sll_ifindex,
+            sll_hatype: sll.sll_hatype,
+            sll_pkttype: sll.sll_pkttype,
+            sll_halen: sll.sll_halen,
+            sll_addr: sll.sll_addr,
+        }
+    }
+}
+
+impl TpacketHdrVariant1 {
+    pub fn new(tp_rxhash: u32, tp_vlan_tci: u32, tp_vlan_tpid: u16) -> TpacketHdrVariant1 {
+        TpacketHdrVariant1 {
+            tp_rxhash,
+            tp_vlan_tci,
+            tp_vlan_tpid,
+            tp_padding: 0,
+        }
+    }
+}
+
+///Converts a kernel timestamp, seconds and nanoseconds since the epoch, to a `SystemTime`
+#[inline]
+pub fn to_system_time(sec: u32, nsec: u32) -> SystemTime {
+    UNIX_EPOCH + Duration::new(u64::from(sec), nsec)
+}
+
 impl Default for TpacketReq3 {
     fn default() -> TpacketReq3 {
         TpacketReq3 {
@@ -105,11 +322,88 @@ impl Default for TpacketReq3 {
     }
 }
 
-#[inline]
+//TPACKET3_HDRLEN, the aligned tpacket3_hdr followed by a sockaddr_ll
+const TPACKET3_HDRLEN: u32 = 68;
+//BLK_HDR_LEN, the block descriptor rounded up to 8 bytes
+const BLK_HDR_LEN: u64 = 48;
+
+impl TpacketReq3 {
+    ///Checks the settings against the constraints the kernel puts on a TPACKET_V3 ring, which it
+    ///only reports as EINVAL
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let priv_len = (u64::from(self.tp_sizeof_priv) + 7) & !7;
+        let min_block = BLK_HDR_LEN + priv_len + u64::from(TPACKET3_HDRLEN);
+        check_geometry(
+            [
+                self.tp_block_size,
+                self.tp_block_nr,
+                self.tp_frame_size,
+                self.tp_frame_nr,
+            ],
+            TPACKET3_HDRLEN,
+            min_block,
+        )
+    }
+}
+
+///Checks block size, block count, frame size and frame count as packet_set_ring does, given the
+///smallest frame and block the ring version allows
+pub(crate) fn check_geometry(
+    [block_size, block_nr, frame_size, frame_nr]: [u32; 4],
+    min_frame: u32,
+    min_block: u64,
+) -> Result<(), ConfigError> {
+    if block_nr == 0 {
+        return Err(ConfigError::NoBlocks);
+    }
+    let page_size = unsafe { sysconf(_SC_PAGESIZE) } as u32;
+    if block_size == 0 || block_size > i32::MAX as u32 || block_size % page_size != 0 {
+        return Err(ConfigError::BlockSizeUnaligned {
+            block_size,
+            page_size,
+        });
+    }
+    if u64::from(block_size) < min_block {
+        return Err(ConfigError::BlockTooSmall {
+            block_size,
+            min: min_block as u32,
+        });
+    }
+    if frame_size < min_frame {
+        return Err(ConfigError::FrameTooSmall {
+            frame_size,
+            min: min_frame,
+        });
+    }
+    if frame_size % 16 != 0 {
+        return Err(ConfigError::FrameSizeUnaligned { frame_size });
+    }
+    let frames_per_block = block_size / frame_size;
+    if frames_per_block == 0 {
+        return Err(ConfigError::BlockTooSmall {
+            block_size,
+            min: frame_size,
+        });
+    }
+    if frames_per_block > u32::MAX / block_nr {
+        return Err(ConfigError::RingTooLarge {
+            block_nr,
+            frames_per_block,
+        });
+    }
+    if frames_per_block * block_nr != frame_nr {
+        return Err(ConfigError::FrameCountMismatch {
+            frame_nr,
+            expected: frames_per_block * block_nr,
+        });
+    }
+    Ok(())
+}
+
 named!(
     pub get_tpacket_block_desc<TpacketBlockDesc>,
     do_parse!(
-        version: le_u32 >> offset_to_priv: le_u32 >> hdr: get_tpacket_bd_header
+        version: ne_u32 >> offset_to_priv: ne_u32 >> hdr: get_tpacket_bd_header
             >> (TpacketBlockDesc {
                 version,
                 offset_to_priv,
@@ -118,15 +412,14 @@ named!(
     )
 );
 
-#[inline]
 named!(
     get_tpacket_bd_header<TpacketBDHeader>,
     do_parse!(
-        block_status: le_u32
-            >> num_pkts: le_u32
-            >> offset_to_first_pkt: le_u32
-            >> blk_len: le_u32
-            >> seq_num: le_u64
+        block_status: ne_u32
+            >> num_pkts: ne_u32
+            >> offset_to_first_pkt: ne_u32
+            >> blk_len: ne_u32
+            >> seq_num: ne_u64
             >> ts_first_pkt: get_tpacket_bdts
             >> ts_last_pkt: get_tpacket_bdts
             >> (TpacketBDHeader {
@@ -141,20 +434,18 @@ named!(
     )
 );
 
-#[inline]
 named!(
     get_tpacket_bdts<TpacketBDTS>,
-    do_parse!(ts_sec: le_u32 >> ts_nsec: le_u32 >> (TpacketBDTS { ts_sec, ts_nsec }))
+    do_parse!(ts_sec: ne_u32 >> ts_nsec: ne_u32 >> (TpacketBDTS { ts_sec, ts_nsec }))
 );
 
-#[inline]
 named!(
     get_tpacket_hdr_variant1<TpacketHdrVariant1>,
     do_parse!(
-        tp_rxhash: le_u32
-            >> tp_vlan_tci: le_u32
-            >> tp_vlan_tpid: le_u16
-            >> tp_padding: le_u16
+        tp_rxhash: ne_u32
+            >> tp_vlan_tci: ne_u32
+            >> tp_vlan_tpid: ne_u16
+            >> tp_padding: ne_u16
             >> (TpacketHdrVariant1 {
                 tp_rxhash,
                 tp_vlan_tci,
@@ -164,12 +455,11 @@ named!(
     )
 );
 
-#[inline]
 named!(
     pub get_tpacket3_hdr<Tpacket3Hdr>,
     do_parse!(
-        tp_next_offset: le_u32 >> tp_sec: le_u32 >> tp_nsec: le_u32 >> tp_snaplen: le_u32
-            >> tp_len: le_u32 >> tp_status: le_u32 >> tp_mac: le_u16 >> tp_net: le_u16
+        tp_next_offset: ne_u32 >> tp_sec: ne_u32 >> tp_nsec: ne_u32 >> tp_snaplen: ne_u32
+            >> tp_len: ne_u32 >> tp_status: ne_u32 >> tp_mac: ne_u16 >> tp_net: ne_u16
             >> hv1: get_tpacket_hdr_variant1 >> (Tpacket3Hdr {
             tp_next_offset,
             tp_sec,
@@ -183,3 +473,224 @@ named!(
         })
     )
 );
+
+named!(
+    pub get_sockaddr_ll<SockaddrLl>,
+    do_parse!(
+        sll_family: ne_u16
+            >> sll_protocol: be_u16
+            >> sll_ifindex: ne_i32
+            >> sll_hatype: ne_u16
+            >> sll_pkttype: le_u8
+            >> sll_halen: le_u8
+            >> addr: take!(8)
+            >> (SockaddrLl {
+                sll_family,
+                sll_protocol,
+                sll_ifindex,
+                sll_hatype,
+                sll_pkttype,
+                sll_halen,
+                sll_addr: [
+                    addr[0], addr[1], addr[2], addr[3], addr[4], addr[5], addr[6], addr[7]
+                ]
+            })
+    )
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page_size() -> u32 {
+        unsafe { sysconf(_SC_PAGESIZE) as u32 }
+    }
+
+    fn block_desc_bytes() -> Vec<u8> {
+        let mut b = Vec::new();
+        for word in &[1u32, 48, TpStatus::USER.bits(), 3, 64, 400] {
+            b.extend_from_slice(&word.to_ne_bytes());
+        }
+        b.extend_from_slice(&0x0102_0304_0506_0708u64.to_ne_bytes());
+        for word in &[100u32, 200, 300, 400] {
+            b.extend_from_slice(&word.to_ne_bytes());
+        }
+        b
+    }
+
+    #[test]
+    fn parses_block_desc() {
+        let b = block_desc_bytes();
+        let (rest, desc) = get_tpacket_block_desc(&b).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(desc.version, 1);
+        assert_eq!(desc.offset_to_priv, 48);
+        assert_eq!(desc.hdr.block_status, TpStatus::USER.bits());
+        assert_eq!(desc.hdr.num_pkts, 3);
+        assert_eq!(desc.hdr.offset_to_first_pkt, 64);
+        assert_eq!(desc.hdr.blk_len, 400);
+        assert_eq!(desc.hdr.seq_num, 0x0102_0304_0506_0708);
+        assert_eq!(desc.hdr.first_packet_time(), to_system_time(100, 200));
+        assert_eq!(desc.hdr.last_packet_time(), to_system_time(300, 400));
+    }
+
+    #[test]
+    fn parses_tpacket3_hdr() {
+        let mut b = Vec::new();
+        for word in &[96u32, 10, 20, 60, 1514, TpStatus::USER.bits()] {
+            b.extend_from_slice(&word.to_ne_bytes());
+        }
+        b.extend_from_slice(&68u16.to_ne_bytes());
+        b.extend_from_slice(&82u16.to_ne_bytes());
+        b.extend_from_slice(&0xdead_beefu32.to_ne_bytes());
+        b.extend_from_slice(&5u32.to_ne_bytes());
+        b.extend_from_slice(&0x8100u16.to_ne_bytes());
+        b.extend_from_slice(&0u16.to_ne_bytes());
+        let (rest, hdr) = get_tpacket3_hdr(&b).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(hdr.tp_next_offset, 96);
+        assert_eq!((hdr.tp_sec, hdr.tp_nsec), (10, 20));
+        assert_eq!((hdr.tp_snaplen, hdr.tp_len), (60, 1514));
+        assert_eq!(hdr.status(), TpStatus::USER);
+        assert_eq!((hdr.tp_mac, hdr.tp_net), (68, 82));
+        assert_eq!(hdr.hv1.tp_rxhash, 0xdead_beef);
+        assert_eq!(hdr.hv1.tp_vlan_tci, 5);
+        assert_eq!(hdr.hv1.tp_vlan_tpid, 0x8100);
+    }
+
+    #[test]
+    fn parses_sockaddr_ll_with_big_endian_protocol() {
+        let mut b = Vec::new();
+        b.extend_from_slice(&17u16.to_ne_bytes());
+        b.extend_from_slice(&0x0800u16.to_be_bytes());
+        b.extend_from_slice(&7i32.to_ne_bytes());
+        b.extend_from_slice(&1u16.to_ne_bytes());
+        b.push(4);
+        b.push(6);
+        b.extend_from_slice(&[1, 2, 3, 4, 5, 6, 0, 0]);
+        let (rest, sll) = get_sockaddr_ll(&b).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(sll.sll_family, 17);
+        assert_eq!(sll.sll_protocol, 0x0800);
+        assert_eq!(sll.sll_ifindex, 7);
+        assert_eq!(sll.sll_hatype, 1);
+        assert_eq!(sll.sll_pkttype, 4);
+        assert_eq!(sll.sll_halen, 6);
+        assert_eq!(sll.sll_addr, [1, 2, 3, 4, 5, 6, 0, 0]);
+    }
+
+    #[test]
+    fn short_headers_fail_to_parse() {
+        let b = block_desc_bytes();
+        assert!(get_tpacket_block_desc(&b[..b.len() - 1]).is_err());
+        assert!(get_tpacket3_hdr(&[0; 39]).is_err());
+        assert!(get_sockaddr_ll(&[0; 19]).is_err());
+    }
+
+    #[test]
+    fn block_status_round_trips() {
+        let mut b = block_desc_bytes();
+        assert_eq!(read_block_status(&b), TpStatus::USER.bits());
+        write_block_status(&mut b, TP_STATUS_KERNEL.into());
+        assert_eq!(read_block_status(&b), u32::from(TP_STATUS_KERNEL));
+        write_block_status(&mut b, 0x8000_0001);
+        assert_eq!(read_block_status(&b), 0x8000_0001);
+        //nothing around the status word moved
+        let (_, desc) = get_tpacket_block_desc(&b).unwrap();
+        assert_eq!(desc.hdr.block_status, 0x8000_0001);
+        assert_eq!(desc.hdr.num_pkts, 3);
+        assert_eq!(desc.offset_to_priv, 48);
+    }
+
+    fn req(block_size: u32, block_nr: u32, frame_size: u32, frame_nr: u32) -> TpacketReq3 {
+        TpacketReq3 {
+            tp_block_size: block_size,
+            tp_block_nr: block_nr,
+            tp_frame_size: frame_size,
+            tp_frame_nr: frame_nr,
+            ..TpacketReq3::default()
+        }
+    }
+
+    #[test]
+    fn default_settings_are_valid() {
+        assert_eq!(TpacketReq3::default().validate(), Ok(()));
+        let page = page_size();
+        assert_eq!(req(page, 4, 2048, page / 2048 * 4).validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_reports_each_geometry_error() {
+        let page = page_size();
+        assert_eq!(req(page, 0, 2048, 0).validate(), Err(ConfigError::NoBlocks));
+        assert_eq!(
+            req(page + 1, 1, 2048, 2).validate(),
+            Err(ConfigError::BlockSizeUnaligned {
+                block_size: page + 1,
+                page_size: page,
+            })
+        );
+        assert_eq!(
+            req(0, 1, 2048, 0).validate(),
+            Err(ConfigError::BlockSizeUnaligned {
+                block_size: 0,
+                page_size: page,
+            })
+        );
+        assert_eq!(
+            req(page, 1, 64, page / 64).validate(),
+            Err(ConfigError::FrameTooSmall {
+                frame_size: 64,
+                min: TPACKET3_HDRLEN,
+            })
+        );
+        assert_eq!(
+            req(page, 1, 2050, 1).validate(),
+            Err(ConfigError::FrameSizeUnaligned { frame_size: 2050 })
+        );
+        assert_eq!(
+            req(page, 1, page * 2, 1).validate(),
+            Err(ConfigError::BlockTooSmall {
+                block_size: page,
+                min: page * 2,
+            })
+        );
+        assert_eq!(
+            req(page, 2, 2048, 3).validate(),
+            Err(ConfigError::FrameCountMismatch {
+                frame_nr: 3,
+                expected: page / 2048 * 2,
+            })
+        );
+        assert_eq!(
+            req(page, u32::MAX, 2048, 0).validate(),
+            Err(ConfigError::RingTooLarge {
+                block_nr: u32::MAX,
+                frames_per_block: page / 2048,
+            })
+        );
+    }
+
+    #[test]
+    fn private_area_counts_towards_the_smallest_block() {
+        let page = page_size();
+        let mut settings = req(page, 1, 2048, page / 2048);
+        settings.tp_sizeof_priv = page;
+        let min = (BLK_HDR_LEN + u64::from(page) + u64::from(TPACKET3_HDRLEN)) as u32;
+        assert_eq!(
+            settings.validate(),
+            Err(ConfigError::BlockTooSmall {
+                block_size: page,
+                min,
+            })
+        );
+        assert_eq!(
+            check_geometry(
+                [page, 1, 2048, page / 2048],
+                TPACKET3_HDRLEN,
+                u64::from(page)
+            ),
+            Ok(())
+        );
+    }
+}
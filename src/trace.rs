@@ -0,0 +1,93 @@
+//!Instrumentation of the capture path. Events and spans are handed to a `Subscriber` installed
+//!with `set_subscriber`, which can forward them to `tracing`, `log`, or any other telemetry
+//!pipeline. Nothing is recorded until a subscriber is installed.
+
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+///A single event emitted by the crate
+#[derive(Debug)]
+pub struct Event<'a> {
+    pub level: Level,
+    ///Module that emitted the event, e.g. `af_packet::rx`
+    pub target: &'static str,
+    pub message: fmt::Arguments<'a>,
+}
+
+///Receives events and span entries/exits from the crate
+pub trait Subscriber: Send + Sync {
+    ///Returning false skips formatting and dispatch of events at this level
+    fn enabled(&self, _level: Level) -> bool {
+        true
+    }
+
+    fn event(&self, event: &Event);
+
+    fn enter(&self, _span: &'static str) {}
+
+    fn exit(&self, _span: &'static str, _elapsed: Duration) {}
+}
+
+static SUBSCRIBER: OnceLock<Box<dyn Subscriber>> = OnceLock::new();
+
+///Installs the global subscriber. This can only be done once, later calls return the
+///subscriber they were given
+pub fn set_subscriber(subscriber: Box<dyn Subscriber>) -> Result<(), Box<dyn Subscriber>> {
+    SUBSCRIBER.set(subscriber)
+}
+
+#[doc(hidden)]
+pub fn enabled(level: Level) -> bool {
+    match SUBSCRIBER.get() {
+        Some(s) => s.enabled(level),
+        None => false,
+    }
+}
+
+#[doc(hidden)]
+pub fn dispatch(level: Level, target: &'static str, message: fmt::Arguments) {
+    if let Some(s) = SUBSCRIBER.get() {
+        s.event(&Event {
+            level,
+            target,
+            message,
+        });
+    }
+}
+
+///Guard returned when entering a span, the exit is reported when it is dropped
+#[doc(hidden)]
+pub struct Span {
+    name: &'static str,
+    start: Instant,
+}
+
+impl Span {
+    pub fn enter(name: &'static str) -> Span {
+        if let Some(s) = SUBSCRIBER.get() {
+            s.enter(name);
+        }
+        Span {
+            name,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(s) = SUBSCRIBER.get() {
+            s.exit(self.name, self.start.elapsed());
+        }
+    }
+}
@@ -1,26 +1,60 @@
+use error::{Operation, RingError};
+use rx::RingLabel;
 use socket::{self, Socket};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::{io, mem};
+use timestamp::{self, HwTimestampGuard, TxTimestamp};
 
-use libc::{c_void, sendto, sockaddr, sockaddr_ll, AF_PACKET, ETH_ALEN};
+use libc::{c_void, sendto, sockaddr, sockaddr_ll, AF_PACKET, ETH_ALEN, SOCK_RAW};
 
 pub struct Player {
     sock: Socket,
+    //frames sent since timestamping was turned on, which is how the kernel numbers timestamps
+    sent: AtomicU32,
+    hw_timestamps: Option<HwTimestampGuard>,
 }
 
 impl Player {
     ///gets a socket ready to play frames
     pub fn open_socket(if_name: &str) -> io::Result<Player> {
-        let sock = Socket::from_if_name(if_name, socket::AF_PACKET)?;
-        Ok(Player { sock })
+        let sock = Socket::from_if_name(if_name, socket::AF_PACKET).map_err(|e| {
+            let label = RingLabel {
+                if_name: String::from(if_name),
+                index: 0,
+                tag: None,
+            };
+            RingError::new(&label, Operation::Socket { kind: SOCK_RAW }, e)
+        })?;
+        Ok(Player::with_socket(sock))
+    }
+
+    fn with_socket(sock: Socket) -> Player {
+        Player {
+            sock,
+            sent: AtomicU32::new(0),
+            hw_timestamps: None,
+        }
+    }
+
+    ///Reads the MAC address of the interface, the source address of frames sent from this host
+    pub fn mac_address(&self) -> io::Result<[u8; 6]> {
+        self.sock.get_mac_address()
     }
 
     ///sends a raw, whole ethernet frame on the socket
-    pub fn send_frame(&self, mut frame: &mut [u8]) -> io::Result<()> {
+    pub fn send_frame(&self, frame: &[u8]) -> io::Result<()> {
+        self.send_timestamped(frame).map(|_| ())
+    }
+
+    ///Sends a frame like `send_frame`, returning the id its timestamps will carry once
+    ///`enable_tx_timestamps` turned them on
+    pub fn send_timestamped(&self, frame: &[u8]) -> io::Result<u32> {
         let mut sa = sockaddr_ll {
             sll_family: AF_PACKET as u16,
             sll_protocol: 0,
             sll_ifindex: self.sock.if_index as i32,
-            sll_hatype: 519,
+            sll_hatype: self.sock.hatype,
             sll_pkttype: 0,
             sll_halen: ETH_ALEN as u8,
             sll_addr: [0; 8], //dest_addr
@@ -34,16 +68,59 @@ impl Player {
         let b = unsafe {
             sendto(
                 self.sock.fd,
-                &mut frame as *mut _ as *mut c_void,
-                mem::size_of_val(&frame),
+                frame.as_ptr() as *const c_void,
+                frame.len(),
                 0,
                 addr_ptr,
                 size as u32,
             )
         };
         if b >= 0 {
-            return Ok(());
+            return Ok(self.sent.fetch_add(1, Ordering::Relaxed));
         }
-        Err(io::Error::last_os_error())
+        let err = io::Error::last_os_error();
+        trace_event!(Warn, "send on {} failed: {}", self.sock.if_name, err);
+        Err(err)
+    }
+
+    ///Timestamps frames sent from now on, numbering them from 0, to be read back with
+    ///`read_tx_timestamp`. With `hardware` the interface's NIC is also told to timestamp them,
+    ///which needs CAP_NET_ADMIN and fails with EOPNOTSUPP where it can't, until the player is
+    ///dropped
+    pub fn enable_tx_timestamps(&mut self, hardware: bool) -> io::Result<()> {
+        if hardware && self.hw_timestamps.is_none() {
+            self.hw_timestamps = Some(HwTimestampGuard::enable_tx(&self.sock)?);
+        }
+        //the kernel only restarts its numbering when it wasn't numbering already
+        timestamp::disable_tx_timestamps(&self.sock)?;
+        timestamp::enable_tx_timestamps(&self.sock, hardware)?;
+        self.sent.store(0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    ///Takes the next timestamp of a sent frame, or returns `None` if none is waiting. A frame
+    ///gets one when it is queued and one when it is sent, and its id is what `send_timestamped`
+    ///returned for it
+    pub fn read_tx_timestamp(&self) -> io::Result<Option<TxTimestamp>> {
+        timestamp::read_tx_timestamp(self.sock.fd)
+    }
+}
+
+impl AsRawFd for Player {
+    fn as_raw_fd(&self) -> RawFd {
+        self.sock.fd
+    }
+}
+
+impl IntoRawFd for Player {
+    fn into_raw_fd(self) -> RawFd {
+        self.sock.into_raw_fd()
+    }
+}
+
+impl FromRawFd for Player {
+    ///Plays frames on a packet socket opened elsewhere, which must be bound to its interface
+    unsafe fn from_raw_fd(fd: RawFd) -> Player {
+        Player::with_socket(Socket::from_raw_fd(fd))
     }
 }
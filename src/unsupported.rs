@@ -0,0 +1,159 @@
+//!Stand-ins for the AF_PACKET backends on platforms other than Linux. They keep the backends'
+//!constructors, which always fail with `ErrorKind::Unsupported`, so crates that also run elsewhere
+//!can name them unconditionally and pick a backend at runtime.
+
+use std::io::{self, Error, ErrorKind};
+
+use capture::{Capture, CaptureStats, Inject};
+use error::{Operation, RingError};
+use filter::SockFilter;
+use libc::{SOCK_DGRAM, SOCK_RAW};
+use rx::{RawPacket, RingDiagnostics, RingLabel, RingSettings, RingStatistics, RingTotals};
+
+//no value of this type exists, so no value of the stubs that hold one can be made either
+#[derive(Clone, Copy, Debug)]
+enum Void {}
+
+fn unsupported(settings: &RingSettings) -> RingError {
+    let kind = if settings.cooked {
+        SOCK_DGRAM
+    } else {
+        SOCK_RAW
+    };
+    RingError::new(
+        &RingLabel::from_settings(settings),
+        Operation::Socket { kind },
+        Error::new(
+            ErrorKind::Unsupported,
+            "AF_PACKET capture is only available on Linux",
+        ),
+    )
+}
+
+///Stands in for `rx::Ring` and `rx_v2::Ring`, it can't be created
+#[derive(Clone, Debug)]
+pub struct Ring {
+    void: Void,
+}
+
+impl Ring {
+    pub fn from_if_name(if_name: &str) -> Result<Ring, RingError> {
+        Ring::new(RingSettings {
+            if_name: String::from(if_name),
+            ..RingSettings::default()
+        })
+    }
+
+    pub fn new(settings: RingSettings) -> Result<Ring, RingError> {
+        Err(unsupported(&settings))
+    }
+
+    pub fn label(&self) -> &RingLabel {
+        match self.void {}
+    }
+
+    pub fn totals(&self) -> RingTotals {
+        match self.void {}
+    }
+
+    pub fn get_rx_statistics(&self) -> io::Result<RingStatistics> {
+        match self.void {}
+    }
+
+    pub fn diagnostics(&self) -> RingDiagnostics {
+        match self.void {}
+    }
+}
+
+impl Capture for Ring {
+    fn next_block(&mut self, _f: &mut dyn FnMut(&RawPacket<'_>)) -> io::Result<usize> {
+        match self.void {}
+    }
+
+    fn label(&self) -> &RingLabel {
+        match self.void {}
+    }
+
+    fn totals(&self) -> RingTotals {
+        match self.void {}
+    }
+
+    fn stats(&mut self) -> io::Result<CaptureStats> {
+        match self.void {}
+    }
+
+    fn attach_filter(&mut self, _prog: &[SockFilter]) -> io::Result<()> {
+        match self.void {}
+    }
+}
+
+pub mod rx_v2 {
+    pub use super::Ring;
+}
+
+pub mod simple {
+    use std::io;
+
+    use super::{unsupported, Void};
+    use error::RingError;
+    use rx::{RingLabel, RingSettings, RingStatistics};
+
+    ///Stands in for `simple::SimpleCapture`, it can't be created
+    #[derive(Debug)]
+    pub struct SimpleCapture {
+        void: Void,
+    }
+
+    impl SimpleCapture {
+        pub fn from_if_name(if_name: &str) -> Result<SimpleCapture, RingError> {
+            SimpleCapture::new(RingSettings {
+                if_name: String::from(if_name),
+                ..RingSettings::default()
+            })
+        }
+
+        pub fn new(settings: RingSettings) -> Result<SimpleCapture, RingError> {
+            Err(unsupported(&settings))
+        }
+
+        pub fn label(&self) -> &RingLabel {
+            match self.void {}
+        }
+
+        pub fn get_rx_statistics(&self) -> io::Result<RingStatistics> {
+            match self.void {}
+        }
+    }
+}
+
+pub mod tx {
+    use std::io;
+
+    use super::{unsupported, Inject, Void};
+    use rx::RingSettings;
+
+    ///Stands in for `tx::Player`, it can't be created
+    pub struct Player {
+        void: Void,
+    }
+
+    impl Player {
+        pub fn open_socket(if_name: &str) -> io::Result<Player> {
+            Err(unsupported(&RingSettings {
+                if_name: String::from(if_name),
+                ..RingSettings::default()
+            })
+            .into())
+        }
+
+        pub fn send_frame(&self, _frame: &[u8]) -> io::Result<()> {
+            match self.void {}
+        }
+    }
+
+    impl Inject for Player {
+        fn send_frame(&mut self, _frame: &[u8]) -> io::Result<()> {
+            match self.void {}
+        }
+    }
+}
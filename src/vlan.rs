@@ -0,0 +1,131 @@
+//!VLAN tag handling. The kernel usually strips the outermost tag of received frames into
+//!tp_vlan_tci/tp_vlan_tpid, so the captured bytes differ from what was on the wire.
+
+///802.1Q customer tag
+pub const ETH_P_8021Q: u16 = 0x8100;
+///802.1ad service tag, the outer tag of QinQ frames
+pub const ETH_P_8021AD: u16 = 0x88a8;
+///Pre-standard QinQ outer tag still used by some equipment
+pub const ETH_P_QINQ1: u16 = 0x9100;
+
+//destination and source MAC, the tag goes right after them
+const MAC_ADDRS_LEN: usize = 12;
+
+///A single VLAN tag
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VlanTag {
+    ///Tag protocol identifier, 0x8100 for 802.1Q or 0x88a8 for 802.1ad
+    pub tpid: u16,
+    ///Tag control information, PCP, DEI and VID together
+    pub tci: u16,
+}
+
+impl VlanTag {
+    ///VLAN identifier
+    #[inline]
+    pub fn vid(&self) -> u16 {
+        self.tci & 0x0fff
+    }
+
+    ///Priority code point
+    #[inline]
+    pub fn pcp(&self) -> u8 {
+        (self.tci >> 13) as u8
+    }
+
+    ///Drop eligible indicator
+    #[inline]
+    pub fn dei(&self) -> bool {
+        self.tci & 0x1000 != 0
+    }
+}
+
+///Returns true if `ethertype` introduces a VLAN tag
+#[inline]
+pub fn is_vlan_tpid(ethertype: u16) -> bool {
+    ethertype == ETH_P_8021Q || ethertype == ETH_P_8021AD || ethertype == ETH_P_QINQ1
+}
+
+///A tag in a frame's VLAN stack
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackedTag {
+    pub tag: VlanTag,
+    ///True if the kernel or NIC stripped this tag into the packet header, false if it is still
+    ///present in the captured bytes
+    pub offloaded: bool,
+}
+
+///Iterates the VLAN stack of a frame from the outermost tag inwards, starting with the offloaded
+///tag (if any) and continuing with the tags still present in the frame
+#[derive(Clone, Debug)]
+pub struct VlanStack<'a> {
+    frame: &'a [u8],
+    offloaded: Option<VlanTag>,
+    offset: usize,
+}
+
+impl<'a> VlanStack<'a> {
+    ///`frame` starts at the Ethernet header, `offloaded` is the tag the kernel stripped from it
+    pub fn new(frame: &'a [u8], offloaded: Option<VlanTag>) -> VlanStack<'a> {
+        VlanStack {
+            frame,
+            offloaded,
+            offset: MAC_ADDRS_LEN,
+        }
+    }
+
+    ///Ethertype following the last in-frame tag read so far. Once the iterator is exhausted this
+    ///is the frame's real payload type
+    pub fn ethertype(&self) -> Option<u16> {
+        read_u16(self.frame, self.offset)
+    }
+
+    ///Offset into the frame of the ethertype returned by `ethertype`
+    pub fn ethertype_offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a> Iterator for VlanStack<'a> {
+    type Item = StackedTag;
+
+    fn next(&mut self) -> Option<StackedTag> {
+        if let Some(tag) = self.offloaded.take() {
+            return Some(StackedTag {
+                tag,
+                offloaded: true,
+            });
+        }
+        let tpid = read_u16(self.frame, self.offset)?;
+        if !is_vlan_tpid(tpid) {
+            return None;
+        }
+        let tci = read_u16(self.frame, self.offset + 2)?;
+        self.offset += 4;
+        Some(StackedTag {
+            tag: VlanTag { tpid, tci },
+            offloaded: false,
+        })
+    }
+}
+
+#[inline]
+fn read_u16(buf: &[u8], offset: usize) -> Option<u16> {
+    let b = buf.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([b[0], b[1]]))
+}
+
+///Writes `frame` into `out` with `tag` inserted after the MAC addresses, reconstructing the frame
+///as it was on the wire. Frames too short to hold MAC addresses are copied unchanged
+pub fn reinsert_tag(frame: &[u8], tag: VlanTag, out: &mut Vec<u8>) {
+    out.clear();
+    if frame.len() < MAC_ADDRS_LEN {
+        out.extend_from_slice(frame);
+        return;
+    }
+    out.reserve(frame.len() + 4);
+    out.extend_from_slice(&frame[..MAC_ADDRS_LEN]);
+    out.extend_from_slice(&tag.tpid.to_be_bytes());
+    out.extend_from_slice(&tag.tci.to_be_bytes());
+    out.extend_from_slice(&frame[MAC_ADDRS_LEN..]);
+}
@@ -0,0 +1,149 @@
+//!The record an `OwnedPacket` is written as when it leaves memory, spilled to disk or streamed to
+//!another process. All fields are little endian:
+//!
+//!- the length of the whole record (u32)
+//!- tp_next_offset, tp_sec, tp_nsec, tp_snaplen, tp_len, tp_status, tp_rxhash and tp_vlan_tci
+//!  (u32 each), then tp_mac, tp_net and tp_vlan_tpid (u16 each) and two bytes of padding
+//!- 1 if a sockaddr_ll follows, else 0 (u8)
+//!- the sockaddr_ll: family, protocol, ifindex (i32), hatype, pkttype (u8), halen (u8) and the
+//!  8 byte address
+//!- the length of the frame (u32) and the frame
+
+use std::io::{self, Error, ErrorKind};
+
+use rx::OwnedPacket;
+use tpacket3::{SockaddrLl, Tpacket3Hdr, TpacketHdrVariant1};
+
+//record length, the header fields and the sockaddr_ll flag
+const RECORD_HEADER_LEN: usize = 4 + 8 * 4 + 4 * 2 + 1;
+const SLL_LEN: usize = 20;
+
+///Records longer than this are refused rather than allocated
+pub(crate) const MAX_RECORD: usize = 16 << 20;
+
+///Appends `packet`'s record to `out`
+pub(crate) fn encode(packet: &OwnedPacket, out: &mut Vec<u8>) {
+    let sll_len = if packet.sockaddr_ll.is_some() {
+        SLL_LEN
+    } else {
+        0
+    };
+    let len = RECORD_HEADER_LEN + sll_len + 4 + packet.data.len();
+    let hdr = &packet.tpacket3_hdr;
+    out.reserve(len);
+    out.extend_from_slice(&(len as u32).to_le_bytes());
+    for field in &[
+        hdr.tp_next_offset,
+        hdr.tp_sec,
+        hdr.tp_nsec,
+        hdr.tp_snaplen,
+        hdr.tp_len,
+        hdr.tp_status,
+        hdr.hv1.tp_rxhash,
+        hdr.hv1.tp_vlan_tci,
+    ] {
+        out.extend_from_slice(&field.to_le_bytes());
+    }
+    out.extend_from_slice(&hdr.tp_mac.to_le_bytes());
+    out.extend_from_slice(&hdr.tp_net.to_le_bytes());
+    out.extend_from_slice(&hdr.hv1.tp_vlan_tpid.to_le_bytes());
+    out.extend_from_slice(&[0, 0]);
+    match packet.sockaddr_ll {
+        Some(ref sll) => {
+            out.push(1);
+            out.extend_from_slice(&sll.sll_family.to_le_bytes());
+            out.extend_from_slice(&sll.sll_protocol.to_le_bytes());
+            out.extend_from_slice(&sll.sll_ifindex.to_le_bytes());
+            out.extend_from_slice(&sll.sll_hatype.to_le_bytes());
+            out.push(sll.sll_pkttype);
+            out.push(sll.sll_halen);
+            out.extend_from_slice(&sll.sll_addr);
+        }
+        None => out.push(0),
+    }
+    out.extend_from_slice(&(packet.data.len() as u32).to_le_bytes());
+    out.extend_from_slice(&packet.data);
+}
+
+///Returns the length of a record from its first four bytes
+pub(crate) fn record_len(prefix: [u8; 4]) -> usize {
+    u32::from_le_bytes(prefix) as usize
+}
+
+///Decodes a whole record, length included
+pub(crate) fn decode(record: &[u8]) -> io::Result<OwnedPacket> {
+    let mut fields = Fields {
+        rest: record.get(4..).unwrap_or(&[]),
+    };
+    let mut u32s = [0; 8];
+    for field in u32s.iter_mut() {
+        *field = fields.u32()?;
+    }
+    let tp_mac = fields.u16()?;
+    let tp_net = fields.u16()?;
+    let tp_vlan_tpid = fields.u16()?;
+    fields.take(2)?;
+    let sockaddr_ll = if fields.take(1)?[0] != 0 {
+        Some(SockaddrLl {
+            sll_family: fields.u16()?,
+            sll_protocol: fields.u16()?,
+            sll_ifindex: fields.u32()? as i32,
+            sll_hatype: fields.u16()?,
+            sll_pkttype: fields.take(1)?[0],
+            sll_halen: fields.take(1)?[0],
+            sll_addr: {
+                let mut addr = [0; 8];
+                addr.copy_from_slice(fields.take(8)?);
+                addr
+            },
+        })
+    } else {
+        None
+    };
+    let data_len = fields.u32()? as usize;
+    let data = fields.take(data_len)?.to_vec();
+    let tpacket3_hdr = Tpacket3Hdr {
+        tp_next_offset: u32s[0],
+        tp_sec: u32s[1],
+        tp_nsec: u32s[2],
+        tp_snaplen: u32s[3],
+        tp_len: u32s[4],
+        tp_status: u32s[5],
+        tp_mac,
+        tp_net,
+        hv1: TpacketHdrVariant1::new(u32s[6], u32s[7], tp_vlan_tpid),
+    };
+    Ok(OwnedPacket {
+        tpacket3_hdr,
+        sockaddr_ll,
+        data,
+    })
+}
+
+struct Fields<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.rest.len() < n {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "packet record cut short",
+            ));
+        }
+        let (bytes, rest) = self.rest.split_at(n);
+        self.rest = rest;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
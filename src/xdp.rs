@@ -0,0 +1,587 @@
+//!AF_XDP (XSK) receive socket that hands out the same `RawPacket`s as the AF_PACKET rings, for
+//!deployments that want to bypass the kernel stack. Packets only reach the socket once an XDP
+//!program on the interface redirects them into an XSKMAP containing it, loading that program is
+//!left to the application (or a tool like xdp-loader), `Ring::register_in_xskmap` does the map
+//!update
+
+use std::io::{self, Error, ErrorKind};
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::SystemTime;
+
+use libc::{
+    bind, c_int, c_long, c_void, close, getsockopt, mmap, munmap, recvfrom, setsockopt, sockaddr,
+    sockaddr_xdp, socket, socklen_t, syscall, xdp_desc, xdp_mmap_offsets, xdp_statistics,
+    xdp_umem_reg, SYS_bpf, AF_XDP, MAP_ANONYMOUS, MAP_FAILED, MAP_POPULATE, MAP_PRIVATE,
+    MAP_SHARED, MSG_DONTWAIT, PROT_READ, PROT_WRITE, SOCK_RAW, SOL_XDP, XDP_COPY, XDP_MMAP_OFFSETS,
+    XDP_PGOFF_RX_RING, XDP_RING_NEED_WAKEUP, XDP_RX_RING, XDP_STATISTICS, XDP_UMEM_COMPLETION_RING,
+    XDP_UMEM_FILL_RING, XDP_UMEM_PGOFF_COMPLETION_RING, XDP_UMEM_PGOFF_FILL_RING, XDP_UMEM_REG,
+    XDP_USE_NEED_WAKEUP, XDP_ZEROCOPY,
+};
+
+use capture::CaptureStats;
+use error::Operation;
+use rx::{self, RawPacket, RingLabel, RingTotals};
+use socket::{self, Socket};
+
+const BPF_MAP_UPDATE_ELEM: c_long = 2;
+
+///Settings to be used to bring up an AF_XDP socket
+#[derive(Clone, Debug)]
+pub struct XdpSettings {
+    ///Interface name
+    pub if_name: String,
+    ///NIC receive queue to bind to, an XSK only sees the traffic of one queue
+    pub queue_id: u32,
+    ///Number of frames in the UMEM shared with the kernel
+    pub frame_count: u32,
+    ///Size of each UMEM frame, 2048 or 4096
+    pub frame_size: u32,
+    ///Entries in the fill and RX rings, must be a power of two
+    pub ring_size: u32,
+    ///Most packets handed out by a single `get_block`
+    pub batch_size: u32,
+    ///Require zero-copy mode, rather than letting the kernel fall back to copy mode
+    pub zero_copy: bool,
+    ///Optional user tag used alongside the interface and queue to identify the socket
+    pub tag: Option<String>,
+}
+
+impl Default for XdpSettings {
+    fn default() -> XdpSettings {
+        XdpSettings {
+            if_name: String::from("eth0"),
+            queue_id: 0,
+            frame_count: 4096,
+            frame_size: 2048,
+            ring_size: 2048,
+            batch_size: 64,
+            zero_copy: false,
+            tag: None,
+        }
+    }
+}
+
+//a producer/consumer ring shared with the kernel
+#[derive(Debug)]
+struct XskRing {
+    map: *mut u8,
+    map_len: usize,
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    flags: *const AtomicU32,
+    descs: *mut u8,
+    mask: u32,
+}
+
+impl XskRing {
+    #[inline]
+    fn producer(&self) -> &AtomicU32 {
+        unsafe { &*self.producer }
+    }
+
+    #[inline]
+    fn consumer(&self) -> &AtomicU32 {
+        unsafe { &*self.consumer }
+    }
+
+    #[inline]
+    fn needs_wakeup(&self) -> bool {
+        unsafe { &*self.flags }.load(Ordering::Relaxed) & XDP_RING_NEED_WAKEUP != 0
+    }
+}
+
+///An AF_XDP socket bound to one queue of an interface, along with its UMEM
+#[derive(Debug)]
+pub struct Ring {
+    pub socket: Socket,
+    label: RingLabel,
+    umem: *mut u8,
+    umem_len: usize,
+    frame_size: u32,
+    batch_size: u32,
+    rx: XskRing,
+    fill: XskRing,
+    completion: XskRing,
+    next_seq: u64,
+    totals: RingTotals,
+    //what stats_since_last last reported, as XDP_STATISTICS only counts up
+    last_packets: u64,
+    last_dropped: u64,
+}
+
+///A batch of received packets, returned to the kernel by `mark_as_consumed`. Dropping it without
+///consuming it leaves the packets in the RX ring, so the next `get_block` returns them again
+#[derive(Debug)]
+pub struct Block<'a> {
+    ring: &'a mut Ring,
+    first: u32,
+    count: u32,
+    seq: u64,
+    received_at: SystemTime,
+}
+
+impl<'a> Block<'a> {
+    ///Returns `Vec` of details and references to raw packets in the batch. AF_XDP doesn't
+    ///timestamp packets, so they all carry the time the batch was handed out
+    pub fn get_raw_packets(&self) -> Vec<RawPacket<'_>> {
+        (0..self.count)
+            .filter_map(|i| {
+                let desc = self.ring.rx_desc(self.first.wrapping_add(i));
+                let frame = self.ring.umem_slice(desc.addr, desc.len)?;
+                Some(RawPacket::from_frame(frame, desc.len, self.received_at))
+            })
+            .collect()
+    }
+
+    ///Hands the frames back to the kernel through the fill ring
+    pub fn mark_as_consumed(&mut self) {
+        let ring = &mut *self.ring;
+        let fill_prod = ring.fill.producer().load(Ordering::Relaxed);
+        for i in 0..self.count {
+            let desc = ring.rx_desc(self.first.wrapping_add(i));
+            //the kernel may have added headroom, the frame is what it was originally given
+            let addr = desc.addr - desc.addr % u64::from(ring.frame_size);
+            let slot = fill_prod.wrapping_add(i) & ring.fill.mask;
+            unsafe {
+                ptr::write_volatile((ring.fill.descs as *mut u64).add(slot as usize), addr);
+            }
+        }
+        ring.fill
+            .producer()
+            .store(fill_prod.wrapping_add(self.count), Ordering::Release);
+        ring.rx
+            .consumer()
+            .store(self.first.wrapping_add(self.count), Ordering::Release);
+        if ring.fill.needs_wakeup() {
+            ring.wakeup();
+        }
+        self.count = 0;
+    }
+
+    #[inline]
+    pub fn packet_count(&self) -> u32 {
+        self.count
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    ///Returns a sequence number counted by the socket, as AF_XDP has no block sequence numbers
+    #[inline]
+    pub fn seq_num(&self) -> u64 {
+        self.seq
+    }
+}
+
+impl Ring {
+    ///Creates an AF_XDP socket on queue 0 of the specified interface
+    pub fn from_if_name(if_name: &str) -> io::Result<Ring> {
+        Ring::new(XdpSettings {
+            if_name: String::from(if_name),
+            ..XdpSettings::default()
+        })
+    }
+
+    ///Creates an AF_XDP socket from the supplied XdpSettings struct
+    pub fn new(settings: XdpSettings) -> io::Result<Ring> {
+        trace_span!(_span, "xdp_setup");
+        let label = RingLabel {
+            if_name: settings.if_name.clone(),
+            index: settings.queue_id,
+            tag: settings.tag.clone(),
+        };
+        if !settings.ring_size.is_power_of_two() {
+            return Err(rx::label_error(
+                &label,
+                "settings",
+                Error::new(ErrorKind::InvalidInput, "ring_size must be a power of two"),
+            ));
+        }
+        let if_index = rx::setup_step(
+            &label,
+            Operation::Other(String::from("if_index")),
+            socket::get_if_index(&settings.if_name),
+        )?;
+        let fd = unsafe { socket(AF_XDP, SOCK_RAW, 0) };
+        if fd < 0 {
+            return Err(rx::label_error(&label, "socket", Error::last_os_error()));
+        }
+        let sock = Socket {
+            fd,
+            if_name: settings.if_name.clone(),
+            if_index,
+            sock_type: AF_XDP,
+            kind: SOCK_RAW,
+            //XDP only runs on Ethernet drivers
+            hatype: libc::ARPHRD_ETHER,
+        };
+
+        //everything mapped so far is released if a later step fails
+        let mut ring = Ring {
+            socket: sock,
+            label,
+            umem: ptr::null_mut(),
+            umem_len: 0,
+            frame_size: settings.frame_size,
+            batch_size: settings.batch_size.max(1),
+            rx: XskRing::unmapped(),
+            fill: XskRing::unmapped(),
+            completion: XskRing::unmapped(),
+            next_seq: 0,
+            totals: RingTotals::default(),
+            last_packets: 0,
+            last_dropped: 0,
+        };
+
+        let umem_len = settings.frame_count as usize * settings.frame_size as usize;
+        ring.setup("umem", |r| {
+            r.umem = map(umem_len, MAP_PRIVATE | MAP_ANONYMOUS | MAP_POPULATE, -1, 0)?;
+            r.umem_len = umem_len;
+            Ok(())
+        })?;
+        ring.setup("XDP_UMEM_REG", |r| {
+            let reg = xdp_umem_reg {
+                addr: r.umem as u64,
+                len: umem_len as u64,
+                chunk_size: settings.frame_size,
+                headroom: 0,
+                flags: 0,
+                tx_metadata_len: 0,
+            };
+            r.xdp_setsockopt(XDP_UMEM_REG, &reg)
+        })?;
+        let ring_size = settings.ring_size;
+        ring.setup("XDP_UMEM_FILL_RING", |r| {
+            r.xdp_setsockopt(XDP_UMEM_FILL_RING, &ring_size)
+        })?;
+        //unused for receiving, but the kernel won't bind without one
+        ring.setup("XDP_UMEM_COMPLETION_RING", |r| {
+            r.xdp_setsockopt(XDP_UMEM_COMPLETION_RING, &ring_size)
+        })?;
+        ring.setup("XDP_RX_RING", |r| r.xdp_setsockopt(XDP_RX_RING, &ring_size))?;
+        ring.setup("mmap", |r| {
+            let mut off: xdp_mmap_offsets = unsafe { mem::zeroed() };
+            let mut len = mem::size_of_val(&off) as socklen_t;
+            if unsafe {
+                getsockopt(
+                    r.socket.fd,
+                    SOL_XDP,
+                    XDP_MMAP_OFFSETS,
+                    &mut off as *mut _ as *mut c_void,
+                    &mut len,
+                )
+            } != 0
+            {
+                return Err(Error::last_os_error());
+            }
+            let fd = r.socket.fd;
+            r.rx = XskRing::map(
+                fd,
+                XDP_PGOFF_RX_RING as u64,
+                &off.rx,
+                ring_size,
+                mem::size_of::<xdp_desc>(),
+            )?;
+            r.fill = XskRing::map(fd, XDP_UMEM_PGOFF_FILL_RING, &off.fr, ring_size, 8)?;
+            r.completion = XskRing::map(fd, XDP_UMEM_PGOFF_COMPLETION_RING, &off.cr, ring_size, 8)?;
+            Ok(())
+        })?;
+
+        //give the kernel as many frames as the fill ring holds
+        let initial = settings.frame_count.min(ring_size);
+        for i in 0..initial {
+            unsafe {
+                ptr::write_volatile(
+                    (ring.fill.descs as *mut u64).add(i as usize),
+                    u64::from(i) * u64::from(settings.frame_size),
+                );
+            }
+        }
+        ring.fill.producer().store(initial, Ordering::Release);
+
+        let mode = if settings.zero_copy { XDP_ZEROCOPY } else { 0 };
+        ring.setup("bind", |r| r.bind(mode | XDP_USE_NEED_WAKEUP))?;
+        trace_event!(
+            Info,
+            "xdp socket {} up (fd {}): {} frames of {} bytes",
+            ring.label,
+            ring.socket.fd,
+            settings.frame_count,
+            settings.frame_size
+        );
+        Ok(ring)
+    }
+
+    ///Returns the label identifying this socket
+    pub fn label(&self) -> &RingLabel {
+        &self.label
+    }
+
+    ///Returns running totals of what this socket has delivered to the application
+    pub fn totals(&self) -> RingTotals {
+        self.totals
+    }
+
+    ///Reads the socket's XDP_STATISTICS, which unlike PACKET_STATISTICS are not reset
+    pub fn xdp_statistics(&self) -> io::Result<xdp_statistics> {
+        let mut stats: xdp_statistics = unsafe { mem::zeroed() };
+        let mut len = mem::size_of_val(&stats) as socklen_t;
+        match unsafe {
+            getsockopt(
+                self.socket.fd,
+                SOL_XDP,
+                XDP_STATISTICS,
+                &mut stats as *mut _ as *mut c_void,
+                &mut len,
+            )
+        } {
+            0 => Ok(stats),
+            _ => Err(rx::label_error(
+                &self.label,
+                "XDP_STATISTICS",
+                Error::last_os_error(),
+            )),
+        }
+    }
+
+    ///Returns packets delivered and dropped since the previous call, for `Capture::stats`
+    pub(crate) fn stats_since_last(&mut self) -> io::Result<CaptureStats> {
+        let stats = self.xdp_statistics()?;
+        let dropped = stats.rx_dropped + stats.rx_ring_full;
+        let result = CaptureStats {
+            packets: self.totals.packets - self.last_packets,
+            drops: dropped.saturating_sub(self.last_dropped),
+        };
+        self.last_packets = self.totals.packets;
+        self.last_dropped = dropped;
+        Ok(result)
+    }
+
+    ///Inserts this socket into the XSKMAP `map_fd` at its queue id, so an XDP program calling
+    ///bpf_redirect_map on that map delivers the queue's packets here
+    pub fn register_in_xskmap(&self, map_fd: c_int) -> io::Result<()> {
+        let key: u32 = self.label.index;
+        let value: u32 = self.socket.fd as u32;
+        //union bpf_attr as used by BPF_MAP_*_ELEM
+        #[repr(C)]
+        struct MapElemAttr {
+            map_fd: u32,
+            pad: u32,
+            key: u64,
+            value: u64,
+            flags: u64,
+        }
+        let attr = MapElemAttr {
+            map_fd: map_fd as u32,
+            pad: 0,
+            key: &key as *const u32 as u64,
+            value: &value as *const u32 as u64,
+            flags: 0,
+        };
+        match unsafe {
+            syscall(
+                SYS_bpf,
+                BPF_MAP_UPDATE_ELEM,
+                &attr as *const MapElemAttr,
+                mem::size_of::<MapElemAttr>(),
+            )
+        } {
+            0 => Ok(()),
+            _ => Err(rx::label_error(
+                &self.label,
+                "BPF_MAP_UPDATE_ELEM",
+                Error::last_os_error(),
+            )),
+        }
+    }
+
+    ///Waits for packets to arrive on the socket's queue and returns up to `batch_size` of them
+    pub fn get_block(&mut self) -> Block<'_> {
+        loop {
+            let first = self.rx.consumer().load(Ordering::Relaxed);
+            let available = self
+                .rx
+                .producer()
+                .load(Ordering::Acquire)
+                .wrapping_sub(first);
+            if available > 0 {
+                let count = available.min(self.batch_size);
+                let seq = self.next_seq;
+                self.next_seq += 1;
+                self.totals.blocks += 1;
+                for i in 0..count {
+                    self.totals.packets += 1;
+                    self.totals.bytes += u64::from(self.rx_desc(first.wrapping_add(i)).len);
+                }
+                return Block {
+                    ring: self,
+                    first,
+                    count,
+                    seq,
+                    received_at: SystemTime::now(),
+                };
+            }
+            if self.fill.needs_wakeup() {
+                self.wakeup();
+            }
+            rx::wait_readable(&self.socket, &self.label);
+        }
+    }
+
+    fn setup<T, F>(&mut self, step: &'static str, f: F) -> io::Result<T>
+    where
+        F: FnOnce(&mut Ring) -> io::Result<T>,
+    {
+        let result = f(self);
+        rx::setup_step(&self.label, Operation::Other(String::from(step)), result)
+            .map_err(io::Error::from)
+    }
+
+    fn xdp_setsockopt<T>(&self, opt: c_int, val: &T) -> io::Result<()> {
+        match unsafe {
+            setsockopt(
+                self.socket.fd,
+                SOL_XDP,
+                opt,
+                val as *const T as *const c_void,
+                mem::size_of::<T>() as socklen_t,
+            )
+        } {
+            0 => Ok(()),
+            _ => Err(Error::last_os_error()),
+        }
+    }
+
+    fn bind(&self, flags: u16) -> io::Result<()> {
+        let mut sa: sockaddr_xdp = unsafe { mem::zeroed() };
+        sa.sxdp_family = AF_XDP as u16;
+        sa.sxdp_flags = flags;
+        sa.sxdp_ifindex = self.socket.if_index;
+        sa.sxdp_queue_id = self.label.index;
+        let bound = unsafe {
+            bind(
+                self.socket.fd,
+                &sa as *const sockaddr_xdp as *const sockaddr,
+                mem::size_of_val(&sa) as socklen_t,
+            )
+        };
+        if bound == 0 {
+            return Ok(());
+        }
+        let e = Error::last_os_error();
+        //drivers without native support refuse the default, ask for copy mode explicitly then
+        if flags & XDP_ZEROCOPY == 0 && flags & XDP_COPY == 0 {
+            return self.bind(flags | XDP_COPY).map_err(|_| e);
+        }
+        Err(e)
+    }
+
+    #[inline]
+    fn wakeup(&self) {
+        unsafe {
+            recvfrom(
+                self.socket.fd,
+                ptr::null_mut(),
+                0,
+                MSG_DONTWAIT,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+        }
+    }
+
+    #[inline]
+    fn rx_desc(&self, index: u32) -> xdp_desc {
+        let slot = (index & self.rx.mask) as usize;
+        unsafe { ptr::read_volatile((self.rx.descs as *const xdp_desc).add(slot)) }
+    }
+
+    #[inline]
+    fn umem_slice(&self, addr: u64, len: u32) -> Option<&[u8]> {
+        let end = (addr as usize).checked_add(len as usize)?;
+        if end > self.umem_len {
+            return None;
+        }
+        Some(unsafe { std::slice::from_raw_parts(self.umem.add(addr as usize), len as usize) })
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        for ring in [&self.rx, &self.fill, &self.completion].iter() {
+            ring.unmap();
+        }
+        unsafe {
+            if !self.umem.is_null() {
+                munmap(self.umem as *mut c_void, self.umem_len);
+            }
+            close(self.socket.fd);
+        }
+    }
+}
+
+impl XskRing {
+    fn unmapped() -> XskRing {
+        XskRing {
+            map: ptr::null_mut(),
+            map_len: 0,
+            producer: ptr::null(),
+            consumer: ptr::null(),
+            flags: ptr::null(),
+            descs: ptr::null_mut(),
+            mask: 0,
+        }
+    }
+
+    fn map(
+        fd: c_int,
+        pgoff: u64,
+        off: &libc::xdp_ring_offset,
+        size: u32,
+        desc_size: usize,
+    ) -> io::Result<XskRing> {
+        let map_len = off.desc as usize + size as usize * desc_size;
+        let base = map(map_len, MAP_SHARED | MAP_POPULATE, fd, pgoff as libc::off_t)?;
+        unsafe {
+            Ok(XskRing {
+                map: base,
+                map_len,
+                producer: base.add(off.producer as usize) as *const AtomicU32,
+                consumer: base.add(off.consumer as usize) as *const AtomicU32,
+                flags: base.add(off.flags as usize) as *const AtomicU32,
+                descs: base.add(off.desc as usize),
+                mask: size - 1,
+            })
+        }
+    }
+
+    fn unmap(&self) {
+        if !self.map.is_null() {
+            unsafe {
+                munmap(self.map as *mut c_void, self.map_len);
+            }
+        }
+    }
+}
+
+fn map(len: usize, flags: c_int, fd: c_int, offset: libc::off_t) -> io::Result<*mut u8> {
+    match unsafe {
+        mmap(
+            ptr::null_mut(),
+            len,
+            PROT_READ | PROT_WRITE,
+            flags,
+            fd,
+            offset,
+        )
+    } {
+        MAP_FAILED => Err(Error::last_os_error()),
+        addr => Ok(addr as *mut u8),
+    }
+}
+
+unsafe impl Send for Ring {}